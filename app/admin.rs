@@ -0,0 +1,672 @@
+use chrono::{Duration, NaiveDate, SecondsFormat, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::rc::Rc;
+
+use app::cached_service::PersonCachedService;
+use app::config::{Backend, ConfigArgs, Settings};
+use app::dao::{LifeStatus, PersonQuery, PersonSearch, SortKey, SortOrder};
+use app::domain::{PersonId, Revision};
+use app::dto::{PersonDto, PersonHistoryDto, PersonPatch};
+use app::exporter;
+use app::importer;
+use app::migration::Migrator;
+use app::outbox::OutboxRelay;
+use app::rest::{ImportResponse, PersonEntry, PersonRequest};
+use app::service::{PersonOutputBoundary, PersonService, ServiceError};
+use app::{MigratorImpl, OutboxRelayImpl, PersonServiceImpl};
+
+/// Administration tool for person records.
+///
+/// Connects with the same settings as the app: `--config` or `APP_CONFIG`,
+/// the environment variables and the flags below.
+#[derive(Debug, Parser)]
+#[command(name = "admin")]
+struct Cli {
+    /// output format
+    #[arg(long, value_enum, default_value_t = Output::Table, global = true)]
+    output: Output,
+
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Output {
+    Table,
+    Json,
+}
+
+#[derive(Debug, PartialEq, Eq, Subcommand)]
+enum Command {
+    /// register a person
+    Register {
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        birth_date: NaiveDate,
+        #[arg(long)]
+        death_date: Option<NaiveDate>,
+        #[arg(long, default_value = "")]
+        data: String,
+    },
+    /// find a person by id
+    Find { id: PersonId },
+    /// list persons, all of them unless any filter or page is given
+    List {
+        #[arg(long)]
+        name_prefix: Option<String>,
+        /// alive or dead
+        #[arg(long)]
+        status: Option<LifeStatus>,
+        #[arg(long)]
+        born_from: Option<NaiveDate>,
+        #[arg(long)]
+        born_until: Option<NaiveDate>,
+        /// id, name or birth_date
+        #[arg(long, default_value = "id")]
+        sort: SortKey,
+        /// asc or desc
+        #[arg(long, default_value = "asc")]
+        order: SortOrder,
+        /// the last id of the previous page
+        #[arg(long)]
+        after: Option<PersonId>,
+        #[arg(long)]
+        limit: Option<u32>,
+    },
+    /// search persons by name, best matches first
+    Search {
+        text: String,
+        /// search the data too
+        #[arg(long)]
+        data: bool,
+        #[arg(long)]
+        limit: Option<u32>,
+    },
+    /// record the death date of a person
+    Death {
+        id: PersonId,
+        #[arg(long)]
+        date: NaiveDate,
+    },
+    /// correct the recorded death date of a person, or clear it without --date
+    CorrectDeath {
+        id: PersonId,
+        #[arg(long)]
+        date: Option<NaiveDate>,
+        /// why the record is corrected
+        #[arg(long)]
+        reason: String,
+    },
+    /// change the name, birth date or data of a person
+    Update {
+        id: PersonId,
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long)]
+        birth_date: Option<NaiveDate>,
+        #[arg(long, conflicts_with = "clear_data")]
+        data: Option<String>,
+        /// remove the data
+        #[arg(long)]
+        clear_data: bool,
+    },
+    /// unregister a person (it can be restored until purged)
+    Unregister { id: PersonId },
+    /// restore an unregistered person
+    Restore { id: PersonId },
+    /// permanently delete the persons unregistered more than the given days ago
+    Purge {
+        #[arg(long, default_value_t = 30)]
+        days: u32,
+    },
+    /// show the changes of a person, or the person as of the given revision
+    History {
+        id: PersonId,
+        #[arg(long)]
+        revision: Option<Revision>,
+    },
+    /// publish the pending notifications in the outbox
+    Relay {
+        #[arg(long, default_value_t = 100)]
+        batch_size: i64,
+    },
+    /// apply the pending schema migrations
+    Migrate {
+        /// only show the pending migrations
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// import persons from a JSON file (an array of persons),
+    /// or stream them from a CSV (`.csv`) or JSON-lines (`.ndjson`, `.jsonl`) file
+    Import { file: PathBuf },
+    /// export all persons to a CSV (`.csv`), JSON-lines (`.ndjson`, `.jsonl`) or JSON (`.json`) file
+    Export { file: PathBuf },
+}
+
+// batch import の進捗は標準エラー出力に出す
+struct ImportProgressPresenter;
+impl PersonOutputBoundary<(u64, u64), ServiceError> for ImportProgressPresenter {
+    fn started(&self) {
+        eprintln!("import started");
+    }
+    fn in_progress(&self, progress: (u64, u64)) {
+        eprintln!("imported {} of {}", progress.1, progress.0);
+    }
+    fn completed(&self) {
+        eprintln!("import completed");
+    }
+    fn aborted(&self, err: ServiceError) {
+        eprintln!("import aborted: {}", err);
+    }
+}
+
+fn format_table(persons: &[PersonEntry]) -> String {
+    let mut lines = vec![format!(
+        "{:>6}  {:<20}  {:<10}  {:<10}  {:>8}  {}",
+        "id", "name", "birth", "death", "revision", "data"
+    )];
+    for PersonEntry { id, person } in persons {
+        lines.push(format!(
+            "{:>6}  {:<20}  {:<10}  {:<10}  {:>8}  {}",
+            id,
+            person.name,
+            person.birth_date,
+            person.death_date.map(|d| d.to_string()).unwrap_or_default(),
+            person.revision,
+            person.data.as_deref().unwrap_or_default(),
+        ));
+    }
+    lines.join("\n")
+}
+
+fn format_history(history: &[PersonHistoryDto]) -> String {
+    let mut lines = vec![format!(
+        "{:>8}  {:<8}  {:<25}  {:<10}  {}",
+        "revision", "kind", "changed_at", "actor", "name"
+    )];
+    for h in history {
+        // 物理削除は new がないので old の名前を出す
+        let name = h.new.as_ref().or(h.old.as_ref()).map(|p| p.name.as_str());
+        lines.push(format!(
+            "{:>8}  {:<8}  {:<25}  {:<10}  {}",
+            h.revision,
+            h.kind,
+            h.changed_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            h.actor,
+            name.unwrap_or_default(),
+        ));
+    }
+    lines.join("\n")
+}
+
+fn print_json(value: &impl Serialize) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).unwrap_or_default()
+    );
+}
+
+fn print_persons(output: Output, persons: Vec<PersonEntry>) {
+    match output {
+        Output::Table => println!("{}", format_table(&persons)),
+        Output::Json => print_json(&persons),
+    }
+}
+
+fn load_persons(file: &PathBuf) -> Result<Vec<PersonDto>, String> {
+    let content = fs::read_to_string(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+    let persons: Vec<PersonRequest> =
+        serde_json::from_str(&content).map_err(|e| format!("{}: {}", file.display(), e))?;
+
+    Ok(persons.into_iter().map(PersonDto::from).collect())
+}
+
+fn relay(output: Output, settings: &Settings, batch_size: i64) -> Result<(), String> {
+    let mut relay = OutboxRelayImpl::from_settings(settings).map_err(|e| e.to_string())?;
+    let mut total = 0;
+    // 空になるか送れなくなるまで繰り返す
+    loop {
+        match relay.relay(batch_size).map_err(|e| e.to_string())? {
+            0 => break,
+            sent => total += sent,
+        }
+    }
+    match output {
+        Output::Table => println!("relayed: {}", total),
+        Output::Json => print_json(&serde_json::json!({ "relayed": total })),
+    }
+    Ok(())
+}
+
+fn migrate(output: Output, settings: &Settings, dry_run: bool) -> Result<(), String> {
+    let mut migrator = MigratorImpl::from_settings(settings).map_err(|e| e.to_string())?;
+    let migrations = migrator.migrate(dry_run).map_err(|e| e.to_string())?;
+    let versions = migrations.iter().map(|m| m.version).collect::<Vec<_>>();
+    match output {
+        Output::Table => {
+            let action = if dry_run { "pending" } else { "applied" };
+            println!("{} {} migrations", action, migrations.len());
+            for m in &migrations {
+                println!("{:>4} {}", m.version, m.name);
+            }
+        }
+        Output::Json if dry_run => print_json(&serde_json::json!({ "pending": versions })),
+        Output::Json => print_json(&serde_json::json!({ "applied": versions })),
+    }
+    Ok(())
+}
+
+fn run(cli: Cli, settings: &Settings) -> Result<(), String> {
+    if settings.backend != Backend::Postgres {
+        return Err(format!(
+            "admin needs the postgres backend, not {}",
+            settings.backend
+        ));
+    }
+    // relay と migrate は database しか使わないので, service を作る (cache や rabbitmq に繋ぐ) 前に分ける.
+    // migrate は service が使う schema を用意するものでもある
+    let command = match cli.command {
+        Command::Relay { batch_size } => return relay(cli.output, settings, batch_size),
+        Command::Migrate { dry_run } => return migrate(cli.output, settings, dry_run),
+        command => command,
+    };
+    let mut service = PersonServiceImpl::from_settings(settings)
+        .map_err(|e| e.to_string())?
+        .with_actor("admin");
+
+    match command {
+        Command::Register {
+            name,
+            birth_date,
+            death_date,
+            data,
+        } => {
+            let (id, person) = service
+                .cached_register(&name, birth_date, death_date, &data)
+                .map_err(|e| e.to_string())?;
+            print_persons(cli.output, vec![PersonEntry { id, person }]);
+        }
+        Command::Find { id } => match service.cached_find(id).map_err(|e| e.to_string())? {
+            Some(person) => print_persons(cli.output, vec![PersonEntry { id, person }]),
+            None => return Err(format!("person not found: {id}")),
+        },
+        Command::List {
+            name_prefix,
+            status,
+            born_from,
+            born_until,
+            sort,
+            order,
+            after,
+            limit,
+        } => {
+            let query = PersonQuery {
+                name_prefix,
+                status,
+                born_from,
+                born_until,
+                sort,
+                order,
+                after,
+                limit,
+            };
+            let persons = if query == PersonQuery::default() {
+                service.cached_list_all()
+            } else {
+                service.list(query)
+            };
+            let persons = persons
+                .map_err(|e| e.to_string())?
+                .into_iter()
+                .map(|(id, person)| PersonEntry { id, person })
+                .collect();
+            print_persons(cli.output, persons);
+        }
+        Command::Search { text, data, limit } => {
+            let persons = service
+                .search(PersonSearch {
+                    text,
+                    include_data: data,
+                    limit,
+                })
+                .map_err(|e| e.to_string())?
+                .into_iter()
+                .map(|(id, person)| PersonEntry { id, person })
+                .collect();
+            print_persons(cli.output, persons);
+        }
+        Command::Death { id, date } => {
+            service.cached_death(id, date).map_err(|e| e.to_string())?;
+            if let Some(person) = service.cached_find(id).map_err(|e| e.to_string())? {
+                print_persons(cli.output, vec![PersonEntry { id, person }]);
+            }
+        }
+        Command::CorrectDeath { id, date, reason } => {
+            let person = service
+                .cached_correct_death(id, date, &reason)
+                .map_err(|e| e.to_string())?;
+            print_persons(cli.output, vec![PersonEntry { id, person }]);
+        }
+        Command::Update {
+            id,
+            name,
+            birth_date,
+            data,
+            clear_data,
+        } => {
+            let patch = PersonPatch {
+                name,
+                birth_date,
+                data: if clear_data {
+                    Some(None)
+                } else {
+                    data.map(Some)
+                },
+            };
+            let person = service
+                .cached_update(id, patch)
+                .map_err(|e| e.to_string())?;
+            print_persons(cli.output, vec![PersonEntry { id, person }]);
+        }
+        Command::Unregister { id } => {
+            service.cached_unregister(id).map_err(|e| e.to_string())?;
+            match cli.output {
+                Output::Table => println!("unregistered: {}", id),
+                Output::Json => print_json(&serde_json::json!({ "unregistered": id })),
+            }
+        }
+        Command::Restore { id } => {
+            let person = service.cached_restore(id).map_err(|e| e.to_string())?;
+            print_persons(cli.output, vec![PersonEntry { id, person }]);
+        }
+        Command::Purge { days } => {
+            let before = Utc::now() - Duration::days(days.into());
+            let count = service.purge(before).map_err(|e| e.to_string())?;
+            match cli.output {
+                Output::Table => println!("purged: {}", count),
+                Output::Json => print_json(&serde_json::json!({ "purged": count })),
+            }
+        }
+        Command::History { id, revision: None } => {
+            let history = service.history(id).map_err(|e| e.to_string())?;
+            match cli.output {
+                Output::Table => println!("{}", format_history(&history)),
+                Output::Json => print_json(&history),
+            }
+        }
+        Command::History {
+            id,
+            revision: Some(revision),
+        } => match service
+            .find_as_of(id, revision)
+            .map_err(|e| e.to_string())?
+        {
+            Some(person) => print_persons(cli.output, vec![PersonEntry { id, person }]),
+            None => return Err(format!("person not found: {id} as of revision {revision}")),
+        },
+        Command::Relay { .. } | Command::Migrate { .. } => {
+            unreachable!("dispatched before building the service")
+        }
+        Command::Import { file } => {
+            let ids = match file.extension().and_then(|ext| ext.to_str()) {
+                Some("json") => {
+                    let persons = load_persons(&file)?;
+                    service
+                        .cached_batch_import(persons, Rc::new(ImportProgressPresenter))
+                        .map_err(|e| e.to_string())?
+                }
+                // 大きなファイルも読み込み切らずに流し込む. キャッシュは cached_find で載る
+                _ => importer::import_file(&mut service, &file, Rc::new(ImportProgressPresenter))
+                    .map_err(|e| format!("{}: {}", file.display(), e))?,
+            };
+            match cli.output {
+                Output::Table => println!("imported {} persons: {:?}", ids.len(), ids),
+                Output::Json => print_json(&ImportResponse { ids }),
+            }
+        }
+        Command::Export { file } => {
+            let count = exporter::export_file(&mut service, &file)
+                .map_err(|e| format!("{}: {}", file.display(), e))?;
+            match cli.output {
+                Output::Table => println!("exported {} persons: {}", count, file.display()),
+                Output::Json => print_json(&serde_json::json!({ "exported": count })),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let settings = match cli.config.load() {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("error: invalid settings: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    env_logger::Builder::new()
+        .parse_filters(settings.log_level.as_deref().unwrap_or("warn"))
+        .init();
+
+    match run(cli, &settings) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use app::domain::date;
+    use app::dto::ChangeKind;
+
+    #[test]
+    fn test_parse_command() {
+        let cli = Cli::try_parse_from([
+            "admin",
+            "register",
+            "--name",
+            "Abel",
+            "--birth-date",
+            "1802-08-05",
+            "--data",
+            "Abel's theorem",
+        ])
+        .unwrap();
+        assert_eq!(cli.output, Output::Table);
+        assert_eq!(
+            cli.command,
+            Command::Register {
+                name: "Abel".to_string(),
+                birth_date: date(1802, 8, 5),
+                death_date: None,
+                data: "Abel's theorem".to_string(),
+            }
+        );
+
+        let cli = Cli::try_parse_from([
+            "admin",
+            "death",
+            "13",
+            "--date",
+            "1829-04-06",
+            "--output",
+            "json",
+        ])
+        .unwrap();
+        assert_eq!(cli.output, Output::Json);
+        assert_eq!(
+            cli.command,
+            Command::Death {
+                id: 13,
+                date: date(1829, 4, 6)
+            }
+        );
+
+        let cli = Cli::try_parse_from(["admin", "correct-death", "13", "--reason", "wrong person"])
+            .unwrap();
+        assert_eq!(
+            cli.command,
+            Command::CorrectDeath {
+                id: 13,
+                date: None,
+                reason: "wrong person".to_string()
+            }
+        );
+        assert!(Cli::try_parse_from(["admin", "correct-death", "13"]).is_err());
+
+        let cli = Cli::try_parse_from(["admin", "purge"]).unwrap();
+        assert_eq!(cli.command, Command::Purge { days: 30 });
+        let cli = Cli::try_parse_from(["admin", "purge", "--days", "7"]).unwrap();
+        assert_eq!(cli.command, Command::Purge { days: 7 });
+
+        let cli = Cli::try_parse_from(["admin", "relay"]).unwrap();
+        assert_eq!(cli.command, Command::Relay { batch_size: 100 });
+
+        let cli = Cli::try_parse_from(["admin", "migrate"]).unwrap();
+        assert_eq!(cli.command, Command::Migrate { dry_run: false });
+        assert_eq!(cli.config, ConfigArgs::default());
+        let cli = Cli::try_parse_from([
+            "admin",
+            "migrate",
+            "--config",
+            "app.toml",
+            "--database-uri",
+            "postgres://localhost/test",
+        ])
+        .unwrap();
+        assert_eq!(cli.config.config, Some(PathBuf::from("app.toml")));
+        assert_eq!(
+            cli.config.database_uri.as_deref(),
+            Some("postgres://localhost/test")
+        );
+        let cli = Cli::try_parse_from(["admin", "migrate", "--dry-run"]).unwrap();
+        assert_eq!(cli.command, Command::Migrate { dry_run: true });
+
+        let cli = Cli::try_parse_from(["admin", "history", "13"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Command::History {
+                id: 13,
+                revision: None
+            }
+        );
+        let cli = Cli::try_parse_from(["admin", "history", "13", "--revision", "2"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Command::History {
+                id: 13,
+                revision: Some(2)
+            }
+        );
+
+        let cli = Cli::try_parse_from([
+            "admin",
+            "list",
+            "--status",
+            "dead",
+            "--sort",
+            "birth_date",
+            "--limit",
+            "10",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.command,
+            Command::List {
+                name_prefix: None,
+                status: Some(LifeStatus::Dead),
+                born_from: None,
+                born_until: None,
+                sort: SortKey::BirthDate,
+                order: SortOrder::Asc,
+                after: None,
+                limit: Some(10),
+            }
+        );
+
+        assert!(Cli::try_parse_from(["admin", "find", "abel"]).is_err());
+        assert!(Cli::try_parse_from(["admin", "list", "--sort", "age"]).is_err());
+    }
+
+    #[test]
+    fn test_format_table() {
+        let persons = vec![
+            PersonEntry {
+                id: 1,
+                person: PersonDto::new(
+                    "Abel",
+                    date(1802, 8, 5),
+                    Some(date(1829, 4, 6)),
+                    Some("Abel's theorem"),
+                    1,
+                ),
+            },
+            PersonEntry {
+                id: 2,
+                person: PersonDto::new("Galois", date(1811, 10, 25), None, None, 0),
+            },
+        ];
+
+        assert_eq!(
+            format_table(&persons),
+            [
+                "    id  name                  birth       death       revision  data",
+                "     1  Abel                  1802-08-05  1829-04-06         1  Abel's theorem",
+                "     2  Galois                1811-10-25                     0  ",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_format_history() {
+        let abel = PersonDto::new("Abel", date(1802, 8, 5), None, None, 0);
+        let changed_at = "2024-01-02T03:04:05Z".parse().unwrap();
+        let history = vec![
+            PersonHistoryDto {
+                person_id: 1,
+                revision: 0,
+                kind: ChangeKind::Insert,
+                old: None,
+                new: Some(abel.clone()),
+                changed_at,
+                actor: "admin".to_string(),
+                reason: None,
+            },
+            PersonHistoryDto {
+                person_id: 1,
+                revision: 0,
+                kind: ChangeKind::Purge,
+                old: Some(abel),
+                new: None,
+                changed_at,
+                actor: "server".to_string(),
+                reason: None,
+            },
+        ];
+
+        assert_eq!(
+            format_history(&history),
+            [
+                "revision  kind      changed_at                 actor       name",
+                "       0  insert    2024-01-02T03:04:05Z       admin       Abel",
+                "       0  purge     2024-01-02T03:04:05Z       server      Abel",
+            ]
+            .join("\n")
+        );
+    }
+}
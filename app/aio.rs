@@ -0,0 +1,20 @@
+//! Asynchronous counterparts of the DAO/CAO/service stack for tokio.
+//!
+//! The layers are the same as the synchronous ones, and so are the errors,
+//! DTOs, events and notifications. Every operation of the sync service is provided,
+//! with and without the cache, except purge, history and find as of.
+use std::future::Future;
+use std::pin::Pin;
+
+pub mod cache;
+pub mod cached_service;
+pub mod dao;
+pub mod pg_db;
+pub mod rabbitmq;
+pub mod redis_cache;
+pub mod reporter;
+pub mod service;
+pub mod usecase;
+
+/// the future returned by the closures run in a transaction
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
@@ -0,0 +1,16 @@
+use async_trait::async_trait;
+
+use crate::cache::CaoError;
+use crate::domain::PersonId;
+use crate::dto::PersonDto;
+
+/// async version of `PersonCao`, which runs on the connection `conn` instead of returning a `Tx`.
+#[async_trait]
+pub trait AsyncPersonCao<Conn: Send>: Send + Sync {
+    async fn get_conn(&self) -> Result<Conn, CaoError>;
+
+    async fn find(&self, conn: &mut Conn, id: PersonId) -> Result<Option<PersonDto>, CaoError>;
+    async fn load(&self, conn: &mut Conn, id: PersonId, person: &PersonDto)
+        -> Result<(), CaoError>;
+    async fn unload(&self, conn: &mut Conn, id: PersonId) -> Result<(), CaoError>;
+}
@@ -0,0 +1,561 @@
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use log::{trace, warn};
+use std::sync::Arc;
+
+use super::cache::AsyncPersonCao;
+use super::reporter::AsyncReporter;
+use super::service::{report_error, AsyncPersonService};
+use crate::cache::CaoError;
+use crate::domain::PersonId;
+use crate::dto::{PersonDto, PersonPatch};
+use crate::service::{InvalidErrorKind, PersonOutputBoundary, ServiceError};
+
+/// async version of `PersonCachedService`.
+///
+/// The cache is only a help: its failures are reported to the admin but never returned.
+#[async_trait]
+pub trait AsyncPersonCachedService<'a, Conn: Send, Ctx: Send>: AsyncPersonService<'a, Ctx> {
+    type C: AsyncPersonCao<Conn>;
+
+    fn get_cao(&self) -> Self::C;
+
+    async fn cached_register(
+        &'a mut self,
+        name: &str,
+        birth_date: NaiveDate,
+        death_date: Option<NaiveDate>,
+        data: &str,
+    ) -> Result<(PersonId, PersonDto), ServiceError> {
+        trace!(
+            "cached register: {} {} {:?} {}",
+            name,
+            birth_date,
+            death_date,
+            data
+        );
+        let cao = self.get_cao();
+        let reporter = self.get_reporter();
+
+        let result = self.register(name, birth_date, death_date, data).await;
+        trace!("register person to db: {:?}", result);
+
+        if let Ok((id, person)) = &result {
+            load(&cao, &reporter, *id, person).await;
+        }
+
+        result
+    }
+
+    async fn cached_batch_import<P>(
+        &'a mut self,
+        persons: Vec<PersonDto>,
+        out_port: Arc<P>,
+    ) -> Result<Vec<PersonId>, ServiceError>
+    where
+        P: PersonOutputBoundary<(u64, u64), ServiceError> + Send + Sync + 'static,
+    {
+        if persons.is_empty() {
+            return Err(ServiceError::InvalidRequest(
+                InvalidErrorKind::EmptyArgument,
+            ));
+        }
+
+        trace!("cached batch import: {:?}", persons);
+        let cao = self.get_cao();
+        let reporter = self.get_reporter();
+
+        let ids = self.batch_import(persons.clone(), out_port).await?;
+
+        // load all persons to the cache
+        for (id, person) in ids.iter().zip(persons.iter()) {
+            if !load(&cao, &reporter, *id, person).await {
+                return Ok(ids);
+            }
+        }
+        trace!("load all persons to cache");
+
+        Ok(ids)
+    }
+
+    async fn cached_find(&'a mut self, id: PersonId) -> Result<Option<PersonDto>, ServiceError> {
+        trace!("cached find: {}", id);
+        let cao = self.get_cao();
+        let reporter = self.get_reporter();
+
+        // if the person is found in the cache, return it
+        if let Ok(mut conn) = cao.get_conn().await {
+            if let Ok(Some(p)) = cao.find(&mut conn, id).await {
+                trace!("cache hit!: {}", id);
+                return Ok(Some(p));
+            }
+        }
+        trace!("cache miss!: {}", id);
+
+        let result = self.find(id).await?;
+        trace!("find person in db: {:?}", result);
+
+        // if the person is found in the db, load it to the cache
+        if let Some(person) = &result {
+            load(&cao, &reporter, id, person).await;
+        }
+
+        Ok(result)
+    }
+
+    async fn cached_list_all(&'a mut self) -> Result<Vec<(PersonId, PersonDto)>, ServiceError> {
+        trace!("cached list all");
+        let cao = self.get_cao();
+        let reporter = self.get_reporter();
+
+        let result = self.list_all().await?;
+
+        // load all persons to the cache
+        for (id, person) in result.iter() {
+            if !load(&cao, &reporter, *id, person).await {
+                return Ok(result);
+            }
+        }
+        trace!("load all persons to cache");
+
+        Ok(result)
+    }
+
+    async fn cached_death(
+        &'a mut self,
+        id: PersonId,
+        death_date: NaiveDate,
+    ) -> Result<(), ServiceError> {
+        trace!("cached death: {} {}", id, death_date);
+        let cao = self.get_cao();
+        let reporter = self.get_reporter();
+
+        self.death(id, death_date).await?;
+        trace!("update death date in db: {} {}", id, death_date);
+
+        unload(&cao, &reporter, id).await;
+
+        Ok(())
+    }
+
+    async fn cached_correct_death(
+        &'a mut self,
+        id: PersonId,
+        death_date: Option<NaiveDate>,
+        reason: &str,
+    ) -> Result<PersonDto, ServiceError> {
+        trace!("cached correct death: {} {:?} {}", id, death_date, reason);
+        let cao = self.get_cao();
+        let reporter = self.get_reporter();
+
+        let person = self.correct_death(id, death_date, reason).await?;
+        trace!("correct death date in db: {} {:?}", id, death_date);
+
+        // 次の cached_find で訂正後の版が載るように消しておく
+        unload(&cao, &reporter, id).await;
+
+        Ok(person)
+    }
+
+    async fn cached_update(
+        &'a mut self,
+        id: PersonId,
+        patch: PersonPatch,
+    ) -> Result<PersonDto, ServiceError> {
+        trace!("cached update: {} {:?}", id, patch);
+        let cao = self.get_cao();
+        let reporter = self.get_reporter();
+
+        let person = self.update(id, patch).await?;
+        trace!("update person in db: {} {:?}", id, person);
+
+        // 次の cached_find で最新版が載るように消しておく
+        unload(&cao, &reporter, id).await;
+
+        Ok(person)
+    }
+
+    async fn cached_unregister(&'a mut self, id: PersonId) -> Result<(), ServiceError> {
+        trace!("cached unregister: {}", id);
+        let cao = self.get_cao();
+        let reporter = self.get_reporter();
+
+        // even if delete from db failed below, this cache clear is not a matter.
+        unload(&cao, &reporter, id).await;
+
+        self.unregister(id).await
+    }
+
+    async fn cached_restore(&'a mut self, id: PersonId) -> Result<PersonDto, ServiceError> {
+        trace!("cached restore: {}", id);
+        let cao = self.get_cao();
+        let reporter = self.get_reporter();
+
+        let person = self.restore(id).await?;
+        trace!("restore person in db: {} {:?}", id, person);
+
+        load(&cao, &reporter, id, &person).await;
+
+        Ok(person)
+    }
+}
+
+// 失敗したら false を返す. ここはエラーを返す必要はない
+async fn load<Conn: Send>(
+    cao: &impl AsyncPersonCao<Conn>,
+    reporter: &impl AsyncReporter,
+    id: PersonId,
+    person: &PersonDto,
+) -> bool {
+    let res = match cao.get_conn().await {
+        Ok(mut conn) => cao.load(&mut conn, id, person).await,
+        Err(e) => Err(e),
+    };
+    cache_succeeded(reporter, res, "load person to cache").await
+}
+
+async fn unload<Conn: Send>(
+    cao: &impl AsyncPersonCao<Conn>,
+    reporter: &impl AsyncReporter,
+    id: PersonId,
+) -> bool {
+    let res = match cao.get_conn().await {
+        Ok(mut conn) => cao.unload(&mut conn, id).await,
+        Err(e) => Err(e),
+    };
+    cache_succeeded(reporter, res, "unload person from cache").await
+}
+
+async fn cache_succeeded(
+    reporter: &impl AsyncReporter,
+    res: Result<(), CaoError>,
+    what: &str,
+) -> bool {
+    match res {
+        Ok(()) => {
+            trace!("{}", what);
+            true
+        }
+        Err(e) => {
+            warn!("failed to {}: {}", what, e);
+            report_error(reporter, "cache service not available").await;
+            false
+        }
+    }
+}
+
+// # フェイクテスト
+//
+// ## 目的
+//
+//   キャッシュにあれば DB を見ずに返し, なければ DB から読んでキャッシュに載せることを保障する
+//   キャッシュが使えなくても DB の結果を返すことを保障する
+//
+// ## 方針
+//
+//   DAO と CAO をメモリ上のフェイクで置き換え, 実行後のそれぞれの状態を確認する
+//
+#[cfg(test)]
+mod fake_tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::aio::dao::{AsyncPersonDao, HaveAsyncPersonDao};
+    use crate::aio::reporter::{AsyncDefaultReporter, AsyncObserver};
+    use crate::aio::usecase::AsyncPersonUsecase;
+    use crate::aio::BoxFuture;
+    use crate::dao::DaoError;
+    use crate::domain::{date, Revision};
+    use crate::reporter::{Level, Location, ReporterError};
+    use crate::usecase::UsecaseError;
+
+    struct FakePersonDao {
+        data: Mutex<Vec<(PersonId, PersonDto)>>,
+        // 論理削除されたもの
+        deleted: Mutex<Vec<(PersonId, PersonDto)>>,
+    }
+    #[async_trait]
+    impl AsyncPersonDao<()> for FakePersonDao {
+        async fn insert(&self, _ctx: &mut (), person: PersonDto) -> Result<PersonId, DaoError> {
+            let mut data = self.data.lock().unwrap();
+            let id = data.len() as PersonId + 1;
+            data.push((id, person));
+
+            Ok(id)
+        }
+        async fn fetch(&self, _ctx: &mut (), id: PersonId) -> Result<Option<PersonDto>, DaoError> {
+            let data = self.data.lock().unwrap();
+
+            Ok(data.iter().find(|(i, _)| *i == id).map(|(_, p)| p.clone()))
+        }
+        async fn select(&self, _ctx: &mut ()) -> Result<Vec<(PersonId, PersonDto)>, DaoError> {
+            Ok(self.data.lock().unwrap().clone())
+        }
+        async fn save(
+            &self,
+            _ctx: &mut (),
+            id: PersonId,
+            _revision: Revision,
+            person: PersonDto,
+        ) -> Result<(), DaoError> {
+            if let Some((_, p)) = self.data.lock().unwrap().iter_mut().find(|(i, _)| *i == id) {
+                *p = person;
+            }
+
+            Ok(())
+        }
+        async fn delete(&self, _ctx: &mut (), id: PersonId) -> Result<Option<Revision>, DaoError> {
+            Ok(toggle(&self.data, &self.deleted, id))
+        }
+        async fn restore(&self, _ctx: &mut (), id: PersonId) -> Result<Option<Revision>, DaoError> {
+            Ok(toggle(&self.deleted, &self.data, id))
+        }
+    }
+    // 削除と復元で行を移し, revision を進める
+    fn toggle(
+        from: &Mutex<Vec<(PersonId, PersonDto)>>,
+        to: &Mutex<Vec<(PersonId, PersonDto)>>,
+        id: PersonId,
+    ) -> Option<Revision> {
+        let mut from = from.lock().unwrap();
+        let i = from.iter().position(|(i, _)| *i == id)?;
+        let (id, mut person) = from.remove(i);
+        person.revision += 1;
+        let revision = person.revision;
+        to.lock().unwrap().push((id, person));
+
+        Some(revision)
+    }
+
+    struct DummyPersonOutputBoundary;
+    impl PersonOutputBoundary<(u64, u64), ServiceError> for DummyPersonOutputBoundary {
+        fn started(&self) {}
+        fn in_progress(&self, _progress: (u64, u64)) {}
+        fn completed(&self) {}
+        fn aborted(&self, _err: ServiceError) {}
+    }
+
+    struct FakePersonUsecase {
+        dao: FakePersonDao,
+    }
+    impl HaveAsyncPersonDao<()> for FakePersonUsecase {
+        type D = FakePersonDao;
+
+        fn get_dao(&self) -> &Self::D {
+            &self.dao
+        }
+    }
+    impl AsyncPersonUsecase<()> for FakePersonUsecase {}
+
+    #[derive(Debug, Clone)]
+    struct FakePersonCao {
+        available: Arc<Mutex<bool>>,
+        cache: Arc<Mutex<HashMap<PersonId, PersonDto>>>,
+    }
+    #[async_trait]
+    impl AsyncPersonCao<()> for FakePersonCao {
+        async fn get_conn(&self) -> Result<(), CaoError> {
+            if !*self.available.lock().unwrap() {
+                return Err(CaoError::Unavailable("fake".to_string()));
+            }
+            Ok(())
+        }
+        async fn find(&self, _conn: &mut (), id: PersonId) -> Result<Option<PersonDto>, CaoError> {
+            Ok(self.cache.lock().unwrap().get(&id).cloned())
+        }
+        async fn load(
+            &self,
+            _conn: &mut (),
+            id: PersonId,
+            person: &PersonDto,
+        ) -> Result<(), CaoError> {
+            self.cache.lock().unwrap().insert(id, person.clone());
+            Ok(())
+        }
+        async fn unload(&self, _conn: &mut (), id: PersonId) -> Result<(), CaoError> {
+            self.cache.lock().unwrap().remove(&id);
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct SpyObserver {
+        received: Arc<Mutex<Vec<(Level, String)>>>,
+    }
+    #[async_trait]
+    impl AsyncObserver for SpyObserver {
+        async fn handle_notification(
+            &self,
+            level: Level,
+            to: &str,
+            _message: &str,
+            _loc: Location<'_>,
+        ) -> Result<(), ReporterError> {
+            self.received.lock().unwrap().push((level, to.to_string()));
+            Ok(())
+        }
+    }
+
+    struct TargetPersonService {
+        usecase: FakePersonUsecase,
+        cao: FakePersonCao,
+        reporter: AsyncDefaultReporter,
+    }
+    #[async_trait]
+    impl<'a> AsyncPersonService<'a, ()> for TargetPersonService {
+        type U = FakePersonUsecase;
+        type N = AsyncDefaultReporter;
+
+        async fn run_tx<T, F>(&mut self, f: F) -> Result<T, ServiceError>
+        where
+            T: Send,
+            F: for<'c> FnOnce(
+                    &'c mut Self::U,
+                    &'c mut (),
+                ) -> BoxFuture<'c, Result<T, UsecaseError>>
+                + Send
+                + 'static,
+        {
+            let mut ctx = ();
+            f(&mut self.usecase, &mut ctx).await.map_err(Into::into)
+        }
+
+        fn get_reporter(&self) -> Self::N {
+            self.reporter.clone()
+        }
+    }
+    impl<'a> AsyncPersonCachedService<'a, (), ()> for TargetPersonService {
+        type C = FakePersonCao;
+
+        fn get_cao(&self) -> Self::C {
+            self.cao.clone()
+        }
+    }
+
+    fn service_with(
+        db: Vec<(PersonId, PersonDto)>,
+        cache: Vec<(PersonId, PersonDto)>,
+    ) -> (TargetPersonService, SpyObserver) {
+        let observer = SpyObserver {
+            received: Arc::new(Mutex::new(vec![])),
+        };
+        let mut reporter = AsyncDefaultReporter::new();
+        reporter.register(observer.clone()).unwrap();
+        let service = TargetPersonService {
+            usecase: FakePersonUsecase {
+                dao: FakePersonDao {
+                    data: Mutex::new(db),
+                    deleted: Mutex::new(vec![]),
+                },
+            },
+            cao: FakePersonCao {
+                available: Arc::new(Mutex::new(true)),
+                cache: Arc::new(Mutex::new(cache.into_iter().collect())),
+            },
+            reporter,
+        };
+
+        (service, observer)
+    }
+
+    #[tokio::test]
+    async fn test_cached_find() {
+        let alice = PersonDto::new("Alice", date(2012, 11, 2), None, None, 3);
+        let bob = PersonDto::new("Bob", date(1995, 11, 6), None, None, 1);
+        let (mut service, _) = service_with(vec![(2, bob.clone())], vec![(1, alice.clone())]);
+
+        // キャッシュにあれば DB を見ない
+        assert_eq!(service.cached_find(1).await, Ok(Some(alice)));
+        // なければ DB から読んでキャッシュに載せる
+        assert_eq!(service.cached_find(2).await, Ok(Some(bob.clone())));
+        assert_eq!(service.cao.cache.lock().unwrap().get(&2), Some(&bob));
+        assert_eq!(service.cached_find(3).await, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn test_cached_find_unavailable() {
+        let alice = PersonDto::new("Alice", date(2012, 11, 2), None, None, 3);
+        let (mut service, observer) = service_with(vec![(1, alice.clone())], vec![]);
+        *service.cao.available.lock().unwrap() = false;
+
+        // キャッシュが使えなくても DB の結果を返し, 管理者に知らせる
+        assert_eq!(service.cached_find(1).await, Ok(Some(alice)));
+        assert_eq!(
+            *observer.received.lock().unwrap(),
+            vec![(Level::Error, "admin".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cached_unregister() {
+        let alice = PersonDto::new("Alice", date(2012, 11, 2), None, None, 3);
+        let (mut service, _) = service_with(vec![(1, alice.clone())], vec![(1, alice)]);
+
+        assert_eq!(service.cached_unregister(1).await, Ok(()));
+        assert!(service.cao.cache.lock().unwrap().is_empty());
+        assert!(service.usecase.dao.data.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cached_batch_import() {
+        let (mut service, _) = service_with(vec![], vec![]);
+        let out_port = Arc::new(DummyPersonOutputBoundary);
+
+        let result = service.cached_batch_import(vec![], out_port.clone()).await;
+        assert_eq!(
+            result,
+            Err(ServiceError::InvalidRequest(
+                InvalidErrorKind::EmptyArgument
+            ))
+        );
+
+        let alice = PersonDto::new("Alice", date(2012, 11, 2), None, None, 0);
+        let bob = PersonDto::new("Bob", date(1995, 11, 6), None, None, 0);
+        let result = service
+            .cached_batch_import(vec![alice.clone(), bob.clone()], out_port)
+            .await;
+        assert_eq!(result, Ok(vec![1, 2]));
+        // 取り込んだものはキャッシュに載せる
+        let cache = service.cao.cache.lock().unwrap();
+        assert_eq!(cache.get(&1), Some(&alice));
+        assert_eq!(cache.get(&2), Some(&bob));
+    }
+
+    #[tokio::test]
+    async fn test_cached_correct_death() {
+        let alice = PersonDto::new("Alice", date(2012, 11, 2), Some(date(2020, 1, 1)), None, 3);
+        let (mut service, _) = service_with(vec![(1, alice.clone())], vec![(1, alice)]);
+
+        let person = service.cached_correct_death(1, None, "typo").await.unwrap();
+        assert_eq!(person.death_date, None);
+        // 次の cached_find で訂正後の版が載る
+        assert!(service.cao.cache.lock().unwrap().is_empty());
+        assert_eq!(service.cached_find(1).await, Ok(Some(person)));
+    }
+
+    #[tokio::test]
+    async fn test_cached_update() {
+        let alice = PersonDto::new("Alice", date(2012, 11, 2), None, None, 3);
+        let (mut service, _) = service_with(vec![(1, alice.clone())], vec![(1, alice)]);
+
+        let patch = PersonPatch {
+            name: Some("Alicia".to_string()),
+            ..Default::default()
+        };
+        let person = service.cached_update(1, patch).await.unwrap();
+        assert_eq!(person.name, "Alicia");
+        assert!(service.cao.cache.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cached_restore() {
+        let alice = PersonDto::new("Alice", date(2012, 11, 2), None, None, 3);
+        let (mut service, _) = service_with(vec![(1, alice.clone())], vec![(1, alice)]);
+
+        assert_eq!(service.cached_unregister(1).await, Ok(()));
+        let person = service.cached_restore(1).await.unwrap();
+        // 復元したものはキャッシュに載せる
+        assert_eq!(service.cao.cache.lock().unwrap().get(&1), Some(&person));
+    }
+}
@@ -0,0 +1,72 @@
+use async_trait::async_trait;
+
+use crate::dao::{DaoError, PersonQuery, PersonSearch};
+use crate::domain::{PersonId, Revision};
+use crate::dto::PersonDto;
+use crate::outbox::Notification;
+
+/// async version of `PersonDao`, which runs on the transaction `ctx` instead of returning a `Tx`.
+///
+/// The writes append to the person history in the same transaction as the sync ones do.
+#[async_trait]
+pub trait AsyncPersonDao<Ctx: Send>: Send + Sync {
+    async fn insert(&self, ctx: &mut Ctx, person: PersonDto) -> Result<PersonId, DaoError>;
+    async fn fetch(&self, ctx: &mut Ctx, id: PersonId) -> Result<Option<PersonDto>, DaoError>;
+    async fn select(&self, ctx: &mut Ctx) -> Result<Vec<(PersonId, PersonDto)>, DaoError>;
+    async fn save(
+        &self,
+        ctx: &mut Ctx,
+        id: PersonId,
+        revision: Revision,
+        person: PersonDto,
+    ) -> Result<(), DaoError>;
+    /// save a correction of the person, see `PersonDao::correct`.
+    ///
+    /// The default implementation just saves without the reason, which is enough for fakes.
+    async fn correct(
+        &self,
+        ctx: &mut Ctx,
+        id: PersonId,
+        revision: Revision,
+        person: PersonDto,
+        _reason: String,
+    ) -> Result<(), DaoError> {
+        self.save(ctx, id, revision, person).await
+    }
+    /// soft delete, see `PersonDao::delete`
+    async fn delete(&self, ctx: &mut Ctx, id: PersonId) -> Result<Option<Revision>, DaoError>;
+    /// undo a soft delete, see `PersonDao::restore`
+    async fn restore(&self, ctx: &mut Ctx, id: PersonId) -> Result<Option<Revision>, DaoError>;
+
+    /// filtered, sorted and paginated select, see `PersonDao::query`.
+    ///
+    /// The default implementation applies the query in memory, which is enough for fakes.
+    async fn query(
+        &self,
+        ctx: &mut Ctx,
+        query: PersonQuery,
+    ) -> Result<Vec<(PersonId, PersonDto)>, DaoError> {
+        self.select(ctx).await.map(|rows| query.apply(rows))
+    }
+    /// ranked name search, see `PersonDao::search`.
+    ///
+    /// The default implementation ranks in memory, which is enough for fakes.
+    async fn search(
+        &self,
+        ctx: &mut Ctx,
+        search: PersonSearch,
+    ) -> Result<Vec<(PersonId, PersonDto)>, DaoError> {
+        self.select(ctx).await.map(|rows| search.apply(rows))
+    }
+}
+
+pub trait HaveAsyncPersonDao<Ctx: Send> {
+    type D: AsyncPersonDao<Ctx>;
+
+    fn get_dao(&self) -> &Self::D;
+}
+
+#[async_trait]
+pub trait AsyncOutboxDao<Ctx: Send>: Send + Sync {
+    async fn enqueue(&self, ctx: &mut Ctx, notification: Notification) -> Result<(), DaoError>;
+}
@@ -0,0 +1,413 @@
+use async_trait::async_trait;
+use log::{trace, warn};
+use tokio_postgres::Client;
+
+use super::dao::{AsyncOutboxDao, AsyncPersonDao};
+use crate::dao::{DaoError, PersonQuery, PersonSearch};
+use crate::domain::{PersonId, Revision};
+use crate::dto::{ChangeKind, PersonDto};
+use crate::outbox::Notification;
+use crate::pg_db::{build_query, build_search, param_refs, person_at, to_person};
+
+/// A connection of tokio-postgres which runs a transaction at a time, for the async DAOs on `Client`.
+///
+/// Unlike `tokio_postgres::Transaction` it does not borrow the client, so that a service can run
+/// each call in a transaction of its own. A transaction left open, because the task running it
+/// was cancelled, is rolled back before the next one begins.
+pub struct AsyncPgConnection {
+    client: Client,
+    in_transaction: bool,
+}
+impl AsyncPgConnection {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            in_transaction: false,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.client.is_closed()
+    }
+
+    pub fn client(&mut self) -> &mut Client {
+        &mut self.client
+    }
+
+    pub async fn begin(&mut self) -> Result<(), tokio_postgres::Error> {
+        if self.in_transaction {
+            warn!("rollback the transaction left open");
+            self.rollback().await?;
+        }
+        self.client.batch_execute("BEGIN").await?;
+        self.in_transaction = true;
+        Ok(())
+    }
+
+    pub async fn commit(&mut self) -> Result<(), tokio_postgres::Error> {
+        self.client.batch_execute("COMMIT").await?;
+        self.in_transaction = false;
+        Ok(())
+    }
+
+    pub async fn rollback(&mut self) -> Result<(), tokio_postgres::Error> {
+        self.client.batch_execute("ROLLBACK").await?;
+        self.in_transaction = false;
+        Ok(())
+    }
+}
+
+/// async version of `PgPersonDao` on tokio-postgres, with the same tables and history.
+#[derive(Debug, Clone)]
+pub struct AsyncPgPersonDao {
+    actor: String,
+}
+impl AsyncPgPersonDao {
+    /// `actor` is recorded in the history as who made the changes.
+    pub fn new(actor: &str) -> Self {
+        Self {
+            actor: actor.to_string(),
+        }
+    }
+
+    // save と correct の共通部分. reason は履歴に残す
+    async fn save_with(
+        &self,
+        tx: &mut Client,
+        id: PersonId,
+        revision: Revision,
+        person: PersonDto,
+        reason: Option<String>,
+    ) -> Result<(), DaoError> {
+        // lock the row to keep the old value for the history
+        let old = tx
+            .query_opt(
+                r#"SELECT name,
+                          birth_date,
+                          death_date,
+                          data,
+                          revision
+                     FROM person
+                    WHERE id = $1
+                      AND deleted_at IS NULL
+                      FOR UPDATE"#,
+                &[&id],
+            )
+            .await
+            .map_err(|e| DaoError::UpdateError(e.to_string()))?
+            .map(|row| person_at(&row, 0));
+        let Some(old) = old else {
+            return Err(DaoError::UpdateError(format!("person not found: {id}")));
+        };
+        if old.revision != revision {
+            warn!(
+                "revision conflict on person {}: expected={}, actual={}",
+                id, revision, old.revision
+            );
+            return Err(DaoError::RevisionConflict {
+                expected: revision,
+                actual: old.revision,
+            });
+        }
+
+        tx.execute(
+            r#"UPDATE person
+                  SET name = $1,
+                      birth_date = $2,
+                      death_date = $3,
+                      data = $4,
+                      revision = $5
+                WHERE id = $6"#,
+            &[
+                &person.name,
+                &person.birth_date,
+                &person.death_date,
+                &person.data.as_ref().map(|d| d.as_bytes().to_vec()),
+                &person.revision,
+                &id,
+            ],
+        )
+        .await
+        .map_err(|e| DaoError::UpdateError(e.to_string()))?;
+
+        record(
+            tx,
+            id,
+            ChangeKind::Update,
+            Some(&old),
+            Some(&person),
+            &self.actor,
+            reason.as_deref(),
+        )
+        .await
+        .map_err(DaoError::UpdateError)
+    }
+}
+#[async_trait]
+impl AsyncPersonDao<Client> for AsyncPgPersonDao {
+    async fn insert(&self, tx: &mut Client, person: PersonDto) -> Result<PersonId, DaoError> {
+        trace!("inserting person: {:?}", person);
+        let id = tx
+            .query_one(
+                r#"INSERT INTO person ( name
+                                      , birth_date
+                                      , death_date
+                                      , data
+                                      , revision
+                                      )
+                   VALUES ($1, $2, $3, $4, $5)
+                RETURNING id"#,
+                &[
+                    &person.name,
+                    &person.birth_date,
+                    &person.death_date,
+                    &person.data.as_ref().map(|d| d.as_bytes().to_vec()),
+                    &person.revision,
+                ],
+            )
+            .await
+            .map(|row| row.get::<usize, PersonId>(0))
+            .map_err(|e| DaoError::InsertError(e.to_string()))?;
+
+        record(
+            tx,
+            id,
+            ChangeKind::Insert,
+            None,
+            Some(&person),
+            &self.actor,
+            None,
+        )
+        .await
+        .map_err(DaoError::InsertError)?;
+
+        Ok(id)
+    }
+    async fn fetch(&self, tx: &mut Client, id: PersonId) -> Result<Option<PersonDto>, DaoError> {
+        trace!("fetching person: {:?}", id);
+        tx.query_opt(
+            r#"SELECT name,
+                      birth_date,
+                      death_date,
+                      data,
+                      revision
+                 FROM person
+                WHERE id = $1
+                  AND deleted_at IS NULL"#,
+            &[&id],
+        )
+        .await
+        .map(|row| row.map(|row| person_at(&row, 0)))
+        .map_err(|e| DaoError::SelectError(e.to_string()))
+    }
+    async fn select(&self, tx: &mut Client) -> Result<Vec<(PersonId, PersonDto)>, DaoError> {
+        trace!("selecting all persons");
+        tx.query(
+            r#"SELECT id,
+                      name,
+                      birth_date,
+                      death_date,
+                      data,
+                      revision
+                 FROM person
+                WHERE deleted_at IS NULL
+             ORDER BY id"#,
+            &[],
+        )
+        .await
+        .map(|rows| rows.iter().map(to_person).collect())
+        .map_err(|e| DaoError::SelectError(e.to_string()))
+    }
+    async fn save(
+        &self,
+        tx: &mut Client,
+        id: PersonId,
+        revision: Revision,
+        person: PersonDto,
+    ) -> Result<(), DaoError> {
+        trace!("saving person: {:?}", id);
+        self.save_with(tx, id, revision, person, None).await
+    }
+    async fn correct(
+        &self,
+        tx: &mut Client,
+        id: PersonId,
+        revision: Revision,
+        person: PersonDto,
+        reason: String,
+    ) -> Result<(), DaoError> {
+        trace!("correcting person: {:?}", id);
+        self.save_with(tx, id, revision, person, Some(reason)).await
+    }
+    async fn delete(&self, tx: &mut Client, id: PersonId) -> Result<Option<Revision>, DaoError> {
+        trace!("deleting person: {:?}", id);
+        let deleted = tx
+            .query_opt(
+                r#"UPDATE person
+                      SET deleted_at = now(),
+                          revision = revision + 1
+                    WHERE id = $1
+                      AND deleted_at IS NULL
+                RETURNING name, birth_date, death_date, data, revision"#,
+                &[&id],
+            )
+            .await
+            .map_err(|e| DaoError::DeleteError(e.to_string()))?;
+
+        match deleted {
+            Some(row) => record_toggle(tx, id, ChangeKind::Delete, &row, &self.actor)
+                .await
+                .map(|_| Some(row.get::<usize, Revision>(4)))
+                .map_err(DaoError::DeleteError),
+            None => Ok(None),
+        }
+    }
+    async fn restore(&self, tx: &mut Client, id: PersonId) -> Result<Option<Revision>, DaoError> {
+        trace!("restoring person: {:?}", id);
+        let restored = tx
+            .query_opt(
+                r#"UPDATE person
+                      SET deleted_at = NULL,
+                          revision = revision + 1
+                    WHERE id = $1
+                      AND deleted_at IS NOT NULL
+                RETURNING name, birth_date, death_date, data, revision"#,
+                &[&id],
+            )
+            .await
+            .map_err(|e| DaoError::RestoreError(e.to_string()))?;
+
+        match restored {
+            Some(row) => record_toggle(tx, id, ChangeKind::Restore, &row, &self.actor)
+                .await
+                .map(|_| Some(row.get::<usize, Revision>(4)))
+                .map_err(DaoError::RestoreError),
+            None => Ok(None),
+        }
+    }
+    async fn query(
+        &self,
+        tx: &mut Client,
+        query: PersonQuery,
+    ) -> Result<Vec<(PersonId, PersonDto)>, DaoError> {
+        trace!("querying persons: {:?}", query);
+        // SQL は同期版と同じものを使う
+        let (sql, params) = build_query(&query);
+        let params = param_refs(&params);
+        tx.query(&sql, &params)
+            .await
+            .map(|rows| rows.iter().map(to_person).collect())
+            .map_err(|e| DaoError::SelectError(e.to_string()))
+    }
+    async fn search(
+        &self,
+        tx: &mut Client,
+        search: PersonSearch,
+    ) -> Result<Vec<(PersonId, PersonDto)>, DaoError> {
+        trace!("searching persons: {:?}", search);
+        let (sql, params) = build_search(&search);
+        let params = param_refs(&params);
+        tx.query(&sql, &params)
+            .await
+            .map(|rows| rows.iter().map(to_person).collect())
+            .map_err(|e| DaoError::SelectError(e.to_string()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AsyncPgOutboxDao;
+#[async_trait]
+impl AsyncOutboxDao<Client> for AsyncPgOutboxDao {
+    async fn enqueue(&self, tx: &mut Client, notification: Notification) -> Result<(), DaoError> {
+        trace!("enqueueing notification: {:?}", notification);
+        let event = notification
+            .event
+            .as_ref()
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(|e| DaoError::InsertError(e.to_string()))?;
+
+        tx.execute(
+            r#"INSERT INTO outbox ( level
+                                  , queue
+                                  , message
+                                  , event
+                                  , file
+                                  , line
+                                  , column_no
+                                  )
+               VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            &[
+                &notification.level.to_string(),
+                &notification.to,
+                &notification.message,
+                &event,
+                &notification.file,
+                &(notification.line as i32),
+                &(notification.column as i32),
+            ],
+        )
+        .await
+        .map(|_| ())
+        .map_err(|e| DaoError::InsertError(e.to_string()))
+    }
+}
+
+// 同期版の record と同じ. 元の書き込みと同じトランザクションで書く
+async fn record(
+    tx: &mut Client,
+    id: PersonId,
+    kind: ChangeKind,
+    old: Option<&PersonDto>,
+    new: Option<&PersonDto>,
+    actor: &str,
+    reason: Option<&str>,
+) -> Result<(), String> {
+    let revision = new.or(old).map(|p| p.revision).unwrap_or_default();
+    let to_value = |p: Option<&PersonDto>| p.map(serde_json::to_value).transpose();
+    let (old, new) = (
+        to_value(old).map_err(|e| e.to_string())?,
+        to_value(new).map_err(|e| e.to_string())?,
+    );
+
+    tx.execute(
+        r#"INSERT INTO person_history ( person_id
+                                      , revision
+                                      , kind
+                                      , old_value
+                                      , new_value
+                                      , actor
+                                      , reason
+                                      )
+           VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+        &[
+            &id,
+            &revision,
+            &kind.to_string(),
+            &old,
+            &new,
+            &actor,
+            &reason,
+        ],
+    )
+    .await
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+// 削除と復元は deleted_at と版だけが変わるので, 変更前は版を戻したもの
+async fn record_toggle(
+    tx: &mut Client,
+    id: PersonId,
+    kind: ChangeKind,
+    row: &tokio_postgres::Row,
+    actor: &str,
+) -> Result<(), String> {
+    let new = person_at(row, 0);
+    let old = PersonDto {
+        revision: new.revision - 1,
+        ..new.clone()
+    };
+
+    record(tx, id, kind, Some(&old), Some(&new), actor, None).await
+}
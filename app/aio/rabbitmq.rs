@@ -0,0 +1,127 @@
+use async_trait::async_trait;
+use log::{error, trace, warn};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use super::reporter::AsyncObserver;
+use crate::backend::ReconnectPolicy;
+use crate::config::QueueSettings;
+use crate::event::PersonEvent;
+use crate::rabbitmq::{publish_confirmed, Payload};
+use crate::reporter::{Level, Location, ReporterError};
+
+async fn connect(addr: &str) -> Result<lapin::Connection, ReporterError> {
+    trace!("connecting to rabbitmq: {}", addr);
+    let conn = lapin::Connection::connect(addr, lapin::ConnectionProperties::default())
+        .await
+        .map_err(|e| {
+            error!("failed to connect to rabbitmq: {}", e);
+            ReporterError::Unavailable(e.to_string())
+        })?;
+    trace!("connected to rabbitmq with {:?}", conn.configuration());
+
+    Ok(conn)
+}
+
+/// async version of `rabbitmq::Client`, which runs lapin on the caller's runtime.
+#[derive(Debug, Clone)]
+pub struct AsyncClient {
+    addr: String,
+    conn: Arc<Mutex<Arc<lapin::Connection>>>,
+    queues: QueueSettings,
+    reconnect_policy: ReconnectPolicy,
+}
+impl AsyncClient {
+    pub async fn open(addr: &str) -> Result<Self, ReporterError> {
+        let conn = connect(addr).await?;
+
+        Ok(Self {
+            addr: addr.to_string(),
+            conn: Arc::new(Mutex::new(Arc::new(conn))),
+            queues: QueueSettings::default(),
+            reconnect_policy: ReconnectPolicy::default(),
+        })
+    }
+
+    /// publish to the queues of the settings instead of the default names
+    pub fn with_queues(mut self, queues: QueueSettings) -> Self {
+        self.queues = queues;
+        self
+    }
+
+    pub fn with_reconnect_policy(mut self, reconnect_policy: ReconnectPolicy) -> Self {
+        self.reconnect_policy = reconnect_policy;
+        self
+    }
+
+    // 同期版と同じく, 切れていれば lock を離して開き直し, 先を越されていればそちらを使う
+    async fn connection(&self) -> Result<Arc<lapin::Connection>, ReporterError> {
+        let conn = self.conn.lock().await.clone();
+        if conn.status().connected() {
+            return Ok(conn);
+        }
+        warn!("rabbitmq connection is lost, reconnecting");
+        let reopened = Arc::new(
+            self.reconnect_policy
+                .run_async("rabbitmq", |_| true, || connect(&self.addr))
+                .await?,
+        );
+
+        let mut conn = self.conn.lock().await;
+        if !conn.status().connected() {
+            *conn = reopened.clone();
+            return Ok(reopened);
+        }
+        let current = conn.clone();
+        drop(conn);
+        if let Err(e) = reopened.close(0, "superseded").await {
+            warn!("failed to close superseded rabbitmq connection: {}", e);
+        }
+        Ok(current)
+    }
+
+    async fn publish(&self, to: &str, payload: &Payload<'_>) -> Result<(), ReporterError> {
+        let conn = self.connection().await?;
+        match self.publish_on(&conn, to, payload).await {
+            Err(e) if !conn.status().connected() => {
+                warn!("rabbitmq connection is lost while publishing: {}", e);
+                let conn = self.connection().await?;
+                self.publish_on(&conn, to, payload).await
+            }
+            res => res,
+        }
+    }
+
+    async fn publish_on(
+        &self,
+        conn: &lapin::Connection,
+        to: &str,
+        payload: &Payload<'_>,
+    ) -> Result<(), ReporterError> {
+        publish_confirmed(conn, self.queues.resolve(to), payload).await
+    }
+}
+
+#[async_trait]
+impl AsyncObserver for AsyncClient {
+    // to: queue name
+    // message: message to send
+    async fn handle_notification(
+        &self,
+        level: Level,
+        to: &str,
+        message: &str,
+        loc: Location<'_>,
+    ) -> Result<(), ReporterError> {
+        self.publish(to, &Payload::new(level, message, loc)).await
+    }
+    async fn handle_event(
+        &self,
+        event: &PersonEvent,
+        loc: Location<'_>,
+    ) -> Result<(), ReporterError> {
+        let message = event.to_string();
+        self.publish(event.queue(), &Payload::event(event, &message, loc))
+            .await
+    }
+}
@@ -0,0 +1,90 @@
+use async_trait::async_trait;
+use log::trace;
+use redis::aio::MultiplexedConnection;
+use redis::{AsyncCommands, AsyncConnectionConfig};
+use std::time::Duration;
+
+use super::cache::AsyncPersonCao;
+use crate::cache::CaoError;
+use crate::domain::PersonId;
+use crate::dto::PersonDto;
+
+// PersonDto の ToRedisArgs / FromRedisValue は同期版のものを使う
+#[derive(Debug, Clone)]
+pub struct AsyncRedisPersonCao {
+    client: redis::Client,
+    connect_timeout: Duration,
+    ttl: Option<Duration>,
+}
+impl AsyncRedisPersonCao {
+    pub fn new(client: redis::Client, connect_timeout: Duration) -> Self {
+        Self {
+            client,
+            connect_timeout,
+            ttl: None,
+        }
+    }
+
+    /// expire cached persons after `ttl`, never by default
+    pub fn with_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.ttl = ttl;
+        self
+    }
+}
+
+#[async_trait]
+impl AsyncPersonCao<MultiplexedConnection> for AsyncRedisPersonCao {
+    async fn get_conn(&self) -> Result<MultiplexedConnection, CaoError> {
+        let config = AsyncConnectionConfig::new().set_connection_timeout(self.connect_timeout);
+        self.client
+            .get_multiplexed_async_connection_with_config(&config)
+            .await
+            .map_err(|e| CaoError::Unavailable(e.to_string()))
+    }
+
+    async fn find(
+        &self,
+        conn: &mut MultiplexedConnection,
+        id: PersonId,
+    ) -> Result<Option<PersonDto>, CaoError> {
+        trace!("find person: {}", id);
+        let key = format!("person:{}", id);
+        let p: Option<PersonDto> = conn
+            .get(&key)
+            .await
+            .map_err(|e| CaoError::Unavailable(e.to_string()))?;
+        trace!("found person in cache: {:?}", p);
+        Ok(p)
+    }
+    async fn load(
+        &self,
+        conn: &mut MultiplexedConnection,
+        id: PersonId,
+        person: &PersonDto,
+    ) -> Result<(), CaoError> {
+        trace!("load person: {}", id);
+        let key = format!("person:{}", id);
+        // NOTE: this is current workaround for: https://github.com/rust-lang/rust/issues/123748
+        // reference: https://github.com/redis-rs/redis-rs/issues/1322
+        let _: () = match self.ttl {
+            // SETEX は 0 秒を受け付けない
+            Some(ttl) => conn.set_ex(&key, person, ttl.as_secs().max(1)).await,
+            None => conn.set(&key, person).await,
+        }
+        .map_err(|e| CaoError::Unavailable(e.to_string()))?;
+        trace!("person loaded into cache: {:?}", person);
+        Ok(())
+    }
+    async fn unload(&self, conn: &mut MultiplexedConnection, id: PersonId) -> Result<(), CaoError> {
+        trace!("unload person: {}", id);
+        let key = format!("person:{}", id);
+        // NOTE: this is current workaround for: https://github.com/rust-lang/rust/issues/123748
+        // reference: https://github.com/redis-rs/redis-rs/issues/1322
+        let _: () = conn
+            .del(&key)
+            .await
+            .map_err(|e| CaoError::Unavailable(e.to_string()))?;
+        trace!("person unloaded from cache: {}", id);
+        Ok(())
+    }
+}
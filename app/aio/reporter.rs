@@ -0,0 +1,160 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use crate::event::PersonEvent;
+use crate::reporter::{Level, Location, ReporterError};
+
+/// async version of `Observer`
+#[async_trait]
+pub trait AsyncObserver: Send + Sync {
+    async fn handle_notification(
+        &self,
+        level: Level,
+        to: &str,
+        message: &str,
+        loc: Location<'_>,
+    ) -> Result<(), ReporterError>;
+    /// by default the event is sent as a message to its queue
+    async fn handle_event(
+        &self,
+        event: &PersonEvent,
+        loc: Location<'_>,
+    ) -> Result<(), ReporterError> {
+        self.handle_notification(Level::Info, event.queue(), &event.to_string(), loc)
+            .await
+    }
+}
+
+/// async version of `Reporter`. The errors of the observers are reported to stderr and ignored.
+#[async_trait]
+pub trait AsyncReporter: Send + Sync {
+    fn register(&mut self, observer: impl AsyncObserver + 'static) -> Result<(), ReporterError>;
+    fn get_observers(&self) -> Vec<&dyn AsyncObserver>;
+    async fn send_report(
+        &self,
+        level: Level,
+        to: &str,
+        message: &str,
+        loc: Location<'_>,
+    ) -> Result<(), ReporterError> {
+        for observer in self.get_observers() {
+            if let Err(e) = observer
+                .handle_notification(level.clone(), to, message, loc.clone())
+                .await
+            {
+                eprintln!("reporter error: {}", e);
+            }
+        }
+        Ok(())
+    }
+    async fn send_event(
+        &self,
+        event: &PersonEvent,
+        loc: Location<'_>,
+    ) -> Result<(), ReporterError> {
+        for observer in self.get_observers() {
+            if let Err(e) = observer.handle_event(event, loc.clone()).await {
+                eprintln!("reporter error: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct AsyncDefaultReporter {
+    observers: Vec<Arc<dyn AsyncObserver>>,
+}
+impl AsyncDefaultReporter {
+    pub fn new() -> Self {
+        Self {
+            observers: Vec::new(),
+        }
+    }
+}
+impl AsyncReporter for AsyncDefaultReporter {
+    fn register(&mut self, observer: impl AsyncObserver + 'static) -> Result<(), ReporterError> {
+        self.observers.push(Arc::new(observer));
+        Ok(())
+    }
+    fn get_observers(&self) -> Vec<&dyn AsyncObserver> {
+        self.observers.iter().map(|o| o.as_ref()).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::location;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Clone)]
+    struct MockObserver {
+        messages: Arc<Mutex<Vec<(Level, String, String)>>>,
+    }
+    #[async_trait]
+    impl AsyncObserver for MockObserver {
+        async fn handle_notification(
+            &self,
+            level: Level,
+            to: &str,
+            message: &str,
+            _loc: Location<'_>,
+        ) -> Result<(), ReporterError> {
+            self.messages
+                .lock()
+                .unwrap()
+                .push((level, to.to_string(), message.to_string()));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reporter_for_multi_observers() {
+        let observer1 = MockObserver {
+            messages: Arc::new(Mutex::new(Vec::new())),
+        };
+        let observer2 = MockObserver {
+            messages: Arc::new(Mutex::new(Vec::new())),
+        };
+        let mut reporter = AsyncDefaultReporter::new();
+        reporter.register(observer1.clone()).unwrap();
+        reporter.register(observer2.clone()).unwrap();
+        reporter
+            .send_report(Level::Info, "to", "message", location!())
+            .await
+            .unwrap();
+
+        for observer in [observer1, observer2] {
+            assert_eq!(
+                observer.messages.lock().unwrap().as_slice(),
+                &[(Level::Info, "to".to_string(), "message".to_string())]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reporter_send_event() {
+        let observer = MockObserver {
+            messages: Arc::new(Mutex::new(Vec::new())),
+        };
+        let mut reporter = AsyncDefaultReporter::new();
+        reporter.register(observer.clone()).unwrap();
+
+        let event = PersonEvent::Unregistered {
+            id: 42,
+            revision: 2,
+        };
+        reporter.send_event(&event, location!()).await.unwrap();
+
+        // handle_event を実装していなければメッセージとして届く
+        assert_eq!(
+            observer.messages.lock().unwrap().as_slice(),
+            &[(
+                Level::Info,
+                "unregister_person".to_string(),
+                "unregistered person_id: 42".to_string()
+            )]
+        );
+    }
+}
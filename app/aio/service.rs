@@ -0,0 +1,951 @@
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use log::{error, trace};
+use std::sync::Arc;
+
+use super::reporter::AsyncReporter;
+use super::usecase::AsyncPersonUsecase;
+use super::BoxFuture;
+use crate::dao::{PersonQuery, PersonSearch};
+use crate::domain::PersonId;
+use crate::dto::{PersonDto, PersonPatch};
+use crate::event::PersonEvent;
+use crate::location;
+use crate::outbox::Notification;
+use crate::reporter::Level;
+use crate::service::{InvalidErrorKind, PersonOutputBoundary, RetryPolicy, ServiceError};
+use crate::usecase::UsecaseError;
+
+/// async version of `PersonService`.
+///
+/// The closures run in a transaction return a boxed future borrowing the usecase and the context,
+/// so they take their arguments by value: `Box::pin(async move { usecase.find(ctx, id).await })`.
+#[async_trait]
+pub trait AsyncPersonService<'a, Ctx: Send>: Send {
+    type U: AsyncPersonUsecase<Ctx>;
+    type N: AsyncReporter;
+
+    /// run `f` in a new transaction, which is committed if it succeeds and rolled back otherwise.
+    async fn run_tx<T, F>(&mut self, f: F) -> Result<T, ServiceError>
+    where
+        T: Send,
+        F: for<'c> FnOnce(&'c mut Self::U, &'c mut Ctx) -> BoxFuture<'c, Result<T, UsecaseError>>
+            + Send
+            + 'static;
+
+    fn get_reporter(&self) -> Self::N;
+
+    /// how `death` is retried on a revision conflict, see `PersonService::retry_policy`
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    /// run `f` in a transaction and deliver the notifications it returns once committed.
+    ///
+    /// See `PersonService::run_tx_and_notify`.
+    async fn run_tx_and_notify<T, F>(&mut self, f: F) -> Result<T, ServiceError>
+    where
+        T: Send,
+        F: for<'c> FnOnce(
+                &'c mut Self::U,
+                &'c mut Ctx,
+            )
+                -> BoxFuture<'c, Result<(T, Vec<Notification>), UsecaseError>>
+            + Send
+            + 'static,
+    {
+        let reporter = self.get_reporter();
+
+        let (v, notifications) = self.run_tx(f).await?;
+        for n in notifications {
+            let res = match &n.event {
+                Some(event) => reporter.send_event(event, n.location()).await,
+                None => {
+                    reporter
+                        .send_report(n.level.clone(), &n.to, &n.message, n.location())
+                        .await
+                }
+            };
+            if let Err(e) = res {
+                error!("reporter service not available: {}", e);
+            }
+        }
+        Ok(v)
+    }
+
+    /// `run_tx_and_notify` retried by `retry_policy` on a revision conflict.
+    ///
+    /// `f` makes the closure for each attempt, which runs in a new transaction.
+    async fn retry_tx_and_notify<T, F, G>(&mut self, mut f: G) -> Result<T, ServiceError>
+    where
+        T: Send,
+        G: FnMut() -> F + Send,
+        F: for<'c> FnOnce(
+                &'c mut Self::U,
+                &'c mut Ctx,
+            )
+                -> BoxFuture<'c, Result<(T, Vec<Notification>), UsecaseError>>
+            + Send
+            + 'static,
+    {
+        let retry_policy = self.retry_policy();
+
+        // 同期版と同じく, 競合したら rollback し, 新しいトランザクションで読み直す
+        let mut attempt = 1;
+        loop {
+            let result = self.run_tx_and_notify(f()).await;
+            match retry_policy.retry_after(attempt, &result) {
+                Some(delay) => {
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                None => return result,
+            }
+        }
+    }
+
+    async fn register(
+        &'a mut self,
+        name: &str,
+        birth_date: NaiveDate,
+        death_date: Option<NaiveDate>,
+        data: &str,
+    ) -> Result<(PersonId, PersonDto), ServiceError> {
+        trace!(
+            "register person: name={}, birth_date={}, death_date={:?}, data={}",
+            name,
+            birth_date,
+            death_date,
+            data
+        );
+        let reporter = self.get_reporter();
+        let person = PersonDto::new(name, birth_date, death_date, Some(data), 0);
+
+        let result = self
+            .run_tx_and_notify(move |usecase, ctx| {
+                Box::pin(async move {
+                    let (id, p) = usecase.entry_and_verify(ctx, person).await?;
+                    let event = PersonEvent::Registered {
+                        id,
+                        revision: p.revision,
+                        birth_date: p.birth_date,
+                        death_date: p.death_date,
+                    };
+                    Ok(((id, p), vec![Notification::event(event, location!())]))
+                })
+            })
+            .await;
+        if result.is_err() {
+            let msg = format!(
+                "cannot register person: name={}, birth_date={}, death_date={:?}, data={}",
+                name, birth_date, death_date, data
+            );
+            report_error(&reporter, &msg).await;
+        }
+        result
+    }
+
+    async fn find(&'a mut self, id: PersonId) -> Result<Option<PersonDto>, ServiceError> {
+        trace!("find person: id={}", id);
+        let reporter = self.get_reporter();
+
+        let result = self
+            .run_tx(move |usecase, ctx| Box::pin(async move { usecase.find(ctx, id).await }))
+            .await;
+        if result.is_err() {
+            report_error(&reporter, &format!("cannot find person: id={}", id)).await;
+        }
+        result
+    }
+
+    async fn list_all(&'a mut self) -> Result<Vec<(PersonId, PersonDto)>, ServiceError> {
+        trace!("list all persons");
+        let reporter = self.get_reporter();
+
+        let result = self
+            .run_tx(move |usecase, ctx| Box::pin(async move { usecase.collect(ctx).await }))
+            .await;
+        if result.is_err() {
+            report_error(&reporter, "cannot list all persons").await;
+        }
+        result
+    }
+
+    async fn death(&'a mut self, id: PersonId, death_date: NaiveDate) -> Result<(), ServiceError> {
+        trace!("death person: id={}, death_date={}", id, death_date);
+        let reporter = self.get_reporter();
+
+        let result = self
+            .retry_tx_and_notify(|| {
+                move |usecase, ctx| {
+                    Box::pin(async move {
+                        let person = usecase.death(ctx, id, death_date).await?;
+                        let event = PersonEvent::Died {
+                            id,
+                            revision: person.revision,
+                            death_date,
+                        };
+                        Ok(((), vec![Notification::event(event, location!())]))
+                    })
+                }
+            })
+            .await;
+        if result.is_err() {
+            let msg = format!("cannot death person: id={}, death_date={}", id, death_date);
+            report_error(&reporter, &msg).await;
+        }
+        result
+    }
+
+    async fn unregister(&'a mut self, id: PersonId) -> Result<(), ServiceError> {
+        trace!("unregister person: id={}", id);
+        let reporter = self.get_reporter();
+
+        let result = self
+            .run_tx_and_notify(move |usecase, ctx| {
+                Box::pin(async move {
+                    // 削除するものがなければ何も起きていないので知らせない
+                    let notifications = usecase
+                        .remove(ctx, id)
+                        .await?
+                        .map(|revision| {
+                            let event = PersonEvent::Unregistered { id, revision };
+                            Notification::event(event, location!())
+                        })
+                        .into_iter()
+                        .collect();
+                    Ok(((), notifications))
+                })
+            })
+            .await;
+        if result.is_err() {
+            report_error(&reporter, &format!("cannot remove person: id={}", id)).await;
+        }
+        result
+    }
+
+    async fn correct_death(
+        &'a mut self,
+        id: PersonId,
+        death_date: Option<NaiveDate>,
+        reason: &str,
+    ) -> Result<PersonDto, ServiceError> {
+        trace!(
+            "correct death person: id={}, death_date={:?}, reason={}",
+            id,
+            death_date,
+            reason
+        );
+        if reason.trim().is_empty() {
+            return Err(ServiceError::InvalidRequest(
+                InvalidErrorKind::EmptyArgument,
+            ));
+        }
+        let reporter = self.get_reporter();
+
+        let result = self
+            .retry_tx_and_notify(|| {
+                let reason = reason.to_string();
+                move |usecase, ctx| {
+                    Box::pin(async move {
+                        let person = usecase
+                            .correct_death(ctx, id, death_date, reason.clone())
+                            .await?;
+                        let event = PersonEvent::DeathCorrected {
+                            id,
+                            revision: person.revision,
+                            death_date,
+                            reason,
+                        };
+                        Ok((person, vec![Notification::event(event, location!())]))
+                    })
+                }
+            })
+            .await;
+        if result.is_err() {
+            let msg = format!(
+                "cannot correct death person: id={}, death_date={:?}",
+                id, death_date
+            );
+            report_error(&reporter, &msg).await;
+        }
+        result
+    }
+
+    async fn update(
+        &'a mut self,
+        id: PersonId,
+        patch: PersonPatch,
+    ) -> Result<PersonDto, ServiceError> {
+        trace!("update person: id={}, patch={:?}", id, patch);
+        if patch.is_empty() {
+            return Err(ServiceError::InvalidRequest(
+                InvalidErrorKind::EmptyArgument,
+            ));
+        }
+        let reporter = self.get_reporter();
+
+        let result = self
+            .retry_tx_and_notify(|| {
+                let patch = patch.clone();
+                move |usecase, ctx| {
+                    Box::pin(async move {
+                        let person = usecase.update(ctx, id, patch).await?;
+                        let event = PersonEvent::Updated {
+                            id,
+                            revision: person.revision,
+                        };
+                        Ok((person, vec![Notification::event(event, location!())]))
+                    })
+                }
+            })
+            .await;
+        if result.is_err() {
+            report_error(&reporter, &format!("cannot update person: id={}", id)).await;
+        }
+        result
+    }
+
+    async fn restore(&'a mut self, id: PersonId) -> Result<PersonDto, ServiceError> {
+        trace!("restore person: id={}", id);
+        let reporter = self.get_reporter();
+
+        let result = self
+            .run_tx_and_notify(move |usecase, ctx| {
+                Box::pin(async move {
+                    let person = usecase.restore(ctx, id).await?;
+                    let event = PersonEvent::Restored {
+                        id,
+                        revision: person.revision,
+                    };
+                    Ok((person, vec![Notification::event(event, location!())]))
+                })
+            })
+            .await;
+        if result.is_err() {
+            report_error(&reporter, &format!("cannot restore person: id={}", id)).await;
+        }
+        result
+    }
+
+    async fn list(
+        &'a mut self,
+        query: PersonQuery,
+    ) -> Result<Vec<(PersonId, PersonDto)>, ServiceError> {
+        trace!("list persons: {:?}", query);
+        let reporter = self.get_reporter();
+
+        let result = self
+            .run_tx(move |usecase, ctx| Box::pin(async move { usecase.query(ctx, query).await }))
+            .await;
+        if result.is_err() {
+            report_error(&reporter, "cannot list persons").await;
+        }
+        result
+    }
+
+    async fn search(
+        &'a mut self,
+        search: PersonSearch,
+    ) -> Result<Vec<(PersonId, PersonDto)>, ServiceError> {
+        trace!("search persons: {:?}", search);
+        if search.text.trim().is_empty() {
+            return Err(ServiceError::InvalidRequest(
+                InvalidErrorKind::EmptyArgument,
+            ));
+        }
+        let reporter = self.get_reporter();
+
+        let result = self
+            .run_tx(move |usecase, ctx| Box::pin(async move { usecase.search(ctx, search).await }))
+            .await;
+        if result.is_err() {
+            report_error(&reporter, "cannot search persons").await;
+        }
+        result
+    }
+
+    /// see `PersonService::batch_import`. The persons are entered in one transaction,
+    /// so none of them is imported if any cannot be.
+    async fn batch_import<P>(
+        &'a mut self,
+        persons: Vec<PersonDto>,
+        out_port: Arc<P>,
+    ) -> Result<Vec<PersonId>, ServiceError>
+    where
+        P: PersonOutputBoundary<(u64, u64), ServiceError> + Send + Sync + 'static,
+    {
+        trace!("batch import persons");
+        out_port.started();
+        let reporter = self.get_reporter();
+
+        let total = persons.len() as u64;
+        let result = self
+            .run_tx_and_notify(move |usecase, ctx| {
+                Box::pin(async move {
+                    let mut ids = vec![];
+                    for person in persons {
+                        match usecase.entry(ctx, person).await {
+                            Ok(id) => ids.push(id),
+                            Err(e) => {
+                                trace!("batch import aborted: {:?}", e);
+                                out_port.aborted(ServiceError::TransactionFailed(e.clone()));
+                                return Err(e);
+                            }
+                        }
+                        trace!("batch import in_progress: {:?}", ids.len());
+                        out_port.in_progress((total, ids.len() as u64));
+                    }
+                    trace!("batch import completed: {:?}", ids.len());
+                    out_port.completed();
+                    // 1 件ずつではなくまとめて通知する
+                    let event = PersonEvent::Imported { ids: ids.clone() };
+                    Ok((ids, vec![Notification::event(event, location!())]))
+                })
+            })
+            .await;
+        if let Err(e) = &result {
+            report_error(&reporter, &format!("cannot entry person: {:?}", e)).await;
+        }
+        result
+    }
+}
+
+// 失敗は管理者に知らせるが, 知らせられなくても呼び出し元には元のエラーを返す
+pub(crate) async fn report_error(reporter: &impl AsyncReporter, msg: &str) {
+    if let Err(e) = reporter
+        .send_report(Level::Error, "admin", msg, location!())
+        .await
+    {
+        error!("reporter service not available: {}", e);
+    }
+}
+
+// # フェイクテスト
+//
+// ## 目的
+//
+//   AsyncPersonService の各メソッドが変更を通知し, 失敗を管理者に知らせることを保障する
+//
+// ## 方針
+//
+//   DAO をメモリ上のフェイクで置き換え, Observer はスパイで届いたものを記録する
+//
+#[cfg(test)]
+mod fake_tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::aio::dao::{AsyncPersonDao, HaveAsyncPersonDao};
+    use crate::aio::reporter::{AsyncDefaultReporter, AsyncObserver};
+    use crate::backend::Backoff;
+    use crate::dao::{DaoError, SortKey};
+    use crate::domain::{date, Revision};
+    use crate::reporter::{Location, ReporterError};
+
+    struct FakePersonDao {
+        next_id: Mutex<PersonId>,
+        data: Mutex<Vec<(PersonId, PersonDto)>>,
+        // 論理削除されたもの
+        deleted: Mutex<Vec<(PersonId, PersonDto)>>,
+        // 最初の conflicts 回の保存は, 他の誰かが先に更新したことにして失敗させる
+        conflicts: Mutex<u32>,
+    }
+    #[async_trait]
+    impl AsyncPersonDao<()> for FakePersonDao {
+        async fn insert(&self, _ctx: &mut (), person: PersonDto) -> Result<PersonId, DaoError> {
+            let mut next_id = self.next_id.lock().unwrap();
+            let id = *next_id;
+            *next_id += 1;
+            self.data.lock().unwrap().push((id, person));
+
+            Ok(id)
+        }
+        async fn fetch(&self, _ctx: &mut (), id: PersonId) -> Result<Option<PersonDto>, DaoError> {
+            let data = self.data.lock().unwrap();
+
+            Ok(data.iter().find(|(i, _)| *i == id).map(|(_, p)| p.clone()))
+        }
+        async fn select(&self, _ctx: &mut ()) -> Result<Vec<(PersonId, PersonDto)>, DaoError> {
+            Ok(self.data.lock().unwrap().clone())
+        }
+        async fn save(
+            &self,
+            _ctx: &mut (),
+            id: PersonId,
+            revision: Revision,
+            person: PersonDto,
+        ) -> Result<(), DaoError> {
+            let mut conflicts = self.conflicts.lock().unwrap();
+            if *conflicts > 0 {
+                *conflicts -= 1;
+                return Err(DaoError::RevisionConflict {
+                    expected: revision,
+                    actual: revision + 1,
+                });
+            }
+            if let Some((_, p)) = self.data.lock().unwrap().iter_mut().find(|(i, _)| *i == id) {
+                *p = person;
+            }
+
+            Ok(())
+        }
+        async fn delete(&self, _ctx: &mut (), id: PersonId) -> Result<Option<Revision>, DaoError> {
+            Ok(toggle(&self.data, &self.deleted, id))
+        }
+        async fn restore(&self, _ctx: &mut (), id: PersonId) -> Result<Option<Revision>, DaoError> {
+            Ok(toggle(&self.deleted, &self.data, id))
+        }
+    }
+    // 削除と復元で行を移し, revision を進める
+    fn toggle(
+        from: &Mutex<Vec<(PersonId, PersonDto)>>,
+        to: &Mutex<Vec<(PersonId, PersonDto)>>,
+        id: PersonId,
+    ) -> Option<Revision> {
+        let mut from = from.lock().unwrap();
+        let i = from.iter().position(|(i, _)| *i == id)?;
+        let (id, mut person) = from.remove(i);
+        person.revision += 1;
+        let revision = person.revision;
+        to.lock().unwrap().push((id, person));
+
+        Some(revision)
+    }
+
+    struct FakePersonUsecase {
+        dao: FakePersonDao,
+    }
+    impl HaveAsyncPersonDao<()> for FakePersonUsecase {
+        type D = FakePersonDao;
+
+        fn get_dao(&self) -> &Self::D {
+            &self.dao
+        }
+    }
+    impl AsyncPersonUsecase<()> for FakePersonUsecase {}
+
+    #[derive(Debug, Clone)]
+    struct SpyObserver {
+        received: Arc<Mutex<Vec<(Level, String, String)>>>,
+    }
+    #[async_trait]
+    impl AsyncObserver for SpyObserver {
+        async fn handle_notification(
+            &self,
+            level: Level,
+            to: &str,
+            message: &str,
+            _loc: Location<'_>,
+        ) -> Result<(), ReporterError> {
+            self.received
+                .lock()
+                .unwrap()
+                .push((level, to.to_string(), message.to_string()));
+            Ok(())
+        }
+    }
+
+    struct TargetPersonService {
+        usecase: FakePersonUsecase,
+        reporter: AsyncDefaultReporter,
+        transactions: u32,
+    }
+    #[async_trait]
+    impl<'a> AsyncPersonService<'a, ()> for TargetPersonService {
+        type U = FakePersonUsecase;
+        type N = AsyncDefaultReporter;
+
+        async fn run_tx<T, F>(&mut self, f: F) -> Result<T, ServiceError>
+        where
+            T: Send,
+            F: for<'c> FnOnce(
+                    &'c mut Self::U,
+                    &'c mut (),
+                ) -> BoxFuture<'c, Result<T, UsecaseError>>
+                + Send
+                + 'static,
+        {
+            self.transactions += 1;
+            let mut ctx = ();
+            f(&mut self.usecase, &mut ctx).await.map_err(Into::into)
+        }
+
+        fn get_reporter(&self) -> Self::N {
+            self.reporter.clone()
+        }
+
+        fn retry_policy(&self) -> RetryPolicy {
+            RetryPolicy::new(
+                3,
+                Backoff {
+                    initial: Duration::ZERO,
+                    max: Duration::ZERO,
+                },
+            )
+        }
+    }
+
+    #[derive(Default)]
+    struct SpyPersonOutputBoundary {
+        in_progress: Mutex<Vec<(u64, u64)>>,
+        completed: Mutex<bool>,
+        aborted: Mutex<bool>,
+    }
+    impl PersonOutputBoundary<(u64, u64), ServiceError> for SpyPersonOutputBoundary {
+        fn started(&self) {}
+        fn in_progress(&self, progress: (u64, u64)) {
+            self.in_progress.lock().unwrap().push(progress);
+        }
+        fn completed(&self) {
+            *self.completed.lock().unwrap() = true;
+        }
+        fn aborted(&self, _err: ServiceError) {
+            *self.aborted.lock().unwrap() = true;
+        }
+    }
+
+    fn service_with(data: Vec<(PersonId, PersonDto)>) -> (TargetPersonService, SpyObserver) {
+        let observer = SpyObserver {
+            received: Arc::new(Mutex::new(vec![])),
+        };
+        let mut reporter = AsyncDefaultReporter::new();
+        reporter.register(observer.clone()).unwrap();
+        let service = TargetPersonService {
+            usecase: FakePersonUsecase {
+                dao: FakePersonDao {
+                    next_id: Mutex::new(42),
+                    data: Mutex::new(data),
+                    deleted: Mutex::new(vec![]),
+                    conflicts: Mutex::new(0),
+                },
+            },
+            reporter,
+            transactions: 0,
+        };
+
+        (service, observer)
+    }
+
+    #[tokio::test]
+    async fn test_register() {
+        let (mut service, observer) = service_with(vec![]);
+
+        let result = service
+            .register("Alice", date(2012, 11, 2), None, "Alice wonderland")
+            .await;
+        let expected = PersonDto::new(
+            "Alice",
+            date(2012, 11, 2),
+            None,
+            Some("Alice wonderland"),
+            0,
+        );
+        assert_eq!(result, Ok((42, expected)));
+        assert_eq!(
+            *observer.received.lock().unwrap(),
+            vec![(
+                Level::Info,
+                "entry_person".to_string(),
+                "registered person_id: 42".to_string()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_invalid() {
+        let (mut service, observer) = service_with(vec![]);
+
+        let result = service
+            .register("Alice", date(2012, 11, 2), Some(date(2012, 11, 1)), "")
+            .await;
+        assert!(matches!(
+            result,
+            Err(ServiceError::TransactionFailed(
+                UsecaseError::DomainObjectChangeFailed(..)
+            ))
+        ));
+        // 失敗は通知せず, 管理者に知らせる
+        let received = observer.received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0, Level::Error);
+        assert_eq!(received[0].1, "admin");
+    }
+
+    #[tokio::test]
+    async fn test_find() {
+        let person = PersonDto::new("Alice", date(2012, 11, 2), None, None, 3);
+        let (mut service, observer) = service_with(vec![(13, person.clone())]);
+
+        assert_eq!(service.find(13).await, Ok(Some(person)));
+        assert_eq!(service.find(99).await, Ok(None));
+        assert!(observer.received.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_list_all() {
+        let data = vec![
+            (
+                13,
+                PersonDto::new("Alice", date(2012, 11, 2), None, None, 3),
+            ),
+            (24, PersonDto::new("Bob", date(1995, 11, 6), None, None, 1)),
+        ];
+        let (mut service, _) = service_with(data.clone());
+
+        assert_eq!(service.list_all().await, Ok(data));
+    }
+
+    #[tokio::test]
+    async fn test_death() {
+        let person = PersonDto::new("Alice", date(2012, 11, 2), None, None, 3);
+        let (mut service, observer) = service_with(vec![(13, person)]);
+
+        assert_eq!(service.death(13, date(2020, 1, 1)).await, Ok(()));
+        assert_eq!(
+            service.usecase.dao.data.lock().unwrap()[0].1.death_date,
+            Some(date(2020, 1, 1))
+        );
+        assert_eq!(
+            *observer.received.lock().unwrap(),
+            vec![(
+                Level::Info,
+                "death_person".to_string(),
+                "death person_id: 13, death_date: 2020-01-01".to_string()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_death_retried() {
+        let person = PersonDto::new("Alice", date(2012, 11, 2), None, None, 3);
+        let (mut service, _) = service_with(vec![(13, person)]);
+        *service.usecase.dao.conflicts.lock().unwrap() = 2;
+
+        assert_eq!(service.death(13, date(2020, 1, 1)).await, Ok(()));
+        // 同期版と同じく, 試すたびに新しいトランザクションで読み直している
+        assert_eq!(service.transactions, 3);
+        assert_eq!(
+            service.usecase.dao.data.lock().unwrap()[0].1.death_date,
+            Some(date(2020, 1, 1))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_death_retry_gives_up() {
+        let person = PersonDto::new("Alice", date(2012, 11, 2), None, None, 3);
+        let (mut service, _) = service_with(vec![(13, person)]);
+        *service.usecase.dao.conflicts.lock().unwrap() = 3;
+
+        let result = service.death(13, date(2020, 1, 1)).await;
+        assert!(matches!(result, Err(ServiceError::RevisionConflict { .. })));
+        assert_eq!(service.transactions, 3);
+    }
+
+    #[tokio::test]
+    async fn test_unregister() {
+        let person = PersonDto::new("Alice", date(2012, 11, 2), None, None, 3);
+        let (mut service, observer) = service_with(vec![(13, person)]);
+
+        assert_eq!(service.unregister(13).await, Ok(()));
+        assert!(service.usecase.dao.data.lock().unwrap().is_empty());
+        assert_eq!(
+            *observer.received.lock().unwrap(),
+            vec![(
+                Level::Info,
+                "unregister_person".to_string(),
+                "unregistered person_id: 13".to_string()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_correct_death() {
+        let person = PersonDto::new("Alice", date(2012, 11, 2), Some(date(2020, 1, 1)), None, 3);
+        let (mut service, observer) = service_with(vec![(13, person)]);
+
+        let result = service.correct_death(13, None, "").await;
+        assert_eq!(
+            result,
+            Err(ServiceError::InvalidRequest(
+                InvalidErrorKind::EmptyArgument
+            ))
+        );
+
+        let result = service.correct_death(13, None, "typo").await.unwrap();
+        assert_eq!(result.death_date, None);
+        assert_eq!(
+            *observer.received.lock().unwrap(),
+            vec![(
+                Level::Info,
+                "correct_death_person".to_string(),
+                "corrected death person_id: 13, death_date: None, reason: typo".to_string()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_correct_death_retried() {
+        let person = PersonDto::new("Alice", date(2012, 11, 2), Some(date(2020, 1, 1)), None, 3);
+        let (mut service, _) = service_with(vec![(13, person)]);
+        *service.usecase.dao.conflicts.lock().unwrap() = 2;
+
+        assert!(service.correct_death(13, None, "typo").await.is_ok());
+        assert_eq!(service.transactions, 3);
+    }
+
+    #[tokio::test]
+    async fn test_update() {
+        let person = PersonDto::new("Alice", date(2012, 11, 2), None, None, 3);
+        let (mut service, observer) = service_with(vec![(13, person)]);
+
+        let result = service.update(13, PersonPatch::default()).await;
+        assert_eq!(
+            result,
+            Err(ServiceError::InvalidRequest(
+                InvalidErrorKind::EmptyArgument
+            ))
+        );
+        assert!(observer.received.lock().unwrap().is_empty());
+
+        let patch = PersonPatch {
+            name: Some("Alicia".to_string()),
+            ..Default::default()
+        };
+        let result = service.update(13, patch).await.unwrap();
+        assert_eq!(result.name, "Alicia");
+        assert_eq!(service.usecase.dao.data.lock().unwrap()[0].1.name, "Alicia");
+        assert_eq!(
+            *observer.received.lock().unwrap(),
+            vec![(
+                Level::Info,
+                "update_person".to_string(),
+                format!("updated person_id: 13, revision: {}", result.revision)
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restore() {
+        let person = PersonDto::new("Alice", date(2012, 11, 2), None, None, 3);
+        let (mut service, observer) = service_with(vec![(13, person)]);
+
+        // 削除されていないものは復元できない
+        let result = service.restore(13).await;
+        assert!(matches!(
+            result,
+            Err(ServiceError::TransactionFailed(
+                UsecaseError::PersonNotFound(13)
+            ))
+        ));
+
+        assert_eq!(service.unregister(13).await, Ok(()));
+        let result = service.restore(13).await.unwrap();
+        assert_eq!(result.revision, 5);
+        assert_eq!(service.find(13).await, Ok(Some(result)));
+        assert_eq!(
+            observer.received.lock().unwrap().last(),
+            Some(&(
+                Level::Info,
+                "restore_person".to_string(),
+                "restored person_id: 13, revision: 5".to_string()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_and_search() {
+        let data = vec![
+            (
+                13,
+                PersonDto::new("Alice", date(2012, 11, 2), None, None, 3),
+            ),
+            (24, PersonDto::new("Bob", date(1995, 11, 6), None, None, 1)),
+        ];
+        let (mut service, _) = service_with(data.clone());
+
+        let query = PersonQuery {
+            sort: SortKey::BirthDate,
+            ..Default::default()
+        };
+        assert_eq!(
+            service.list(query).await,
+            Ok(vec![data[1].clone(), data[0].clone()])
+        );
+
+        let search = PersonSearch {
+            text: "ali".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(service.search(search).await, Ok(vec![data[0].clone()]));
+
+        let search = PersonSearch {
+            text: " ".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            service.search(search).await,
+            Err(ServiceError::InvalidRequest(
+                InvalidErrorKind::EmptyArgument
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_import() {
+        let (mut service, observer) = service_with(vec![]);
+        let persons = vec![
+            PersonDto::new("Alice", date(2012, 11, 2), None, None, 0),
+            PersonDto::new("Bob", date(1995, 11, 6), None, None, 0),
+        ];
+        let out_port = Arc::new(SpyPersonOutputBoundary::default());
+
+        let result = service
+            .batch_import(persons.clone(), out_port.clone())
+            .await;
+        assert_eq!(result, Ok(vec![42, 43]));
+        assert_eq!(*out_port.in_progress.lock().unwrap(), vec![(2, 1), (2, 2)]);
+        assert!(*out_port.completed.lock().unwrap());
+        assert_eq!(
+            service
+                .usecase
+                .dao
+                .data
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(_, p)| p.clone())
+                .collect::<Vec<_>>(),
+            persons
+        );
+        // 1 件ずつではなくまとめて通知する
+        assert_eq!(
+            *observer.received.lock().unwrap(),
+            vec![(
+                Level::Info,
+                "entry_person".to_string(),
+                "imported person_ids: [42, 43]".to_string()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_import_aborted() {
+        let (mut service, observer) = service_with(vec![]);
+        let persons = vec![
+            PersonDto::new("Alice", date(2012, 11, 2), None, None, 0),
+            PersonDto::new("Bob", date(1995, 11, 6), Some(date(1995, 11, 5)), None, 0),
+        ];
+        let out_port = Arc::new(SpyPersonOutputBoundary::default());
+
+        let result = service.batch_import(persons, out_port.clone()).await;
+        assert!(result.is_err());
+        assert!(*out_port.aborted.lock().unwrap());
+        assert!(!*out_port.completed.lock().unwrap());
+        let received = observer.received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0, Level::Error);
+        assert_eq!(received[0].1, "admin");
+    }
+}
@@ -0,0 +1,531 @@
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use log::{trace, warn};
+
+use super::dao::{AsyncPersonDao, HaveAsyncPersonDao};
+use crate::dao::{DaoError, PersonQuery, PersonSearch};
+use crate::domain::{Person, PersonId, Revision};
+use crate::dto::{PersonDto, PersonPatch};
+use crate::usecase::UsecaseError;
+
+// ドメインの不変条件を満たさないものは DB に入れない
+fn validate(person: PersonDto) -> Result<PersonDto, UsecaseError> {
+    Person::try_from(person)
+        .map(PersonDto::from)
+        .map_err(UsecaseError::DomainObjectChangeFailed)
+}
+
+/// async version of `PersonUsecase`
+#[async_trait]
+pub trait AsyncPersonUsecase<Ctx: Send>: HaveAsyncPersonDao<Ctx> + Send {
+    async fn entry(&mut self, ctx: &mut Ctx, person: PersonDto) -> Result<PersonId, UsecaseError> {
+        let dao = self.get_dao();
+        trace!("entry person: {:?}", person);
+        let p = validate(person)?;
+        dao.insert(ctx, p)
+            .await
+            .map_err(UsecaseError::EntryPersonFailed)
+    }
+    async fn find(
+        &mut self,
+        ctx: &mut Ctx,
+        id: PersonId,
+    ) -> Result<Option<PersonDto>, UsecaseError> {
+        let dao = self.get_dao();
+        trace!("find person_id: {:?}", id);
+        dao.fetch(ctx, id)
+            .await
+            .map_err(UsecaseError::FindPersonFailed)
+    }
+    async fn entry_and_verify(
+        &mut self,
+        ctx: &mut Ctx,
+        person: PersonDto,
+    ) -> Result<(PersonId, PersonDto), UsecaseError> {
+        let dao = self.get_dao();
+        trace!("entry and verify person: {:?}", person);
+        let p = validate(person)?;
+        let id = dao
+            .insert(ctx, p)
+            .await
+            .map_err(UsecaseError::EntryAndVerifyPersonFailed)?;
+        match dao.fetch(ctx, id).await {
+            Ok(Some(p)) => Ok((id, p)),
+            Ok(None) => {
+                warn!("can't find the person just entried: {}", id);
+                Err(UsecaseError::EntryAndVerifyPersonFailed(
+                    DaoError::SelectError(format!("not found: {id}")),
+                ))
+            }
+            Err(e) => Err(UsecaseError::EntryAndVerifyPersonFailed(e)),
+        }
+    }
+    async fn collect(&mut self, ctx: &mut Ctx) -> Result<Vec<(PersonId, PersonDto)>, UsecaseError> {
+        let dao = self.get_dao();
+        trace!("collect all persons");
+        dao.select(ctx)
+            .await
+            .map_err(UsecaseError::CollectPersonFailed)
+    }
+    async fn query(
+        &mut self,
+        ctx: &mut Ctx,
+        query: PersonQuery,
+    ) -> Result<Vec<(PersonId, PersonDto)>, UsecaseError> {
+        let dao = self.get_dao();
+        trace!("query persons: {:?}", query);
+        dao.query(ctx, query)
+            .await
+            .map_err(UsecaseError::CollectPersonFailed)
+    }
+    async fn search(
+        &mut self,
+        ctx: &mut Ctx,
+        search: PersonSearch,
+    ) -> Result<Vec<(PersonId, PersonDto)>, UsecaseError> {
+        let dao = self.get_dao();
+        trace!("search persons: {:?}", search);
+        dao.search(ctx, search)
+            .await
+            .map_err(UsecaseError::CollectPersonFailed)
+    }
+    async fn death(
+        &mut self,
+        ctx: &mut Ctx,
+        id: PersonId,
+        date: NaiveDate,
+    ) -> Result<PersonDto, UsecaseError> {
+        let dao = self.get_dao();
+        trace!("death person: id={} date={}", id, date);
+        let Some(person) = dao
+            .fetch(ctx, id)
+            .await
+            .map_err(UsecaseError::FindPersonFailed)?
+        else {
+            warn!("can't find the person to dead: {}", id);
+            return Err(UsecaseError::PersonNotFound(id));
+        };
+        trace!("found person (id={}): {:?}", id, person);
+        let mut p = Person::try_from(person).map_err(UsecaseError::DomainObjectChangeFailed)?;
+        p.dead_at(date)
+            .map_err(UsecaseError::DomainObjectChangeFailed)?;
+
+        let mut p: PersonDto = p.into();
+        trace!("save dead person (id={}): {:?}", id, p);
+        // 最新版の管理はユースケースの責務
+        let orig_revision = p.revision;
+        p.revision += 1;
+        dao.save(ctx, id, orig_revision, p.clone())
+            .await
+            .map(|_| p)
+            .map_err(UsecaseError::save_failed)
+    }
+    async fn correct_death(
+        &mut self,
+        ctx: &mut Ctx,
+        id: PersonId,
+        date: Option<NaiveDate>,
+        reason: String,
+    ) -> Result<PersonDto, UsecaseError> {
+        let dao = self.get_dao();
+        trace!(
+            "correct death person: id={} date={:?} reason={}",
+            id,
+            date,
+            reason
+        );
+        let Some(person) = dao
+            .fetch(ctx, id)
+            .await
+            .map_err(UsecaseError::FindPersonFailed)?
+        else {
+            warn!("can't find the person to correct death: {}", id);
+            return Err(UsecaseError::PersonNotFound(id));
+        };
+        trace!("found person (id={}): {:?}", id, person);
+        let mut p = Person::try_from(person).map_err(UsecaseError::DomainObjectChangeFailed)?;
+        p.correct_death(date, &reason)
+            .map_err(UsecaseError::DomainObjectChangeFailed)?;
+
+        let mut p: PersonDto = p.into();
+        trace!("save corrected person (id={}): {:?}", id, p);
+        // 最新版の管理はユースケースの責務
+        let orig_revision = p.revision;
+        p.revision += 1;
+        dao.correct(ctx, id, orig_revision, p.clone(), reason)
+            .await
+            .map(|_| p)
+            .map_err(UsecaseError::save_failed)
+    }
+    async fn update(
+        &mut self,
+        ctx: &mut Ctx,
+        id: PersonId,
+        patch: PersonPatch,
+    ) -> Result<PersonDto, UsecaseError> {
+        let dao = self.get_dao();
+        trace!("update person: id={} patch={:?}", id, patch);
+        let Some(person) = dao
+            .fetch(ctx, id)
+            .await
+            .map_err(UsecaseError::FindPersonFailed)?
+        else {
+            warn!("can't find the person to update: {}", id);
+            return Err(UsecaseError::PersonNotFound(id));
+        };
+        trace!("found person (id={}): {:?}", id, person);
+        let mut p = Person::try_from(person).map_err(UsecaseError::DomainObjectChangeFailed)?;
+        if let Some(name) = &patch.name {
+            p.rename(name)
+                .map_err(UsecaseError::DomainObjectChangeFailed)?;
+        }
+        if let Some(birth_date) = patch.birth_date {
+            p.change_birth_date(birth_date)
+                .map_err(UsecaseError::DomainObjectChangeFailed)?;
+        }
+        if let Some(data) = &patch.data {
+            p.change_data(data.as_deref());
+        }
+
+        let mut p: PersonDto = p.into();
+        trace!("save updated person (id={}): {:?}", id, p);
+        // 最新版の管理はユースケースの責務
+        let orig_revision = p.revision;
+        p.revision += 1;
+        dao.save(ctx, id, orig_revision, p.clone())
+            .await
+            .map(|_| p)
+            .map_err(UsecaseError::save_failed)
+    }
+    /// see `PersonUsecase::remove`
+    async fn remove(
+        &mut self,
+        ctx: &mut Ctx,
+        id: PersonId,
+    ) -> Result<Option<Revision>, UsecaseError> {
+        let dao = self.get_dao();
+        trace!("remove person_id: {:?}", id);
+        dao.delete(ctx, id)
+            .await
+            .map_err(UsecaseError::RemovePersonFailed)
+    }
+    /// see `PersonUsecase::restore`
+    async fn restore(&mut self, ctx: &mut Ctx, id: PersonId) -> Result<PersonDto, UsecaseError> {
+        let dao = self.get_dao();
+        trace!("restore person_id: {:?}", id);
+        // 削除されていなければ戻すものがない
+        if dao
+            .restore(ctx, id)
+            .await
+            .map_err(UsecaseError::RestorePersonFailed)?
+            .is_none()
+        {
+            warn!("no deleted person to restore: {}", id);
+            return Err(UsecaseError::PersonNotFound(id));
+        }
+        dao.fetch(ctx, id)
+            .await
+            .map_err(UsecaseError::FindPersonFailed)?
+            .ok_or_else(|| {
+                warn!("can't find the person to restore: {}", id);
+                UsecaseError::PersonNotFound(id)
+            })
+    }
+}
+
+// # フェイクテスト
+//
+// ## 目的
+//
+//   AsyncPersonUsecase の各メソッドが同期版と同じ振る舞いをすることを保障する
+//
+// ## 方針
+//
+//   DAO をメモリ上のフェイクで置き換え, 実行後の状態を確認する
+//
+#[cfg(test)]
+mod fake_tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::dao::SortKey;
+    use crate::domain::{date, PersonDomainError, Revision};
+
+    struct FakePersonDao {
+        next_id: Mutex<PersonId>,
+        data: Mutex<Vec<(PersonId, PersonDto)>>,
+        // 論理削除されたもの
+        deleted: Mutex<Vec<(PersonId, PersonDto)>>,
+    }
+    // Ctx 不要なので () にしている
+    #[async_trait]
+    impl AsyncPersonDao<()> for FakePersonDao {
+        async fn insert(&self, _ctx: &mut (), person: PersonDto) -> Result<PersonId, DaoError> {
+            let mut next_id = self.next_id.lock().unwrap();
+            let id = *next_id;
+            *next_id += 1;
+            self.data.lock().unwrap().push((id, person));
+
+            Ok(id)
+        }
+        async fn fetch(&self, _ctx: &mut (), id: PersonId) -> Result<Option<PersonDto>, DaoError> {
+            let data = self.data.lock().unwrap();
+
+            Ok(data.iter().find(|(i, _)| *i == id).map(|(_, p)| p.clone()))
+        }
+        async fn select(&self, _ctx: &mut ()) -> Result<Vec<(PersonId, PersonDto)>, DaoError> {
+            Ok(self.data.lock().unwrap().clone())
+        }
+        async fn save(
+            &self,
+            _ctx: &mut (),
+            id: PersonId,
+            revision: Revision,
+            person: PersonDto,
+        ) -> Result<(), DaoError> {
+            match self.data.lock().unwrap().iter_mut().find(|(i, _)| *i == id) {
+                Some((_, p)) if p.revision == revision => {
+                    *p = person;
+                    Ok(())
+                }
+                Some((_, p)) => Err(DaoError::RevisionConflict {
+                    expected: revision,
+                    actual: p.revision,
+                }),
+                None => Err(DaoError::UpdateError(format!("person not found: {id}"))),
+            }
+        }
+        async fn delete(&self, _ctx: &mut (), id: PersonId) -> Result<Option<Revision>, DaoError> {
+            Ok(toggle(&self.data, &self.deleted, id))
+        }
+        async fn restore(&self, _ctx: &mut (), id: PersonId) -> Result<Option<Revision>, DaoError> {
+            Ok(toggle(&self.deleted, &self.data, id))
+        }
+    }
+    // from から to へ移して版を上げる
+    fn toggle(
+        from: &Mutex<Vec<(PersonId, PersonDto)>>,
+        to: &Mutex<Vec<(PersonId, PersonDto)>>,
+        id: PersonId,
+    ) -> Option<Revision> {
+        let mut from = from.lock().unwrap();
+        let i = from.iter().position(|(i, _)| *i == id)?;
+        let (id, mut person) = from.remove(i);
+        person.revision += 1;
+        let revision = person.revision;
+        to.lock().unwrap().push((id, person));
+
+        Some(revision)
+    }
+
+    struct TargetPersonUsecase {
+        dao: FakePersonDao,
+    }
+    impl HaveAsyncPersonDao<()> for TargetPersonUsecase {
+        type D = FakePersonDao;
+
+        fn get_dao(&self) -> &Self::D {
+            &self.dao
+        }
+    }
+    impl AsyncPersonUsecase<()> for TargetPersonUsecase {}
+
+    fn usecase_with(next_id: PersonId, data: Vec<(PersonId, PersonDto)>) -> TargetPersonUsecase {
+        TargetPersonUsecase {
+            dao: FakePersonDao {
+                next_id: Mutex::new(next_id),
+                data: Mutex::new(data),
+                deleted: Mutex::new(vec![]),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_entry() {
+        let mut usecase = usecase_with(42, vec![]);
+
+        let person = PersonDto::new(
+            "Alice",
+            date(2012, 11, 2),
+            None,
+            Some("Alice wonderland"),
+            0,
+        );
+        let result = usecase.entry(&mut (), person.clone()).await;
+        assert_eq!(result, Ok(42));
+        assert_eq!(*usecase.dao.data.lock().unwrap(), vec![(42, person)]);
+    }
+
+    #[tokio::test]
+    async fn test_entry_invalid() {
+        let mut usecase = usecase_with(42, vec![]);
+
+        // 死亡日が誕生日より前の person は登録されない
+        let person = PersonDto::new("Alice", date(2012, 11, 2), Some(date(2012, 11, 1)), None, 0);
+        let result = usecase.entry_and_verify(&mut (), person).await;
+        assert!(matches!(
+            result,
+            Err(UsecaseError::DomainObjectChangeFailed(
+                PersonDomainError::InvalidFieldValue(..)
+            ))
+        ));
+        assert!(usecase.dao.data.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_entry_and_verify() {
+        let mut usecase = usecase_with(13, vec![]);
+
+        let person = PersonDto::new("Alice", date(2012, 11, 2), None, None, 3);
+        let result = usecase.entry_and_verify(&mut (), person.clone()).await;
+        assert_eq!(result, Ok((13, person)));
+    }
+
+    #[tokio::test]
+    async fn test_collect() {
+        let data = vec![
+            (
+                13,
+                PersonDto::new("Alice", date(2012, 11, 2), None, None, 3),
+            ),
+            (24, PersonDto::new("Bob", date(1995, 11, 6), None, None, 1)),
+        ];
+        let mut usecase = usecase_with(0, data.clone());
+
+        let result = usecase.collect(&mut ()).await;
+        assert_eq!(result, Ok(data));
+    }
+
+    #[tokio::test]
+    async fn test_death() {
+        let person = PersonDto::new("Alice", date(2012, 11, 2), None, None, 3);
+        let mut usecase = usecase_with(0, vec![(13, person.clone())]);
+
+        let result = usecase.death(&mut (), 13, date(2020, 1, 1)).await;
+        // 死亡日が入り, 版が上がる
+        let expected = PersonDto {
+            death_date: Some(date(2020, 1, 1)),
+            revision: 4,
+            ..person
+        };
+        assert_eq!(result, Ok(expected.clone()));
+        assert_eq!(*usecase.dao.data.lock().unwrap(), vec![(13, expected)]);
+
+        let result = usecase.death(&mut (), 99, date(2020, 1, 1)).await;
+        assert_eq!(result, Err(UsecaseError::PersonNotFound(99)));
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        let alice = PersonDto::new("Alice", date(2012, 11, 2), None, None, 3);
+        let bob = PersonDto::new("Bob", date(1995, 11, 6), None, None, 1);
+        let mut usecase = usecase_with(0, vec![(13, alice.clone()), (24, bob.clone())]);
+
+        // 既定では DAO が select したものにメモリ上で適用する
+        let query = PersonQuery {
+            sort: SortKey::BirthDate,
+            ..Default::default()
+        };
+        let result = usecase.query(&mut (), query).await;
+        assert_eq!(result, Ok(vec![(24, bob), (13, alice.clone())]));
+
+        let search = PersonSearch {
+            text: "ali".to_string(),
+            ..Default::default()
+        };
+        let result = usecase.search(&mut (), search).await;
+        assert_eq!(result, Ok(vec![(13, alice)]));
+    }
+
+    #[tokio::test]
+    async fn test_correct_death() {
+        let person = PersonDto::new("Alice", date(2012, 11, 2), Some(date(2020, 1, 1)), None, 3);
+        let mut usecase = usecase_with(0, vec![(13, person.clone())]);
+
+        let result = usecase
+            .correct_death(&mut (), 13, None, "still alive".to_string())
+            .await;
+        // 死亡日が取り消され, 版が上がる
+        let expected = PersonDto {
+            death_date: None,
+            revision: 4,
+            ..person
+        };
+        assert_eq!(result, Ok(expected.clone()));
+        assert_eq!(*usecase.dao.data.lock().unwrap(), vec![(13, expected)]);
+
+        let result = usecase
+            .correct_death(&mut (), 99, None, "still alive".to_string())
+            .await;
+        assert_eq!(result, Err(UsecaseError::PersonNotFound(99)));
+    }
+
+    #[tokio::test]
+    async fn test_update() {
+        let person = PersonDto::new("Alice", date(2012, 11, 2), None, Some("wonderland"), 3);
+        let mut usecase = usecase_with(0, vec![(13, person.clone())]);
+
+        let patch = PersonPatch {
+            name: Some("Alice Liddell".to_string()),
+            data: Some(None),
+            ..Default::default()
+        };
+        let result = usecase.update(&mut (), 13, patch).await;
+        // 指定したものだけが変わり, 版が上がる
+        let expected = PersonDto {
+            name: "Alice Liddell".to_string(),
+            data: None,
+            revision: 4,
+            ..person
+        };
+        assert_eq!(result, Ok(expected.clone()));
+        assert_eq!(*usecase.dao.data.lock().unwrap(), vec![(13, expected)]);
+
+        // 空の名前には変えられないので, 何も保存しない
+        let patch = PersonPatch {
+            name: Some(String::new()),
+            ..Default::default()
+        };
+        let result = usecase.update(&mut (), 13, patch).await;
+        assert!(matches!(
+            result,
+            Err(UsecaseError::DomainObjectChangeFailed(_))
+        ));
+        assert_eq!(usecase.dao.data.lock().unwrap()[0].1.revision, 4);
+    }
+
+    #[tokio::test]
+    async fn test_remove() {
+        let person = PersonDto::new("Alice", date(2012, 11, 2), None, None, 3);
+        let mut usecase = usecase_with(0, vec![(13, person)]);
+
+        let result = usecase.remove(&mut (), 13).await;
+        assert_eq!(result, Ok(Some(4)));
+        assert!(usecase.dao.data.lock().unwrap().is_empty());
+
+        // もういないので何も起きない
+        let result = usecase.remove(&mut (), 13).await;
+        assert_eq!(result, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn test_restore() {
+        let person = PersonDto::new("Alice", date(2012, 11, 2), None, None, 3);
+        let mut usecase = usecase_with(0, vec![(13, person.clone())]);
+
+        // 削除されていなければ戻すものがない
+        let result = usecase.restore(&mut (), 13).await;
+        assert_eq!(result, Err(UsecaseError::PersonNotFound(13)));
+
+        usecase.remove(&mut (), 13).await.unwrap();
+        let result = usecase.restore(&mut (), 13).await;
+        // 削除と復元でそれぞれ版が上がる
+        assert_eq!(
+            result,
+            Ok(PersonDto {
+                revision: 5,
+                ..person
+            })
+        );
+    }
+}
@@ -0,0 +1,341 @@
+use log::{info, warn};
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::config::ConfigError;
+use crate::reporter::ReporterError;
+
+/// Why a service cannot start.
+///
+/// Only the database is mandatory. Without the cache or the message queue the service
+/// starts degraded and reconnects them in the background.
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum StartupError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error("database unavailable: {0}")]
+    DatabaseUnavailable(String),
+    #[error("cannot register observer: {0}")]
+    Observer(#[from] ReporterError),
+}
+
+/// Whether a backend can be used, shared by the service and the thread reconnecting it.
+#[derive(Debug, Clone)]
+pub struct Health(Arc<AtomicBool>);
+impl Health {
+    pub fn up() -> Self {
+        Self(Arc::new(AtomicBool::new(true)))
+    }
+    pub fn down() -> Self {
+        Self(Arc::new(AtomicBool::new(false)))
+    }
+    pub fn is_up(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+    pub fn set_up(&self, up: bool) {
+        self.0.store(up, Ordering::SeqCst);
+    }
+}
+impl Default for Health {
+    fn default() -> Self {
+        Self::up()
+    }
+}
+
+/// How long to wait before the next attempt to reconnect: initial, initial * 2, ... up to max.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+        }
+    }
+}
+impl Backoff {
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+/// How a caller waiting for a lost connection tries to open it again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    pub max_attempts: u32,
+    pub backoff: Backoff,
+}
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff: Backoff {
+                initial: Duration::from_millis(100),
+                max: Duration::from_secs(1),
+            },
+        }
+    }
+}
+impl ReconnectPolicy {
+    /// run `f` again while it fails with an error `is_lost` says is a lost connection.
+    ///
+    /// ```ignore
+    /// let conn = ReconnectPolicy::default().run("postgres", is_connect_error, || pool.get())?;
+    /// ```
+    pub fn run<T, E: fmt::Display>(
+        &self,
+        name: &str,
+        is_lost: impl Fn(&E) -> bool,
+        f: impl FnMut() -> Result<T, E>,
+    ) -> Result<T, E> {
+        self.run_with_sleep(name, is_lost, f, thread::sleep)
+    }
+
+    fn run_with_sleep<T, E: fmt::Display>(
+        &self,
+        name: &str,
+        is_lost: impl Fn(&E) -> bool,
+        mut f: impl FnMut() -> Result<T, E>,
+        mut sleep: impl FnMut(Duration),
+    ) -> Result<T, E> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match f() {
+                Err(e) if is_lost(&e) && attempt < self.max_attempts => {
+                    let delay = self.backoff.delay(attempt);
+                    warn!(
+                        "lost connection to {} (attempts: {}), retry in {:?}: {}",
+                        name, attempt, delay, e
+                    );
+                    sleep(delay);
+                }
+                res => {
+                    if attempt > 1 && res.is_ok() {
+                        info!("reconnected to {} (attempts: {})", name, attempt);
+                    }
+                    return res;
+                }
+            }
+        }
+    }
+
+    /// async version of `run`, which waits on the tokio timer
+    pub async fn run_async<T, E, F, Fut>(
+        &self,
+        name: &str,
+        is_lost: impl Fn(&E) -> bool,
+        mut f: F,
+    ) -> Result<T, E>
+    where
+        E: fmt::Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match f().await {
+                Err(e) if is_lost(&e) && attempt < self.max_attempts => {
+                    let delay = self.backoff.delay(attempt);
+                    warn!(
+                        "lost connection to {} (attempts: {}), retry in {:?}: {}",
+                        name, attempt, delay, e
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                res => {
+                    if attempt > 1 && res.is_ok() {
+                        info!("reconnected to {} (attempts: {})", name, attempt);
+                    }
+                    return res;
+                }
+            }
+        }
+    }
+}
+
+/// try `connect` until it succeeds, passing the delay after each failure to `sleep`.
+pub fn reconnect<T, E: fmt::Display>(
+    name: &str,
+    backoff: &Backoff,
+    mut connect: impl FnMut() -> Result<T, E>,
+    mut sleep: impl FnMut(Duration),
+) -> T {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match connect() {
+            Ok(conn) => {
+                info!("{} is available (attempts: {})", name, attempt);
+                return conn;
+            }
+            Err(e) => {
+                let delay = backoff.delay(attempt);
+                warn!(
+                    "cannot connect to {} (attempts: {}), retry in {:?}: {}",
+                    name, attempt, delay, e
+                );
+                sleep(delay);
+            }
+        }
+    }
+}
+
+/// `reconnect` in a new thread, and hand the connection to `on_connect`.
+pub fn spawn_reconnect<T, E, C, F>(
+    name: &str,
+    backoff: Backoff,
+    connect: C,
+    on_connect: F,
+) -> thread::JoinHandle<()>
+where
+    E: fmt::Display,
+    C: FnMut() -> Result<T, E> + Send + 'static,
+    F: FnOnce(T) + Send + 'static,
+{
+    let name = name.to_string();
+    thread::spawn(move || {
+        let conn = reconnect(&name, &backoff, connect, thread::sleep);
+        on_connect(conn);
+    })
+}
+
+// # フェイクテスト
+//
+// ## 目的
+//
+//   繋がるまで間隔を延ばしながら試し, 繋がったものを渡すことを保障する
+//
+// ## 方針
+//
+//   指定回数だけ失敗するフェイクの接続と, 待ち時間を記録するだけの sleep で確認する
+//
+#[cfg(test)]
+mod fake_tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    // n 回失敗してから何回目で繋がったかを返す
+    fn fail_times(n: u32) -> impl FnMut() -> Result<u32, String> {
+        let mut attempts = 0;
+        move || {
+            attempts += 1;
+            if attempts <= n {
+                Err(format!("refused {}", attempts))
+            } else {
+                Ok(attempts)
+            }
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+        };
+        let delays = (1..=6).map(|n| backoff.delay(n)).collect::<Vec<_>>();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800, 1000, 1000].map(Duration::from_millis)
+        );
+        // 桁あふれしても上限で止まる
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let mut slept = vec![];
+        let attempts = reconnect("fake", &Backoff::default(), fail_times(3), |d| {
+            slept.push(d)
+        });
+        assert_eq!(attempts, 4);
+        assert_eq!(slept, [500, 1000, 2000].map(Duration::from_millis));
+    }
+
+    #[test]
+    fn test_reconnect_at_once() {
+        let mut slept = vec![];
+        let attempts = reconnect("fake", &Backoff::default(), fail_times(0), |d| {
+            slept.push(d)
+        });
+        assert_eq!(attempts, 1);
+        assert!(slept.is_empty());
+    }
+
+    #[test]
+    fn test_reconnect_policy() {
+        let policy = ReconnectPolicy::default();
+        let mut slept = vec![];
+        let res = policy.run_with_sleep("fake", |_| true, fail_times(2), |d| slept.push(d));
+        assert_eq!(res, Ok(3));
+        assert_eq!(slept, [100, 200].map(Duration::from_millis));
+
+        // 回数を使い切ったら最後のエラーを返す
+        let mut slept = vec![];
+        let res = policy.run_with_sleep("fake", |_| true, fail_times(5), |d| slept.push(d));
+        assert_eq!(res, Err("refused 3".to_string()));
+        assert_eq!(slept.len(), 2);
+    }
+
+    #[test]
+    fn test_reconnect_policy_not_lost() {
+        // 接続が切れたのでなければやり直さない
+        let mut slept = vec![];
+        let res = ReconnectPolicy::default().run_with_sleep(
+            "fake",
+            |e: &String| e.starts_with("lost"),
+            fail_times(2),
+            |d| slept.push(d),
+        );
+        assert_eq!(res, Err("refused 1".to_string()));
+        assert!(slept.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_policy_async() {
+        let policy = ReconnectPolicy {
+            max_attempts: 3,
+            backoff: Backoff {
+                initial: Duration::from_millis(1),
+                max: Duration::from_millis(1),
+            },
+        };
+        let mut connect = fail_times(1);
+        let res = policy
+            .run_async("fake", |_| true, || std::future::ready(connect()))
+            .await;
+        assert_eq!(res, Ok(2));
+    }
+
+    #[test]
+    fn test_spawn_reconnect() {
+        let health = Health::down();
+        let (tx, rx) = mpsc::channel();
+        let backoff = Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(1),
+        };
+        let target = health.clone();
+        spawn_reconnect("fake", backoff, fail_times(2), move |attempts| {
+            target.set_up(true);
+            tx.send(attempts).unwrap();
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(rx.recv().unwrap(), 3);
+        assert!(health.is_up());
+    }
+}
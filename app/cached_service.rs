@@ -4,7 +4,7 @@ use std::rc::Rc;
 
 use crate::cache::PersonCao;
 use crate::domain::PersonId;
-use crate::dto::PersonDto;
+use crate::dto::{PersonDto, PersonPatch};
 use crate::location;
 use crate::reporter::{Level, Reporter};
 use crate::service::{InvalidErrorKind, PersonOutputBoundary, PersonService, ServiceError};
@@ -35,7 +35,7 @@ pub trait PersonCachedService<'a, Conn, Ctx>: PersonService<'a, Ctx> {
         trace!("register person to db: {:?}", result);
 
         if let Ok((id, person)) = &result {
-            if let Err(e) = cao.run_tx(cao.load(*id, &person)) {
+            if let Err(e) = cao.run_tx(cao.load(*id, person)) {
                 // ここはエラーを返す必要はない
                 warn!("failed to load person to cache: {}", e);
                 if let Err(e) = reporter.send_report(
@@ -71,7 +71,7 @@ pub trait PersonCachedService<'a, Conn, Ctx>: PersonService<'a, Ctx> {
 
         // if the person is found in the db, load it to the cache
         if let Some(person) = &result {
-            if let Err(e) = cao.run_tx(cao.load(id, &person)) {
+            if let Err(e) = cao.run_tx(cao.load(id, person)) {
                 // ここはエラーを返す必要はない
                 warn!("failed to load person to cache: {}", e);
                 if let Err(e) = reporter.send_report(
@@ -110,7 +110,7 @@ pub trait PersonCachedService<'a, Conn, Ctx>: PersonService<'a, Ctx> {
         // load all persons to the cache
         for (id, person) in ids.iter().zip(persons.iter()) {
             // ここはエラーを返す必要はない
-            if let Err(e) = cao.run_tx(cao.load(*id, &person)) {
+            if let Err(e) = cao.run_tx(cao.load(*id, person)) {
                 warn!("failed to load person to cache: {}", e);
                 if let Err(e) = reporter.send_report(
                     Level::Error,
@@ -138,7 +138,7 @@ pub trait PersonCachedService<'a, Conn, Ctx>: PersonService<'a, Ctx> {
         // load all persons to the cache
         for (id, person) in result.iter() {
             // ここはエラーを返す必要はない
-            if let Err(e) = cao.run_tx(cao.load(*id, &person)) {
+            if let Err(e) = cao.run_tx(cao.load(*id, person)) {
                 warn!("failed to load person to cache: {}", e);
                 if let Err(e) = reporter.send_report(
                     Level::Error,
@@ -161,7 +161,7 @@ pub trait PersonCachedService<'a, Conn, Ctx>: PersonService<'a, Ctx> {
         let cao = self.get_cao();
         let reporter = self.get_reporter();
 
-        let _ = self.death(id, death_date)?;
+        self.death(id, death_date)?;
         trace!("update death date in db: {} {}", id, death_date);
 
         // even if delete from db failed below, this cache clear is not a matter.
@@ -183,6 +183,69 @@ pub trait PersonCachedService<'a, Conn, Ctx>: PersonService<'a, Ctx> {
         Ok(())
     }
 
+    fn cached_correct_death(
+        &'a mut self,
+        id: PersonId,
+        death_date: Option<NaiveDate>,
+        reason: &str,
+    ) -> Result<PersonDto, ServiceError> {
+        trace!("cached correct death: {} {:?} {}", id, death_date, reason);
+        let cao = self.get_cao();
+        let reporter = self.get_reporter();
+
+        let person = self.correct_death(id, death_date, reason)?;
+        trace!("correct death date in db: {} {:?}", id, death_date);
+
+        // 次の cached_find で訂正後の版が載るように消しておく
+        if let Err(e) = cao.run_tx(cao.unload(id)) {
+            // ここはエラーを返す必要はない
+            warn!("failed to unload person from cache: {}", e);
+            if let Err(e) = reporter.send_report(
+                Level::Error,
+                "admin",
+                "cache service not available",
+                location!(),
+            ) {
+                error!("reporter service not available: {}", e);
+            }
+        } else {
+            trace!("unload from cache: {}", id);
+        }
+
+        Ok(person)
+    }
+
+    fn cached_update(
+        &'a mut self,
+        id: PersonId,
+        patch: PersonPatch,
+    ) -> Result<PersonDto, ServiceError> {
+        trace!("cached update: {} {:?}", id, patch);
+        let cao = self.get_cao();
+        let reporter = self.get_reporter();
+
+        let person = self.update(id, patch)?;
+        trace!("update person in db: {} {:?}", id, person);
+
+        // 次の cached_find で最新版が載るように消しておく
+        if let Err(e) = cao.run_tx(cao.unload(id)) {
+            // ここはエラーを返す必要はない
+            warn!("failed to unload person from cache: {}", e);
+            if let Err(e) = reporter.send_report(
+                Level::Error,
+                "admin",
+                "cache service not available",
+                location!(),
+            ) {
+                error!("reporter service not available: {}", e);
+            }
+        } else {
+            trace!("unload from cache: {}", id);
+        }
+
+        Ok(person)
+    }
+
     fn cached_unregister(&'a mut self, id: PersonId) -> Result<(), ServiceError> {
         trace!("cached unregister: {}", id);
         let cao = self.get_cao();
@@ -209,6 +272,32 @@ pub trait PersonCachedService<'a, Conn, Ctx>: PersonService<'a, Ctx> {
 
         result
     }
+
+    fn cached_restore(&'a mut self, id: PersonId) -> Result<PersonDto, ServiceError> {
+        trace!("cached restore: {}", id);
+        let cao = self.get_cao();
+        let reporter = self.get_reporter();
+
+        let person = self.restore(id)?;
+        trace!("restore person in db: {} {:?}", id, person);
+
+        if let Err(e) = cao.run_tx(cao.load(id, &person)) {
+            // ここはエラーを返す必要はない
+            warn!("failed to load person to cache: {}", e);
+            if let Err(e) = reporter.send_report(
+                Level::Error,
+                "admin",
+                "cache service not available",
+                location!(),
+            ) {
+                error!("reporter service not available: {}", e);
+            }
+        } else {
+            trace!("load person to cache: {:?}", person);
+        }
+
+        Ok(person)
+    }
 }
 
 // # フェイクテスト
@@ -259,8 +348,8 @@ pub trait PersonCachedService<'a, Conn, Ctx>: PersonService<'a, Ctx> {
 //      この構造体は実質使われないが、 Service に必要なので用意する
 //   2. CachedService のメソッド呼び出しに対して、期待される結果を返す Service の実装を用意する
 //      この Service 実装はフェイクなので、間接的な入力と間接的な出力が整合するようにする
-//   3. CachedService のメソッド呼び出しに対して、期待される結果を返す Cache 構造体を用意する
-//      この Cache 構造体はフェイクなので、間接的な入力と間接的な出力が整合するようにする
+//   3. Cache にはインメモリのバックエンド (MemoryPersonCao) を使う
+//      MemoryCache 上で実際に動くので、間接的な入力と間接的な出力が整合する
 //   4. ダミーの Reporter 構造体を用意する
 //   5. CachedService をここまでに用意したフェイクとダミーで構築する
 //   6. Service のメソッドを呼び出す
@@ -281,14 +370,15 @@ mod fake_tests {
     use self::location::Location;
 
     use super::*;
+    use crate::memory::{MemoryCache, MemoryPersonCao};
     use crate::{
-        cache::CaoError,
         dao::{DaoError, PersonDao},
         domain::{date, Revision},
-        dto::PersonDto,
+        dto::{PersonDto, PersonHistoryDto},
         reporter::ReporterError,
         HavePersonDao, PersonUsecase, UsecaseError,
     };
+    use chrono::{DateTime, Utc};
 
     struct DummyPersonDao;
     impl PersonDao<()> for DummyPersonDao {
@@ -315,8 +405,26 @@ mod fake_tests {
         ) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
             tx_rs::with_tx(move |&mut ()| Ok(()))
         }
-        fn delete(&self, _id: PersonId) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
-            tx_rs::with_tx(move |&mut ()| Ok(()))
+        fn delete(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(None))
+        }
+        fn restore(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(None))
+        }
+        fn purge(&self, _before: DateTime<Utc>) -> impl tx_rs::Tx<(), Item = u64, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(0))
+        }
+        fn history(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Vec<PersonHistoryDto>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(vec![]))
         }
     }
 
@@ -368,20 +476,20 @@ mod fake_tests {
             &'a mut self,
             _id: PersonId,
             _date: NaiveDate,
-        ) -> impl tx_rs::Tx<(), Item = (), Err = UsecaseError>
+        ) -> impl tx_rs::Tx<(), Item = PersonDto, Err = UsecaseError>
         where
             (): 'a,
         {
-            tx_rs::with_tx(move |&mut ()| Ok(()))
+            tx_rs::with_tx(move |&mut ()| Ok(PersonDto::default()))
         }
         fn remove<'a>(
             &'a mut self,
             _id: PersonId,
-        ) -> impl tx_rs::Tx<(), Item = (), Err = UsecaseError>
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = UsecaseError>
         where
             (): 'a,
         {
-            tx_rs::with_tx(move |&mut ()| Ok(()))
+            tx_rs::with_tx(move |&mut ()| Ok(None))
         }
     }
 
@@ -409,12 +517,12 @@ mod fake_tests {
 
     /// テスト用のフェイクサービスです。
     /// Clone できるようにしていないので基本は Rc でラップしていません。
-    /// FakePersonCao のみ get_cao() で clone されるため内部データを Rc でラップしています。
+    /// キャッシュは get_cao() で clone されるので、中身を共有する MemoryCache にしています。
     struct TargetPersonService {
         next_id: RefCell<PersonId>,
         db: RefCell<HashMap<PersonId, PersonDto>>,
         usecase: Rc<RefCell<DummyPersonUsecase>>,
-        cao: FakePersonCao,
+        cache: MemoryCache,
     }
     // フェイクのサービス実装です。ユースケースより先はダミーです。
     impl PersonService<'_, ()> for TargetPersonService {
@@ -476,53 +584,70 @@ mod fake_tests {
                 .collect())
         }
 
-        fn unregister(&'_ mut self, id: PersonId) -> Result<(), ServiceError> {
-            self.db.borrow_mut().remove(&id);
-            Ok(())
-        }
-    }
-    #[derive(Debug, Clone, PartialEq, Eq)]
-    struct FakePersonCao {
-        cache: Rc<RefCell<HashMap<PersonId, PersonDto>>>,
-    }
-    impl PersonCao<()> for FakePersonCao {
-        fn get_conn(&self) -> Result<(), CaoError> {
-            Ok(())
-        }
-        fn run_tx<T, F>(&self, f: F) -> Result<T, CaoError>
-        where
-            F: tx_rs::Tx<(), Item = T, Err = CaoError>,
-        {
-            f.run(&mut ())
-        }
-        fn find(
-            &self,
+        fn update(
+            &'_ mut self,
             id: PersonId,
-        ) -> impl tx_rs::Tx<(), Item = Option<PersonDto>, Err = CaoError> {
-            tx_rs::with_tx(move |&mut ()| Ok(self.cache.borrow().get(&id).cloned()))
+            patch: PersonPatch,
+        ) -> Result<PersonDto, ServiceError> {
+            let mut db = self.db.borrow_mut();
+            let Some(person) = db.get_mut(&id) else {
+                return Err(ServiceError::TransactionFailed(
+                    UsecaseError::PersonNotFound(id),
+                ));
+            };
+            if let Some(name) = patch.name {
+                person.name = name;
+            }
+            if let Some(birth_date) = patch.birth_date {
+                person.birth_date = birth_date;
+            }
+            if let Some(data) = patch.data {
+                person.data = data;
+            }
+            person.revision += 1;
+
+            Ok(person.clone())
         }
-        fn load(
-            &self,
+
+        fn correct_death(
+            &'_ mut self,
             id: PersonId,
-            person: &PersonDto,
-        ) -> impl tx_rs::Tx<(), Item = (), Err = CaoError> {
-            tx_rs::with_tx(move |&mut ()| {
-                self.cache.borrow_mut().insert(id, person.clone());
-                Ok(())
-            })
+            death_date: Option<NaiveDate>,
+            _reason: &str,
+        ) -> Result<PersonDto, ServiceError> {
+            let mut db = self.db.borrow_mut();
+            let Some(person) = db.get_mut(&id) else {
+                return Err(ServiceError::TransactionFailed(
+                    UsecaseError::PersonNotFound(id),
+                ));
+            };
+            person.death_date = death_date;
+            person.revision += 1;
+
+            Ok(person.clone())
         }
-        fn unload(&self, id: PersonId) -> impl tx_rs::Tx<(), Item = (), Err = CaoError> {
-            tx_rs::with_tx(move |&mut ()| {
-                self.cache.borrow_mut().remove(&id);
-                Ok(())
-            })
+
+        fn unregister(&'_ mut self, id: PersonId) -> Result<(), ServiceError> {
+            self.db.borrow_mut().remove(&id);
+            Ok(())
+        }
+
+        // 論理削除は持たないので, 残っているものをそのまま返す
+        fn restore(&'_ mut self, id: PersonId) -> Result<PersonDto, ServiceError> {
+            self.db
+                .borrow()
+                .get(&id)
+                .cloned()
+                .ok_or(ServiceError::TransactionFailed(
+                    UsecaseError::PersonNotFound(id),
+                ))
         }
     }
-    impl PersonCachedService<'_, (), ()> for TargetPersonService {
-        type C = FakePersonCao;
+    impl PersonCachedService<'_, MemoryCache, ()> for TargetPersonService {
+        type C = MemoryPersonCao;
 
         fn get_cao(&self) -> Self::C {
-            self.cao.clone()
+            MemoryPersonCao::new(self.cache.clone())
         }
     }
 
@@ -542,9 +667,7 @@ mod fake_tests {
             usecase: Rc::new(RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
             })),
-            cao: FakePersonCao {
-                cache: RefCell::new(HashMap::new()).into(),
-            },
+            cache: MemoryCache::new(),
         };
 
         let expected = PersonDto::new("Alice", date(2000, 1, 1), None, Some("Alice is here"), 0);
@@ -562,9 +685,7 @@ mod fake_tests {
             usecase: Rc::new(RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
             })),
-            cao: FakePersonCao {
-                cache: RefCell::new(HashMap::new()).into(),
-            },
+            cache: MemoryCache::new(),
         };
 
         let result = service.cached_find(1);
@@ -578,17 +699,12 @@ mod fake_tests {
             usecase: Rc::new(RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
             })),
-            cao: FakePersonCao {
-                cache: RefCell::new(
-                    vec![(
-                        1,
-                        PersonDto::new("Alice", date(2000, 1, 1), None, Some("Alice is here"), 0),
-                    )]
-                    .into_iter()
-                    .collect(),
-                )
-                .into(),
-            },
+            cache: vec![(
+                1,
+                PersonDto::new("Alice", date(2000, 1, 1), None, Some("Alice is here"), 0),
+            )]
+            .into_iter()
+            .collect(),
         };
 
         let expected = PersonDto::new("Alice", date(2000, 1, 1), None, Some("Alice is here"), 0);
@@ -610,9 +726,7 @@ mod fake_tests {
             usecase: Rc::new(RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
             })),
-            cao: FakePersonCao {
-                cache: RefCell::new(HashMap::new()).into(),
-            },
+            cache: MemoryCache::new(),
         };
 
         let expected = PersonDto::new("Alice", date(2000, 1, 1), None, Some("Alice is here"), 0);
@@ -630,9 +744,7 @@ mod fake_tests {
             usecase: Rc::new(RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
             })),
-            cao: FakePersonCao {
-                cache: RefCell::new(HashMap::new()).into(),
-            },
+            cache: MemoryCache::new(),
         };
 
         let result = service.cached_batch_import(
@@ -668,9 +780,7 @@ mod fake_tests {
             usecase: Rc::new(RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
             })),
-            cao: FakePersonCao {
-                cache: RefCell::new(HashMap::new()).into(),
-            },
+            cache: MemoryCache::new(),
         };
 
         let result = service.cached_list_all();
@@ -700,9 +810,7 @@ mod fake_tests {
             usecase: Rc::new(RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
             })),
-            cao: FakePersonCao {
-                cache: RefCell::new(HashMap::new()).into(),
-            },
+            cache: MemoryCache::new(),
         };
 
         let result = service.cached_death(1, date(2030, 11, 22));
@@ -711,6 +819,52 @@ mod fake_tests {
         assert_eq!(result, Ok(()));
     }
 
+    #[test]
+    fn test_cached_update() {
+        let gauss = PersonDto::new("Gauss", date(1777, 4, 30), None, None, 0);
+        let mut service = TargetPersonService {
+            next_id: RefCell::new(2),
+            db: RefCell::new(vec![(1, gauss.clone())].into_iter().collect()),
+            usecase: Rc::new(RefCell::new(DummyPersonUsecase {
+                dao: DummyPersonDao,
+            })),
+            cache: vec![(1, gauss)].into_iter().collect(),
+        };
+
+        let patch = PersonPatch {
+            name: Some("Carl Friedrich Gauss".to_string()),
+            ..Default::default()
+        };
+        let result = service.cached_update(1, patch);
+
+        let expected = PersonDto::new("Carl Friedrich Gauss", date(1777, 4, 30), None, None, 1);
+        assert_eq!(result, Ok(expected.clone()));
+        // 古い版はキャッシュから消えている
+        assert_eq!(service.cache.get(1), None);
+        assert_eq!(service.cached_find(1), Ok(Some(expected)));
+    }
+
+    #[test]
+    fn test_cached_correct_death() {
+        let gauss = PersonDto::new("Gauss", date(1777, 4, 30), Some(date(1855, 2, 22)), None, 1);
+        let mut service = TargetPersonService {
+            next_id: RefCell::new(2),
+            db: RefCell::new(vec![(1, gauss.clone())].into_iter().collect()),
+            usecase: Rc::new(RefCell::new(DummyPersonUsecase {
+                dao: DummyPersonDao,
+            })),
+            cache: vec![(1, gauss)].into_iter().collect(),
+        };
+
+        let result = service.cached_correct_death(1, Some(date(1855, 2, 23)), "typo");
+
+        let expected = PersonDto::new("Gauss", date(1777, 4, 30), Some(date(1855, 2, 23)), None, 2);
+        assert_eq!(result, Ok(expected.clone()));
+        // 訂正前の版はキャッシュから消えている
+        assert_eq!(service.cache.get(1), None);
+        assert_eq!(service.cached_find(1), Ok(Some(expected)));
+    }
+
     #[test]
     fn test_cached_unregister() {
         let mut service = TargetPersonService {
@@ -732,9 +886,7 @@ mod fake_tests {
             usecase: Rc::new(RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
             })),
-            cao: FakePersonCao {
-                cache: RefCell::new(HashMap::new()).into(),
-            },
+            cache: MemoryCache::new(),
         };
 
         let result = service.cached_unregister(1);
@@ -742,6 +894,33 @@ mod fake_tests {
         assert!(result.is_ok());
         assert_eq!(result, Ok(()));
     }
+
+    #[test]
+    fn test_cached_restore() {
+        let bob = PersonDto::new("Bob", date(2000, 1, 2), None, Some("Bob is here"), 2);
+        let mut service = TargetPersonService {
+            next_id: RefCell::new(3),
+            db: RefCell::new(vec![(2, bob.clone())].into_iter().collect()),
+            usecase: Rc::new(RefCell::new(DummyPersonUsecase {
+                dao: DummyPersonDao,
+            })),
+            cache: MemoryCache::new(),
+        };
+
+        let result = service.cached_restore(2);
+
+        assert_eq!(result, Ok(bob.clone()));
+        // 戻したものはキャッシュに載る
+        assert_eq!(service.cache.get(2), Some(bob));
+
+        let result = service.cached_restore(1);
+        assert_eq!(
+            result,
+            Err(ServiceError::TransactionFailed(
+                UsecaseError::PersonNotFound(1)
+            ))
+        );
+    }
 }
 
 // # スパイテスト(モック利用)
@@ -826,10 +1005,11 @@ mod spy_tests {
         cache::CaoError,
         dao::{DaoError, PersonDao},
         domain::{date, Revision},
-        dto::PersonDto,
+        dto::{PersonDto, PersonHistoryDto},
         reporter::ReporterError,
         HavePersonDao, PersonUsecase, UsecaseError,
     };
+    use chrono::{DateTime, Utc};
 
     struct DummyPersonDao;
     impl PersonDao<()> for DummyPersonDao {
@@ -856,8 +1036,26 @@ mod spy_tests {
         ) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
             tx_rs::with_tx(move |&mut ()| Ok(()))
         }
-        fn delete(&self, _id: PersonId) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
-            tx_rs::with_tx(move |&mut ()| Ok(()))
+        fn delete(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(None))
+        }
+        fn restore(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(None))
+        }
+        fn purge(&self, _before: DateTime<Utc>) -> impl tx_rs::Tx<(), Item = u64, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(0))
+        }
+        fn history(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Vec<PersonHistoryDto>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(vec![]))
         }
     }
 
@@ -909,20 +1107,20 @@ mod spy_tests {
             &'a mut self,
             _id: PersonId,
             _date: NaiveDate,
-        ) -> impl tx_rs::Tx<(), Item = (), Err = UsecaseError>
+        ) -> impl tx_rs::Tx<(), Item = PersonDto, Err = UsecaseError>
         where
             (): 'a,
         {
-            tx_rs::with_tx(move |&mut ()| Ok(()))
+            tx_rs::with_tx(move |&mut ()| Ok(PersonDto::default()))
         }
         fn remove<'a>(
             &'a mut self,
             _id: PersonId,
-        ) -> impl tx_rs::Tx<(), Item = (), Err = UsecaseError>
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = UsecaseError>
         where
             (): 'a,
         {
-            tx_rs::with_tx(move |&mut ()| Ok(()))
+            tx_rs::with_tx(move |&mut ()| Ok(None))
         }
     }
 
@@ -956,9 +1154,12 @@ mod spy_tests {
         }
     }
 
+    // register の引数 (name, birth_date, death_date, data)
+    type RegisterArgs = (String, NaiveDate, Option<NaiveDate>, Option<String>);
+
     /// テスト用のスパイサービスです。
     struct TargetPersonService {
-        register: RefCell<Vec<(String, NaiveDate, Option<NaiveDate>, Option<String>)>>,
+        register: RefCell<Vec<RegisterArgs>>,
         register_result: Result<(PersonId, PersonDto), ServiceError>,
         find: RefCell<Vec<PersonId>>,
         find_result: Result<Option<PersonDto>, ServiceError>,
@@ -1988,10 +2189,11 @@ mod error_stub_tests {
         cache::CaoError,
         dao::{DaoError, PersonDao},
         domain::{date, Revision},
-        dto::PersonDto,
+        dto::{PersonDto, PersonHistoryDto},
         reporter::ReporterError,
         HavePersonDao, PersonUsecase, UsecaseError,
     };
+    use chrono::{DateTime, Utc};
 
     struct DummyPersonDao;
     impl PersonDao<()> for DummyPersonDao {
@@ -2018,8 +2220,26 @@ mod error_stub_tests {
         ) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
             tx_rs::with_tx(move |&mut ()| Ok(()))
         }
-        fn delete(&self, _id: PersonId) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
-            tx_rs::with_tx(move |&mut ()| Ok(()))
+        fn delete(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(None))
+        }
+        fn restore(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(None))
+        }
+        fn purge(&self, _before: DateTime<Utc>) -> impl tx_rs::Tx<(), Item = u64, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(0))
+        }
+        fn history(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Vec<PersonHistoryDto>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(vec![]))
         }
     }
 
@@ -2071,20 +2291,20 @@ mod error_stub_tests {
             &'a mut self,
             _id: PersonId,
             _date: NaiveDate,
-        ) -> impl tx_rs::Tx<(), Item = (), Err = UsecaseError>
+        ) -> impl tx_rs::Tx<(), Item = PersonDto, Err = UsecaseError>
         where
             (): 'a,
         {
-            tx_rs::with_tx(move |&mut ()| Ok(()))
+            tx_rs::with_tx(move |&mut ()| Ok(PersonDto::default()))
         }
         fn remove<'a>(
             &'a mut self,
             _id: PersonId,
-        ) -> impl tx_rs::Tx<(), Item = (), Err = UsecaseError>
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = UsecaseError>
         where
             (): 'a,
         {
-            tx_rs::with_tx(move |&mut ()| Ok(()))
+            tx_rs::with_tx(move |&mut ()| Ok(None))
         }
     }
 
@@ -2117,6 +2337,7 @@ mod error_stub_tests {
         batch_import_result: Result<Vec<PersonId>, ServiceError>,
         list_all_result: Result<Vec<(PersonId, PersonDto)>, ServiceError>,
         death_result: Result<(), ServiceError>,
+        update_result: Result<PersonDto, ServiceError>,
         unregister_result: Result<(), ServiceError>,
 
         usecase: RefCell<DummyPersonUsecase>,
@@ -2169,6 +2390,14 @@ mod error_stub_tests {
             self.death_result.clone()
         }
 
+        fn update(
+            &'_ mut self,
+            _id: PersonId,
+            _patch: PersonPatch,
+        ) -> Result<PersonDto, ServiceError> {
+            self.update_result.clone()
+        }
+
         fn unregister(&'_ mut self, _id: PersonId) -> Result<(), ServiceError> {
             self.unregister_result.clone()
         }
@@ -2233,6 +2462,7 @@ mod error_stub_tests {
             batch_import_result: Ok(vec![]),
             list_all_result: Ok(vec![]),
             death_result: Ok(()),
+            update_result: Ok(PersonDto::default()),
             unregister_result: Ok(()),
             usecase: RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
@@ -2260,6 +2490,7 @@ mod error_stub_tests {
             batch_import_result: Ok(vec![]),
             list_all_result: Ok(vec![]),
             death_result: Ok(()),
+            update_result: Ok(PersonDto::default()),
             unregister_result: Ok(()),
             usecase: RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
@@ -2293,6 +2524,7 @@ mod error_stub_tests {
             batch_import_result: Ok(vec![]),
             list_all_result: Ok(vec![]),
             death_result: Ok(()),
+            update_result: Ok(PersonDto::default()),
             unregister_result: Ok(()),
             usecase: RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
@@ -2326,6 +2558,7 @@ mod error_stub_tests {
             batch_import_result: Ok(vec![]),
             list_all_result: Ok(vec![]),
             death_result: Ok(()),
+            update_result: Ok(PersonDto::default()),
             unregister_result: Ok(()),
             usecase: RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
@@ -2362,6 +2595,7 @@ mod error_stub_tests {
             )),
             list_all_result: Ok(vec![]),
             death_result: Ok(()),
+            update_result: Ok(PersonDto::default()),
             unregister_result: Ok(()),
             usecase: RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
@@ -2391,6 +2625,7 @@ mod error_stub_tests {
             )),
             list_all_result: Ok(vec![]),
             death_result: Ok(()),
+            update_result: Ok(PersonDto::default()),
             unregister_result: Ok(()),
             usecase: RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
@@ -2427,6 +2662,7 @@ mod error_stub_tests {
             batch_import_result: Ok(vec![1]),
             list_all_result: Ok(vec![]),
             death_result: Ok(()),
+            update_result: Ok(PersonDto::default()),
             unregister_result: Ok(()),
             usecase: RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
@@ -2463,6 +2699,7 @@ mod error_stub_tests {
                 UsecaseError::CollectPersonFailed(DaoError::SelectError("valid dao".to_string())),
             )),
             death_result: Ok(()),
+            update_result: Ok(PersonDto::default()),
             unregister_result: Ok(()),
             usecase: RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
@@ -2493,6 +2730,7 @@ mod error_stub_tests {
                 PersonDto::new("Alice", date(2000, 1, 1), None, Some("Alice is here"), 0),
             )]),
             death_result: Ok(()),
+            update_result: Ok(PersonDto::default()),
             unregister_result: Ok(()),
             usecase: RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
@@ -2532,6 +2770,7 @@ mod error_stub_tests {
             death_result: Err(ServiceError::TransactionFailed(
                 UsecaseError::SavePersonFailed(DaoError::UpdateError("valid dao".to_string())),
             )),
+            update_result: Ok(PersonDto::default()),
             unregister_result: Ok(()),
             usecase: RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
@@ -2565,6 +2804,7 @@ mod error_stub_tests {
             batch_import_result: Ok(vec![]),
             list_all_result: Ok(vec![]),
             death_result: Ok(()),
+            update_result: Ok(PersonDto::default()),
             unregister_result: Ok(()),
             usecase: RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
@@ -2579,6 +2819,64 @@ mod error_stub_tests {
         assert_eq!(result, Ok(()));
     }
 
+    #[test]
+    fn test_cached_update() {
+        let mut service = TargetPersonService {
+            register_result: Ok((1, PersonDto::default())),
+            find_result: Ok(None),
+            batch_import_result: Ok(vec![]),
+            list_all_result: Ok(vec![]),
+            death_result: Ok(()),
+            update_result: Err(ServiceError::RevisionConflict {
+                expected: 1,
+                actual: 2,
+            }),
+            unregister_result: Ok(()),
+            usecase: RefCell::new(DummyPersonUsecase {
+                dao: DummyPersonDao,
+            }),
+            cao: StubPersonCao {
+                find_result: Ok(None),
+                load_result: Ok(()),
+                unload_result: Ok(()),
+            },
+        };
+        let patch = PersonPatch {
+            name: Some("Gauss".to_string()),
+            ..Default::default()
+        };
+        let result = service.cached_update(1, patch.clone());
+        assert_eq!(
+            result,
+            Err(ServiceError::RevisionConflict {
+                expected: 1,
+                actual: 2
+            })
+        );
+
+        let updated = PersonDto::new("Gauss", date(1777, 4, 30), None, None, 1);
+        let mut service = TargetPersonService {
+            register_result: Ok((1, PersonDto::default())),
+            find_result: Ok(None),
+            batch_import_result: Ok(vec![]),
+            list_all_result: Ok(vec![]),
+            death_result: Ok(()),
+            update_result: Ok(updated.clone()),
+            unregister_result: Ok(()),
+            usecase: RefCell::new(DummyPersonUsecase {
+                dao: DummyPersonDao,
+            }),
+            cao: StubPersonCao {
+                find_result: Ok(None),
+                load_result: Ok(()),
+                unload_result: Err(CaoError::Unavailable("valid cao".to_string())),
+            },
+        };
+        // キャッシュが使えなくても更新は成功する
+        let result = service.cached_update(1, patch);
+        assert_eq!(result, Ok(updated));
+    }
+
     #[test]
     fn test_cached_unregister() {
         let mut service = TargetPersonService {
@@ -2590,6 +2888,7 @@ mod error_stub_tests {
             batch_import_result: Ok(vec![]),
             list_all_result: Ok(vec![]),
             death_result: Ok(()),
+            update_result: Ok(PersonDto::default()),
             unregister_result: Err(ServiceError::TransactionFailed(
                 UsecaseError::RemovePersonFailed(DaoError::DeleteError("valid dao".to_string())),
             )),
@@ -2619,6 +2918,7 @@ mod error_stub_tests {
             batch_import_result: Ok(vec![]),
             list_all_result: Ok(vec![]),
             death_result: Ok(()),
+            update_result: Ok(PersonDto::default()),
             unregister_result: Ok(()),
             usecase: RefCell::new(DummyPersonUsecase {
                 dao: DummyPersonDao,
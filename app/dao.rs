@@ -1,7 +1,12 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::str::FromStr;
 use thiserror::Error;
+use tx_rs::Tx;
 
 use crate::domain::{PersonId, Revision};
-use crate::dto::PersonDto;
+use crate::dto::{PersonDto, PersonHistoryDto};
 
 #[derive(Error, Debug, Clone, PartialEq, Eq)]
 pub enum DaoError {
@@ -13,6 +18,15 @@ pub enum DaoError {
     UpdateError(String),
     #[error("delete error: {0}")]
     DeleteError(String),
+    #[error("restore error: {0}")]
+    RestoreError(String),
+    #[error("purge error: {0}")]
+    PurgeError(String),
+    #[error("revision conflict: expected={expected}, actual={actual}")]
+    RevisionConflict {
+        expected: Revision,
+        actual: Revision,
+    },
 }
 pub trait PersonDao<Ctx> {
     fn insert(&self, person: PersonDto) -> impl tx_rs::Tx<Ctx, Item = PersonId, Err = DaoError>;
@@ -24,9 +38,316 @@ pub trait PersonDao<Ctx> {
         revision: Revision,
         person: PersonDto,
     ) -> impl tx_rs::Tx<Ctx, Item = (), Err = DaoError>;
-    fn delete(&self, id: PersonId) -> impl tx_rs::Tx<Ctx, Item = (), Err = DaoError>;
+    /// save a correction of the person, recording `reason` in the history.
+    ///
+    /// The default implementation just saves without the reason, which is enough for fakes.
+    fn correct(
+        &self,
+        id: PersonId,
+        revision: Revision,
+        person: PersonDto,
+        _reason: String,
+    ) -> impl tx_rs::Tx<Ctx, Item = (), Err = DaoError> {
+        self.save(id, revision, person)
+    }
+    /// soft delete.
+    ///
+    /// The person is kept until purged, but `fetch`, `select`, `query` and `search` no longer see it.
+    /// The revision is bumped so that a save based on an older read conflicts,
+    /// and returned. Nothing happens and None is returned unless the person is alive.
+    fn delete(&self, id: PersonId) -> impl tx_rs::Tx<Ctx, Item = Option<Revision>, Err = DaoError>;
+    /// undo a soft delete, bumping the revision, and return the new revision.
+    /// Nothing happens and None is returned unless the person is deleted.
+    fn restore(&self, id: PersonId)
+        -> impl tx_rs::Tx<Ctx, Item = Option<Revision>, Err = DaoError>;
+    /// physically delete the persons soft deleted before `before`, and return how many.
+    fn purge(&self, before: DateTime<Utc>) -> impl tx_rs::Tx<Ctx, Item = u64, Err = DaoError>;
+    /// the history of the person in the order of the changes, including deleted and purged ones.
+    ///
+    /// Every write above appends to it in the same transaction.
+    fn history(
+        &self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<Ctx, Item = Vec<PersonHistoryDto>, Err = DaoError>;
+
+    /// filtered, sorted and paginated select.
+    ///
+    /// The default implementation selects all rows and applies the query in memory,
+    /// which is enough for fakes. Real stores should override this.
+    fn query(
+        &self,
+        query: PersonQuery,
+    ) -> impl tx_rs::Tx<Ctx, Item = Vec<(PersonId, PersonDto)>, Err = DaoError> {
+        self.select().map(move |rows| query.apply(rows))
+    }
+
+    /// ranked name search.
+    ///
+    /// The default implementation ranks all rows in memory, which is enough for fakes.
+    /// Real stores should override this.
+    fn search(
+        &self,
+        search: PersonSearch,
+    ) -> impl tx_rs::Tx<Ctx, Item = Vec<(PersonId, PersonDto)>, Err = DaoError> {
+        self.select().map(move |rows| search.apply(rows))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortKey {
+    #[default]
+    Id,
+    Name,
+    BirthDate,
+}
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "id" => Ok(SortKey::Id),
+            "name" => Ok(SortKey::Name),
+            "birth_date" => Ok(SortKey::BirthDate),
+            _ => Err(format!("unknown sort key: {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asc" => Ok(SortOrder::Asc),
+            "desc" => Ok(SortOrder::Desc),
+            _ => Err(format!("unknown sort order: {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifeStatus {
+    Alive,
+    Dead,
+}
+impl FromStr for LifeStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "alive" => Ok(LifeStatus::Alive),
+            "dead" => Ok(LifeStatus::Dead),
+            _ => Err(format!("unknown status: {s}")),
+        }
+    }
+}
+
+/// Query for `PersonDao::query`.
+///
+/// Pagination is keyset based: pass the last id of the previous page as `after`.
+/// The page continues from the position of that person in the sort order,
+/// so the person must still exist: the page after a deleted person is empty.
+/// Ties of the sort key are broken by id.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PersonQuery {
+    pub name_prefix: Option<String>,
+    pub status: Option<LifeStatus>,
+    /// inclusive
+    pub born_from: Option<NaiveDate>,
+    /// inclusive
+    pub born_until: Option<NaiveDate>,
+    pub sort: SortKey,
+    pub order: SortOrder,
+    pub after: Option<PersonId>,
+    pub limit: Option<u32>,
+}
+impl PersonQuery {
+    pub fn matches(&self, person: &PersonDto) -> bool {
+        self.name_prefix
+            .as_ref()
+            .is_none_or(|prefix| person.name.starts_with(prefix.as_str()))
+            && self.status.is_none_or(|status| match status {
+                LifeStatus::Alive => person.death_date.is_none(),
+                LifeStatus::Dead => person.death_date.is_some(),
+            })
+            && self.born_from.is_none_or(|d| d <= person.birth_date)
+            && self.born_until.is_none_or(|d| person.birth_date <= d)
+    }
+
+    pub fn compare(&self, a: &(PersonId, PersonDto), b: &(PersonId, PersonDto)) -> Ordering {
+        let ord = match self.sort {
+            SortKey::Id => Ordering::Equal,
+            SortKey::Name => a.1.name.cmp(&b.1.name),
+            SortKey::BirthDate => a.1.birth_date.cmp(&b.1.birth_date),
+        }
+        .then(a.0.cmp(&b.0));
+
+        match self.order {
+            SortOrder::Asc => ord,
+            SortOrder::Desc => ord.reverse(),
+        }
+    }
+
+    /// apply this query to all rows in memory
+    pub fn apply(&self, rows: Vec<(PersonId, PersonDto)>) -> Vec<(PersonId, PersonDto)> {
+        // カーソルの位置はフィルタ前の全件から探す
+        let cursor = match self.after {
+            Some(after) => match rows.iter().find(|(id, _)| *id == after) {
+                Some(row) => Some(row.clone()),
+                None => return vec![],
+            },
+            None => None,
+        };
+
+        let mut rows = rows
+            .into_iter()
+            .filter(|(_, p)| self.matches(p))
+            .filter(|row| {
+                cursor
+                    .as_ref()
+                    .is_none_or(|c| self.compare(row, c) == Ordering::Greater)
+            })
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| self.compare(a, b));
+        if let Some(limit) = self.limit {
+            rows.truncate(limit as usize);
+        }
+
+        rows
+    }
+}
+
+/// Search for `PersonDao::search`.
+///
+/// Matching is case-insensitive. Results are ranked in this order:
+///
+///   1. name equals the text
+///   2. name starts with the text
+///   3. name contains the text
+///   4. data contains the text (only if `include_data`)
+///   5. name is similar to the text (trigram similarity, as pg_trgm does)
+///
+/// and then by the similarity of name, and by id.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PersonSearch {
+    pub text: String,
+    pub include_data: bool,
+    pub limit: Option<u32>,
+}
+impl PersonSearch {
+    /// pg_trgm の similarity_threshold の既定値
+    pub const SIMILARITY_THRESHOLD: f32 = 0.3;
+
+    /// rank and similarity of the person, or None if not matched
+    pub fn rank(&self, person: &PersonDto) -> Option<(u8, f32)> {
+        let text = self.text.to_lowercase();
+        let name = person.name.to_lowercase();
+        let sim = similarity(&name, &text);
+
+        let rank = if name == text {
+            4
+        } else if name.starts_with(&text) {
+            3
+        } else if name.contains(&text) {
+            2
+        } else if self.include_data
+            && person
+                .data
+                .as_ref()
+                .is_some_and(|d| d.to_lowercase().contains(&text))
+        {
+            1
+        } else if sim >= Self::SIMILARITY_THRESHOLD {
+            0
+        } else {
+            return None;
+        };
+
+        Some((rank, sim))
+    }
+
+    /// apply this search to all rows in memory
+    pub fn apply(&self, rows: Vec<(PersonId, PersonDto)>) -> Vec<(PersonId, PersonDto)> {
+        let mut hits = rows
+            .into_iter()
+            .filter_map(|row| self.rank(&row.1).map(|rank| (rank, row)))
+            .collect::<Vec<_>>();
+        hits.sort_by(|((r1, s1), (id1, _)), ((r2, s2), (id2, _))| {
+            r2.cmp(r1).then(s2.total_cmp(s1)).then(id1.cmp(id2))
+        });
+        if let Some(limit) = self.limit {
+            hits.truncate(limit as usize);
+        }
+
+        hits.into_iter().map(|(_, row)| row).collect()
+    }
+}
+
+// pg_trgm と同じく単語ごとに前に空白 2 つ, 後ろに空白 1 つを補って 3-gram を取る
+fn trigrams(s: &str) -> HashSet<Vec<char>> {
+    s.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .flat_map(|w| {
+            let padded = format!("  {w} ").chars().collect::<Vec<_>>();
+            padded.windows(3).map(|t| t.to_vec()).collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// trigram similarity of two strings, between 0.0 and 1.0
+pub fn similarity(a: &str, b: &str) -> f32 {
+    let (a, b) = (trigrams(a), trigrams(b));
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let shared = a.intersection(&b).count();
+
+    shared as f32 / (a.len() + b.len() - shared) as f32
 }
 
 pub trait HavePersonDao<Ctx> {
     fn get_dao(&self) -> &impl PersonDao<Ctx>;
 }
+
+// keyset のカーソルはどのバックエンドでも同じに扱われることを確かめる共通のケース.
+// 各バックエンドのテストから, 空の DB を渡して呼ぶ
+#[cfg(test)]
+pub(crate) fn check_query_cursor<Ctx>(dao: &impl PersonDao<Ctx>, ctx: &mut Ctx) {
+    use crate::domain::date;
+
+    let mut ids = vec![];
+    for (name, birth_date) in [
+        ("Gauss", date(1777, 4, 30)),
+        ("Abel", date(1802, 8, 5)),
+        ("Galois", date(1811, 10, 25)),
+    ] {
+        let person = PersonDto::new(name, birth_date, None, None, 0);
+        ids.push((dao.insert(person.clone()).run(ctx).expect("insert"), person));
+    }
+    let after = |id| PersonQuery {
+        sort: SortKey::BirthDate,
+        after: Some(id),
+        ..Default::default()
+    };
+
+    // カーソルの位置の次から続く
+    assert_eq!(
+        dao.query(after(ids[0].0)).run(ctx),
+        Ok(vec![ids[1].clone(), ids[2].clone()])
+    );
+
+    // 削除された人の位置からは続けない
+    dao.delete(ids[1].0).run(ctx).expect("delete");
+    assert_eq!(dao.query(after(ids[1].0)).run(ctx), Ok(vec![]));
+    assert_eq!(
+        dao.query(after(ids[0].0)).run(ctx),
+        Ok(vec![ids[2].clone()])
+    );
+}
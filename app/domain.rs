@@ -1,6 +1,6 @@
-use chrono::NaiveDate;
+use chrono::{Local, NaiveDate};
 use core::fmt;
-use log::{trace, warn};
+use log::{info, trace, warn};
 use thiserror::Error;
 
 use crate::dto::PersonDto;
@@ -16,6 +16,8 @@ pub enum PersonDomainError {
     InvalidFieldValue(FieldName, String),
     #[error("already dead")]
     AlreadyDead,
+    #[error("not dead")]
+    NotDead,
 }
 
 pub type PersonId = i32;
@@ -50,6 +52,82 @@ impl Person {
         Ok(())
     }
 
+    /// correct the death date of a dead person, or clear it (resurrection) if `date` is None.
+    ///
+    /// This rewrites a recorded fact, so `reason` is required and left in the log.
+    pub fn correct_death(
+        &mut self,
+        date: Option<NaiveDate>,
+        reason: &str,
+    ) -> Result<(), PersonDomainError> {
+        if self.death_date.is_none() {
+            warn!("person is not dead: {}", self);
+            return Err(PersonDomainError::NotDead);
+        }
+        if reason.trim().is_empty() {
+            warn!("reason must not be empty: {}", self);
+            return Err(PersonDomainError::InvalidFieldValue(
+                "reason".into(),
+                "must not be empty".into(),
+            ));
+        }
+        if date.is_some_and(|date| date < self.birth_date) {
+            warn!("death date must be after birth date: {}", self);
+            return Err(PersonDomainError::InvalidFieldValue(
+                "death_date".into(),
+                "must be after birth date".into(),
+            ));
+        }
+
+        info!(
+            "correct death date: {:?} -> {:?} (reason: {})",
+            self.death_date, date, reason
+        );
+        self.death_date = date;
+
+        Ok(())
+    }
+
+    pub fn rename(&mut self, name: &str) -> Result<(), PersonDomainError> {
+        let name = name.trim();
+        if name.is_empty() {
+            warn!("name must not be empty: {}", self);
+            return Err(PersonDomainError::InvalidFieldValue(
+                "name".into(),
+                "must not be empty".into(),
+            ));
+        }
+
+        self.name = name.to_string();
+
+        Ok(())
+    }
+
+    pub fn change_birth_date(&mut self, date: NaiveDate) -> Result<(), PersonDomainError> {
+        if date > Local::now().date_naive() {
+            warn!("birth date must not be in the future: {}", self);
+            return Err(PersonDomainError::InvalidFieldValue(
+                "birth_date".into(),
+                "must not be in the future".into(),
+            ));
+        }
+        if self.death_date.is_some_and(|death_date| death_date < date) {
+            warn!("birth date must be before death date: {}", self);
+            return Err(PersonDomainError::InvalidFieldValue(
+                "birth_date".into(),
+                "must be before death date".into(),
+            ));
+        }
+
+        self.birth_date = date;
+
+        Ok(())
+    }
+
+    pub fn change_data(&mut self, data: Option<&str>) {
+        self.data = data.map(|d| d.to_string());
+    }
+
     pub fn notify(&self, dto: &mut impl PersonNotification) {
         trace!("notifying to dto: {}", self);
         dto.set_name(&self.name);
@@ -77,15 +155,39 @@ impl Person {
         }
     }
 }
-impl From<PersonDto> for Person {
-    fn from(person: PersonDto) -> Self {
-        Self {
+impl TryFrom<PersonDto> for Person {
+    type Error = PersonDomainError;
+
+    fn try_from(person: PersonDto) -> Result<Self, Self::Error> {
+        if person.name.trim().is_empty() {
+            warn!("name must not be empty: {:?}", person);
+            return Err(PersonDomainError::InvalidFieldValue(
+                "name".into(),
+                "must not be empty".into(),
+            ));
+        }
+        if person.birth_date > Local::now().date_naive() {
+            warn!("birth date must not be in the future: {:?}", person);
+            return Err(PersonDomainError::InvalidFieldValue(
+                "birth_date".into(),
+                "must not be in the future".into(),
+            ));
+        }
+        if person.death_date.is_some_and(|d| d < person.birth_date) {
+            warn!("death date must be after birth date: {:?}", person);
+            return Err(PersonDomainError::InvalidFieldValue(
+                "death_date".into(),
+                "must be after birth date".into(),
+            ));
+        }
+
+        Ok(Self {
             name: person.name,
             birth_date: person.birth_date,
             death_date: person.death_date,
             data: person.data,
             revision: person.revision,
-        }
+        })
     }
 }
 
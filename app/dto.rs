@@ -1,8 +1,10 @@
-use chrono::NaiveDate;
+use chrono::{DateTime, NaiveDate, Utc};
 use log::trace;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+use std::str::FromStr;
 
-use crate::domain::{Person, PersonNotification, Revision};
+use crate::domain::{Person, PersonId, PersonNotification, Revision};
 
 #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PersonDto {
@@ -31,6 +33,103 @@ impl PersonDto {
     }
 }
 
+/// Partial update of a person. `None` fields are left as they are.
+///
+/// `data` is doubly optional to tell "leave it" (`None`) from "clear it" (`Some(None)`).
+/// In JSON, a missing `data` leaves it and `"data": null` clears it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PersonPatch {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub birth_date: Option<NaiveDate>,
+    #[serde(
+        default,
+        deserialize_with = "double_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub data: Option<Option<String>>,
+}
+impl PersonPatch {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.birth_date.is_none() && self.data.is_none()
+    }
+}
+
+// フィールドがあれば null でも Some(None) にする
+fn double_option<'de, D, T>(de: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(de).map(Some)
+}
+
+/// kind of a change recorded in the history
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Insert,
+    Update,
+    Delete,
+    Restore,
+    Purge,
+}
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            ChangeKind::Insert => "insert",
+            ChangeKind::Update => "update",
+            ChangeKind::Delete => "delete",
+            ChangeKind::Restore => "restore",
+            ChangeKind::Purge => "purge",
+        };
+        f.pad(s)
+    }
+}
+impl FromStr for ChangeKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "insert" => Ok(ChangeKind::Insert),
+            "update" => Ok(ChangeKind::Update),
+            "delete" => Ok(ChangeKind::Delete),
+            "restore" => Ok(ChangeKind::Restore),
+            "purge" => Ok(ChangeKind::Purge),
+            _ => Err(format!("unknown change kind: {s}")),
+        }
+    }
+}
+
+/// One entry of the append-only history of a person.
+///
+/// `revision` is the revision after the change, except for `Purge` which has no new value.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PersonHistoryDto {
+    pub person_id: PersonId,
+    pub revision: Revision,
+    pub kind: ChangeKind,
+    pub old: Option<PersonDto>,
+    pub new: Option<PersonDto>,
+    pub changed_at: DateTime<Utc>,
+    pub actor: String,
+    /// why the change was made, given only for corrections of the death
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// the person as of `revision`, or None if it did not exist (or was deleted) at that point.
+///
+/// `history` must be in the order of the changes.
+pub fn as_of(history: &[PersonHistoryDto], revision: Revision) -> Option<PersonDto> {
+    let last = history.iter().rev().find(|h| h.revision <= revision)?;
+    match last.kind {
+        ChangeKind::Delete | ChangeKind::Purge => None,
+        _ => last.new.clone(),
+    }
+}
+
 impl PersonNotification for PersonDto {
     fn set_name(&mut self, name: &str) {
         trace!("set_name: {}", name);
@@ -65,7 +164,8 @@ impl From<Person> for PersonDto {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::domain::date;
+    use crate::domain::{date, PersonDomainError};
+    use chrono::Local;
 
     #[test]
     fn test_person_dto() {
@@ -114,7 +214,7 @@ mod tests {
             0,
         );
 
-        let person = Person::from(dto.clone());
+        let person = Person::try_from(dto.clone()).unwrap();
         assert_eq!(
             person,
             Person::new(
@@ -140,4 +240,101 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_dto_person_invalid() {
+        // 名前が空白のみ
+        let dto = PersonDto::new("  ", date(2000, 1, 1), None, None, 0);
+        assert_eq!(
+            Person::try_from(dto),
+            Err(PersonDomainError::InvalidFieldValue(
+                "name".into(),
+                "must not be empty".into()
+            ))
+        );
+
+        // 誕生日が未来
+        let tomorrow = Local::now().date_naive().succ_opt().unwrap();
+        let dto = PersonDto::new("name", tomorrow, None, None, 0);
+        assert_eq!(
+            Person::try_from(dto),
+            Err(PersonDomainError::InvalidFieldValue(
+                "birth_date".into(),
+                "must not be in the future".into()
+            ))
+        );
+
+        // 死亡日が誕生日より前
+        let dto = PersonDto::new("name", date(2000, 1, 2), Some(date(2000, 1, 1)), None, 0);
+        assert_eq!(
+            Person::try_from(dto),
+            Err(PersonDomainError::InvalidFieldValue(
+                "death_date".into(),
+                "must be after birth date".into()
+            ))
+        );
+
+        // 誕生日と同日の死亡は許す
+        let dto = PersonDto::new("name", date(2000, 1, 1), Some(date(2000, 1, 1)), None, 0);
+        assert!(Person::try_from(dto).is_ok());
+    }
+
+    fn history(
+        revision: Revision,
+        kind: ChangeKind,
+        old: Option<&PersonDto>,
+        new: Option<&PersonDto>,
+    ) -> PersonHistoryDto {
+        PersonHistoryDto {
+            person_id: 1,
+            revision,
+            kind,
+            old: old.cloned(),
+            new: new.cloned(),
+            changed_at: Utc::now(),
+            actor: "test".to_string(),
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn test_as_of() {
+        let v0 = PersonDto::new("Abel", date(1802, 8, 5), None, None, 0);
+        let v1 = PersonDto::new("Abel", date(1802, 8, 5), Some(date(1829, 4, 6)), None, 1);
+        let v2 = PersonDto::new("Abel", date(1802, 8, 5), Some(date(1829, 4, 6)), None, 2);
+        let v3 = PersonDto::new("Abel", date(1802, 8, 5), Some(date(1829, 4, 6)), None, 3);
+        let hist = vec![
+            history(0, ChangeKind::Insert, None, Some(&v0)),
+            history(1, ChangeKind::Update, Some(&v0), Some(&v1)),
+            history(2, ChangeKind::Delete, Some(&v1), Some(&v2)),
+            history(3, ChangeKind::Restore, Some(&v2), Some(&v3)),
+        ];
+
+        assert_eq!(as_of(&hist, -1), None);
+        assert_eq!(as_of(&hist, 0), Some(v0));
+        assert_eq!(as_of(&hist, 1), Some(v1));
+        // 削除されていた版
+        assert_eq!(as_of(&hist, 2), None);
+        assert_eq!(as_of(&hist, 3), Some(v3.clone()));
+        assert_eq!(as_of(&hist, 99), Some(v3.clone()));
+
+        let mut hist = hist;
+        hist.push(history(3, ChangeKind::Purge, Some(&v3), None));
+        assert_eq!(as_of(&hist, 3), None);
+    }
+
+    #[test]
+    fn test_change_kind() {
+        for kind in [
+            ChangeKind::Insert,
+            ChangeKind::Update,
+            ChangeKind::Delete,
+            ChangeKind::Restore,
+            ChangeKind::Purge,
+        ] {
+            assert_eq!(kind.to_string().parse(), Ok(kind));
+            assert_eq!(serde_json::to_string(&kind).unwrap(), format!("\"{kind}\""));
+        }
+        assert!("upsert".parse::<ChangeKind>().is_err());
+    }
 }
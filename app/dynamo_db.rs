@@ -0,0 +1,813 @@
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::BehaviorVersion;
+use aws_sdk_dynamodb::error::DisplayErrorContext;
+use aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsError;
+use aws_sdk_dynamodb::primitives::Blob;
+use aws_sdk_dynamodb::types::{
+    AttributeValue, Delete, Put, ReturnValue, TransactWriteItem, Update,
+};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use log::{trace, warn};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::future::Future;
+use std::str::{self, FromStr};
+use std::sync::Arc;
+
+use crate::dao::{DaoError, PersonDao};
+use crate::domain::{PersonId, Revision};
+use crate::dto::{ChangeKind, PersonDto, PersonHistoryDto};
+
+const PERSON_TABLE: &str = "person";
+const HISTORY_TABLE: &str = "person_history";
+const SEQUENCE_TABLE: &str = "sequence";
+
+type Item = HashMap<String, AttributeValue>;
+
+/// A blocking client of DynamoDB, the context of `DynamoPersonDao`.
+///
+/// DynamoDB has no transaction spanning several requests, so the Tx runs on the client as is
+/// and each write of the dao is made atomic by itself with a conditional transactional write.
+#[derive(Debug, Clone)]
+pub struct DynamoDbClient {
+    runtime: Arc<tokio::runtime::Runtime>,
+    client: aws_sdk_dynamodb::Client,
+}
+impl DynamoDbClient {
+    /// a client of `endpoint_url`, e.g. `http://localhost:18000` for dynamodb-local.
+    ///
+    /// The credentials and region are read from the environment as the aws cli does.
+    /// The region is `us-east-1` unless given.
+    pub fn open(endpoint_url: &str) -> Self {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        trace!("connecting to dynamodb: {}", endpoint_url);
+        let config = runtime.block_on(
+            aws_config::defaults(BehaviorVersion::latest())
+                .region(RegionProviderChain::default_provider().or_else("us-east-1"))
+                .endpoint_url(endpoint_url)
+                .load(),
+        );
+
+        Self {
+            runtime: Arc::new(runtime),
+            client: aws_sdk_dynamodb::Client::new(&config),
+        }
+    }
+
+    /// a client of `DYNAMODB_ENDPOINT`
+    pub fn from_env() -> Self {
+        let endpoint_url =
+            env::var("DYNAMODB_ENDPOINT").unwrap_or("http://localhost:18000".to_string());
+
+        Self::open(&endpoint_url)
+    }
+
+    fn block_on<F: Future>(&self, f: F) -> F::Output {
+        self.runtime.block_on(f)
+    }
+}
+
+/// `PersonDao` on the `person`, `person_history` and `sequence` tables of DynamoDB.
+///
+/// The ids are allocated by an atomic counter in `sequence`, like a sequence of postgres.
+/// `save`, `delete` and `restore` are conditional on the revision read before,
+/// so a concurrent change makes them fail instead of being overwritten.
+/// `select`, `query`, `search` and `purge` scan the whole table.
+#[derive(Debug, Clone)]
+pub struct DynamoPersonDao {
+    actor: String,
+}
+impl DynamoPersonDao {
+    /// `actor` is recorded in the history as who made the changes.
+    pub fn new(actor: &str) -> Self {
+        Self {
+            actor: actor.to_string(),
+        }
+    }
+
+    // save と correct の共通部分. reason は履歴に残す
+    fn save_with(
+        &self,
+        id: PersonId,
+        revision: Revision,
+        person: PersonDto,
+        reason: Option<String>,
+    ) -> impl tx_rs::Tx<DynamoDbClient, Item = (), Err = DaoError> {
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |db: &mut DynamoDbClient| {
+            db.block_on(async {
+                let Some((_, old)) = current(&db.client, id, false)
+                    .await
+                    .map_err(DaoError::UpdateError)?
+                else {
+                    return Err(DaoError::UpdateError(format!("person not found: {id}")));
+                };
+                if old.revision != revision {
+                    warn!(
+                        "revision conflict on person {}: expected={}, actual={}",
+                        id, revision, old.revision
+                    );
+                    return Err(DaoError::RevisionConflict {
+                        expected: revision,
+                        actual: old.revision,
+                    });
+                }
+
+                // name は range key なので, 変わるときは古い item を消して入れ直す
+                let unchanged = "revision = :revision AND attribute_not_exists(deleted_at)";
+                let mut writes = vec![];
+                let put = Put::builder()
+                    .table_name(PERSON_TABLE)
+                    .set_item(Some(to_item(id, &person)));
+                if old.name == person.name {
+                    writes.push(write(
+                        put.condition_expression(unchanged)
+                            .expression_attribute_values(":revision", number(revision))
+                            .build()
+                            .map_err(|e| DaoError::UpdateError(e.to_string()))?,
+                    ));
+                } else {
+                    let delete = Delete::builder()
+                        .table_name(PERSON_TABLE)
+                        .set_key(Some(key(id, &old.name)))
+                        .condition_expression(unchanged)
+                        .expression_attribute_values(":revision", number(revision))
+                        .build()
+                        .map_err(|e| DaoError::UpdateError(e.to_string()))?;
+                    writes.push(TransactWriteItem::builder().delete(delete).build());
+                    writes.push(write(
+                        put.condition_expression("attribute_not_exists(id)")
+                            .build()
+                            .map_err(|e| DaoError::UpdateError(e.to_string()))?,
+                    ));
+                }
+                writes.push(
+                    record(
+                        &db.client,
+                        id,
+                        ChangeKind::Update,
+                        Some(&old),
+                        Some(&person),
+                        &actor,
+                        reason.as_deref(),
+                    )
+                    .await
+                    .map_err(DaoError::UpdateError)?,
+                );
+
+                if transact(&db.client, writes)
+                    .await
+                    .map_err(DaoError::UpdateError)?
+                {
+                    return Ok(());
+                }
+                // 読んでから書くまでの間に変更された
+                match current(&db.client, id, false)
+                    .await
+                    .map_err(DaoError::UpdateError)?
+                {
+                    Some((_, p)) => {
+                        warn!(
+                            "revision conflict on person {}: expected={}, actual={}",
+                            id, revision, p.revision
+                        );
+                        Err(DaoError::RevisionConflict {
+                            expected: revision,
+                            actual: p.revision,
+                        })
+                    }
+                    None => Err(DaoError::UpdateError(format!("person not found: {id}"))),
+                }
+            })
+        })
+    }
+}
+// query と search は既定の実装 (select して絞り込む) のまま. DynamoDB では scan になる
+impl PersonDao<DynamoDbClient> for DynamoPersonDao {
+    fn insert(
+        &self,
+        person: PersonDto,
+    ) -> impl tx_rs::Tx<DynamoDbClient, Item = PersonId, Err = DaoError> {
+        trace!("inserting person: {:?}", person);
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |db: &mut DynamoDbClient| {
+            db.block_on(async {
+                let id = next_id(&db.client, PERSON_TABLE)
+                    .await
+                    .map_err(DaoError::InsertError)?;
+                let put = Put::builder()
+                    .table_name(PERSON_TABLE)
+                    .set_item(Some(to_item(id, &person)))
+                    .condition_expression("attribute_not_exists(id)")
+                    .build()
+                    .map_err(|e| DaoError::InsertError(e.to_string()))?;
+                let history = record(
+                    &db.client,
+                    id,
+                    ChangeKind::Insert,
+                    None,
+                    Some(&person),
+                    &actor,
+                    None,
+                )
+                .await
+                .map_err(DaoError::InsertError)?;
+
+                if !transact(&db.client, vec![write(put), history])
+                    .await
+                    .map_err(DaoError::InsertError)?
+                {
+                    return Err(DaoError::InsertError(format!("person exists: {id}")));
+                }
+
+                Ok(id)
+            })
+        })
+    }
+    fn fetch(
+        &self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<DynamoDbClient, Item = Option<PersonDto>, Err = DaoError> {
+        trace!("fetching person: {:?}", id);
+        tx_rs::with_tx(move |db: &mut DynamoDbClient| {
+            db.block_on(current(&db.client, id, false))
+                .map(|p| p.map(|(_, p)| p))
+                .map_err(DaoError::SelectError)
+        })
+    }
+    fn select(
+        &self,
+    ) -> impl tx_rs::Tx<DynamoDbClient, Item = Vec<(PersonId, PersonDto)>, Err = DaoError> {
+        trace!("selecting all persons");
+        tx_rs::with_tx(move |db: &mut DynamoDbClient| {
+            let items = db
+                .block_on(scan(
+                    &db.client,
+                    "attribute_not_exists(deleted_at)",
+                    HashMap::new(),
+                ))
+                .map_err(DaoError::SelectError)?;
+            let mut persons = items
+                .iter()
+                .map(from_item)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(DaoError::SelectError)?;
+            // scan の順は不定なので postgres 版に合わせて id 順にする
+            persons.sort_by_key(|(id, _)| *id);
+
+            Ok(persons)
+        })
+    }
+    fn save(
+        &self,
+        id: PersonId,
+        revision: Revision,
+        person: PersonDto,
+    ) -> impl tx_rs::Tx<DynamoDbClient, Item = (), Err = DaoError> {
+        trace!("saving person: {:?}", id);
+        self.save_with(id, revision, person, None)
+    }
+    fn correct(
+        &self,
+        id: PersonId,
+        revision: Revision,
+        person: PersonDto,
+        reason: String,
+    ) -> impl tx_rs::Tx<DynamoDbClient, Item = (), Err = DaoError> {
+        trace!("correcting person: {:?}", id);
+        self.save_with(id, revision, person, Some(reason))
+    }
+    fn delete(
+        &self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<DynamoDbClient, Item = Option<Revision>, Err = DaoError> {
+        trace!("deleting person: {:?}", id);
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |db: &mut DynamoDbClient| {
+            db.block_on(toggle(
+                &db.client,
+                id,
+                ChangeKind::Delete,
+                "SET deleted_at = :now, revision = :new",
+                &actor,
+            ))
+            .map_err(DaoError::DeleteError)
+        })
+    }
+    fn restore(
+        &self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<DynamoDbClient, Item = Option<Revision>, Err = DaoError> {
+        trace!("restoring person: {:?}", id);
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |db: &mut DynamoDbClient| {
+            db.block_on(toggle(
+                &db.client,
+                id,
+                ChangeKind::Restore,
+                "SET revision = :new REMOVE deleted_at",
+                &actor,
+            ))
+            .map_err(DaoError::RestoreError)
+        })
+    }
+    fn purge(
+        &self,
+        before: DateTime<Utc>,
+    ) -> impl tx_rs::Tx<DynamoDbClient, Item = u64, Err = DaoError> {
+        trace!("purging persons deleted before: {:?}", before);
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |db: &mut DynamoDbClient| {
+            db.block_on(async {
+                let deleted_before = "deleted_at < :before";
+                let values = HashMap::from([(":before".to_string(), timestamp(before))]);
+                let items = scan(&db.client, deleted_before, values.clone())
+                    .await
+                    .map_err(DaoError::PurgeError)?;
+
+                let mut purged = 0;
+                for item in items {
+                    let (id, person) = from_item(&item).map_err(DaoError::PurgeError)?;
+                    let delete = Delete::builder()
+                        .table_name(PERSON_TABLE)
+                        .set_key(Some(key(id, &person.name)))
+                        .condition_expression(deleted_before)
+                        .set_expression_attribute_values(Some(values.clone()))
+                        .build()
+                        .map_err(|e| DaoError::PurgeError(e.to_string()))?;
+                    let history = record(
+                        &db.client,
+                        id,
+                        ChangeKind::Purge,
+                        Some(&person),
+                        None,
+                        &actor,
+                        None,
+                    )
+                    .await
+                    .map_err(DaoError::PurgeError)?;
+
+                    // scan の後で復元されたものは残す
+                    let delete = TransactWriteItem::builder().delete(delete).build();
+                    if transact(&db.client, vec![delete, history])
+                        .await
+                        .map_err(DaoError::PurgeError)?
+                    {
+                        purged += 1;
+                    }
+                }
+
+                Ok(purged)
+            })
+        })
+    }
+    fn history(
+        &self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<DynamoDbClient, Item = Vec<PersonHistoryDto>, Err = DaoError> {
+        trace!("fetching history of person: {:?}", id);
+        tx_rs::with_tx(move |db: &mut DynamoDbClient| {
+            db.block_on(async {
+                let mut items = vec![];
+                let mut start = None;
+                loop {
+                    let out = db
+                        .client
+                        .query()
+                        .table_name(HISTORY_TABLE)
+                        .key_condition_expression("person_id = :id")
+                        .expression_attribute_values(":id", number(id))
+                        .consistent_read(true)
+                        .set_exclusive_start_key(start)
+                        .send()
+                        .await
+                        .map_err(|e| DaoError::SelectError(DisplayErrorContext(e).to_string()))?;
+                    items.extend(out.items().iter().cloned());
+                    start = out.last_evaluated_key().cloned();
+                    if start.is_none() {
+                        break;
+                    }
+                }
+
+                // range key が履歴の id なので変更順に並んでいる
+                items
+                    .iter()
+                    .map(from_history_item)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(DaoError::SelectError)
+            })
+        })
+    }
+}
+
+// sequence テーブルのカウンタを原子的に進めて, 進めた後の値を返す
+async fn next_id<T>(client: &aws_sdk_dynamodb::Client, name: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let out = client
+        .update_item()
+        .table_name(SEQUENCE_TABLE)
+        .key("name", AttributeValue::S(name.to_string()))
+        .update_expression("ADD next_id :one")
+        .expression_attribute_values(":one", number(1))
+        .return_values(ReturnValue::UpdatedNew)
+        .send()
+        .await
+        .map_err(|e| DisplayErrorContext(e).to_string())?;
+
+    out.attributes()
+        .and_then(|attrs| attrs.get("next_id"))
+        .ok_or(format!("no next_id of {name}"))
+        .and_then(parse_number)
+}
+
+// name は range key なので, id だけで引くには query になる
+async fn current(
+    client: &aws_sdk_dynamodb::Client,
+    id: PersonId,
+    deleted: bool,
+) -> Result<Option<(PersonId, PersonDto)>, String> {
+    let filter = if deleted {
+        "attribute_exists(deleted_at)"
+    } else {
+        "attribute_not_exists(deleted_at)"
+    };
+    let out = client
+        .query()
+        .table_name(PERSON_TABLE)
+        .key_condition_expression("id = :id")
+        .filter_expression(filter)
+        .expression_attribute_values(":id", number(id))
+        .consistent_read(true)
+        .send()
+        .await
+        .map_err(|e| DisplayErrorContext(e).to_string())?;
+
+    out.items().first().map(from_item).transpose()
+}
+
+async fn scan(
+    client: &aws_sdk_dynamodb::Client,
+    filter: &str,
+    values: Item,
+) -> Result<Vec<Item>, String> {
+    let values = (!values.is_empty()).then_some(values);
+    let mut items = vec![];
+    let mut start = None;
+    loop {
+        let out = client
+            .scan()
+            .table_name(PERSON_TABLE)
+            .filter_expression(filter)
+            .set_expression_attribute_values(values.clone())
+            .consistent_read(true)
+            .set_exclusive_start_key(start)
+            .send()
+            .await
+            .map_err(|e| DisplayErrorContext(e).to_string())?;
+        items.extend(out.items().iter().cloned());
+        start = out.last_evaluated_key().cloned();
+        if start.is_none() {
+            return Ok(items);
+        }
+    }
+}
+
+// 条件付きの書き込みが通らずに取り消されたら Ok(false)
+async fn transact(
+    client: &aws_sdk_dynamodb::Client,
+    writes: Vec<TransactWriteItem>,
+) -> Result<bool, String> {
+    match client
+        .transact_write_items()
+        .set_transact_items(Some(writes))
+        .send()
+        .await
+    {
+        Ok(_) => Ok(true),
+        Err(e)
+            if matches!(
+                e.as_service_error(),
+                Some(TransactWriteItemsError::TransactionCanceledException(_))
+            ) =>
+        {
+            trace!("transaction canceled: {}", DisplayErrorContext(&e));
+            Ok(false)
+        }
+        Err(e) => Err(DisplayErrorContext(e).to_string()),
+    }
+}
+
+// 削除と復元は deleted_at と版だけが変わるので, 変更前は版を戻したもの
+async fn toggle(
+    client: &aws_sdk_dynamodb::Client,
+    id: PersonId,
+    kind: ChangeKind,
+    update: &str,
+    actor: &str,
+) -> Result<Option<Revision>, String> {
+    let deleted = kind == ChangeKind::Restore;
+    let Some((_, old)) = current(client, id, deleted).await? else {
+        return Ok(None);
+    };
+    let new = PersonDto {
+        revision: old.revision + 1,
+        ..old.clone()
+    };
+
+    let state = if deleted {
+        "attribute_exists(deleted_at)"
+    } else {
+        "attribute_not_exists(deleted_at)"
+    };
+    let mut values = HashMap::from([
+        (":old".to_string(), number(old.revision)),
+        (":new".to_string(), number(new.revision)),
+    ]);
+    if !deleted {
+        values.insert(":now".to_string(), timestamp(Utc::now()));
+    }
+    let toggle = Update::builder()
+        .table_name(PERSON_TABLE)
+        .set_key(Some(key(id, &old.name)))
+        .update_expression(update)
+        .condition_expression(format!("revision = :old AND {state}"))
+        .set_expression_attribute_values(Some(values))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let history = record(client, id, kind, Some(&old), Some(&new), actor, None).await?;
+
+    let toggle = TransactWriteItem::builder().update(toggle).build();
+    if transact(client, vec![toggle, history]).await? {
+        Ok(Some(new.revision))
+    } else {
+        Err(format!("person changed concurrently: {id}"))
+    }
+}
+
+// 履歴は追記のみ. 元の書き込みと同じ transact_write_items に入れるので, どちらかだけが残ることはない
+async fn record(
+    client: &aws_sdk_dynamodb::Client,
+    id: PersonId,
+    kind: ChangeKind,
+    old: Option<&PersonDto>,
+    new: Option<&PersonDto>,
+    actor: &str,
+    reason: Option<&str>,
+) -> Result<TransactWriteItem, String> {
+    let history_id: i64 = next_id(client, HISTORY_TABLE).await?;
+    let history = PersonHistoryDto {
+        person_id: id,
+        revision: new.or(old).map(|p| p.revision).unwrap_or_default(),
+        kind,
+        old: old.cloned(),
+        new: new.cloned(),
+        changed_at: Utc::now(),
+        actor: actor.to_string(),
+        reason: reason.map(str::to_string),
+    };
+
+    Put::builder()
+        .table_name(HISTORY_TABLE)
+        .set_item(Some(to_history_item(history_id, &history)?))
+        .build()
+        .map(write)
+        .map_err(|e| e.to_string())
+}
+
+fn write(put: Put) -> TransactWriteItem {
+    TransactWriteItem::builder().put(put).build()
+}
+
+// 日付は README の例と同じく epoch 秒で持つ. UTC の 0 時とする
+fn date_value(date: NaiveDate) -> AttributeValue {
+    number(date.and_time(NaiveTime::MIN).and_utc().timestamp())
+}
+
+fn parse_date(v: &AttributeValue) -> Result<NaiveDate, String> {
+    let secs = parse_number::<i64>(v)?;
+    DateTime::from_timestamp(secs, 0)
+        .map(|t| t.date_naive())
+        .ok_or(format!("invalid date: {secs}"))
+}
+
+// deleted_at はミリ秒の epoch. 数値なので purge で大小比較できる
+fn timestamp(t: DateTime<Utc>) -> AttributeValue {
+    number(t.timestamp_millis())
+}
+
+fn number(n: impl fmt::Display) -> AttributeValue {
+    AttributeValue::N(n.to_string())
+}
+
+fn parse_number<T>(v: &AttributeValue) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    v.as_n()
+        .map_err(|v| format!("not a number: {v:?}"))?
+        .parse()
+        .map_err(|e: T::Err| e.to_string())
+}
+
+fn attr<'a>(item: &'a Item, name: &str) -> Result<&'a AttributeValue, String> {
+    item.get(name).ok_or(format!("missing attribute: {name}"))
+}
+
+fn string<'a>(item: &'a Item, name: &str) -> Result<&'a str, String> {
+    attr(item, name)?
+        .as_s()
+        .map(|s| s.as_str())
+        .map_err(|v| format!("not a string: {v:?}"))
+}
+
+fn key(id: PersonId, name: &str) -> Item {
+    HashMap::from([
+        ("id".to_string(), number(id)),
+        ("name".to_string(), AttributeValue::S(name.to_string())),
+    ])
+}
+
+fn to_item(id: PersonId, person: &PersonDto) -> Item {
+    let mut item = key(id, &person.name);
+    item.insert("birth_date".to_string(), date_value(person.birth_date));
+    if let Some(death_date) = person.death_date {
+        item.insert("death_date".to_string(), date_value(death_date));
+    }
+    if let Some(data) = &person.data {
+        item.insert(
+            "data".to_string(),
+            AttributeValue::B(Blob::new(data.as_bytes())),
+        );
+    }
+    item.insert("revision".to_string(), number(person.revision));
+
+    item
+}
+
+fn from_item(item: &Item) -> Result<(PersonId, PersonDto), String> {
+    let id = parse_number(attr(item, "id")?)?;
+    let name = string(item, "name")?;
+    let birth_date = parse_date(attr(item, "birth_date")?)?;
+    let death_date = item.get("death_date").map(parse_date).transpose()?;
+    let data = item
+        .get("data")
+        .and_then(|v| v.as_b().ok())
+        .and_then(|b| str::from_utf8(b.as_ref()).ok());
+    let revision = parse_number(attr(item, "revision")?)?;
+
+    Ok((
+        id,
+        PersonDto::new(name, birth_date, death_date, data, revision),
+    ))
+}
+
+// 変更前後の値は postgres 版と同じく JSON で持つ
+fn to_history_item(history_id: i64, history: &PersonHistoryDto) -> Result<Item, String> {
+    let mut item = HashMap::from([
+        ("person_id".to_string(), number(history.person_id)),
+        ("id".to_string(), number(history_id)),
+        ("revision".to_string(), number(history.revision)),
+        (
+            "kind".to_string(),
+            AttributeValue::S(history.kind.to_string()),
+        ),
+        (
+            "changed_at".to_string(),
+            AttributeValue::S(history.changed_at.to_rfc3339()),
+        ),
+        (
+            "actor".to_string(),
+            AttributeValue::S(history.actor.clone()),
+        ),
+    ]);
+    for (name, value) in [("old_value", &history.old), ("new_value", &history.new)] {
+        if let Some(p) = value {
+            let json = serde_json::to_string(p).map_err(|e| e.to_string())?;
+            item.insert(name.to_string(), AttributeValue::S(json));
+        }
+    }
+    if let Some(reason) = &history.reason {
+        item.insert("reason".to_string(), AttributeValue::S(reason.clone()));
+    }
+
+    Ok(item)
+}
+
+fn from_history_item(item: &Item) -> Result<PersonHistoryDto, String> {
+    let value = |name: &str| -> Result<Option<PersonDto>, String> {
+        item.get(name)
+            .map(|_| string(item, name))
+            .transpose()?
+            .map(serde_json::from_str)
+            .transpose()
+            .map_err(|e| e.to_string())
+    };
+
+    Ok(PersonHistoryDto {
+        person_id: parse_number(attr(item, "person_id")?)?,
+        revision: parse_number(attr(item, "revision")?)?,
+        kind: string(item, "kind")?.parse()?,
+        old: value("old_value")?,
+        new: value("new_value")?,
+        changed_at: DateTime::parse_from_rfc3339(string(item, "changed_at")?)
+            .map_err(|e| e.to_string())?
+            .with_timezone(&Utc),
+        actor: string(item, "actor")?.to_string(),
+        reason: item
+            .get("reason")
+            .map(|_| string(item, "reason").map(str::to_string))
+            .transpose()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::date;
+
+    #[test]
+    fn test_item() {
+        let person = PersonDto::new(
+            "Abel",
+            date(1802, 8, 5),
+            Some(date(1829, 4, 6)),
+            Some("Abel's theorem"),
+            3,
+        );
+        let item = to_item(10001, &person);
+
+        assert_eq!(item.get("id"), Some(&number(10001)));
+        assert_eq!(
+            item.get("name"),
+            Some(&AttributeValue::S("Abel".to_string()))
+        );
+        // README の例と同じ epoch 秒
+        assert_eq!(item.get("birth_date"), Some(&number(-5282928000_i64)));
+        assert_eq!(from_item(&item), Ok((10001, person)));
+    }
+
+    #[test]
+    fn test_item_without_optionals() {
+        let person = PersonDto::new("Galois", date(1811, 10, 25), None, None, 0);
+        let item = to_item(13, &person);
+
+        assert!(!item.contains_key("death_date"));
+        assert!(!item.contains_key("data"));
+        assert_eq!(from_item(&item), Ok((13, person)));
+    }
+
+    #[test]
+    fn test_item_missing_attribute() {
+        let mut item = to_item(
+            13,
+            &PersonDto::new("Galois", date(1811, 10, 25), None, None, 0),
+        );
+        item.remove("revision");
+
+        assert_eq!(
+            from_item(&item),
+            Err("missing attribute: revision".to_string())
+        );
+    }
+
+    #[test]
+    fn test_history_item() {
+        let old = PersonDto::new("Abel", date(1802, 8, 5), None, None, 0);
+        let new = PersonDto::new("Abel", date(1802, 8, 5), Some(date(1829, 4, 6)), None, 1);
+        let history = PersonHistoryDto {
+            person_id: 10001,
+            revision: 1,
+            kind: ChangeKind::Update,
+            old: Some(old),
+            new: Some(new),
+            changed_at: Utc::now(),
+            actor: "test".to_string(),
+            reason: Some("found the record".to_string()),
+        };
+        let item = to_history_item(42, &history).unwrap();
+
+        assert_eq!(item.get("id"), Some(&number(42)));
+        assert_eq!(from_history_item(&item), Ok(history.clone()));
+
+        // purge は変更後の値を持たない
+        let purged = PersonHistoryDto {
+            kind: ChangeKind::Purge,
+            new: None,
+            reason: None,
+            ..history
+        };
+        let item = to_history_item(43, &purged).unwrap();
+        assert!(!item.contains_key("new_value"));
+        assert!(!item.contains_key("reason"));
+        assert_eq!(from_history_item(&item), Ok(purged));
+    }
+}
@@ -0,0 +1,179 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::domain::{PersonId, Revision};
+
+/// version of the JSON schema of the published events.
+///
+/// Bump it on an incompatible change, so that consumers can tell the old payloads from the new.
+pub const EVENT_VERSION: u32 = 1;
+
+/// What happened to persons, published to the queue of `queue()` once committed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PersonEvent {
+    Registered {
+        id: PersonId,
+        revision: Revision,
+        birth_date: NaiveDate,
+        death_date: Option<NaiveDate>,
+    },
+    Imported {
+        ids: Vec<PersonId>,
+    },
+    Died {
+        id: PersonId,
+        revision: Revision,
+        death_date: NaiveDate,
+    },
+    DeathCorrected {
+        id: PersonId,
+        revision: Revision,
+        death_date: Option<NaiveDate>,
+        reason: String,
+    },
+    Updated {
+        id: PersonId,
+        revision: Revision,
+    },
+    Unregistered {
+        id: PersonId,
+        revision: Revision,
+    },
+    Restored {
+        id: PersonId,
+        revision: Revision,
+    },
+    Purged {
+        count: u64,
+        before: DateTime<Utc>,
+    },
+}
+impl PersonEvent {
+    /// the queue to publish to
+    pub fn queue(&self) -> &'static str {
+        match self {
+            PersonEvent::Registered { .. } | PersonEvent::Imported { .. } => "entry_person",
+            PersonEvent::Died { .. } => "death_person",
+            // 訂正の記録は専用のキューに残す
+            PersonEvent::DeathCorrected { .. } => "correct_death_person",
+            PersonEvent::Updated { .. } => "update_person",
+            PersonEvent::Unregistered { .. } => "unregister_person",
+            PersonEvent::Restored { .. } => "restore_person",
+            PersonEvent::Purged { .. } => "purge_person",
+        }
+    }
+}
+// 人が読むためのもの. 機械的に読むなら JSON を使うこと
+impl fmt::Display for PersonEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PersonEvent::Registered { id, .. } => write!(f, "registered person_id: {}", id),
+            PersonEvent::Imported { ids } => write!(f, "imported person_ids: {:?}", ids),
+            PersonEvent::Died { id, death_date, .. } => {
+                write!(f, "death person_id: {}, death_date: {}", id, death_date)
+            }
+            PersonEvent::DeathCorrected {
+                id,
+                death_date,
+                reason,
+                ..
+            } => write!(
+                f,
+                "corrected death person_id: {}, death_date: {:?}, reason: {}",
+                id, death_date, reason
+            ),
+            PersonEvent::Updated { id, revision } => {
+                write!(f, "updated person_id: {}, revision: {}", id, revision)
+            }
+            PersonEvent::Unregistered { id, .. } => write!(f, "unregistered person_id: {}", id),
+            PersonEvent::Restored { id, revision } => {
+                write!(f, "restored person_id: {}, revision: {}", id, revision)
+            }
+            PersonEvent::Purged { count, before } => {
+                write!(f, "purged {} persons deleted before: {}", count, before)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::date;
+    use serde_json::json;
+
+    #[test]
+    fn test_serialize() {
+        let event = PersonEvent::Registered {
+            id: 1,
+            revision: 0,
+            birth_date: date(1802, 8, 5),
+            death_date: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({
+                "type": "registered",
+                "id": 1,
+                "revision": 0,
+                "birth_date": "1802-08-05",
+                "death_date": null,
+            })
+        );
+
+        let event = PersonEvent::DeathCorrected {
+            id: 1,
+            revision: 3,
+            death_date: Some(date(1829, 4, 6)),
+            reason: "typo".to_string(),
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(json.contains(r#""type":"death_corrected""#));
+        assert_eq!(serde_json::from_str::<PersonEvent>(&json).unwrap(), event);
+
+        let event = PersonEvent::Unregistered { id: 1, revision: 4 };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({
+                "type": "unregistered",
+                "id": 1,
+                "revision": 4,
+            })
+        );
+    }
+
+    #[test]
+    fn test_queue() {
+        assert_eq!(
+            PersonEvent::Imported { ids: vec![] }.queue(),
+            "entry_person"
+        );
+        assert_eq!(
+            PersonEvent::Died {
+                id: 1,
+                revision: 1,
+                death_date: date(1829, 4, 6)
+            }
+            .queue(),
+            "death_person"
+        );
+        assert_eq!(
+            PersonEvent::Unregistered { id: 1, revision: 2 }.queue(),
+            "unregister_person"
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            PersonEvent::Updated { id: 1, revision: 2 }.to_string(),
+            "updated person_id: 1, revision: 2"
+        );
+        assert_eq!(
+            PersonEvent::Imported { ids: vec![1, 2] }.to_string(),
+            "imported person_ids: [1, 2]"
+        );
+    }
+}
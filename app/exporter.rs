@@ -0,0 +1,436 @@
+use chrono::NaiveDate;
+use log::trace;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use thiserror::Error;
+
+use crate::domain::{PersonId, Revision};
+use crate::dto::PersonDto;
+use crate::service::{PersonService, ServiceError};
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ExportError {
+    #[error("unsupported format: {0}")]
+    UnsupportedFormat(String),
+    #[error("cannot write output: {0}")]
+    Unwritable(String),
+    #[error("export failed: {0}")]
+    ServiceFailed(ServiceError),
+}
+impl From<std::io::Error> for ExportError {
+    fn from(e: std::io::Error) -> Self {
+        ExportError::Unwritable(e.to_string())
+    }
+}
+impl From<csv::Error> for ExportError {
+    fn from(e: csv::Error) -> Self {
+        ExportError::Unwritable(e.to_string())
+    }
+}
+impl From<serde_json::Error> for ExportError {
+    fn from(e: serde_json::Error) -> Self {
+        ExportError::Unwritable(e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Ndjson,
+    Json,
+}
+impl Format {
+    pub fn from_path(path: &Path) -> Result<Self, ExportError> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => Ok(Format::Csv),
+            Some("ndjson") | Some("jsonl") => Ok(Format::Ndjson),
+            Some("json") => Ok(Format::Json),
+            _ => Err(ExportError::UnsupportedFormat(path.display().to_string())),
+        }
+    }
+}
+
+// importer が読む name,birth_date,death_date,data に id と revision を加えたもの
+const CSV_HEADER: [&str; 6] = ["id", "name", "birth_date", "death_date", "data", "revision"];
+
+#[derive(Debug, Serialize)]
+struct PersonRecord<'p> {
+    id: PersonId,
+    name: &'p str,
+    birth_date: NaiveDate,
+    death_date: Option<NaiveDate>,
+    data: Option<&'p str>,
+    revision: Revision,
+}
+impl<'p> PersonRecord<'p> {
+    fn new(id: PersonId, person: &'p PersonDto) -> Self {
+        Self {
+            id,
+            name: &person.name,
+            birth_date: person.birth_date,
+            death_date: person.death_date,
+            data: person.data.as_deref(),
+            revision: person.revision,
+        }
+    }
+}
+
+enum Sink<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    Ndjson(W),
+    Json(W),
+}
+
+/// writes persons one by one, so that the whole output is never held in memory.
+pub struct PersonWriter<W: Write> {
+    sink: Sink<W>,
+    count: usize,
+}
+impl<W: Write> PersonWriter<W> {
+    pub fn new(format: Format, out: W) -> Result<Self, ExportError> {
+        let sink = match format {
+            Format::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(out);
+                // 0 件でもヘッダは出す
+                writer.write_record(CSV_HEADER)?;
+                Sink::Csv(Box::new(writer))
+            }
+            Format::Ndjson => Sink::Ndjson(out),
+            Format::Json => {
+                let mut out = out;
+                out.write_all(b"[")?;
+                Sink::Json(out)
+            }
+        };
+
+        Ok(Self { sink, count: 0 })
+    }
+
+    pub fn write(&mut self, id: PersonId, person: &PersonDto) -> Result<(), ExportError> {
+        let record = PersonRecord::new(id, person);
+        match &mut self.sink {
+            Sink::Csv(writer) => writer.serialize(record)?,
+            Sink::Ndjson(out) => {
+                serde_json::to_writer(&mut *out, &record)?;
+                out.write_all(b"\n")?;
+            }
+            Sink::Json(out) => {
+                out.write_all(if self.count == 0 { b"\n" } else { b",\n" })?;
+                serde_json::to_writer(&mut *out, &record)?;
+            }
+        }
+        self.count += 1;
+
+        Ok(())
+    }
+
+    /// finish the document and return the number of written persons
+    pub fn finish(self) -> Result<usize, ExportError> {
+        match self.sink {
+            Sink::Csv(mut writer) => writer.flush()?,
+            Sink::Ndjson(mut out) => out.flush()?,
+            Sink::Json(mut out) => {
+                out.write_all(if self.count == 0 { b"]\n" } else { b"\n]\n" })?;
+                out.flush()?;
+            }
+        }
+
+        Ok(self.count)
+    }
+}
+
+pub fn write_persons<W: Write>(
+    format: Format,
+    out: W,
+    persons: impl IntoIterator<Item = (PersonId, PersonDto)>,
+) -> Result<usize, ExportError> {
+    let mut writer = PersonWriter::new(format, out)?;
+    for (id, person) in persons {
+        writer.write(id, &person)?;
+    }
+    writer.finish()
+}
+
+/// export all persons.
+///
+/// The output is readable by the import path: CSV and NDJSON by `importer`,
+/// and JSON as an array of persons. `id` and `revision` are ignored on import.
+pub fn export<'a, Ctx, S, W>(
+    service: &'a mut S,
+    format: Format,
+    out: W,
+) -> Result<usize, ExportError>
+where
+    S: PersonService<'a, Ctx>,
+    W: Write,
+{
+    trace!("export persons: {:?}", format);
+    let persons = service.list_all().map_err(ExportError::ServiceFailed)?;
+
+    write_persons(format, out, persons)
+}
+
+/// export to a `.csv`, `.ndjson` (`.jsonl`) or `.json` file
+pub fn export_file<'a, Ctx, S>(service: &'a mut S, path: &Path) -> Result<usize, ExportError>
+where
+    S: PersonService<'a, Ctx>,
+{
+    let format = Format::from_path(path)?;
+    let out = BufWriter::new(File::create(path)?);
+
+    export(service, format, out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dao::{DaoError, HavePersonDao, PersonDao},
+        domain::date,
+        dto::PersonHistoryDto,
+        importer,
+        reporter::{Level, Location, Reporter, ReporterError},
+        rest::PersonRequest,
+        usecase::{PersonUsecase, UsecaseError},
+    };
+    use chrono::{DateTime, Utc};
+
+    fn persons() -> Vec<(PersonId, PersonDto)> {
+        vec![
+            (
+                1,
+                PersonDto::new(
+                    "Abel",
+                    date(1802, 8, 5),
+                    Some(date(1829, 4, 6)),
+                    Some("Abel's theorem, \"quintic\""),
+                    2,
+                ),
+            ),
+            (
+                3,
+                PersonDto::new("Galois", date(1811, 10, 25), None, None, 1),
+            ),
+        ]
+    }
+
+    // import 側では id と revision は捨てられる
+    fn imported() -> Vec<PersonDto> {
+        persons()
+            .into_iter()
+            .map(|(_, p)| PersonDto { revision: 0, ..p })
+            .collect()
+    }
+
+    fn write(format: Format, persons: Vec<(PersonId, PersonDto)>) -> String {
+        let mut buf = vec![];
+        write_persons(format, &mut buf, persons).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_write_csv() {
+        assert_eq!(
+            write(Format::Csv, persons()),
+            "\
+id,name,birth_date,death_date,data,revision
+1,Abel,1802-08-05,1829-04-06,\"Abel's theorem, \"\"quintic\"\"\",2
+3,Galois,1811-10-25,,,1
+"
+        );
+        assert_eq!(
+            write(Format::Csv, vec![]),
+            "id,name,birth_date,death_date,data,revision\n"
+        );
+    }
+
+    #[test]
+    fn test_write_json() {
+        let json = write(Format::Json, persons());
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value[1]["id"], 3);
+        assert_eq!(value[1]["death_date"], serde_json::Value::Null);
+
+        assert_eq!(write(Format::Json, vec![]), "[]\n");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for format in [Format::Csv, Format::Ndjson] {
+            let out = write(format, persons());
+            let import_format = match format {
+                Format::Csv => importer::Format::Csv,
+                _ => importer::Format::Ndjson,
+            };
+            let result =
+                importer::rows(import_format, out.as_bytes()).collect::<Result<Vec<_>, _>>();
+
+            assert_eq!(result, Ok(imported()), "{:?}", format);
+        }
+
+        let out = write(Format::Json, persons());
+        let result = serde_json::from_str::<Vec<PersonRequest>>(&out)
+            .unwrap()
+            .into_iter()
+            .map(PersonDto::from)
+            .collect::<Vec<_>>();
+        // REST から入れ直すと data のない人は空文字列になる
+        let expected = imported()
+            .into_iter()
+            .map(|p| PersonDto {
+                data: p.data.or_else(|| Some(String::new())),
+                ..p
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(Format::from_path(Path::new("a.csv")), Ok(Format::Csv));
+        assert_eq!(Format::from_path(Path::new("a.jsonl")), Ok(Format::Ndjson));
+        assert_eq!(Format::from_path(Path::new("a.json")), Ok(Format::Json));
+        assert!(Format::from_path(Path::new("a")).is_err());
+    }
+
+    struct StubPersonDao {
+        select_result: Result<Vec<(PersonId, PersonDto)>, DaoError>,
+    }
+    impl PersonDao<()> for StubPersonDao {
+        fn insert(
+            &self,
+            _person: PersonDto,
+        ) -> impl tx_rs::Tx<(), Item = PersonId, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(1))
+        }
+        fn fetch(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<PersonDto>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(None))
+        }
+        fn select(&self) -> impl tx_rs::Tx<(), Item = Vec<(PersonId, PersonDto)>, Err = DaoError> {
+            let result = self.select_result.clone();
+            tx_rs::with_tx(move |&mut ()| result)
+        }
+        fn save(
+            &self,
+            _id: PersonId,
+            _revision: Revision,
+            _person: PersonDto,
+        ) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(()))
+        }
+        fn delete(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(None))
+        }
+        fn restore(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(None))
+        }
+        fn purge(&self, _before: DateTime<Utc>) -> impl tx_rs::Tx<(), Item = u64, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(0))
+        }
+        fn history(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Vec<PersonHistoryDto>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(vec![]))
+        }
+    }
+
+    struct DummyPersonUsecase {
+        dao: StubPersonDao,
+    }
+    impl HavePersonDao<()> for DummyPersonUsecase {
+        fn get_dao(&self) -> &impl PersonDao<()> {
+            &self.dao
+        }
+    }
+    impl PersonUsecase<()> for DummyPersonUsecase {}
+
+    struct DummyReporter;
+    impl Reporter<'_> for DummyReporter {
+        fn register(
+            &mut self,
+            _observer: impl crate::reporter::Observer,
+        ) -> Result<(), ReporterError> {
+            Ok(())
+        }
+        fn get_observers(&self) -> Vec<&dyn crate::reporter::Observer> {
+            vec![]
+        }
+        fn send_report(
+            &self,
+            _level: Level,
+            _to: &str,
+            _message: &str,
+            _loc: Location,
+        ) -> Result<(), ReporterError> {
+            Ok(())
+        }
+    }
+
+    struct TargetPersonService {
+        usecase: DummyPersonUsecase,
+    }
+    impl PersonService<'_, ()> for TargetPersonService {
+        type U = DummyPersonUsecase;
+        type N = DummyReporter;
+
+        fn run_tx<T, F>(&mut self, f: F) -> Result<T, ServiceError>
+        where
+            F: FnOnce(&mut Self::U, &mut ()) -> Result<T, UsecaseError>,
+        {
+            f(&mut self.usecase, &mut ()).map_err(ServiceError::from)
+        }
+
+        fn get_reporter(&self) -> Self::N {
+            DummyReporter
+        }
+    }
+
+    #[test]
+    fn test_export() {
+        let mut service = TargetPersonService {
+            usecase: DummyPersonUsecase {
+                dao: StubPersonDao {
+                    select_result: Ok(persons()),
+                },
+            },
+        };
+        let mut buf = vec![];
+
+        let result = export(&mut service, Format::Ndjson, &mut buf);
+
+        assert_eq!(result, Ok(2));
+        assert_eq!(String::from_utf8(buf).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn test_export_failed() {
+        let mut service = TargetPersonService {
+            usecase: DummyPersonUsecase {
+                dao: StubPersonDao {
+                    select_result: Err(DaoError::SelectError("valid dao".to_string())),
+                },
+            },
+        };
+        let mut buf = vec![];
+
+        let result = export(&mut service, Format::Csv, &mut buf);
+
+        assert!(matches!(result, Err(ExportError::ServiceFailed(_))));
+        // 読み出しに失敗したら何も書かない
+        assert!(buf.is_empty());
+    }
+}
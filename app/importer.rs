@@ -0,0 +1,619 @@
+use chrono::NaiveDate;
+use itertools::Itertools;
+use log::{trace, warn};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::rc::Rc;
+use thiserror::Error;
+
+use crate::domain::{Person, PersonId};
+use crate::dto::PersonDto;
+use crate::service::{InvalidErrorKind, PersonOutputBoundary, PersonService, ServiceError};
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("line {line}: {reason}")]
+pub struct RowError {
+    pub line: u64,
+    pub reason: String,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ImportError {
+    #[error("unsupported format: {0}")]
+    UnsupportedFormat(String),
+    #[error("cannot read input: {0}")]
+    Unreadable(String),
+    #[error("invalid rows: {}", .0.iter().join(", "))]
+    InvalidRows(Vec<RowError>),
+    #[error("import failed: {0}")]
+    ServiceFailed(ServiceError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Ndjson,
+}
+impl Format {
+    pub fn from_path(path: &Path) -> Result<Self, ImportError> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => Ok(Format::Csv),
+            Some("ndjson") | Some("jsonl") => Ok(Format::Ndjson),
+            _ => Err(ImportError::UnsupportedFormat(path.display().to_string())),
+        }
+    }
+}
+
+// 1 行分の入力。日付の解析とバリデーションは自前で行い、行番号つきのエラーにする
+#[derive(Debug, Clone, Deserialize)]
+struct PersonRow {
+    name: String,
+    birth_date: String,
+    death_date: Option<String>,
+    data: Option<String>,
+}
+impl PersonRow {
+    fn validate(self, line: u64) -> Result<PersonDto, RowError> {
+        let invalid = |reason: String| RowError { line, reason };
+
+        let birth_date = parse_date("birth_date", &self.birth_date).map_err(invalid)?;
+        let death_date = match self.death_date.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(s) => Some(parse_date("death_date", s).map_err(invalid)?),
+        };
+        let person = PersonDto::new(
+            self.name.trim(),
+            birth_date,
+            death_date,
+            self.data.as_deref(),
+            0,
+        );
+
+        // 登録時と同じドメインの不変条件で確かめておく
+        Person::try_from(person)
+            .map(PersonDto::from)
+            .map_err(|e| invalid(e.to_string()))
+    }
+}
+
+fn parse_date(field: &str, s: &str) -> Result<NaiveDate, String> {
+    s.trim()
+        .parse()
+        .map_err(|e| format!("invalid {field} {s:?}: {e}"))
+}
+
+/// rows of a CSV with header `name,birth_date,death_date,data`
+pub struct CsvRows<R> {
+    reader: csv::Reader<R>,
+    headers: Option<csv::StringRecord>,
+    record: csv::StringRecord,
+    done: bool,
+}
+impl<R: Read> CsvRows<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(reader),
+            headers: None,
+            record: csv::StringRecord::new(),
+            done: false,
+        }
+    }
+}
+impl<R: Read> Iterator for CsvRows<R> {
+    type Item = Result<PersonDto, RowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let headers = match &self.headers {
+            Some(headers) => headers,
+            None => match self.reader.headers() {
+                Ok(headers) => self.headers.insert(headers.clone()),
+                Err(e) => {
+                    // ヘッダが読めなければ以降の行も解釈できない
+                    self.done = true;
+                    return Some(Err(RowError {
+                        line: 1,
+                        reason: e.to_string(),
+                    }));
+                }
+            },
+        };
+        if self.done {
+            return None;
+        }
+        match self.reader.read_record(&mut self.record) {
+            Ok(false) => None,
+            Ok(true) => {
+                let line = self.record.position().map(|p| p.line()).unwrap_or_default();
+                let row = self
+                    .record
+                    .deserialize::<PersonRow>(Some(headers))
+                    .map_err(|e| RowError {
+                        line,
+                        reason: e.to_string(),
+                    });
+                Some(row.and_then(|row| row.validate(line)))
+            }
+            Err(e) => {
+                let line = e.position().map(|p| p.line()).unwrap_or_default();
+                if !matches!(e.kind(), csv::ErrorKind::UnequalLengths { .. }) {
+                    self.done = true;
+                }
+                Some(Err(RowError {
+                    line,
+                    reason: e.to_string(),
+                }))
+            }
+        }
+    }
+}
+
+/// rows of newline delimited JSON, one `{"name", "birth_date", "death_date", "data"}` per line
+pub struct NdjsonRows<R> {
+    lines: io::Lines<R>,
+    line: u64,
+}
+impl<R: BufRead> NdjsonRows<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            line: 0,
+        }
+    }
+}
+impl<R: BufRead> Iterator for NdjsonRows<R> {
+    type Item = Result<PersonDto, RowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let text = self.lines.next()?;
+            self.line += 1;
+            let line = self.line;
+
+            let text = match text {
+                Ok(text) => text,
+                Err(e) => {
+                    return Some(Err(RowError {
+                        line,
+                        reason: e.to_string(),
+                    }))
+                }
+            };
+            if text.trim().is_empty() {
+                continue;
+            }
+
+            let row = serde_json::from_str::<PersonRow>(&text).map_err(|e| RowError {
+                line,
+                reason: e.to_string(),
+            });
+            return Some(row.and_then(|row| row.validate(line)));
+        }
+    }
+}
+
+pub fn rows<'r>(
+    format: Format,
+    reader: impl Read + 'r,
+) -> Box<dyn Iterator<Item = Result<PersonDto, RowError>> + 'r> {
+    match format {
+        Format::Csv => Box::new(CsvRows::new(reader)),
+        Format::Ndjson => Box::new(NdjsonRows::new(BufReader::new(reader))),
+    }
+}
+
+// batch_import は size_hint から総数を取るので、検証時に数えた件数を教える
+struct Counted<I> {
+    inner: I,
+    remaining: usize,
+}
+impl<I: Iterator> Iterator for Counted<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.inner.next()?;
+        self.remaining = self.remaining.saturating_sub(1);
+        Some(item)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+/// import persons by streaming the input twice.
+///
+/// The first pass only validates and reports every invalid row with its line number,
+/// so nothing is written unless the whole input is valid.
+/// The second pass feeds the rows into `PersonService::try_batch_import` one by one,
+/// so the input is never loaded into memory at once.
+pub fn import<'a, Ctx, S, R>(
+    service: &'a mut S,
+    format: Format,
+    open: impl Fn() -> io::Result<R>,
+    out_port: Rc<impl PersonOutputBoundary<(u64, u64), ServiceError>>,
+) -> Result<Vec<PersonId>, ImportError>
+where
+    S: PersonService<'a, Ctx>,
+    R: Read,
+{
+    let unreadable = |e: io::Error| ImportError::Unreadable(e.to_string());
+
+    trace!("validate rows: {:?}", format);
+    let mut count = 0;
+    let mut errors = vec![];
+    for row in rows(format, open().map_err(unreadable)?) {
+        match row {
+            Ok(_) => count += 1,
+            Err(e) => errors.push(e),
+        }
+    }
+    if !errors.is_empty() {
+        warn!("{} invalid rows found", errors.len());
+        return Err(ImportError::InvalidRows(errors));
+    }
+    if count == 0 {
+        return Err(ImportError::ServiceFailed(ServiceError::InvalidRequest(
+            InvalidErrorKind::EmptyArgument,
+        )));
+    }
+
+    trace!("import {} rows: {:?}", count, format);
+    // the input has been validated, so this only happens when it changed in the meantime.
+    // the failed row aborts the import, and is kept to be reported with its line number
+    let changed = RefCell::new(None);
+    let persons = rows(format, open().map_err(unreadable)?).map(|row| {
+        row.map_err(|e| {
+            warn!("input changed during import: {}", e);
+            let reason = e.to_string();
+            changed.replace(Some(e));
+            reason
+        })
+    });
+    let result = service.try_batch_import(
+        Counted {
+            inner: persons,
+            remaining: count,
+        },
+        out_port,
+    );
+
+    match (result, changed.into_inner()) {
+        (Ok(ids), _) => Ok(ids),
+        (Err(_), Some(e)) => Err(ImportError::InvalidRows(vec![e])),
+        (Err(e), None) => Err(ImportError::ServiceFailed(e)),
+    }
+}
+
+/// import a `.csv` or `.ndjson` (`.jsonl`) file
+pub fn import_file<'a, Ctx, S>(
+    service: &'a mut S,
+    path: &Path,
+    out_port: Rc<impl PersonOutputBoundary<(u64, u64), ServiceError>>,
+) -> Result<Vec<PersonId>, ImportError>
+where
+    S: PersonService<'a, Ctx>,
+{
+    let format = Format::from_path(path)?;
+    import(service, format, || File::open(path), out_port)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        dao::{DaoError, HavePersonDao, PersonDao},
+        domain::{date, Revision},
+        dto::PersonHistoryDto,
+        reporter::{Level, Location, Reporter, ReporterError},
+        usecase::{PersonUsecase, UsecaseError},
+    };
+    use chrono::{DateTime, Utc};
+
+    const CSV: &str = "\
+name,birth_date,death_date,data
+Abel,1802-08-05,1829-04-06,Abel's theorem
+Galois,1811-10-25,,
+";
+    const NDJSON: &str = r#"{"name":"Abel","birth_date":"1802-08-05","death_date":"1829-04-06","data":"Abel's theorem"}
+
+{"name":"Galois","birth_date":"1811-10-25","death_date":null,"data":null}
+"#;
+
+    fn expected() -> Vec<PersonDto> {
+        vec![
+            PersonDto::new(
+                "Abel",
+                date(1802, 8, 5),
+                Some(date(1829, 4, 6)),
+                Some("Abel's theorem"),
+                0,
+            ),
+            PersonDto::new("Galois", date(1811, 10, 25), None, None, 0),
+        ]
+    }
+
+    #[test]
+    fn test_csv_rows() {
+        let result = rows(Format::Csv, CSV.as_bytes()).collect::<Result<Vec<_>, _>>();
+
+        assert_eq!(result, Ok(expected()));
+    }
+
+    #[test]
+    fn test_csv_rows_error() {
+        let input = "\
+name,birth_date,death_date,data
+,1802-08-05,,
+Euler,1707-04-15,1783-09-18,
+Galois,1811-13-25,,
+Gauss,1855-02-23,1777-04-30,
+";
+        let errors = rows(Format::Csv, input.as_bytes())
+            .filter_map(Result::err)
+            .map(|e| e.line)
+            .collect::<Vec<_>>();
+
+        assert_eq!(errors, vec![2, 4, 5]);
+    }
+
+    #[test]
+    fn test_ndjson_rows() {
+        let result = rows(Format::Ndjson, NDJSON.as_bytes()).collect::<Result<Vec<_>, _>>();
+
+        assert_eq!(result, Ok(expected()));
+    }
+
+    #[test]
+    fn test_ndjson_rows_error() {
+        let input = r#"{"name":"Abel","birth_date":"1802-08-05"}
+{"name":"Euler"}
+{"name":"Galois","birth_date":"25/10/1811"}
+not a json
+"#;
+        let errors = rows(Format::Ndjson, input.as_bytes())
+            .filter_map(Result::err)
+            .map(|e| e.line)
+            .collect::<Vec<_>>();
+
+        assert_eq!(errors, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(Format::from_path(Path::new("a.csv")), Ok(Format::Csv));
+        assert_eq!(Format::from_path(Path::new("a.ndjson")), Ok(Format::Ndjson));
+        assert_eq!(Format::from_path(Path::new("a.jsonl")), Ok(Format::Ndjson));
+        assert!(Format::from_path(Path::new("a.xml")).is_err());
+    }
+
+    struct DummyPersonDao;
+    impl PersonDao<()> for DummyPersonDao {
+        fn insert(
+            &self,
+            _person: PersonDto,
+        ) -> impl tx_rs::Tx<(), Item = PersonId, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(1))
+        }
+        fn fetch(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<PersonDto>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(None))
+        }
+        fn select(&self) -> impl tx_rs::Tx<(), Item = Vec<(PersonId, PersonDto)>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(vec![]))
+        }
+        fn save(
+            &self,
+            _id: PersonId,
+            _revision: Revision,
+            _person: PersonDto,
+        ) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(()))
+        }
+        fn delete(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(None))
+        }
+        fn restore(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(None))
+        }
+        fn purge(&self, _before: DateTime<Utc>) -> impl tx_rs::Tx<(), Item = u64, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(0))
+        }
+        fn history(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Vec<PersonHistoryDto>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(vec![]))
+        }
+    }
+
+    struct FakePersonUsecase {
+        db: Vec<(PersonId, PersonDto)>,
+        dao: DummyPersonDao,
+    }
+    impl HavePersonDao<()> for FakePersonUsecase {
+        fn get_dao(&self) -> &impl PersonDao<()> {
+            &self.dao
+        }
+    }
+    impl PersonUsecase<()> for FakePersonUsecase {
+        fn entry<'a>(
+            &'a mut self,
+            person: PersonDto,
+        ) -> impl tx_rs::Tx<(), Item = PersonId, Err = UsecaseError>
+        where
+            (): 'a,
+        {
+            let id = self.db.len() as PersonId + 1;
+            self.db.push((id, person));
+
+            tx_rs::with_tx(move |&mut ()| Ok(id))
+        }
+    }
+
+    struct DummyReporter;
+    impl Reporter<'_> for DummyReporter {
+        fn register(
+            &mut self,
+            _observer: impl crate::reporter::Observer,
+        ) -> Result<(), ReporterError> {
+            Ok(())
+        }
+        fn get_observers(&self) -> Vec<&dyn crate::reporter::Observer> {
+            vec![]
+        }
+        fn send_report(
+            &self,
+            _level: Level,
+            _to: &str,
+            _message: &str,
+            _loc: Location,
+        ) -> Result<(), ReporterError> {
+            Ok(())
+        }
+    }
+
+    struct TargetPersonService {
+        usecase: FakePersonUsecase,
+    }
+    impl PersonService<'_, ()> for TargetPersonService {
+        type U = FakePersonUsecase;
+        type N = DummyReporter;
+
+        fn run_tx<T, F>(&mut self, f: F) -> Result<T, ServiceError>
+        where
+            F: FnOnce(&mut Self::U, &mut ()) -> Result<T, UsecaseError>,
+        {
+            f(&mut self.usecase, &mut ()).map_err(ServiceError::from)
+        }
+
+        fn get_reporter(&self) -> Self::N {
+            DummyReporter
+        }
+    }
+
+    #[derive(Default)]
+    struct SpyPersonOutputBoundary {
+        in_progress: RefCell<Vec<(u64, u64)>>,
+    }
+    impl PersonOutputBoundary<(u64, u64), ServiceError> for SpyPersonOutputBoundary {
+        fn started(&self) {}
+        fn in_progress(&self, progress: (u64, u64)) {
+            self.in_progress.borrow_mut().push(progress);
+        }
+        fn completed(&self) {}
+        fn aborted(&self, _err: ServiceError) {}
+    }
+
+    fn service() -> TargetPersonService {
+        TargetPersonService {
+            usecase: FakePersonUsecase {
+                db: vec![],
+                dao: DummyPersonDao,
+            },
+        }
+    }
+
+    #[test]
+    fn test_import() {
+        let mut service = service();
+        let out_port = Rc::new(SpyPersonOutputBoundary::default());
+
+        let result = import(
+            &mut service,
+            Format::Csv,
+            || Ok(Cursor::new(CSV)),
+            out_port.clone(),
+        );
+
+        assert_eq!(result, Ok(vec![1, 2]));
+        assert_eq!(
+            service.usecase.db,
+            vec![(1, expected()[0].clone()), (2, expected()[1].clone())]
+        );
+        // 総数は検証時に数えたものが渡る
+        assert_eq!(*out_port.in_progress.borrow(), vec![(2, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn test_import_invalid_rows() {
+        let mut service = service();
+        let input = "\
+name,birth_date,death_date,data
+Abel,1802-08-05,1829-04-06,
+Galois,1811-10-xx,,
+";
+
+        let result = import(
+            &mut service,
+            Format::Csv,
+            || Ok(Cursor::new(input)),
+            Rc::new(SpyPersonOutputBoundary::default()),
+        );
+
+        assert!(matches!(
+            result,
+            Err(ImportError::InvalidRows(errors)) if errors.iter().map(|e| e.line).collect::<Vec<_>>() == vec![3]
+        ));
+        // 1 行でも不正なら何も登録しない
+        assert!(service.usecase.db.is_empty());
+    }
+
+    #[test]
+    fn test_import_changed_input() {
+        let mut service = crate::MemoryPersonServiceImpl::new();
+        // 検証の後で入力が書き換えられた
+        let opened = Cell::new(0);
+        let open = || {
+            opened.set(opened.get() + 1);
+            let input = match opened.get() {
+                1 => CSV,
+                _ => "name,birth_date,death_date,data\nAbel,1802-08-05,1829-04-06,\nGalois,1811-10-xx,,\n",
+            };
+            Ok(Cursor::new(input))
+        };
+
+        let result = import(
+            &mut service,
+            Format::Csv,
+            open,
+            Rc::new(SpyPersonOutputBoundary::default()),
+        );
+
+        assert!(matches!(
+            result,
+            Err(ImportError::InvalidRows(errors)) if errors.iter().map(|e| e.line).collect::<Vec<_>>() == vec![3]
+        ));
+        // 途中まで読んだ行も登録しない
+        assert_eq!(service.list_all(), Ok(vec![]));
+        assert!(service.observer().events().is_empty());
+    }
+
+    #[test]
+    fn test_import_empty() {
+        let mut service = service();
+
+        let result = import(
+            &mut service,
+            Format::Ndjson,
+            || Ok(Cursor::new("")),
+            Rc::new(SpyPersonOutputBoundary::default()),
+        );
+
+        assert!(matches!(result, Err(ImportError::ServiceFailed(_))));
+    }
+}
@@ -0,0 +1,1581 @@
+use log::{error, trace, warn};
+use postgres::NoTls;
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub mod aio;
+pub mod backend;
+pub mod cache;
+pub mod cached_service;
+pub mod config;
+pub mod dao;
+pub mod domain;
+pub mod dto;
+pub mod dynamo_db;
+pub mod event;
+pub mod exporter;
+pub mod importer;
+#[macro_use]
+pub mod location;
+pub mod memory;
+pub mod migration;
+pub mod outbox;
+pub mod pg_db;
+pub mod pool;
+pub mod rabbitmq;
+pub mod redis_cache;
+pub mod reporter;
+pub mod rest;
+pub mod service;
+pub mod sqlite_db;
+pub mod usecase;
+
+use aio::cached_service::AsyncPersonCachedService;
+use aio::dao::{AsyncOutboxDao, HaveAsyncPersonDao};
+use aio::pg_db::{AsyncPgConnection, AsyncPgOutboxDao, AsyncPgPersonDao};
+use aio::redis_cache::AsyncRedisPersonCao;
+use aio::reporter::{AsyncDefaultReporter, AsyncReporter};
+use aio::service::AsyncPersonService;
+use aio::usecase::AsyncPersonUsecase;
+use aio::BoxFuture;
+use backend::{Backoff, Health, ReconnectPolicy, StartupError};
+use cached_service::PersonCachedService;
+use config::{ConfigError, Settings};
+use dao::{DaoError, HavePersonDao};
+use dynamo_db::{DynamoDbClient, DynamoPersonDao};
+use memory::{MemoryCache, MemoryDb, MemoryPersonCao, MemoryPersonDao, RecordingObserver};
+use migration::{MigrationError, Migrator};
+use outbox::{Notification, OutboxDao, OutboxError, OutboxRelay};
+use pg_db::{
+    is_connection_error, PgConnectionManager, PgMigrationDao, PgOutboxDao, PgPersonDao,
+    PgTransaction,
+};
+use pool::{Pool, PoolConfig, PoolError};
+use redis_cache::{RedisConnection, RedisConnectionManager};
+use reporter::{BufferedObserver, DefaultReporter, Reporter, SyncReporter};
+use service::{PersonService, RetryPolicy, ServiceError};
+use sqlite_db::SqlitePersonDao;
+use tx_rs::Tx;
+use usecase::{PersonUsecase, UsecaseError};
+
+#[derive(Debug, Clone)]
+pub struct PersonUsecaseImpl {
+    dao: PgPersonDao,
+}
+impl PersonUsecaseImpl {
+    pub fn new(dao: PgPersonDao) -> Self {
+        Self { dao }
+    }
+}
+impl PersonUsecase<postgres::Client> for PersonUsecaseImpl {}
+impl HavePersonDao<postgres::Client> for PersonUsecaseImpl {
+    fn get_dao(&self) -> &impl dao::PersonDao<postgres::Client> {
+        &self.dao
+    }
+}
+
+/// The usecase on DynamoDB. Run its Tx on a `DynamoDbClient`.
+#[derive(Debug, Clone)]
+pub struct DynamoPersonUsecaseImpl {
+    dao: DynamoPersonDao,
+}
+impl DynamoPersonUsecaseImpl {
+    pub fn new(dao: DynamoPersonDao) -> Self {
+        Self { dao }
+    }
+}
+impl PersonUsecase<DynamoDbClient> for DynamoPersonUsecaseImpl {}
+impl HavePersonDao<DynamoDbClient> for DynamoPersonUsecaseImpl {
+    fn get_dao(&self) -> &impl dao::PersonDao<DynamoDbClient> {
+        &self.dao
+    }
+}
+
+/// The service on postgres, redis and rabbitmq.
+///
+/// The connections to postgres and redis are checked out of pools. A transaction checks out its
+/// connection and returns it when committed or rolled back, so a worker holds at most one.
+/// Give each worker its own service built by `from_pools` with clones of the same pools,
+/// or by `SharedPersonServiceImpl`, and make the postgres pool at least as large as the workers.
+pub struct PersonServiceImpl {
+    db_pool: Pool<PgConnectionManager>,
+    cache_pool: Pool<RedisConnectionManager>,
+    reporter: DefaultReporter<'static>,
+    usecase: RefCell<PersonUsecaseImpl>,
+    outbox: PgOutboxDao,
+    retry_policy: RetryPolicy,
+    cache_ttl: Option<Duration>,
+    cache_health: Health,
+}
+impl PersonServiceImpl {
+    /// build the service with the default settings but the URIs
+    pub fn new(db_uri: &str, cache_uri: &str, mq_uri: &str) -> Result<Self, StartupError> {
+        Self::from_settings(&settings_with(db_uri, cache_uri, mq_uri))
+    }
+
+    /// build the service with the pools, cache and queues of the settings.
+    ///
+    /// It fails only if the settings are invalid or postgres is unreachable. Without redis it skips
+    /// the cache, and without rabbitmq it keeps up to `mq.buffer_size` reports, until they are
+    /// reconnected in the background.
+    pub fn from_settings(settings: &Settings) -> Result<Self, StartupError> {
+        let backends = connect_backends(settings)?;
+        let mut reporter = DefaultReporter::new();
+        reporter.register(backends.observer)?;
+
+        Ok(
+            Self::from_pools(backends.db_pool, backends.cache_pool, reporter)
+                .with_retry_policy(settings.retry.policy())
+                .with_cache_ttl(settings.cache.ttl())
+                .with_cache_health(backends.cache_health),
+        )
+    }
+
+    pub fn from_pools(
+        db_pool: Pool<PgConnectionManager>,
+        cache_pool: Pool<RedisConnectionManager>,
+        reporter: DefaultReporter<'static>,
+    ) -> Self {
+        let usecase = RefCell::new(PersonUsecaseImpl::new(PgPersonDao::new("app")));
+
+        Self {
+            db_pool,
+            cache_pool,
+            reporter,
+            usecase,
+            outbox: PgOutboxDao,
+            retry_policy: RetryPolicy::default(),
+            cache_ttl: None,
+            cache_health: Health::up(),
+        }
+    }
+
+    /// build the service from the environment variables (see `Settings::apply_env`)
+    pub fn from_env() -> Result<Self, StartupError> {
+        Self::from_settings(&Settings::from_env()?)
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// who is recorded in the person history, `app` by default
+    pub fn with_actor(self, actor: &str) -> Self {
+        self.usecase
+            .replace(PersonUsecaseImpl::new(PgPersonDao::new(actor)));
+        self
+    }
+
+    /// how long a person is cached, forever by default
+    pub fn with_cache_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// skip the cache while `health` is down
+    pub fn with_cache_health(mut self, health: Health) -> Self {
+        self.cache_health = health;
+        self
+    }
+}
+/// A handle to the pools and observers of `PersonServiceImpl` that can be shared across threads.
+///
+/// `PersonServiceImpl` is not `Send`, since its reporter and usecase are not, so each thread
+/// takes its own service from `service`. It costs no connection, so it can be done per request.
+#[derive(Clone)]
+pub struct SharedPersonServiceImpl {
+    db_pool: Pool<PgConnectionManager>,
+    cache_pool: Pool<RedisConnectionManager>,
+    reporter: SyncReporter,
+    actor: String,
+    retry_policy: RetryPolicy,
+    cache_ttl: Option<Duration>,
+    cache_health: Health,
+}
+impl SharedPersonServiceImpl {
+    pub fn new(db_uri: &str, cache_uri: &str, mq_uri: &str) -> Result<Self, StartupError> {
+        Self::from_settings(&settings_with(db_uri, cache_uri, mq_uri))
+    }
+
+    pub fn from_settings(settings: &Settings) -> Result<Self, StartupError> {
+        let backends = connect_backends(settings)?;
+        let mut reporter = SyncReporter::new();
+        reporter.register(backends.observer)?;
+
+        let mut shared = Self::from_pools(backends.db_pool, backends.cache_pool, reporter);
+        shared.retry_policy = settings.retry.policy();
+        shared.cache_ttl = settings.cache.ttl();
+        shared.cache_health = backends.cache_health;
+        Ok(shared)
+    }
+
+    pub fn from_pools(
+        db_pool: Pool<PgConnectionManager>,
+        cache_pool: Pool<RedisConnectionManager>,
+        reporter: SyncReporter,
+    ) -> Self {
+        Self {
+            db_pool,
+            cache_pool,
+            reporter,
+            actor: "app".to_string(),
+            retry_policy: RetryPolicy::default(),
+            cache_ttl: None,
+            cache_health: Health::up(),
+        }
+    }
+
+    /// build the service from the environment variables (see `Settings::apply_env`)
+    pub fn from_env() -> Result<Self, StartupError> {
+        Self::from_settings(&Settings::from_env()?)
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// who is recorded in the person history, `app` by default
+    pub fn with_actor(mut self, actor: &str) -> Self {
+        self.actor = actor.to_string();
+        self
+    }
+
+    /// a service for the current thread, on the shared pools and observers
+    pub fn service(&self) -> PersonServiceImpl {
+        PersonServiceImpl::from_pools(
+            self.db_pool.clone(),
+            self.cache_pool.clone(),
+            self.reporter.local(),
+        )
+        .with_actor(&self.actor)
+        .with_retry_policy(self.retry_policy.clone())
+        .with_cache_ttl(self.cache_ttl)
+        .with_cache_health(self.cache_health.clone())
+    }
+}
+
+impl<'a> PersonService<'a, postgres::Client> for PersonServiceImpl {
+    type U = PersonUsecaseImpl;
+    type N = DefaultReporter<'a>;
+
+    // service is responsible for transaction management
+    fn run_tx<T, F>(&mut self, f: F) -> Result<T, ServiceError>
+    where
+        F: FnOnce(&mut PersonUsecaseImpl, &mut postgres::Client) -> Result<T, UsecaseError>,
+    {
+        // 接続はトランザクションの間だけ借り, 終われば pool に返す
+        let mut ctx = begin(&self.db_pool).map_err(|e| {
+            error!("{}", e);
+            ServiceError::ServiceUnavailable(e.to_string())
+        })?;
+        trace!("transaction started");
+
+        let mut usecase = self.usecase.borrow_mut();
+        let res = f(&mut usecase, ctx.client());
+
+        // 途中で接続が切れても panic せず, 切れた接続は返したときに pool が捨てる
+        match res {
+            Ok(v) => {
+                ctx.commit().map_err(|e| {
+                    error!("failed to commit: {}", e);
+                    ServiceError::ServiceUnavailable(format!("{}", e))
+                })?;
+                trace!("transaction committed");
+                Ok(v)
+            }
+            Err(e) => {
+                if let Err(e) = ctx.rollback() {
+                    error!("failed to rollback: {}", e);
+                }
+                error!("transaction rollbacked");
+                Err(e.into())
+            }
+        }
+    }
+
+    fn get_reporter(&self) -> Self::N {
+        self.reporter.clone()
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy.clone()
+    }
+
+    // 通知は変更と同じトランザクションで outbox に書き, OutboxRelayImpl が送る
+    fn run_tx_and_notify<T, F>(&mut self, f: F) -> Result<T, ServiceError>
+    where
+        F: FnOnce(
+            &mut PersonUsecaseImpl,
+            &mut postgres::Client,
+        ) -> Result<(T, Vec<Notification>), UsecaseError>,
+    {
+        let outbox = self.outbox.clone();
+
+        self.run_tx(move |usecase, ctx| {
+            let (v, notifications) = f(usecase, ctx)?;
+            for notification in notifications {
+                outbox
+                    .enqueue(notification)
+                    .run(ctx)
+                    .map_err(UsecaseError::EnqueueNotificationFailed)?;
+            }
+            Ok(v)
+        })
+    }
+}
+impl PersonCachedService<'_, RedisConnection, postgres::Client> for PersonServiceImpl {
+    type C = redis_cache::RedisPersonCao;
+
+    fn get_cao(&self) -> Self::C {
+        redis_cache::RedisPersonCao::new(self.cache_pool.clone())
+            .with_ttl(self.cache_ttl)
+            .with_health(self.cache_health.clone())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SqlitePersonUsecaseImpl {
+    dao: SqlitePersonDao,
+}
+impl SqlitePersonUsecaseImpl {
+    pub fn new(dao: SqlitePersonDao) -> Self {
+        Self { dao }
+    }
+}
+impl PersonUsecase<rusqlite::Connection> for SqlitePersonUsecaseImpl {}
+impl HavePersonDao<rusqlite::Connection> for SqlitePersonUsecaseImpl {
+    fn get_dao(&self) -> &impl dao::PersonDao<rusqlite::Connection> {
+        &self.dao
+    }
+}
+
+/// The service on an embedded SQLite database, for local development and tests.
+///
+/// It needs no external service. The notifications are sent to the reporter after the commit,
+/// and the reporter has no observer unless given by `with_reporter`.
+pub struct SqlitePersonServiceImpl {
+    db_conn: rusqlite::Connection,
+    reporter: DefaultReporter<'static>,
+    usecase: RefCell<SqlitePersonUsecaseImpl>,
+    retry_policy: RetryPolicy,
+}
+impl SqlitePersonServiceImpl {
+    /// open the database file, creating it and the tables if they do not exist
+    pub fn open(path: &str) -> Result<Self, StartupError> {
+        Self::from_conn(rusqlite::Connection::open(path))
+    }
+
+    /// a new database in memory, which is gone when the service is dropped
+    pub fn open_in_memory() -> Result<Self, StartupError> {
+        Self::from_conn(rusqlite::Connection::open_in_memory())
+    }
+
+    fn from_conn(db_conn: rusqlite::Result<rusqlite::Connection>) -> Result<Self, StartupError> {
+        let db_conn = db_conn
+            .and_then(|conn| sqlite_db::create_tables(&conn).map(|_| conn))
+            .map_err(|e| {
+                error!("sqlite is unavailable: {}", e);
+                StartupError::DatabaseUnavailable(e.to_string())
+            })?;
+
+        Ok(Self {
+            db_conn,
+            reporter: DefaultReporter::new(),
+            usecase: RefCell::new(SqlitePersonUsecaseImpl::new(SqlitePersonDao::new("app"))),
+            retry_policy: RetryPolicy::default(),
+        })
+    }
+
+    pub fn with_reporter(mut self, reporter: DefaultReporter<'static>) -> Self {
+        self.reporter = reporter;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// who is recorded in the person history, `app` by default
+    pub fn with_actor(self, actor: &str) -> Self {
+        self.usecase
+            .replace(SqlitePersonUsecaseImpl::new(SqlitePersonDao::new(actor)));
+        self
+    }
+}
+impl<'a> PersonService<'a, rusqlite::Connection> for SqlitePersonServiceImpl {
+    type U = SqlitePersonUsecaseImpl;
+    type N = DefaultReporter<'a>;
+
+    fn run_tx<T, F>(&mut self, f: F) -> Result<T, ServiceError>
+    where
+        F: FnOnce(
+            &mut SqlitePersonUsecaseImpl,
+            &mut rusqlite::Connection,
+        ) -> Result<T, UsecaseError>,
+    {
+        let ctx = &mut self.db_conn;
+        // panic などで終わらなかったトランザクションが残っていれば戻す
+        if !ctx.is_autocommit() {
+            warn!("rollback the transaction left open");
+            if let Err(e) = ctx.execute_batch("ROLLBACK") {
+                error!("failed to rollback: {}", e);
+            }
+        }
+        ctx.execute_batch("BEGIN").map_err(|e| {
+            error!("failed to start transaction: {}", e);
+            ServiceError::ServiceUnavailable(format!("{}", e))
+        })?;
+        trace!("transaction started");
+
+        let mut usecase = self.usecase.borrow_mut();
+        let res = f(&mut usecase, ctx);
+
+        // postgres と同じく, commit できなければ使えないものとし, rollback の失敗は記録だけする
+        match res {
+            Ok(v) => {
+                ctx.execute_batch("COMMIT").map_err(|e| {
+                    error!("failed to commit: {}", e);
+                    ServiceError::ServiceUnavailable(format!("{}", e))
+                })?;
+                trace!("transaction committed");
+                Ok(v)
+            }
+            Err(e) => {
+                if let Err(e) = ctx.execute_batch("ROLLBACK") {
+                    error!("failed to rollback: {}", e);
+                }
+                error!("transaction rollbacked");
+                Err(e.into())
+            }
+        }
+    }
+
+    fn get_reporter(&self) -> Self::N {
+        self.reporter.clone()
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy.clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryPersonUsecaseImpl {
+    dao: MemoryPersonDao,
+}
+impl MemoryPersonUsecaseImpl {
+    pub fn new(dao: MemoryPersonDao) -> Self {
+        Self { dao }
+    }
+}
+impl PersonUsecase<MemoryDb> for MemoryPersonUsecaseImpl {}
+impl HavePersonDao<MemoryDb> for MemoryPersonUsecaseImpl {
+    fn get_dao(&self) -> &impl dao::PersonDao<MemoryDb> {
+        &self.dao
+    }
+}
+
+/// The service on the in-memory backend, which needs no external service.
+///
+/// The data is lost when the last clone is dropped. The clones share the database,
+/// the cache and the observer, so they can serve in parallel threads.
+/// The notifications are kept by the `RecordingObserver` instead of being published.
+#[derive(Debug, Clone)]
+pub struct MemoryPersonServiceImpl {
+    db: Arc<Mutex<MemoryDb>>,
+    cache: MemoryCache,
+    observer: RecordingObserver,
+    usecase: MemoryPersonUsecaseImpl,
+    retry_policy: RetryPolicy,
+}
+impl Default for MemoryPersonServiceImpl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl MemoryPersonServiceImpl {
+    pub fn new() -> Self {
+        Self {
+            db: Arc::new(Mutex::new(MemoryDb::new())),
+            cache: MemoryCache::new(),
+            observer: RecordingObserver::new(),
+            usecase: MemoryPersonUsecaseImpl::new(MemoryPersonDao::new("app")),
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// who is recorded in the person history, `app` by default
+    pub fn with_actor(mut self, actor: &str) -> Self {
+        self.usecase = MemoryPersonUsecaseImpl::new(MemoryPersonDao::new(actor));
+        self
+    }
+
+    /// the notifications sent so far
+    pub fn observer(&self) -> &RecordingObserver {
+        &self.observer
+    }
+
+    pub fn cache(&self) -> &MemoryCache {
+        &self.cache
+    }
+}
+impl<'a> PersonService<'a, MemoryDb> for MemoryPersonServiceImpl {
+    type U = MemoryPersonUsecaseImpl;
+    type N = DefaultReporter<'a>;
+
+    // 変えた行だけを覚えながらその場で実行し, 失敗すれば戻す. 何も残らない
+    fn run_tx<T, F>(&mut self, f: F) -> Result<T, ServiceError>
+    where
+        F: FnOnce(&mut MemoryPersonUsecaseImpl, &mut MemoryDb) -> Result<T, UsecaseError>,
+    {
+        let mut db = self.db.lock().unwrap();
+        db.begin();
+        trace!("transaction started");
+
+        match f(&mut self.usecase, &mut db) {
+            Ok(v) => {
+                db.commit();
+                trace!("transaction committed");
+                Ok(v)
+            }
+            Err(e) => {
+                db.rollback();
+                error!("transaction rollbacked");
+                Err(e.into())
+            }
+        }
+    }
+
+    fn get_reporter(&self) -> Self::N {
+        let mut reporter = DefaultReporter::new();
+        reporter
+            .register(self.observer.clone())
+            .expect("register observer");
+        reporter
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy.clone()
+    }
+}
+impl<'a> PersonCachedService<'a, MemoryCache, MemoryDb> for MemoryPersonServiceImpl {
+    type C = MemoryPersonCao;
+
+    fn get_cao(&self) -> Self::C {
+        MemoryPersonCao::new(self.cache.clone())
+    }
+}
+
+/// Publishes the notifications in the outbox to RabbitMQ.
+///
+/// It works on a single connection, opened again when it is lost.
+pub struct OutboxRelayImpl {
+    db_pool: Pool<PgConnectionManager>,
+    reporter: DefaultReporter<'static>,
+    dao: PgOutboxDao,
+}
+impl OutboxRelayImpl {
+    pub fn new(db_uri: &str, mq_uri: &str) -> Result<Self, StartupError> {
+        Self::from_settings(&settings_with(
+            db_uri,
+            &Settings::default().cache.uri,
+            mq_uri,
+        ))
+    }
+
+    /// It fails if the settings are invalid or postgres is unreachable. While rabbitmq is,
+    /// the entries stay in the outbox.
+    pub fn from_settings(settings: &Settings) -> Result<Self, StartupError> {
+        settings.validate()?;
+        let db_pool = db_pool(
+            settings,
+            // 始められなかった接続は返してから試し直すので, 1 本で足りる
+            PoolConfig {
+                max_size: 1,
+                ..settings.database.pool_config()
+            },
+        );
+        db_pool.get().map_err(|e| {
+            error!("postgres is unavailable: {}", e);
+            StartupError::DatabaseUnavailable(e.to_string())
+        })?;
+        let mut reporter = DefaultReporter::new();
+        // 溜めると outbox から消えてしまうので, 繋がるまでは失敗させる
+        reporter.register(mq_observer(settings, 0))?;
+
+        Ok(Self {
+            db_pool,
+            reporter,
+            dao: PgOutboxDao,
+        })
+    }
+
+    /// build the relay from the environment variables (see `Settings::apply_env`)
+    pub fn from_env() -> Result<Self, StartupError> {
+        Self::from_settings(&Settings::from_env()?)
+    }
+}
+impl<'a> OutboxRelay<'a, postgres::Client> for OutboxRelayImpl {
+    type D = PgOutboxDao;
+    type N = DefaultReporter<'a>;
+
+    fn run_tx<T, F>(&'a mut self, f: F) -> Result<T, OutboxError>
+    where
+        F: FnOnce(&PgOutboxDao, &mut postgres::Client) -> Result<T, DaoError>,
+    {
+        // 接続は毎回 pool から確かめて借りる. 切れていれば pool が開き直す
+        let mut ctx = begin(&self.db_pool).map_err(|e| {
+            error!("{}", e);
+            OutboxError::Unavailable(e.to_string())
+        })?;
+        trace!("transaction started");
+
+        match f(&self.dao, ctx.client()) {
+            Ok(v) => {
+                ctx.commit().map_err(|e| {
+                    error!("failed to commit: {}", e);
+                    OutboxError::Unavailable(format!("{}", e))
+                })?;
+                trace!("transaction committed");
+                Ok(v)
+            }
+            Err(e) => {
+                if let Err(e) = ctx.rollback() {
+                    error!("failed to rollback: {}", e);
+                }
+                error!("transaction rollbacked");
+                Err(OutboxError::TransactionFailed(e))
+            }
+        }
+    }
+
+    fn get_reporter(&self) -> Self::N {
+        self.reporter.clone()
+    }
+}
+
+/// Applies the migrations of `app::migration` to postgres.
+pub struct MigratorImpl {
+    db_client: postgres::Client,
+    dao: PgMigrationDao,
+}
+impl MigratorImpl {
+    pub fn new(db_uri: &str) -> Result<Self, MigrationError> {
+        let db_client = postgres::Client::connect(db_uri, NoTls).map_err(|e| {
+            error!("failed to connect db: {}", e);
+            MigrationError::Unavailable(format!("{}", e))
+        })?;
+
+        Ok(Self {
+            db_client,
+            dao: PgMigrationDao,
+        })
+    }
+
+    pub fn from_settings(settings: &Settings) -> Result<Self, MigrationError> {
+        Self::new(&settings.database.uri)
+    }
+
+    /// connect to `DATABASE_URI`
+    pub fn from_env() -> Result<Self, MigrationError> {
+        let settings =
+            Settings::from_env().map_err(|e| MigrationError::Unavailable(e.to_string()))?;
+        Self::from_settings(&settings)
+    }
+}
+impl<'a> Migrator<'a, postgres::Transaction<'a>> for MigratorImpl {
+    type D = PgMigrationDao;
+
+    fn run_tx<T, F>(&'a mut self, f: F) -> Result<T, MigrationError>
+    where
+        F: FnOnce(&PgMigrationDao, &mut postgres::Transaction<'a>) -> Result<T, MigrationError>,
+    {
+        let mut ctx = self.db_client.transaction().map_err(|e| {
+            error!("failed to start transaction: {}", e);
+            MigrationError::Unavailable(format!("{}", e))
+        })?;
+        trace!("transaction started");
+
+        match f(&self.dao, &mut ctx) {
+            Ok(v) => {
+                ctx.commit().map_err(|e| {
+                    error!("failed to commit: {}", e);
+                    MigrationError::Unavailable(format!("{}", e))
+                })?;
+                trace!("transaction committed");
+                Ok(v)
+            }
+            Err(e) => {
+                // 失敗した移行のエラーを返す. 切れた接続で rollback できなくても database が捨てる
+                if let Err(e) = ctx.rollback() {
+                    error!("failed to rollback: {}", e);
+                }
+                error!("transaction rollbacked");
+                Err(e)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AsyncPersonUsecaseImpl {
+    dao: AsyncPgPersonDao,
+}
+impl AsyncPersonUsecaseImpl {
+    pub fn new(dao: AsyncPgPersonDao) -> Self {
+        Self { dao }
+    }
+}
+impl AsyncPersonUsecase<tokio_postgres::Client> for AsyncPersonUsecaseImpl {}
+impl HaveAsyncPersonDao<tokio_postgres::Client> for AsyncPersonUsecaseImpl {
+    type D = AsyncPgPersonDao;
+
+    fn get_dao(&self) -> &Self::D {
+        &self.dao
+    }
+}
+
+/// async version of `PersonServiceImpl` on tokio-postgres, async redis and lapin.
+///
+/// It has a single connection to the database, so run one per task to serve requests concurrently.
+/// The connection is opened again when it has been closed.
+pub struct AsyncPersonServiceImpl {
+    db_uri: String,
+    db_conn: Option<AsyncPgConnection>,
+    cache_client: redis::Client,
+    reporter: AsyncDefaultReporter,
+    usecase: AsyncPersonUsecaseImpl,
+    outbox: AsyncPgOutboxDao,
+    cache_connect_timeout: Duration,
+    cache_ttl: Option<Duration>,
+    retry_policy: RetryPolicy,
+}
+impl AsyncPersonServiceImpl {
+    pub async fn new(db_uri: &str, cache_uri: &str, mq_uri: &str) -> Result<Self, StartupError> {
+        Self::from_settings(&settings_with(db_uri, cache_uri, mq_uri)).await
+    }
+
+    /// It fails if the settings are invalid or postgres is unreachable. Without rabbitmq it starts
+    /// with no observer, so only the notifications in the outbox are published later.
+    pub async fn from_settings(settings: &Settings) -> Result<Self, StartupError> {
+        settings.validate()?;
+        let cache_client = cache_client(settings)?;
+        let db_client = connect_async_db(&settings.database.uri)
+            .await
+            .map_err(|e| {
+                error!("postgres is unavailable: {}", e);
+                StartupError::DatabaseUnavailable(e.to_string())
+            })?;
+        let mut reporter = AsyncDefaultReporter::new();
+        match aio::rabbitmq::AsyncClient::open(&settings.mq.uri).await {
+            Ok(client) => reporter.register(client.with_queues(settings.queues.clone()))?,
+            Err(e) => warn!(
+                "rabbitmq is unavailable, running without notifications: {}",
+                e
+            ),
+        }
+
+        Ok(Self {
+            db_uri: settings.database.uri.clone(),
+            db_conn: Some(AsyncPgConnection::new(db_client)),
+            cache_client,
+            reporter,
+            usecase: AsyncPersonUsecaseImpl::new(AsyncPgPersonDao::new("app")),
+            outbox: AsyncPgOutboxDao,
+            cache_connect_timeout: settings.cache.connect_timeout(),
+            cache_ttl: settings.cache.ttl(),
+            retry_policy: settings.retry.policy(),
+        })
+    }
+
+    /// build the service from the environment variables (see `Settings::apply_env`)
+    pub async fn from_env() -> Result<Self, StartupError> {
+        Self::from_settings(&Settings::from_env()?).await
+    }
+}
+#[async_trait::async_trait]
+impl<'a> AsyncPersonService<'a, tokio_postgres::Client> for AsyncPersonServiceImpl {
+    type U = AsyncPersonUsecaseImpl;
+    type N = AsyncDefaultReporter;
+
+    // service is responsible for transaction management
+    async fn run_tx<T, F>(&mut self, f: F) -> Result<T, ServiceError>
+    where
+        T: Send,
+        F: for<'c> FnOnce(
+                &'c mut AsyncPersonUsecaseImpl,
+                &'c mut tokio_postgres::Client,
+            ) -> BoxFuture<'c, Result<T, UsecaseError>>
+            + Send
+            + 'static,
+    {
+        let ctx = begin_async(&self.db_uri, &mut self.db_conn)
+            .await
+            .map_err(|e| {
+                error!("failed to start transaction: {}", e);
+                ServiceError::ServiceUnavailable(format!("{}", e))
+            })?;
+        trace!("transaction started");
+
+        match f(&mut self.usecase, ctx.client()).await {
+            Ok(v) => {
+                ctx.commit().await.map_err(|e| {
+                    error!("failed to commit: {}", e);
+                    ServiceError::ServiceUnavailable(format!("{}", e))
+                })?;
+                trace!("transaction committed");
+                Ok(v)
+            }
+            Err(e) => {
+                if let Err(e) = ctx.rollback().await {
+                    error!("failed to rollback: {}", e);
+                }
+                error!("transaction rollbacked");
+                Err(e.into())
+            }
+        }
+    }
+
+    fn get_reporter(&self) -> Self::N {
+        self.reporter.clone()
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy.clone()
+    }
+
+    // 同期版と同じく, 通知は変更と同じトランザクションで outbox に書く
+    async fn run_tx_and_notify<T, F>(&mut self, f: F) -> Result<T, ServiceError>
+    where
+        T: Send,
+        F: for<'c> FnOnce(
+                &'c mut AsyncPersonUsecaseImpl,
+                &'c mut tokio_postgres::Client,
+            )
+                -> BoxFuture<'c, Result<(T, Vec<Notification>), UsecaseError>>
+            + Send
+            + 'static,
+    {
+        let outbox = self.outbox.clone();
+
+        self.run_tx(move |usecase, ctx| {
+            Box::pin(async move {
+                let (v, notifications) = f(usecase, ctx).await?;
+                for notification in notifications {
+                    outbox
+                        .enqueue(ctx, notification)
+                        .await
+                        .map_err(UsecaseError::EnqueueNotificationFailed)?;
+                }
+                Ok(v)
+            })
+        })
+        .await
+    }
+}
+impl<'a> AsyncPersonCachedService<'a, redis::aio::MultiplexedConnection, tokio_postgres::Client>
+    for AsyncPersonServiceImpl
+{
+    type C = AsyncRedisPersonCao;
+
+    fn get_cao(&self) -> Self::C {
+        AsyncRedisPersonCao::new(self.cache_client.clone(), self.cache_connect_timeout)
+            .with_ttl(self.cache_ttl)
+    }
+}
+
+async fn connect_async_db(uri: &str) -> Result<tokio_postgres::Client, tokio_postgres::Error> {
+    let (db_client, conn) = tokio_postgres::connect(uri, NoTls).await?;
+    // 接続は別のタスクで動かす必要がある
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            error!("db connection error: {}", e);
+        }
+    });
+    Ok(db_client)
+}
+
+// 接続のタスクは切れたら終わるので, 閉じていれば開き直し, 始められなければ捨てて開き直して試し直す.
+// 始められた接続だけを次の呼び出しに持ち越す
+async fn begin_async<'a>(
+    uri: &str,
+    conn: &'a mut Option<AsyncPgConnection>,
+) -> Result<&'a mut AsyncPgConnection, tokio_postgres::Error> {
+    let mut last = conn.take();
+    let begun = ReconnectPolicy::default()
+        .run_async("postgres", is_connection_error, || {
+            let last = last.take();
+            async move {
+                let mut conn = match last {
+                    Some(conn) if !conn.is_closed() => conn,
+                    _ => {
+                        warn!("db connection is closed, reconnecting");
+                        AsyncPgConnection::new(connect_async_db(uri).await?)
+                    }
+                };
+                conn.begin().await?;
+                Ok(conn)
+            }
+        })
+        .await?;
+    Ok(conn.insert(begun))
+}
+
+// 既定の設定の URI だけを差し替えたもの
+fn settings_with(db_uri: &str, cache_uri: &str, mq_uri: &str) -> Settings {
+    let mut settings = Settings::default();
+    settings.database.uri = db_uri.to_string();
+    settings.cache.uri = cache_uri.to_string();
+    settings.mq.uri = mq_uri.to_string();
+    settings
+}
+
+// postgres と redis の pool. 接続は使うときに開く
+fn pools(
+    settings: &Settings,
+) -> Result<(Pool<PgConnectionManager>, Pool<RedisConnectionManager>), StartupError> {
+    let db_pool = db_pool(settings, settings.database.pool_config());
+    let cache_pool = Pool::new(
+        RedisConnectionManager::new(cache_client(settings)?, settings.cache.connect_timeout()),
+        settings.cache.pool_config(),
+    );
+
+    Ok((db_pool, cache_pool))
+}
+
+// redis::Client::open は接続せずに URI を読むだけなので, 失敗するのは設定の誤り
+fn cache_client(settings: &Settings) -> Result<redis::Client, StartupError> {
+    redis::Client::open(settings.cache.uri.as_str()).map_err(|e| {
+        ConfigError::Invalid {
+            key: "cache.uri".to_string(),
+            reason: e.to_string(),
+        }
+        .into()
+    })
+}
+
+fn db_pool(settings: &Settings, config: PoolConfig) -> Pool<PgConnectionManager> {
+    let db_manager = PgConnectionManager::new(&settings.database.uri)
+        .with_validation_timeout(settings.database.validation_timeout());
+    Pool::new(db_manager, config)
+}
+
+// トランザクションを始められない理由
+#[derive(Debug, thiserror::Error)]
+enum BeginError {
+    #[error("failed to get db connection: {0}")]
+    Checkout(#[from] PoolError),
+    #[error("failed to start transaction: {0}")]
+    Begin(#[from] postgres::Error),
+}
+impl BeginError {
+    // 繋ぎ直せば通るかもしれないもの
+    fn is_lost(&self) -> bool {
+        match self {
+            Self::Checkout(e) => matches!(e, PoolError::Connect(_)),
+            Self::Begin(e) => is_connection_error(e),
+        }
+    }
+}
+
+// 切れた接続は checkout のときに pool が確かめて開き直すが, 確かめた後に切れて始められなければ
+// 別の接続で試し直す. 始められなかった接続は次を借りる前に pool に返すので, 借りるのは常に 1 本
+fn begin(pool: &Pool<PgConnectionManager>) -> Result<PgTransaction, BeginError> {
+    ReconnectPolicy::default().run("postgres", BeginError::is_lost, || {
+        Ok(PgTransaction::begin(pool.get()?)?)
+    })
+}
+
+// postgres と redis の pool, rabbitmq への observer と, 始めたときの redis の状態
+struct Backends {
+    db_pool: Pool<PgConnectionManager>,
+    cache_pool: Pool<RedisConnectionManager>,
+    cache_health: Health,
+    observer: BufferedObserver<rabbitmq::Client>,
+}
+
+// postgres は必須なので繋がらなければ始めない.
+// redis と rabbitmq は繋がらなくても縮退して始め, background で繋ぎ直す
+fn connect_backends(settings: &Settings) -> Result<Backends, StartupError> {
+    settings.validate()?;
+    let (db_pool, cache_pool) = pools(settings)?;
+    db_pool.get().map_err(|e| {
+        error!("postgres is unavailable: {}", e);
+        StartupError::DatabaseUnavailable(e.to_string())
+    })?;
+
+    Ok(Backends {
+        cache_health: cache_health(&cache_pool),
+        observer: mq_observer(settings, settings.mq.buffer_size),
+        db_pool,
+        cache_pool,
+    })
+}
+
+// 繋がらなければ down にして, 繋がったら古いかもしれない cache を消してから up に戻す
+fn cache_health(cache_pool: &Pool<RedisConnectionManager>) -> Health {
+    let Err(e) = cache_pool.get() else {
+        return Health::up();
+    };
+    warn!("redis is unavailable, running without the cache: {}", e);
+    let health = Health::down();
+    let cao = redis_cache::RedisPersonCao::new(cache_pool.clone());
+    let target = health.clone();
+    backend::spawn_reconnect(
+        "redis",
+        Backoff::default(),
+        move || cao.clear(),
+        move |cleared| {
+            trace!("cleared {} cached persons", cleared);
+            target.set_up(true);
+        },
+    );
+    health
+}
+
+// 繋がらなければ capacity まで通知を溜めておき, 繋がったら送る
+fn mq_observer(settings: &Settings, capacity: usize) -> BufferedObserver<rabbitmq::Client> {
+    let uri = settings.mq.uri.clone();
+    let queues = settings.queues.clone();
+    let open =
+        move || rabbitmq::Client::open(&uri).map(|client| client.with_queues(queues.clone()));
+    match open() {
+        Ok(client) => BufferedObserver::connected(client),
+        Err(e) => {
+            warn!(
+                "rabbitmq is unavailable, keeping up to {} notifications: {}",
+                capacity, e
+            );
+            let observer = BufferedObserver::new(capacity);
+            let target = observer.clone();
+            backend::spawn_reconnect("rabbitmq", Backoff::default(), open, move |client| {
+                target.connect(client)
+            });
+            observer
+        }
+    }
+}
+
+// # 結合テスト
+//
+// ## 目的
+//
+//   外部のサービスなしに, SQLite 上でサービスを端から端まで動かせることを保障する
+//
+// ## 方針
+//
+//   メモリ上の SQLite で登録から削除までを実行し, 結果と履歴を確認する
+//   開けない場所を渡したときは, 使えないことがエラーで返ることを確認する
+//
+#[cfg(test)]
+mod sqlite_tests {
+    use super::*;
+    use crate::domain::date;
+    use crate::dto::ChangeKind;
+
+    #[test]
+    fn test_service_on_sqlite() {
+        let mut service = SqlitePersonServiceImpl::open_in_memory()
+            .unwrap()
+            .with_actor("test");
+
+        let (id, person) = service
+            .register("Abel", date(1802, 8, 5), None, "Abel's theorem")
+            .unwrap();
+        assert_eq!(service.find(id), Ok(Some(person.clone())));
+
+        service.death(id, date(1829, 4, 6)).unwrap();
+        let dead = service.find(id).unwrap().unwrap();
+        assert_eq!(dead.death_date, Some(date(1829, 4, 6)));
+        assert_eq!(dead.revision, person.revision + 1);
+
+        service.unregister(id).unwrap();
+        assert_eq!(service.find(id), Ok(None));
+        assert_eq!(service.list_all(), Ok(vec![]));
+
+        let history = service.history(id).unwrap();
+        assert_eq!(
+            history.iter().map(|h| h.kind).collect::<Vec<_>>(),
+            [ChangeKind::Insert, ChangeKind::Update, ChangeKind::Delete]
+        );
+        assert!(history.iter().all(|h| h.actor == "test"));
+    }
+
+    #[test]
+    fn test_service_on_sqlite_rollback() {
+        let mut service = SqlitePersonServiceImpl::open_in_memory().unwrap();
+
+        // 死亡日が誕生日より前なら登録されず, 何も残らない
+        let result = service.register("Abel", date(1802, 8, 5), Some(date(1802, 8, 4)), "");
+        assert!(result.is_err());
+        assert_eq!(service.list_all(), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_open_unavailable() {
+        // 開けなければ panic せずに知らせる
+        let result = SqlitePersonServiceImpl::open("/nonexistent/person.db");
+        assert!(matches!(result, Err(StartupError::DatabaseUnavailable(_))));
+    }
+}
+
+// # 結合テスト
+//
+// ## 目的
+//
+//   外部のサービスなしに, インメモリのバックエンドでサービスを端から端まで動かせることを保障する
+//
+// ## 方針
+//
+//   キャッシュ付きのサービスを実行し, 結果とキャッシュ, 記録された通知を確認する
+//
+#[cfg(test)]
+mod memory_tests {
+    use super::*;
+    use crate::domain::date;
+    use crate::event::PersonEvent;
+
+    #[test]
+    fn test_service_on_memory() {
+        let mut service = MemoryPersonServiceImpl::new().with_actor("test");
+
+        let (id, person) = service
+            .cached_register("Abel", date(1802, 8, 5), None, "Abel's theorem")
+            .unwrap();
+        assert_eq!(service.cached_find(id), Ok(Some(person.clone())));
+        assert_eq!(service.cache().get(id), Some(person));
+
+        // クローンは同じデータを見る
+        let mut other = service.clone();
+        other.cached_death(id, date(1829, 4, 6)).unwrap();
+        assert_eq!(service.cache().get(id), None);
+        let dead = service.cached_find(id).unwrap().unwrap();
+        assert_eq!(dead.death_date, Some(date(1829, 4, 6)));
+
+        let history = service.history(id).unwrap();
+        assert!(history.iter().all(|h| h.actor == "test"));
+
+        // 削除は 2 度目には何も起きないので通知もない
+        service.cached_unregister(id).unwrap();
+        service.cached_unregister(id).unwrap();
+
+        // イベントには保存された版が入る
+        let events = service.observer().events();
+        assert!(matches!(
+            events[..],
+            [
+                PersonEvent::Registered { .. },
+                PersonEvent::Died { revision, .. },
+                PersonEvent::Unregistered { revision: deleted, .. },
+            ] if revision == dead.revision && deleted == dead.revision + 1
+        ));
+    }
+
+    #[test]
+    fn test_service_on_memory_restore_alive() {
+        let mut service = MemoryPersonServiceImpl::new();
+        let (id, person) = service
+            .cached_register("Abel", date(1802, 8, 5), None, "")
+            .unwrap();
+
+        // 削除されていなければ戻すものがないので, 版も上がらず通知もない
+        let result = service.cached_restore(id);
+        assert_eq!(
+            result,
+            Err(ServiceError::TransactionFailed(
+                UsecaseError::PersonNotFound(id)
+            ))
+        );
+        assert_eq!(service.find(id), Ok(Some(person)));
+        assert!(matches!(
+            service.observer().events()[..],
+            [PersonEvent::Registered { .. }]
+        ));
+    }
+
+    #[test]
+    fn test_service_on_memory_rollback() {
+        let mut service = MemoryPersonServiceImpl::new();
+
+        // 死亡日が誕生日より前なら登録されず, 何も残らない
+        let result = service.register("Abel", date(1802, 8, 5), Some(date(1802, 8, 4)), "");
+        assert!(result.is_err());
+        assert_eq!(service.list_all(), Ok(vec![]));
+        // 失敗の報告はあっても, 登録のイベントはない
+        assert_eq!(service.observer().events(), vec![]);
+
+        // 採番も戻っている
+        let (id, _) = service
+            .register("Abel", date(1802, 8, 5), None, "")
+            .unwrap();
+        assert_eq!(id, 1);
+    }
+}
+
+// # 結合テスト
+//
+// ## 目的
+//
+//   設定が誤っていれば, どのバックエンドにも繋ぐ前に panic せずエラーで知らせることを保障する
+//
+// ## 方針
+//
+//   読めない cache の URI や, pool より多い workers を渡して, 設定のエラーが返ることを確認する
+//   繋ぐ前に弾かれるので, postgres などは要らない
+//
+#[cfg(test)]
+mod startup_tests {
+    use super::*;
+
+    fn invalid_key(result: Result<impl Sized, StartupError>) -> String {
+        match result {
+            Err(StartupError::Config(ConfigError::Invalid { key, .. })) => key,
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("started with invalid settings"),
+        }
+    }
+
+    fn bad_cache_uri() -> Settings {
+        let mut settings = Settings::default();
+        settings.cache.uri = "not a redis uri".to_string();
+        settings
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        assert_eq!(
+            invalid_key(PersonServiceImpl::from_settings(&bad_cache_uri())),
+            "cache.uri"
+        );
+        assert_eq!(
+            invalid_key(SharedPersonServiceImpl::from_settings(&bad_cache_uri())),
+            "cache.uri"
+        );
+
+        let mut settings = Settings::default();
+        settings.server.workers = settings.database.pool_size + 1;
+        assert_eq!(
+            invalid_key(PersonServiceImpl::from_settings(&settings)),
+            "server.workers"
+        );
+
+        settings.mq.uri = "not an amqp uri".to_string();
+        assert_eq!(
+            invalid_key(OutboxRelayImpl::from_settings(&settings)),
+            "mq.uri"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_settings_async() {
+        assert_eq!(
+            invalid_key(AsyncPersonServiceImpl::from_settings(&bad_cache_uri()).await),
+            "cache.uri"
+        );
+    }
+}
+
+// # 結合テスト
+//
+// ## 目的
+//
+//   migration を導入する前の initdb.sh で作った database にも, migration を適用できることと,
+//   pool から渡された接続が切れていてもトランザクションを始められることを保障する
+//
+// ## 方針
+//
+//   TEST_DATABASE_URI の postgres に使い捨ての schema を作り, 当時の形の表と行を置いてから migrate する
+//   後から増えた列が足され, 行が残り, 二度目は何もしないことを確認する
+//   接続は pool に戻した後に server 側で切り, 確かめずに渡させて別の接続で始まることを確認する
+//   pool は 1 本にして, 切れた接続を返してから次を借りていることを確認する
+//   postgres が要るので, 既定では実行しない (cargo test -- --ignored で実行する)
+//
+#[cfg(test)]
+mod pg_tests {
+    use super::*;
+    use crate::dao::PersonDao;
+    use crate::domain::date;
+    use crate::dto::PersonDto;
+    use crate::migration::{Migrator, MIGRATIONS};
+    use crate::pool::PoolState;
+    use crate::service::PersonOutputBoundary;
+    use std::rc::Rc;
+
+    fn uri() -> String {
+        std::env::var("TEST_DATABASE_URI").expect("TEST_DATABASE_URI is not set")
+    }
+
+    // 使い捨ての schema を search_path の先頭にした接続. テストは並行に走るので schema はテストごとに分ける
+    fn connect(schema: &str) -> postgres::Client {
+        let mut client = postgres::Client::connect(&uri(), NoTls).expect("connect");
+        client
+            .batch_execute(&format!(
+                "DROP SCHEMA IF EXISTS {0} CASCADE; CREATE SCHEMA {0}; SET search_path TO {0}, public",
+                schema
+            ))
+            .expect("create schema");
+        client
+    }
+
+    // 最新の schema まで migrate した接続
+    fn migrated(schema: &str) -> postgres::Client {
+        let mut migrator = MigratorImpl {
+            db_client: connect(schema),
+            dao: PgMigrationDao,
+        };
+        migrator.migrate(false).expect("migrate");
+        migrator.db_client
+    }
+
+    fn drop_schema(client: &mut postgres::Client, schema: &str) {
+        client
+            .batch_execute(&format!("DROP SCHEMA {} CASCADE", schema))
+            .expect("drop schema");
+    }
+
+    fn columns(client: &mut postgres::Client, schema: &str, table: &str) -> Vec<String> {
+        client
+            .query(
+                r#"SELECT column_name::TEXT
+                     FROM information_schema.columns
+                    WHERE table_schema = $1 AND table_name = $2
+                 ORDER BY ordinal_position"#,
+                &[&schema, &table],
+            )
+            .expect("columns")
+            .iter()
+            .map(|row| row.get::<usize, String>(0))
+            .collect()
+    }
+
+    #[test]
+    #[ignore = "needs postgres at TEST_DATABASE_URI"]
+    fn test_migrate_baseline_schema() {
+        const SCHEMA: &str = "migration_test";
+        let mut client = connect(SCHEMA);
+        // baseline の person と, events を型付けする前の outbox と, reason を足す前の person_history
+        client
+            .batch_execute(
+                r#"CREATE TABLE person (
+                     id           SERIAL PRIMARY KEY,
+                     name         TEXT NOT NULL,
+                     birth_date   DATE NOT NULL,
+                     death_date   DATE,
+                     data         BYTEA,
+
+                     revision     INT NOT NULL
+                   );
+                   INSERT INTO person (name, birth_date, data, revision)
+                   VALUES ('Gauss', '1777-04-30', 'King of Mathematics', 1);
+                   CREATE TABLE outbox (
+                     id              BIGSERIAL PRIMARY KEY,
+                     level           TEXT NOT NULL,
+                     queue           TEXT NOT NULL,
+                     message         TEXT NOT NULL,
+                     file            TEXT NOT NULL,
+                     line            INT NOT NULL,
+                     column_no       INT NOT NULL,
+                     attempts        INT NOT NULL DEFAULT 0,
+                     last_error      TEXT,
+                     next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                     created_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+                   );
+                   CREATE TABLE person_history (
+                     id           BIGSERIAL PRIMARY KEY,
+                     person_id    INT NOT NULL,
+                     revision     INT NOT NULL,
+                     kind         TEXT NOT NULL,
+                     old_value    JSONB,
+                     new_value    JSONB,
+                     changed_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+                     actor        TEXT NOT NULL
+                   );"#,
+            )
+            .expect("baseline schema");
+
+        let mut migrator = MigratorImpl {
+            db_client: client,
+            dao: PgMigrationDao,
+        };
+        let applied = migrator.migrate(false).expect("migrate");
+        assert_eq!(applied.len(), MIGRATIONS.len());
+        assert_eq!(migrator.migrate(false), Ok(vec![]));
+
+        let client = &mut migrator.db_client;
+        assert!(columns(client, SCHEMA, "person").contains(&"deleted_at".to_string()));
+        assert!(columns(client, SCHEMA, "outbox").contains(&"event".to_string()));
+        assert!(columns(client, SCHEMA, "person_history").contains(&"reason".to_string()));
+        let names = client
+            .query("SELECT name FROM person WHERE deleted_at IS NULL", &[])
+            .expect("select person")
+            .iter()
+            .map(|row| row.get::<usize, String>(0))
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Gauss"]);
+
+        // 死亡日の訂正は理由を履歴に残す
+        let dao = PgPersonDao::new("test");
+        client.batch_execute("BEGIN").expect("begin");
+        let dead = PersonDto::new(
+            "Gauss",
+            date(1777, 4, 30),
+            Some(date(1855, 2, 23)),
+            Some("King of Mathematics"),
+            2,
+        );
+        dao.correct(1, 1, dead.clone(), "found the record".to_string())
+            .run(client)
+            .expect("correct");
+        let history = dao.history(1).run(client).expect("history");
+        client.batch_execute("COMMIT").expect("commit");
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].new, Some(dead));
+        assert_eq!(history[0].reason.as_deref(), Some("found the record"));
+
+        drop_schema(client, SCHEMA);
+    }
+
+    #[test]
+    #[ignore = "needs postgres at TEST_DATABASE_URI"]
+    fn test_clear_data() {
+        const SCHEMA: &str = "clear_data_test";
+        let mut client = migrated(SCHEMA);
+        let dao = PgPersonDao::new("test");
+        let abel = PersonDto::new("Abel", date(1802, 8, 5), None, Some("Abel's theorem"), 0);
+
+        // update --clear-data と同じく data を NULL にしてから読み直す
+        client.batch_execute("BEGIN").expect("begin");
+        let id = dao.insert(abel.clone()).run(&mut client).expect("insert");
+        let cleared = PersonDto {
+            data: None,
+            revision: 1,
+            ..abel
+        };
+        dao.save(id, 0, cleared.clone())
+            .run(&mut client)
+            .expect("save");
+        assert_eq!(dao.fetch(id).run(&mut client), Ok(Some(cleared.clone())));
+        assert_eq!(
+            dao.select().run(&mut client),
+            Ok(vec![(id, cleared.clone())])
+        );
+        let history = dao.history(id).run(&mut client).expect("history");
+        assert_eq!(history[1].new, Some(cleared));
+        client.batch_execute("COMMIT").expect("commit");
+
+        drop_schema(&mut client, SCHEMA);
+    }
+
+    #[test]
+    #[ignore = "needs postgres at TEST_DATABASE_URI"]
+    fn test_query_cursor() {
+        const SCHEMA: &str = "query_cursor_test";
+        let mut client = migrated(SCHEMA);
+
+        // インメモリ版と同じケースで, 同じ結果になる
+        crate::dao::check_query_cursor(&PgPersonDao::new("test"), &mut client);
+
+        drop_schema(&mut client, SCHEMA);
+    }
+
+    struct NoProgress;
+    impl PersonOutputBoundary<(u64, u64), ServiceError> for NoProgress {
+        fn started(&self) {}
+        fn in_progress(&self, _progress: (u64, u64)) {}
+        fn completed(&self) {}
+        fn aborted(&self, _err: ServiceError) {}
+    }
+
+    #[test]
+    #[ignore = "needs postgres at TEST_DATABASE_URI"]
+    fn test_import_without_data() {
+        const SCHEMA: &str = "import_test";
+        let mut client = migrated(SCHEMA);
+        // pool の接続も同じ schema を見るようにする
+        let uri = if uri().contains("://") {
+            let sep = if uri().contains('?') { '&' } else { '?' };
+            format!("{}{}options=-c%20search_path%3D{}", uri(), sep, SCHEMA)
+        } else {
+            format!("{} options='-c search_path={}'", uri(), SCHEMA)
+        };
+        let cache_client = redis::Client::open("redis://localhost").expect("cache client");
+        let mut service = PersonServiceImpl::from_pools(
+            Pool::new(PgConnectionManager::new(&uri), PoolConfig::default()),
+            Pool::new(
+                RedisConnectionManager::new(cache_client, Duration::from_secs(1)),
+                PoolConfig::default(),
+            ),
+            DefaultReporter::new(),
+        );
+        let csv = "name,birth_date,death_date,data\nGalois,1811-10-25,,\n";
+
+        // data の列が空なら NULL で入る
+        let ids = crate::importer::import(
+            &mut service,
+            crate::importer::Format::Csv,
+            || Ok(std::io::Cursor::new(csv)),
+            Rc::new(NoProgress),
+        )
+        .expect("import");
+        let galois = PersonDto::new("Galois", date(1811, 10, 25), None, None, 0);
+        assert_eq!(service.find(ids[0]), Ok(Some(galois.clone())));
+        assert_eq!(service.list_all(), Ok(vec![(ids[0], galois)]));
+
+        drop(service);
+        drop_schema(&mut client, SCHEMA);
+    }
+
+    #[test]
+    #[ignore = "needs postgres at TEST_DATABASE_URI"]
+    fn test_begin_after_connection_lost() {
+        let pool = Pool::new(
+            PgConnectionManager::new(&uri()),
+            // 試し直しで 2 本目を借りようとすれば, 返されるまで待って時間切れになる
+            PoolConfig {
+                max_size: 1,
+                checkout_timeout: Duration::from_millis(100),
+                test_on_checkout: false,
+            },
+        );
+        let pid: i32 = {
+            let mut tx = begin(&pool).expect("begin");
+            let pid = tx
+                .client()
+                .query_one("SELECT pg_backend_pid()", &[])
+                .unwrap()
+                .get(0);
+            tx.commit().expect("commit");
+            pid
+        };
+        let mut admin = postgres::Client::connect(&uri(), NoTls).expect("connect");
+        admin
+            .query_one("SELECT pg_terminate_backend($1)", &[&pid])
+            .expect("terminate");
+
+        // 切られた接続では始められないので, 開き直した別の接続で始まる
+        let mut tx = begin(&pool).expect("begin again");
+        let other: i32 = tx
+            .client()
+            .query_one("SELECT pg_backend_pid()", &[])
+            .unwrap()
+            .get(0);
+        assert_ne!(pid, other);
+        drop(tx);
+        assert_eq!(
+            pool.state(),
+            PoolState {
+                connections: 1,
+                idle_connections: 1
+            }
+        );
+    }
+}
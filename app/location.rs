@@ -11,7 +11,7 @@ pub struct Location<'a> {
 #[macro_export]
 macro_rules! location {
     () => {
-        crate::location::Location {
+        $crate::location::Location {
             file: file!(),
             line: line!(),
             column: column!(),
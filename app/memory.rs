@@ -0,0 +1,804 @@
+use chrono::{DateTime, Utc};
+use log::{info, trace, warn};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::cache::{CaoError, PersonCao};
+use crate::dao::{DaoError, PersonDao};
+use crate::domain::{PersonId, Revision};
+use crate::dto::{ChangeKind, PersonDto, PersonHistoryDto};
+use crate::event::PersonEvent;
+use crate::reporter::{Level, Location, Observer, ReporterError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Row {
+    person: PersonDto,
+    // 論理削除された日時
+    deleted_at: Option<DateTime<Utc>>,
+}
+
+// トランザクションを始めたときの採番と履歴の長さ, 変更した行の元の値 (変更した順)
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Undo {
+    next_id: PersonId,
+    history_len: usize,
+    rows: Vec<(PersonId, Option<Row>)>,
+}
+
+/// The tables of the in-memory backend, the context of `MemoryPersonDao`.
+///
+/// Between `begin` and `commit` the rows are changed in place, remembering their old values,
+/// so `rollback` puts them back and a failed transaction leaves nothing as one of postgres does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryDb {
+    next_id: PersonId,
+    persons: HashMap<PersonId, Row>,
+    history: Vec<PersonHistoryDto>,
+    undo: Option<Undo>,
+}
+impl Default for MemoryDb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl MemoryDb {
+    /// empty tables. The ids start from 1.
+    pub fn new() -> Self {
+        Self::with_persons(1, vec![])
+    }
+
+    /// the tables with `persons`. The ids of new ones are allocated from `next_id`.
+    pub fn with_persons(
+        next_id: PersonId,
+        persons: impl IntoIterator<Item = (PersonId, PersonDto)>,
+    ) -> Self {
+        Self {
+            next_id,
+            persons: persons
+                .into_iter()
+                .map(|(id, person)| {
+                    let row = Row {
+                        person,
+                        deleted_at: None,
+                    };
+                    (id, row)
+                })
+                .collect(),
+            history: vec![],
+            undo: None,
+        }
+    }
+
+    /// add persons soft deleted at the given time
+    pub fn with_deleted(
+        mut self,
+        persons: impl IntoIterator<Item = (PersonId, PersonDto, DateTime<Utc>)>,
+    ) -> Self {
+        for (id, person, deleted_at) in persons {
+            let row = Row {
+                person,
+                deleted_at: Some(deleted_at),
+            };
+            self.persons.insert(id, row);
+        }
+        self
+    }
+
+    /// the id allocated to the next person
+    pub fn next_id(&self) -> PersonId {
+        self.next_id
+    }
+
+    /// the persons not deleted, in the order of the ids
+    pub fn persons(&self) -> Vec<(PersonId, PersonDto)> {
+        self.rows(|row| row.deleted_at.is_none())
+            .into_iter()
+            .map(|(id, row)| (id, row.person))
+            .collect()
+    }
+
+    /// the persons soft deleted and when, in the order of the ids
+    pub fn deleted(&self) -> Vec<(PersonId, PersonDto, DateTime<Utc>)> {
+        self.rows(|row| row.deleted_at.is_some())
+            .into_iter()
+            .filter_map(|(id, row)| row.deleted_at.map(|at| (id, row.person, at)))
+            .collect()
+    }
+
+    fn rows(&self, p: impl Fn(&Row) -> bool) -> Vec<(PersonId, Row)> {
+        let mut rows = self
+            .persons
+            .iter()
+            .filter(|(_, row)| p(row))
+            .map(|(id, row)| (*id, row.clone()))
+            .collect::<Vec<_>>();
+        rows.sort_by_key(|(id, _)| *id);
+        rows
+    }
+
+    /// start a transaction, remembering what is changed until `commit` or `rollback`
+    pub fn begin(&mut self) {
+        self.undo = Some(Undo {
+            next_id: self.next_id,
+            history_len: self.history.len(),
+            rows: vec![],
+        });
+    }
+
+    /// keep the changes since `begin`
+    pub fn commit(&mut self) {
+        self.undo = None;
+    }
+
+    /// undo the changes since `begin`
+    pub fn rollback(&mut self) {
+        let Some(undo) = self.undo.take() else {
+            return;
+        };
+        // 同じ行を何度も変えていれば, 逆順に戻すと最初の値になる
+        for (id, row) in undo.rows.into_iter().rev() {
+            match row {
+                Some(row) => self.persons.insert(id, row),
+                None => self.persons.remove(&id),
+            };
+        }
+        self.history.truncate(undo.history_len);
+        self.next_id = undo.next_id;
+    }
+
+    fn alive(&self, id: PersonId) -> Option<&Row> {
+        self.persons.get(&id).filter(|row| row.deleted_at.is_none())
+    }
+
+    fn alive_mut(&mut self, id: PersonId) -> Option<&mut Row> {
+        self.row_mut(id).filter(|row| row.deleted_at.is_none())
+    }
+
+    fn row_mut(&mut self, id: PersonId) -> Option<&mut Row> {
+        self.remember(id);
+        self.persons.get_mut(&id)
+    }
+
+    // トランザクション中なら, 変える前の行を覚えておく
+    fn remember(&mut self, id: PersonId) {
+        if let Some(undo) = &mut self.undo {
+            undo.rows.push((id, self.persons.get(&id).cloned()));
+        }
+    }
+
+    // 履歴は追記のみ. rollback では元の変更と一緒に切り詰めるので, どちらかだけが残ることはない
+    fn record(
+        &mut self,
+        id: PersonId,
+        kind: ChangeKind,
+        old: Option<&PersonDto>,
+        new: Option<&PersonDto>,
+        actor: &str,
+        reason: Option<&str>,
+    ) {
+        self.history.push(PersonHistoryDto {
+            person_id: id,
+            revision: new.or(old).map(|p| p.revision).unwrap_or_default(),
+            kind,
+            old: old.cloned(),
+            new: new.cloned(),
+            changed_at: Utc::now(),
+            actor: actor.to_string(),
+            reason: reason.map(str::to_string),
+        });
+    }
+}
+
+/// `PersonDao` on `MemoryDb`, with the same semantics, revision check and history as `PgPersonDao`.
+#[derive(Debug, Clone)]
+pub struct MemoryPersonDao {
+    actor: String,
+}
+impl MemoryPersonDao {
+    /// `actor` is recorded in the history as who made the changes.
+    pub fn new(actor: &str) -> Self {
+        Self {
+            actor: actor.to_string(),
+        }
+    }
+
+    // save と correct の共通部分. reason は履歴に残す
+    fn save_with(
+        &self,
+        id: PersonId,
+        revision: Revision,
+        person: PersonDto,
+        reason: Option<String>,
+    ) -> impl tx_rs::Tx<MemoryDb, Item = (), Err = DaoError> {
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |db: &mut MemoryDb| {
+            let Some(row) = db.alive_mut(id) else {
+                return Err(DaoError::UpdateError(format!("person not found: {id}")));
+            };
+            if row.person.revision != revision {
+                warn!(
+                    "revision conflict on person {}: expected={}, actual={}",
+                    id, revision, row.person.revision
+                );
+                return Err(DaoError::RevisionConflict {
+                    expected: revision,
+                    actual: row.person.revision,
+                });
+            }
+
+            let old = std::mem::replace(&mut row.person, person.clone());
+            db.record(
+                id,
+                ChangeKind::Update,
+                Some(&old),
+                Some(&person),
+                &actor,
+                reason.as_deref(),
+            );
+
+            Ok(())
+        })
+    }
+}
+// query と search は既定の実装 (select して絞り込む) のまま
+impl PersonDao<MemoryDb> for MemoryPersonDao {
+    fn insert(
+        &self,
+        person: PersonDto,
+    ) -> impl tx_rs::Tx<MemoryDb, Item = PersonId, Err = DaoError> {
+        trace!("inserting person: {:?}", person);
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |db: &mut MemoryDb| {
+            let id = db.next_id;
+            db.next_id += 1;
+            db.record(id, ChangeKind::Insert, None, Some(&person), &actor, None);
+            db.remember(id);
+            db.persons.insert(
+                id,
+                Row {
+                    person,
+                    deleted_at: None,
+                },
+            );
+
+            Ok(id)
+        })
+    }
+    fn fetch(
+        &self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<MemoryDb, Item = Option<PersonDto>, Err = DaoError> {
+        trace!("fetching person: {:?}", id);
+        tx_rs::with_tx(move |db: &mut MemoryDb| Ok(db.alive(id).map(|row| row.person.clone())))
+    }
+    fn select(
+        &self,
+    ) -> impl tx_rs::Tx<MemoryDb, Item = Vec<(PersonId, PersonDto)>, Err = DaoError> {
+        trace!("selecting all persons");
+        tx_rs::with_tx(|db: &mut MemoryDb| Ok(db.persons()))
+    }
+    fn save(
+        &self,
+        id: PersonId,
+        revision: Revision,
+        person: PersonDto,
+    ) -> impl tx_rs::Tx<MemoryDb, Item = (), Err = DaoError> {
+        trace!("saving person: {:?}", id);
+        self.save_with(id, revision, person, None)
+    }
+    fn correct(
+        &self,
+        id: PersonId,
+        revision: Revision,
+        person: PersonDto,
+        reason: String,
+    ) -> impl tx_rs::Tx<MemoryDb, Item = (), Err = DaoError> {
+        trace!("correcting person: {:?}", id);
+        self.save_with(id, revision, person, Some(reason))
+    }
+    fn delete(
+        &self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<MemoryDb, Item = Option<Revision>, Err = DaoError> {
+        trace!("deleting person: {:?}", id);
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |db: &mut MemoryDb| {
+            let Some(row) = db.alive_mut(id) else {
+                return Ok(None);
+            };
+            let old = row.person.clone();
+            row.person.revision += 1;
+            row.deleted_at = Some(Utc::now());
+            let new = row.person.clone();
+            db.record(id, ChangeKind::Delete, Some(&old), Some(&new), &actor, None);
+
+            Ok(Some(new.revision))
+        })
+    }
+    fn restore(
+        &self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<MemoryDb, Item = Option<Revision>, Err = DaoError> {
+        trace!("restoring person: {:?}", id);
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |db: &mut MemoryDb| {
+            let Some(row) = db.row_mut(id).filter(|row| row.deleted_at.is_some()) else {
+                return Ok(None);
+            };
+            let old = row.person.clone();
+            row.person.revision += 1;
+            row.deleted_at = None;
+            let new = row.person.clone();
+            db.record(
+                id,
+                ChangeKind::Restore,
+                Some(&old),
+                Some(&new),
+                &actor,
+                None,
+            );
+
+            Ok(Some(new.revision))
+        })
+    }
+    fn purge(&self, before: DateTime<Utc>) -> impl tx_rs::Tx<MemoryDb, Item = u64, Err = DaoError> {
+        trace!("purging persons deleted before: {:?}", before);
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |db: &mut MemoryDb| {
+            let purged = db
+                .deleted()
+                .into_iter()
+                .filter(|(_, _, deleted_at)| *deleted_at < before)
+                .collect::<Vec<_>>();
+            for (id, person, _) in &purged {
+                db.remember(*id);
+                db.persons.remove(id);
+                db.record(*id, ChangeKind::Purge, Some(person), None, &actor, None);
+            }
+
+            Ok(purged.len() as u64)
+        })
+    }
+    fn history(
+        &self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<MemoryDb, Item = Vec<PersonHistoryDto>, Err = DaoError> {
+        trace!("fetching history of person: {:?}", id);
+        tx_rs::with_tx(move |db: &mut MemoryDb| {
+            Ok(db
+                .history
+                .iter()
+                .filter(|h| h.person_id == id)
+                .cloned()
+                .collect())
+        })
+    }
+}
+
+/// The cache of the in-memory backend, the connection of `MemoryPersonCao`.
+///
+/// The clones share the same entries.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryCache {
+    entries: Arc<Mutex<HashMap<PersonId, PersonDto>>>,
+}
+impl MemoryCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, id: PersonId) -> Option<PersonDto> {
+        self.entries.lock().unwrap().get(&id).cloned()
+    }
+
+    pub fn insert(&self, id: PersonId, person: PersonDto) {
+        self.entries.lock().unwrap().insert(id, person);
+    }
+
+    pub fn remove(&self, id: PersonId) {
+        self.entries.lock().unwrap().remove(&id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl FromIterator<(PersonId, PersonDto)> for MemoryCache {
+    fn from_iter<I: IntoIterator<Item = (PersonId, PersonDto)>>(iter: I) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(iter.into_iter().collect())),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryPersonCao {
+    cache: MemoryCache,
+}
+impl MemoryPersonCao {
+    pub fn new(cache: MemoryCache) -> Self {
+        Self { cache }
+    }
+}
+impl PersonCao<MemoryCache> for MemoryPersonCao {
+    fn get_conn(&self) -> Result<MemoryCache, CaoError> {
+        Ok(self.cache.clone())
+    }
+
+    fn run_tx<T, F>(&self, f: F) -> Result<T, CaoError>
+    where
+        F: tx_rs::Tx<MemoryCache, Item = T, Err = CaoError>,
+    {
+        let mut conn = self.get_conn()?;
+        f.run(&mut conn)
+    }
+
+    fn find(
+        &self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<MemoryCache, Item = Option<PersonDto>, Err = CaoError> {
+        tx_rs::with_tx(move |cache: &mut MemoryCache| Ok(cache.get(id)))
+    }
+    fn load(
+        &self,
+        id: PersonId,
+        person: &PersonDto,
+    ) -> impl tx_rs::Tx<MemoryCache, Item = (), Err = CaoError> {
+        let person = person.clone();
+        tx_rs::with_tx(move |cache: &mut MemoryCache| {
+            cache.insert(id, person);
+            Ok(())
+        })
+    }
+    fn unload(&self, id: PersonId) -> impl tx_rs::Tx<MemoryCache, Item = (), Err = CaoError> {
+        tx_rs::with_tx(move |cache: &mut MemoryCache| {
+            cache.remove(id);
+            Ok(())
+        })
+    }
+}
+
+/// A notification received by `RecordingObserver`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recorded {
+    pub level: Level,
+    pub to: String,
+    pub message: String,
+    /// the event if sent by `send_event`
+    pub event: Option<PersonEvent>,
+}
+
+/// An `Observer` that keeps the notifications instead of publishing them, and logs them.
+///
+/// The clones share the same record.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingObserver {
+    recorded: Arc<Mutex<Vec<Recorded>>>,
+}
+impl RecordingObserver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// what has been received, in order
+    pub fn recorded(&self) -> Vec<Recorded> {
+        self.recorded.lock().unwrap().clone()
+    }
+
+    /// the events received, in order
+    pub fn events(&self) -> Vec<PersonEvent> {
+        self.recorded()
+            .into_iter()
+            .filter_map(|r| r.event)
+            .collect()
+    }
+
+    fn push(&self, recorded: Recorded) {
+        info!(
+            "notified {} to {}: {}",
+            recorded.level, recorded.to, recorded.message
+        );
+        self.recorded.lock().unwrap().push(recorded);
+    }
+}
+impl Observer for RecordingObserver {
+    fn handle_notification(
+        &self,
+        level: Level,
+        to: &str,
+        message: &str,
+        _loc: Location,
+    ) -> Result<(), ReporterError> {
+        self.push(Recorded {
+            level,
+            to: to.to_string(),
+            message: message.to_string(),
+            event: None,
+        });
+        Ok(())
+    }
+    fn handle_event(&self, event: &PersonEvent, _loc: Location) -> Result<(), ReporterError> {
+        self.push(Recorded {
+            level: Level::Info,
+            to: event.queue().to_string(),
+            message: event.to_string(),
+            event: Some(event.clone()),
+        });
+        Ok(())
+    }
+}
+
+// # フェイクテスト
+//
+// ## 目的
+//
+//   インメモリのバックエンドが postgres 版と同じ振る舞いをすることを保障する
+//   他のテストはこれをフェイクとして使うので, ここで振る舞いを確認しておく
+//
+// ## 方針
+//
+//   MemoryDb に対して DAO を実行し, 結果と実行後の状態を確認する
+//
+#[cfg(test)]
+mod fake_tests {
+    use super::*;
+    use crate::domain::date;
+    use crate::reporter::{DefaultReporter, Reporter};
+    use tx_rs::Tx;
+
+    #[test]
+    fn test_insert_and_fetch() {
+        let mut db = MemoryDb::new();
+        let dao = MemoryPersonDao::new("test");
+        let abel = PersonDto::new("Abel", date(1802, 8, 5), None, None, 0);
+        let galois = PersonDto::new("Galois", date(1811, 10, 25), None, None, 0);
+
+        assert_eq!(dao.insert(abel.clone()).run(&mut db), Ok(1));
+        assert_eq!(dao.insert(galois.clone()).run(&mut db), Ok(2));
+        assert_eq!(db.next_id(), 3);
+
+        assert_eq!(dao.fetch(1).run(&mut db), Ok(Some(abel.clone())));
+        assert_eq!(dao.fetch(99).run(&mut db), Ok(None));
+        assert_eq!(dao.select().run(&mut db), Ok(vec![(1, abel), (2, galois)]));
+    }
+
+    #[test]
+    fn test_save() {
+        let abel = PersonDto::new("Abel", date(1802, 8, 5), None, None, 0);
+        let mut db = MemoryDb::with_persons(2, vec![(1, abel.clone())]);
+        let dao = MemoryPersonDao::new("test");
+
+        let dead = PersonDto {
+            death_date: Some(date(1829, 4, 6)),
+            revision: 1,
+            ..abel
+        };
+        assert_eq!(dao.save(1, 0, dead.clone()).run(&mut db), Ok(()));
+        assert_eq!(db.persons(), vec![(1, dead.clone())]);
+
+        // 古い版に基づく保存は衝突する
+        assert_eq!(
+            dao.save(1, 0, dead.clone()).run(&mut db),
+            Err(DaoError::RevisionConflict {
+                expected: 0,
+                actual: 1
+            })
+        );
+        assert!(matches!(
+            dao.save(99, 0, dead).run(&mut db),
+            Err(DaoError::UpdateError(_))
+        ));
+    }
+
+    #[test]
+    fn test_correct() {
+        let abel = PersonDto::new("Abel", date(1802, 8, 5), None, None, 0);
+        let mut db = MemoryDb::with_persons(2, vec![(1, abel.clone())]);
+        let dao = MemoryPersonDao::new("test");
+
+        let dead = PersonDto {
+            death_date: Some(date(1829, 4, 6)),
+            revision: 1,
+            ..abel
+        };
+        let reason = "found the death certificate".to_string();
+        assert_eq!(dao.correct(1, 0, dead.clone(), reason).run(&mut db), Ok(()));
+        assert_eq!(db.persons(), vec![(1, dead.clone())]);
+
+        // 訂正の理由は履歴にだけ残り, 通常の保存には付かない
+        assert_eq!(
+            dao.save(
+                1,
+                1,
+                PersonDto {
+                    revision: 2,
+                    ..dead
+                }
+            )
+            .run(&mut db),
+            Ok(())
+        );
+        let history = dao.history(1).run(&mut db).unwrap();
+        let reasons = history
+            .iter()
+            .map(|h| h.reason.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(reasons, [Some("found the death certificate"), None]);
+    }
+
+    #[test]
+    fn test_delete_restore_and_purge() {
+        let abel = PersonDto::new("Abel", date(1802, 8, 5), None, None, 0);
+        let mut db = MemoryDb::with_persons(2, vec![(1, abel.clone())]);
+        let dao = MemoryPersonDao::new("test");
+
+        dao.delete(1).run(&mut db).unwrap();
+        assert_eq!(dao.fetch(1).run(&mut db), Ok(None));
+        assert_eq!(db.deleted().len(), 1);
+        // 保存もできない
+        assert!(dao.save(1, 1, abel.clone()).run(&mut db).is_err());
+
+        assert_eq!(dao.restore(1).run(&mut db), Ok(Some(2)));
+        // 削除されていなければ何もしない
+        assert_eq!(dao.restore(1).run(&mut db), Ok(None));
+        assert_eq!(
+            db.persons(),
+            vec![(
+                1,
+                PersonDto {
+                    revision: 2,
+                    ..abel.clone()
+                }
+            )]
+        );
+
+        dao.delete(1).run(&mut db).unwrap();
+        let before = Utc::now() + chrono::Duration::seconds(1);
+        assert_eq!(dao.purge(before).run(&mut db), Ok(1));
+        assert_eq!(dao.purge(before).run(&mut db), Ok(0));
+        assert!(db.deleted().is_empty());
+
+        let kinds = dao
+            .history(1)
+            .run(&mut db)
+            .unwrap()
+            .into_iter()
+            .map(|h| (h.kind, h.revision))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                (ChangeKind::Delete, 1),
+                (ChangeKind::Restore, 2),
+                (ChangeKind::Delete, 3),
+                (ChangeKind::Purge, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_query_cursor() {
+        crate::dao::check_query_cursor(&MemoryPersonDao::new("test"), &mut MemoryDb::new());
+    }
+
+    #[test]
+    fn test_rollback() {
+        let abel = PersonDto::new("Abel", date(1802, 8, 5), None, None, 0);
+        let galois = PersonDto::new("Galois", date(1811, 10, 25), None, None, 0);
+        let mut db = MemoryDb::with_persons(3, vec![(1, abel.clone()), (2, galois)]);
+        let dao = MemoryPersonDao::new("test");
+        dao.delete(2).run(&mut db).unwrap();
+        let before = db.clone();
+
+        db.begin();
+        let dead = PersonDto {
+            death_date: Some(date(1829, 4, 6)),
+            revision: 1,
+            ..abel.clone()
+        };
+        dao.save(1, 0, dead.clone()).run(&mut db).unwrap();
+        dao.save(
+            1,
+            1,
+            PersonDto {
+                revision: 2,
+                ..dead
+            },
+        )
+        .run(&mut db)
+        .unwrap();
+        dao.restore(2).run(&mut db).unwrap();
+        dao.insert(abel.clone()).run(&mut db).unwrap();
+        dao.delete(3).run(&mut db).unwrap();
+        dao.purge(Utc::now() + chrono::Duration::seconds(1))
+            .run(&mut db)
+            .unwrap();
+        db.rollback();
+
+        // 何度変えても, 始める前のまま残る
+        assert_eq!(db, before);
+
+        // commit すれば残る
+        db.begin();
+        dao.insert(abel.clone()).run(&mut db).unwrap();
+        db.commit();
+        db.rollback();
+        assert_eq!(dao.fetch(3).run(&mut db), Ok(Some(abel)));
+        assert_eq!(db.next_id(), 4);
+    }
+
+    #[test]
+    fn test_purge_keeps_recent() {
+        let now = Utc::now();
+        let bob = PersonDto::new("Bob", date(1995, 11, 6), None, None, 2);
+        let eve = PersonDto::new("Eve", date(1996, 12, 15), None, None, 8);
+        let mut db = MemoryDb::new().with_deleted(vec![
+            (24, bob, now - chrono::Duration::days(40)),
+            (99, eve.clone(), now - chrono::Duration::days(10)),
+        ]);
+        let dao = MemoryPersonDao::new("test");
+
+        let result = dao.purge(now - chrono::Duration::days(30)).run(&mut db);
+
+        assert_eq!(result, Ok(1));
+        assert_eq!(
+            db.deleted(),
+            vec![(99, eve, now - chrono::Duration::days(10))]
+        );
+    }
+
+    #[test]
+    fn test_cache() {
+        let cache = MemoryCache::new();
+        let cao = MemoryPersonCao::new(cache.clone());
+        let abel = PersonDto::new("Abel", date(1802, 8, 5), None, None, 0);
+
+        cao.run_tx(cao.load(1, &abel)).unwrap();
+        // 同じキャッシュを共有している
+        assert_eq!(cache.get(1), Some(abel.clone()));
+        assert_eq!(cao.run_tx(cao.find(1)), Ok(Some(abel)));
+
+        cao.run_tx(cao.unload(1)).unwrap();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_recording_observer() {
+        let observer = RecordingObserver::new();
+        let mut reporter = DefaultReporter::new();
+        reporter.register(observer.clone()).unwrap();
+
+        reporter
+            .send_report(Level::Warn, "admin", "message", location!())
+            .unwrap();
+        let event = PersonEvent::Unregistered {
+            id: 42,
+            revision: 2,
+        };
+        reporter.send_event(&event, location!()).unwrap();
+
+        assert_eq!(
+            observer.recorded(),
+            vec![
+                Recorded {
+                    level: Level::Warn,
+                    to: "admin".to_string(),
+                    message: "message".to_string(),
+                    event: None,
+                },
+                Recorded {
+                    level: Level::Info,
+                    to: "unregister_person".to_string(),
+                    message: "unregistered person_id: 42".to_string(),
+                    event: Some(event.clone()),
+                },
+            ]
+        );
+        assert_eq!(observer.events(), vec![event]);
+    }
+}
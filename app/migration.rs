@@ -0,0 +1,390 @@
+use chrono::{DateTime, Utc};
+use log::{info, trace, warn};
+use thiserror::Error;
+use tx_rs::Tx;
+
+use crate::dao::DaoError;
+
+pub type MigrationVersion = i64;
+
+/// A versioned SQL script of the schema, embedded in the binary.
+///
+/// Once released a migration must not be changed; add a new one instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Migration {
+    pub version: MigrationVersion,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+impl Migration {
+    /// FNV-1a (64 bit) of the script, ignoring the line endings
+    pub fn checksum(&self) -> String {
+        let hash = self
+            .sql
+            .bytes()
+            .filter(|b| *b != b'\r')
+            .fold(0xcbf29ce484222325u64, |h, b| {
+                (h ^ b as u64).wrapping_mul(0x100000001b3)
+            });
+        format!("{:016x}", hash)
+    }
+}
+
+/// the migrations of the postgres schema, in the order of the versions
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_person",
+        sql: include_str!("../migrations/0001_create_person.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "create_person_history",
+        sql: include_str!("../migrations/0002_create_person_history.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "create_outbox",
+        sql: include_str!("../migrations/0003_create_outbox.sql"),
+    },
+];
+
+/// a migration recorded in the `schema_migrations` table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedMigration {
+    pub version: MigrationVersion,
+    pub name: String,
+    pub checksum: String,
+    pub applied_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum MigrationError {
+    #[error("migration unavailable: {0}")]
+    Unavailable(String),
+    #[error("migration transaction failed: {0}")]
+    TransactionFailed(DaoError),
+    #[error("migration {version} ({name}) was changed after applied: applied={applied}, embedded={embedded}")]
+    ChecksumMismatch {
+        version: MigrationVersion,
+        name: String,
+        applied: String,
+        embedded: String,
+    },
+    #[error("unknown migration {version} ({name}) is applied, the database is newer than the app")]
+    UnknownMigration {
+        version: MigrationVersion,
+        name: String,
+    },
+}
+
+pub trait MigrationDao<Ctx> {
+    /// create the `schema_migrations` table if missing, and lock it against other migrators
+    /// until the end of the transaction
+    fn prepare(&self) -> impl Tx<Ctx, Item = (), Err = DaoError>;
+    /// the applied migrations in the order of the versions, none if not prepared yet
+    fn applied(&self) -> impl Tx<Ctx, Item = Vec<AppliedMigration>, Err = DaoError>;
+    /// run the script and record it as applied
+    fn apply(&self, migration: Migration) -> impl Tx<Ctx, Item = (), Err = DaoError>;
+}
+
+/// the migrations not applied yet, in order.
+///
+/// Fails if an applied migration has been changed or is not known to this binary.
+pub fn pending(
+    migrations: &[Migration],
+    applied: &[AppliedMigration],
+) -> Result<Vec<Migration>, MigrationError> {
+    for a in applied {
+        let Some(m) = migrations.iter().find(|m| m.version == a.version) else {
+            return Err(MigrationError::UnknownMigration {
+                version: a.version,
+                name: a.name.clone(),
+            });
+        };
+        if m.checksum() != a.checksum {
+            return Err(MigrationError::ChecksumMismatch {
+                version: a.version,
+                name: a.name.clone(),
+                applied: a.checksum.clone(),
+                embedded: m.checksum(),
+            });
+        }
+    }
+
+    let mut pending = migrations
+        .iter()
+        .filter(|m| applied.iter().all(|a| a.version != m.version))
+        .copied()
+        .collect::<Vec<_>>();
+    pending.sort_by_key(|m| m.version);
+    Ok(pending)
+}
+
+/// Brings the schema up to date with the embedded migrations.
+///
+/// The pending migrations are applied in one transaction, so a failure leaves the schema as it was.
+pub trait Migrator<'a, Ctx> {
+    type D: MigrationDao<Ctx>;
+
+    fn run_tx<T, F>(&'a mut self, f: F) -> Result<T, MigrationError>
+    where
+        F: FnOnce(&Self::D, &mut Ctx) -> Result<T, MigrationError>;
+
+    fn migrations(&self) -> &'static [Migration] {
+        MIGRATIONS
+    }
+
+    /// apply the pending migrations and return them.
+    /// On `dry_run` they are only returned, and nothing is written.
+    fn migrate(&'a mut self, dry_run: bool) -> Result<Vec<Migration>, MigrationError> {
+        trace!("migrate: dry_run={}", dry_run);
+        let migrations = self.migrations();
+
+        self.run_tx(move |dao, ctx| {
+            if !dry_run {
+                dao.prepare()
+                    .run(ctx)
+                    .map_err(MigrationError::TransactionFailed)?;
+            }
+            let applied = dao
+                .applied()
+                .run(ctx)
+                .map_err(MigrationError::TransactionFailed)?;
+            let pending = pending(migrations, &applied).inspect_err(|e| {
+                warn!("cannot migrate: {}", e);
+            })?;
+            if dry_run {
+                return Ok(pending);
+            }
+
+            for migration in &pending {
+                info!(
+                    "applying migration {}: {}",
+                    migration.version, migration.name
+                );
+                dao.apply(*migration)
+                    .run(ctx)
+                    .map_err(MigrationError::TransactionFailed)?;
+            }
+            Ok(pending)
+        })
+    }
+}
+
+// # フェイクテスト
+//
+// ## 目的
+//
+//   Migrator が未適用のものだけを順に適用し, 変更されたものや未知のものを拒むことを保障する
+//
+// ## 方針
+//
+//   適用済みの記録をメモリに持つフェイクの DAO で置き換え, 結果と記録を確認する
+//   フェイクは失敗するとそのトランザクションの記録を捨てる
+//
+#[cfg(test)]
+mod fake_tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    const TEST_MIGRATIONS: &[Migration] = &[
+        Migration {
+            version: 1,
+            name: "first",
+            sql: "CREATE TABLE a ();",
+        },
+        Migration {
+            version: 2,
+            name: "second",
+            sql: "CREATE TABLE b ();",
+        },
+        Migration {
+            version: 3,
+            name: "broken",
+            sql: "",
+        },
+    ];
+
+    struct FakeMigrationDao {
+        prepared: RefCell<bool>,
+        applied: RefCell<Vec<AppliedMigration>>,
+    }
+    // Ctx 不要なので () にしている
+    impl MigrationDao<()> for FakeMigrationDao {
+        fn prepare(&self) -> impl Tx<(), Item = (), Err = DaoError> {
+            self.prepared.replace(true);
+            tx_rs::with_tx(|()| Ok(()))
+        }
+        fn applied(&self) -> impl Tx<(), Item = Vec<AppliedMigration>, Err = DaoError> {
+            let applied = self.applied.borrow().clone();
+            tx_rs::with_tx(move |()| Ok(applied))
+        }
+        fn apply(&self, migration: Migration) -> impl Tx<(), Item = (), Err = DaoError> {
+            // 空のスクリプトは SQL のエラーとして扱う
+            let result = if migration.sql.is_empty() {
+                Err(DaoError::UpdateError("syntax error".to_string()))
+            } else {
+                self.applied.borrow_mut().push(record(&migration));
+                Ok(())
+            };
+            tx_rs::with_tx(move |()| result)
+        }
+    }
+
+    struct TargetMigrator {
+        dao: FakeMigrationDao,
+        migrations: &'static [Migration],
+    }
+    impl TargetMigrator {
+        fn new(migrations: &'static [Migration], applied: Vec<AppliedMigration>) -> Self {
+            Self {
+                dao: FakeMigrationDao {
+                    prepared: RefCell::new(false),
+                    applied: RefCell::new(applied),
+                },
+                migrations,
+            }
+        }
+    }
+    impl Migrator<'_, ()> for TargetMigrator {
+        type D = FakeMigrationDao;
+
+        fn run_tx<T, F>(&mut self, f: F) -> Result<T, MigrationError>
+        where
+            F: FnOnce(&FakeMigrationDao, &mut ()) -> Result<T, MigrationError>,
+        {
+            let before = self.dao.applied.borrow().clone();
+            let result = f(&self.dao, &mut ());
+            if result.is_err() {
+                self.dao.applied.replace(before);
+            }
+            result
+        }
+        fn migrations(&self) -> &'static [Migration] {
+            self.migrations
+        }
+    }
+
+    fn record(migration: &Migration) -> AppliedMigration {
+        AppliedMigration {
+            version: migration.version,
+            name: migration.name.to_string(),
+            checksum: migration.checksum(),
+            applied_at: Utc::now(),
+        }
+    }
+
+    fn versions(migrations: &[Migration]) -> Vec<MigrationVersion> {
+        migrations.iter().map(|m| m.version).collect()
+    }
+
+    #[test]
+    fn test_migrations() {
+        // 版は 1 から欠けずに並んでいる
+        let expected = (1..=MIGRATIONS.len() as MigrationVersion).collect::<Vec<_>>();
+        assert_eq!(versions(MIGRATIONS), expected);
+        assert!(MIGRATIONS.iter().all(|m| !m.sql.trim().is_empty()));
+    }
+
+    #[test]
+    fn test_checksum() {
+        let m = TEST_MIGRATIONS[0];
+        assert_eq!(m.checksum(), m.checksum());
+        assert_eq!(m.checksum().len(), 16);
+        assert_ne!(m.checksum(), TEST_MIGRATIONS[1].checksum());
+
+        // 改行コードの違いは変更とみなさない
+        let crlf = Migration {
+            sql: "CREATE TABLE a (\r\n);",
+            ..m
+        };
+        let lf = Migration {
+            sql: "CREATE TABLE a (\n);",
+            ..m
+        };
+        assert_eq!(crlf.checksum(), lf.checksum());
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut migrator = TargetMigrator::new(&TEST_MIGRATIONS[..2], vec![]);
+
+        let result = migrator.migrate(false).map(|v| versions(&v));
+        assert_eq!(result, Ok(vec![1, 2]));
+        assert!(*migrator.dao.prepared.borrow());
+        assert_eq!(migrator.dao.applied.borrow().len(), 2);
+
+        // 最新なら何もしない
+        let result = migrator.migrate(false);
+        assert_eq!(result, Ok(vec![]));
+    }
+
+    #[test]
+    fn test_migrate_pending_only() {
+        let applied = vec![record(&TEST_MIGRATIONS[0])];
+        let mut migrator = TargetMigrator::new(&TEST_MIGRATIONS[..2], applied);
+
+        let result = migrator.migrate(false).map(|v| versions(&v));
+        assert_eq!(result, Ok(vec![2]));
+    }
+
+    #[test]
+    fn test_migrate_dry_run() {
+        let applied = vec![record(&TEST_MIGRATIONS[0])];
+        let mut migrator = TargetMigrator::new(&TEST_MIGRATIONS[..2], applied);
+
+        let result = migrator.migrate(true).map(|v| versions(&v));
+        assert_eq!(result, Ok(vec![2]));
+        // 何も書かない
+        assert!(!*migrator.dao.prepared.borrow());
+        assert_eq!(migrator.dao.applied.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_migrate_failed() {
+        let mut migrator = TargetMigrator::new(TEST_MIGRATIONS, vec![]);
+
+        let result = migrator.migrate(false);
+        assert!(matches!(result, Err(MigrationError::TransactionFailed(_))));
+        // 途中まで適用したものも残らない
+        assert!(migrator.dao.applied.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_migrate_checksum_mismatch() {
+        let applied = vec![AppliedMigration {
+            checksum: "0000000000000000".to_string(),
+            ..record(&TEST_MIGRATIONS[0])
+        }];
+        let mut migrator = TargetMigrator::new(&TEST_MIGRATIONS[..2], applied);
+
+        let result = migrator.migrate(true);
+        assert!(matches!(
+            result,
+            Err(MigrationError::ChecksumMismatch { version: 1, .. })
+        ));
+        let result = migrator.migrate(false);
+        assert!(matches!(
+            result,
+            Err(MigrationError::ChecksumMismatch { version: 1, .. })
+        ));
+        assert_eq!(migrator.dao.applied.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_migrate_unknown() {
+        let applied = vec![record(&TEST_MIGRATIONS[0]), record(&TEST_MIGRATIONS[1])];
+        let mut migrator = TargetMigrator::new(&TEST_MIGRATIONS[..1], applied);
+
+        let result = migrator.migrate(false);
+        assert_eq!(
+            result,
+            Err(MigrationError::UnknownMigration {
+                version: 2,
+                name: "second".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,368 @@
+use chrono::{DateTime, Utc};
+use log::{trace, warn};
+use thiserror::Error;
+use tx_rs::Tx;
+
+use crate::dao::DaoError;
+use crate::event::PersonEvent;
+use crate::reporter::{Level, Location, Observer, Reporter, ReporterError};
+
+pub type OutboxId = i64;
+
+/// A report to be published once the transaction which caused it has committed.
+///
+/// `event` is set for a `PersonEvent`, and then `message` is only its summary for humans.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub level: Level,
+    pub to: String,
+    pub message: String,
+    pub event: Option<PersonEvent>,
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+impl Notification {
+    pub fn new(level: Level, to: &str, message: &str, loc: Location) -> Self {
+        Self {
+            level,
+            to: to.to_string(),
+            message: message.to_string(),
+            event: None,
+            file: loc.file.to_string(),
+            line: loc.line,
+            column: loc.column,
+        }
+    }
+    pub fn event(event: PersonEvent, loc: Location) -> Self {
+        Self {
+            event: Some(event.clone()),
+            ..Self::new(Level::Info, event.queue(), &event.to_string(), loc)
+        }
+    }
+    pub fn location(&self) -> Location<'_> {
+        Location {
+            file: &self.file,
+            line: self.line,
+            column: self.column,
+        }
+    }
+    /// hand this to `observer`, as an event if it is one
+    pub fn send_to(&self, observer: &(impl Observer + ?Sized)) -> Result<(), ReporterError> {
+        match &self.event {
+            Some(event) => observer.handle_event(event, self.location()),
+            None => observer.handle_notification(
+                self.level.clone(),
+                &self.to,
+                &self.message,
+                self.location(),
+            ),
+        }
+    }
+}
+
+/// a notification waiting in the outbox
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxEntry {
+    pub id: OutboxId,
+    pub notification: Notification,
+    pub attempts: i32,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum OutboxError {
+    #[error("outbox unavailable: {0}")]
+    Unavailable(String),
+    #[error("outbox transaction failed: {0}")]
+    TransactionFailed(DaoError),
+}
+
+pub trait OutboxDao<Ctx> {
+    fn enqueue(&self, notification: Notification) -> impl Tx<Ctx, Item = (), Err = DaoError>;
+    /// lock and fetch the entries due to be published, oldest first
+    fn fetch_pending(&self, limit: i64) -> impl Tx<Ctx, Item = Vec<OutboxEntry>, Err = DaoError>;
+    fn mark_sent(&self, id: OutboxId) -> impl Tx<Ctx, Item = (), Err = DaoError>;
+    fn mark_failed(
+        &self,
+        id: OutboxId,
+        error: &str,
+        retry_at: DateTime<Utc>,
+    ) -> impl Tx<Ctx, Item = (), Err = DaoError>;
+}
+
+// exponential backoff: 1s, 2s, 4s, ... up to 5 minutes
+pub fn retry_delay(attempts: i32) -> chrono::Duration {
+    let secs = 2i64.saturating_pow(attempts.saturating_sub(1).clamp(0, 30) as u32);
+    chrono::Duration::seconds(secs.min(300))
+}
+
+/// Drains the outbox to the observers of the reporter.
+///
+/// An entry is removed only after every observer accepted it, in the same transaction
+/// that locked it, so a crash or an outage leads to a redelivery (at-least-once) but never a loss.
+pub trait OutboxRelay<'a, Ctx> {
+    type D: OutboxDao<Ctx>;
+    type N: Reporter<'a>;
+
+    fn run_tx<T, F>(&'a mut self, f: F) -> Result<T, OutboxError>
+    where
+        F: FnOnce(&Self::D, &mut Ctx) -> Result<T, DaoError>;
+
+    fn get_reporter(&self) -> Self::N;
+
+    /// publish up to `limit` pending notifications and return how many were sent.
+    fn relay(&'a mut self, limit: i64) -> Result<usize, OutboxError> {
+        trace!("relay outbox: limit={}", limit);
+        let reporter = self.get_reporter();
+
+        self.run_tx(move |dao, ctx| {
+            let entries = dao.fetch_pending(limit).run(ctx)?;
+            let mut sent = 0;
+            for entry in entries {
+                if let Err(e) = publish(&reporter, &entry.notification) {
+                    let retry_at = Utc::now() + retry_delay(entry.attempts + 1);
+                    warn!(
+                        "cannot publish outbox entry {} (attempts: {}), retry at {}: {}",
+                        entry.id,
+                        entry.attempts + 1,
+                        retry_at,
+                        e
+                    );
+                    dao.mark_failed(entry.id, &e.to_string(), retry_at)
+                        .run(ctx)?;
+                    // 後続も失敗するはずなので次回に回す
+                    break;
+                }
+                dao.mark_sent(entry.id).run(ctx)?;
+                sent += 1;
+            }
+            Ok(sent)
+        })
+    }
+}
+
+// Reporter::send_report は Observer のエラーを握り潰すので直接呼ぶ
+fn publish<'a>(
+    reporter: &impl Reporter<'a>,
+    notification: &Notification,
+) -> Result<(), ReporterError> {
+    for observer in reporter.get_observers() {
+        notification.send_to(observer)?;
+    }
+    Ok(())
+}
+
+// # フェイクテスト
+//
+// ## 目的
+//
+//   Relay が Outbox の内容を Observer に届け、届いたものだけを Outbox から消すことを保障する
+//
+// ## 方針
+//
+//   Outbox をメモリ上のフェイクで置き換え、Observer はスタブで成功か失敗かを切り替える
+//   Relay の実行後に Outbox の状態と Observer に届いたものを確認する
+//
+#[cfg(test)]
+mod fake_tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::reporter::{DefaultReporter, Observer};
+
+    struct FakeOutboxDao {
+        next_id: RefCell<OutboxId>,
+        entries: RefCell<Vec<(OutboxEntry, DateTime<Utc>)>>,
+    }
+    impl OutboxDao<()> for FakeOutboxDao {
+        fn enqueue(&self, notification: Notification) -> impl Tx<(), Item = (), Err = DaoError> {
+            let id = self.next_id.replace_with(|&mut id| id + 1);
+            let entry = OutboxEntry {
+                id,
+                notification,
+                attempts: 0,
+            };
+            self.entries.borrow_mut().push((entry, Utc::now()));
+
+            tx_rs::with_tx(|()| Ok(()))
+        }
+        fn fetch_pending(
+            &self,
+            limit: i64,
+        ) -> impl Tx<(), Item = Vec<OutboxEntry>, Err = DaoError> {
+            let now = Utc::now();
+            let result = self
+                .entries
+                .borrow()
+                .iter()
+                .filter(|(_, retry_at)| *retry_at <= now)
+                .take(limit as usize)
+                .map(|(e, _)| e.clone())
+                .collect::<Vec<_>>();
+
+            tx_rs::with_tx(move |()| Ok(result))
+        }
+        fn mark_sent(&self, id: OutboxId) -> impl Tx<(), Item = (), Err = DaoError> {
+            self.entries.borrow_mut().retain(|(e, _)| e.id != id);
+
+            tx_rs::with_tx(|()| Ok(()))
+        }
+        fn mark_failed(
+            &self,
+            id: OutboxId,
+            _error: &str,
+            retry_at: DateTime<Utc>,
+        ) -> impl Tx<(), Item = (), Err = DaoError> {
+            if let Some((e, at)) = self
+                .entries
+                .borrow_mut()
+                .iter_mut()
+                .find(|(e, _)| e.id == id)
+            {
+                e.attempts += 1;
+                *at = retry_at;
+            }
+
+            tx_rs::with_tx(|()| Ok(()))
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct StubObserver {
+        available: Rc<RefCell<bool>>,
+        received: Rc<RefCell<Vec<(String, String)>>>,
+    }
+    impl Observer for StubObserver {
+        fn handle_notification(
+            &self,
+            _level: Level,
+            to: &str,
+            message: &str,
+            _loc: Location,
+        ) -> Result<(), ReporterError> {
+            if !*self.available.borrow() {
+                return Err(ReporterError::Unavailable("stub".to_string()));
+            }
+            self.received
+                .borrow_mut()
+                .push((to.to_string(), message.to_string()));
+            Ok(())
+        }
+    }
+
+    struct TargetOutboxRelay {
+        dao: FakeOutboxDao,
+        reporter: DefaultReporter<'static>,
+    }
+    impl<'a> OutboxRelay<'a, ()> for TargetOutboxRelay {
+        type D = FakeOutboxDao;
+        type N = DefaultReporter<'a>;
+
+        fn run_tx<T, F>(&mut self, f: F) -> Result<T, OutboxError>
+        where
+            F: FnOnce(&Self::D, &mut ()) -> Result<T, DaoError>,
+        {
+            f(&self.dao, &mut ()).map_err(OutboxError::TransactionFailed)
+        }
+        fn get_reporter(&self) -> Self::N {
+            self.reporter.clone()
+        }
+    }
+
+    fn relay_with(observer: StubObserver, messages: &[&str]) -> TargetOutboxRelay {
+        let dao = FakeOutboxDao {
+            next_id: RefCell::new(1),
+            entries: RefCell::new(vec![]),
+        };
+        for msg in messages {
+            let n = Notification::new(Level::Info, "entry_person", msg, location!());
+            let _ = dao.enqueue(n).run(&mut ());
+        }
+        let mut reporter = DefaultReporter::new();
+        reporter.register(observer).unwrap();
+
+        TargetOutboxRelay { dao, reporter }
+    }
+
+    #[test]
+    fn test_relay() {
+        let observer = StubObserver {
+            available: Rc::new(RefCell::new(true)),
+            received: Rc::new(RefCell::new(vec![])),
+        };
+        let mut relay = relay_with(observer.clone(), &["1", "2", "3"]);
+
+        assert_eq!(relay.relay(2), Ok(2));
+        assert_eq!(relay.dao.entries.borrow().len(), 1);
+        assert_eq!(relay.relay(2), Ok(1));
+        assert!(relay.dao.entries.borrow().is_empty());
+        assert_eq!(relay.relay(2), Ok(0));
+
+        // 順番通りに届く
+        let received = observer.received.borrow();
+        let messages = received.iter().map(|(_, m)| m.as_str()).collect::<Vec<_>>();
+        assert_eq!(messages, vec!["1", "2", "3"]);
+        assert!(received.iter().all(|(to, _)| to == "entry_person"));
+    }
+
+    #[test]
+    fn test_relay_unavailable() {
+        let observer = StubObserver {
+            available: Rc::new(RefCell::new(false)),
+            received: Rc::new(RefCell::new(vec![])),
+        };
+        let mut relay = relay_with(observer.clone(), &["1", "2"]);
+
+        // 失敗したものは残して後回しにする
+        assert_eq!(relay.relay(10), Ok(0));
+        {
+            let entries = relay.dao.entries.borrow();
+            assert_eq!(entries.len(), 2);
+            assert_eq!(entries[0].0.attempts, 1);
+            assert!(entries[0].1 > Utc::now());
+            // 後続には手を付けない
+            assert_eq!(entries[1].0.attempts, 0);
+        }
+
+        // 復旧後に届く
+        *observer.available.borrow_mut() = true;
+        assert_eq!(relay.relay(10), Ok(1));
+        let received = observer.received.borrow();
+        assert_eq!(
+            *received,
+            vec![("entry_person".to_string(), "2".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), chrono::Duration::seconds(1));
+        assert_eq!(retry_delay(2), chrono::Duration::seconds(2));
+        assert_eq!(retry_delay(4), chrono::Duration::seconds(8));
+        assert_eq!(retry_delay(100), chrono::Duration::seconds(300));
+    }
+
+    #[test]
+    fn test_notification_event() {
+        let event = PersonEvent::Died {
+            id: 42,
+            revision: 1,
+            death_date: crate::domain::date(1829, 4, 6),
+        };
+        let n = Notification::event(event.clone(), location!());
+
+        assert_eq!(n.level, Level::Info);
+        assert_eq!(n.to, "death_person");
+        assert_eq!(n.message, "death person_id: 42, death_date: 1829-04-06");
+        assert_eq!(n.event, Some(event));
+    }
+
+    #[test]
+    fn test_notification_location() {
+        let loc = location!();
+        let n = Notification::new(Level::Warn, "admin", "message", loc.clone());
+
+        assert_eq!(n.location(), loc);
+    }
+}
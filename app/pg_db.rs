@@ -1,47 +1,239 @@
-use chrono::NaiveDate;
-use log::trace;
+use chrono::{DateTime, NaiveDate, Utc};
+use log::{trace, warn};
+use postgres::types::ToSql;
+use postgres::NoTls;
+use std::error::Error as _;
 use std::str;
+use std::time::Duration;
 
-use crate::dao::{DaoError, PersonDao};
+use crate::dao::{DaoError, LifeStatus, PersonDao, PersonQuery, PersonSearch, SortKey, SortOrder};
 use crate::domain::{PersonId, Revision};
-use crate::dto::PersonDto;
+use crate::dto::{ChangeKind, PersonDto, PersonHistoryDto};
+use crate::migration::{AppliedMigration, Migration, MigrationDao, MigrationVersion};
+use crate::outbox::{Notification, OutboxDao, OutboxEntry, OutboxId};
+use crate::pool::{ManageConnection, PooledConnection};
 
+/// whether `e` is about the connection rather than the statement, so worth connecting again
+pub fn is_connection_error(e: &postgres::Error) -> bool {
+    match e.code() {
+        // 08: connection exception, 57P: operator intervention such as admin_shutdown
+        Some(code) => code.code().starts_with("08") || code.code().starts_with("57P"),
+        None => e.is_closed() || e.source().is_some_and(|e| e.is::<std::io::Error>()),
+    }
+}
+
+/// opens the connections of the pool to postgres
 #[derive(Debug, Clone)]
-pub struct PgPersonDao;
-impl<'a> PersonDao<postgres::Transaction<'a>> for PgPersonDao {
-    fn insert(
+pub struct PgConnectionManager {
+    uri: String,
+    validation_timeout: Duration,
+}
+impl PgConnectionManager {
+    pub fn new(uri: &str) -> Self {
+        Self {
+            uri: uri.to_string(),
+            validation_timeout: Duration::from_secs(2),
+        }
+    }
+
+    /// how long `is_valid` waits for the server, 2 seconds by default
+    pub fn with_validation_timeout(mut self, timeout: Duration) -> Self {
+        self.validation_timeout = timeout;
+        self
+    }
+}
+impl ManageConnection for PgConnectionManager {
+    type Connection = postgres::Client;
+    type Error = postgres::Error;
+
+    fn connect(&self) -> Result<postgres::Client, postgres::Error> {
+        postgres::Client::connect(&self.uri, NoTls)
+    }
+    fn is_valid(&self, conn: &mut postgres::Client) -> Result<(), postgres::Error> {
+        conn.is_valid(self.validation_timeout)
+    }
+    fn has_broken(&self, conn: &mut postgres::Client) -> bool {
+        conn.is_closed()
+    }
+}
+
+/// A transaction on a connection checked out of the pool, for the DAOs on `postgres::Client`.
+///
+/// Unlike `postgres::Transaction` it owns the connection, so that a service can run each call,
+/// and each attempt of a retried call, in a transaction of its own. It is rolled back if dropped
+/// before `commit` or `rollback`, and the connection goes back to the pool.
+pub struct PgTransaction {
+    conn: PooledConnection<PgConnectionManager>,
+    finished: bool,
+}
+impl PgTransaction {
+    /// start a transaction. The connection goes back to the pool if it cannot.
+    pub fn begin(mut conn: PooledConnection<PgConnectionManager>) -> Result<Self, postgres::Error> {
+        conn.batch_execute("BEGIN")?;
+        Ok(Self {
+            conn,
+            finished: false,
+        })
+    }
+
+    pub fn client(&mut self) -> &mut postgres::Client {
+        &mut self.conn
+    }
+
+    pub fn commit(mut self) -> Result<(), postgres::Error> {
+        self.finished = true;
+        self.conn.batch_execute("COMMIT")
+    }
+
+    pub fn rollback(mut self) -> Result<(), postgres::Error> {
+        self.finished = true;
+        self.conn.batch_execute("ROLLBACK")
+    }
+}
+impl Drop for PgTransaction {
+    // panic したときなど. 開いたままの接続を pool に返さない
+    fn drop(&mut self) {
+        if !self.finished {
+            if let Err(e) = self.conn.batch_execute("ROLLBACK") {
+                warn!("failed to rollback: {}", e);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PgPersonDao {
+    actor: String,
+}
+impl PgPersonDao {
+    /// `actor` is recorded in the history as who made the changes.
+    pub fn new(actor: &str) -> Self {
+        Self {
+            actor: actor.to_string(),
+        }
+    }
+
+    // save と correct の共通部分. reason は履歴に残す
+    fn save_with(
         &self,
+        id: PersonId,
+        revision: Revision,
         person: PersonDto,
-    ) -> impl tx_rs::Tx<postgres::Transaction<'a>, Item = PersonId, Err = DaoError> {
-        trace!("inserting person: {:?}", person);
-        tx_rs::with_tx(move |tx: &mut postgres::Transaction<'_>| {
-            tx.query_one(
-                r#"INSERT INTO person ( name
-                                      , birth_date
-                                      , death_date
-                                      , data
-                                      , revision
-                                      )
-                   VALUES ($1, $2, $3, $4, $5)
-                RETURNING id"#,
+        reason: Option<String>,
+    ) -> impl tx_rs::Tx<postgres::Client, Item = (), Err = DaoError> {
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |tx: &mut postgres::Client| {
+            // lock the row to keep the old value for the history
+            let old = tx
+                .query_opt(
+                    r#"SELECT name,
+                              birth_date,
+                              death_date,
+                              data,
+                              revision
+                         FROM person
+                        WHERE id = $1
+                          AND deleted_at IS NULL
+                          FOR UPDATE"#,
+                    &[&id],
+                )
+                .map_err(|e| DaoError::UpdateError(e.to_string()))?
+                .map(|row| person_at(&row, 0));
+            let Some(old) = old else {
+                return Err(DaoError::UpdateError(format!("person not found: {id}")));
+            };
+            if old.revision != revision {
+                warn!(
+                    "revision conflict on person {}: expected={}, actual={}",
+                    id, revision, old.revision
+                );
+                return Err(DaoError::RevisionConflict {
+                    expected: revision,
+                    actual: old.revision,
+                });
+            }
+
+            tx.execute(
+                r#"UPDATE person
+                      SET name = $1,
+                          birth_date = $2,
+                          death_date = $3,
+                          data = $4,
+                          revision = $5
+                    WHERE id = $6"#,
                 &[
                     &person.name,
                     &person.birth_date,
                     &person.death_date,
-                    &person.data.map(|d| d.as_str().as_bytes().to_vec()),
+                    &person.data.as_ref().map(|d| d.as_bytes().to_vec()),
                     &person.revision,
+                    &id,
                 ],
             )
-            .map(|row| row.get::<usize, PersonId>(0))
-            .map_err(|e| DaoError::InsertError(e.to_string()))
+            .map_err(|e| DaoError::UpdateError(e.to_string()))?;
+
+            record(
+                tx,
+                id,
+                ChangeKind::Update,
+                Some(&old),
+                Some(&person),
+                &actor,
+                reason.as_deref(),
+            )
+            .map_err(DaoError::UpdateError)
+        })
+    }
+}
+impl PersonDao<postgres::Client> for PgPersonDao {
+    fn insert(
+        &self,
+        person: PersonDto,
+    ) -> impl tx_rs::Tx<postgres::Client, Item = PersonId, Err = DaoError> {
+        trace!("inserting person: {:?}", person);
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |tx: &mut postgres::Client| {
+            let id = tx
+                .query_one(
+                    r#"INSERT INTO person ( name
+                                          , birth_date
+                                          , death_date
+                                          , data
+                                          , revision
+                                          )
+                       VALUES ($1, $2, $3, $4, $5)
+                    RETURNING id"#,
+                    &[
+                        &person.name,
+                        &person.birth_date,
+                        &person.death_date,
+                        &person.data.as_ref().map(|d| d.as_bytes().to_vec()),
+                        &person.revision,
+                    ],
+                )
+                .map(|row| row.get::<usize, PersonId>(0))
+                .map_err(|e| DaoError::InsertError(e.to_string()))?;
+
+            record(
+                tx,
+                id,
+                ChangeKind::Insert,
+                None,
+                Some(&person),
+                &actor,
+                None,
+            )
+            .map_err(DaoError::InsertError)?;
+
+            Ok(id)
         })
     }
     fn fetch(
         &self,
         id: PersonId,
-    ) -> impl tx_rs::Tx<postgres::Transaction<'a>, Item = Option<PersonDto>, Err = DaoError> {
+    ) -> impl tx_rs::Tx<postgres::Client, Item = Option<PersonDto>, Err = DaoError> {
         trace!("fetching person: {:?}", id);
-        tx_rs::with_tx(move |tx: &mut postgres::Transaction<'_>| {
+        tx_rs::with_tx(move |tx: &mut postgres::Client| {
             tx.query_opt(
                 r#"SELECT name,
                           birth_date,
@@ -49,29 +241,19 @@ impl<'a> PersonDao<postgres::Transaction<'a>> for PgPersonDao {
                           data,
                           revision
                      FROM person
-                    WHERE id = $1"#,
+                    WHERE id = $1
+                      AND deleted_at IS NULL"#,
                 &[&id],
             )
-            .map(|row| {
-                row.map(|row| {
-                    let name = row.get::<usize, &str>(0);
-                    let birth_date = row.get::<usize, NaiveDate>(1);
-                    let death_date = row.get::<usize, Option<NaiveDate>>(2);
-                    let data = str::from_utf8(row.get::<usize, &[u8]>(3)).ok();
-                    let revision = row.get::<usize, Revision>(4);
-
-                    PersonDto::new(name, birth_date, death_date, data, revision)
-                })
-            })
+            .map(|row| row.map(|row| person_at(&row, 0)))
             .map_err(|e| DaoError::SelectError(e.to_string()))
         })
     }
     fn select(
         &self,
-    ) -> impl tx_rs::Tx<postgres::Transaction<'a>, Item = Vec<(PersonId, PersonDto)>, Err = DaoError>
-    {
+    ) -> impl tx_rs::Tx<postgres::Client, Item = Vec<(PersonId, PersonDto)>, Err = DaoError> {
         trace!("selecting all persons");
-        tx_rs::with_tx(|tx: &mut postgres::Transaction<'_>| {
+        tx_rs::with_tx(|tx: &mut postgres::Client| {
             tx.query(
                 r#"SELECT id,
                           name,
@@ -79,24 +261,12 @@ impl<'a> PersonDao<postgres::Transaction<'a>> for PgPersonDao {
                           death_date,
                           data,
                           revision
-                     FROM person"#,
+                     FROM person
+                    WHERE deleted_at IS NULL
+                 ORDER BY id"#,
                 &[],
             )
-            .map(|rows| {
-                rows.iter()
-                    .map(|row| {
-                        let id = row.get::<usize, PersonId>(0);
-                        let name = row.get::<usize, &str>(1);
-                        let birth_date = row.get::<usize, NaiveDate>(2);
-                        let death_date = row.get::<usize, Option<NaiveDate>>(3);
-                        let data = str::from_utf8(row.get::<usize, &[u8]>(4)).ok();
-                        let revision = row.get::<usize, Revision>(5);
-                        let person = PersonDto::new(name, birth_date, death_date, data, revision);
-
-                        (id, person)
-                    })
-                    .collect()
-            })
+            .map(|rows| rows.iter().map(to_person).collect())
             .map_err(|e| DaoError::SelectError(e.to_string()))
         })
     }
@@ -105,42 +275,638 @@ impl<'a> PersonDao<postgres::Transaction<'a>> for PgPersonDao {
         id: PersonId,
         revision: Revision,
         person: PersonDto,
-    ) -> impl tx_rs::Tx<postgres::Transaction<'a>, Item = (), Err = DaoError> {
+    ) -> impl tx_rs::Tx<postgres::Client, Item = (), Err = DaoError> {
         trace!("saving person: {:?}", id);
-        tx_rs::with_tx(move |tx: &mut postgres::Transaction<'_>| {
-            tx.query_one(
-                r#"UPDATE person
-                      SET name = $1,
-                          birth_date = $2,
-                          death_date = $3,
-                          data = $4,
-                          revision = $5
-                    WHERE id = $6
-                      AND revision = $7
-                RETURNING id"#,
+        self.save_with(id, revision, person, None)
+    }
+    fn correct(
+        &self,
+        id: PersonId,
+        revision: Revision,
+        person: PersonDto,
+        reason: String,
+    ) -> impl tx_rs::Tx<postgres::Client, Item = (), Err = DaoError> {
+        trace!("correcting person: {:?}", id);
+        self.save_with(id, revision, person, Some(reason))
+    }
+    fn delete(
+        &self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<postgres::Client, Item = Option<Revision>, Err = DaoError> {
+        trace!("deleting person: {:?}", id);
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |tx: &mut postgres::Client| {
+            let deleted = tx
+                .query_opt(
+                    r#"UPDATE person
+                          SET deleted_at = now(),
+                              revision = revision + 1
+                        WHERE id = $1
+                          AND deleted_at IS NULL
+                    RETURNING name, birth_date, death_date, data, revision"#,
+                    &[&id],
+                )
+                .map_err(|e| DaoError::DeleteError(e.to_string()))?;
+
+            match deleted {
+                Some(row) => record_toggle(tx, id, ChangeKind::Delete, &row, &actor)
+                    .map(|_| Some(row.get::<usize, Revision>(4)))
+                    .map_err(DaoError::DeleteError),
+                None => Ok(None),
+            }
+        })
+    }
+    fn restore(
+        &self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<postgres::Client, Item = Option<Revision>, Err = DaoError> {
+        trace!("restoring person: {:?}", id);
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |tx: &mut postgres::Client| {
+            let restored = tx
+                .query_opt(
+                    r#"UPDATE person
+                          SET deleted_at = NULL,
+                              revision = revision + 1
+                        WHERE id = $1
+                          AND deleted_at IS NOT NULL
+                    RETURNING name, birth_date, death_date, data, revision"#,
+                    &[&id],
+                )
+                .map_err(|e| DaoError::RestoreError(e.to_string()))?;
+
+            match restored {
+                Some(row) => record_toggle(tx, id, ChangeKind::Restore, &row, &actor)
+                    .map(|_| Some(row.get::<usize, Revision>(4)))
+                    .map_err(DaoError::RestoreError),
+                None => Ok(None),
+            }
+        })
+    }
+    fn purge(
+        &self,
+        before: DateTime<Utc>,
+    ) -> impl tx_rs::Tx<postgres::Client, Item = u64, Err = DaoError> {
+        trace!("purging persons deleted before: {:?}", before);
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |tx: &mut postgres::Client| {
+            let purged = tx
+                .query(
+                    r#"DELETE FROM person
+                        WHERE deleted_at < $1
+                    RETURNING id, name, birth_date, death_date, data, revision"#,
+                    &[&before],
+                )
+                .map_err(|e| DaoError::PurgeError(e.to_string()))?;
+
+            for (id, person) in purged.iter().map(to_person) {
+                record(tx, id, ChangeKind::Purge, Some(&person), None, &actor, None)
+                    .map_err(DaoError::PurgeError)?;
+            }
+
+            Ok(purged.len() as u64)
+        })
+    }
+    fn history(
+        &self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<postgres::Client, Item = Vec<PersonHistoryDto>, Err = DaoError> {
+        trace!("fetching history of person: {:?}", id);
+        tx_rs::with_tx(move |tx: &mut postgres::Client| {
+            tx.query(
+                r#"SELECT person_id,
+                          revision,
+                          kind,
+                          old_value,
+                          new_value,
+                          changed_at,
+                          actor,
+                          reason
+                     FROM person_history
+                    WHERE person_id = $1
+                 ORDER BY id"#,
+                &[&id],
+            )
+            .map_err(|e| DaoError::SelectError(e.to_string()))?
+            .iter()
+            .map(to_history)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(DaoError::SelectError)
+        })
+    }
+    fn query(
+        &self,
+        query: PersonQuery,
+    ) -> impl tx_rs::Tx<postgres::Client, Item = Vec<(PersonId, PersonDto)>, Err = DaoError> {
+        trace!("querying persons: {:?}", query);
+        tx_rs::with_tx(move |tx: &mut postgres::Client| {
+            let (sql, params) = build_query(&query);
+            let params = param_refs(&params);
+            tx.query(&sql, &params)
+                .map(|rows| rows.iter().map(to_person).collect())
+                .map_err(|e| DaoError::SelectError(e.to_string()))
+        })
+    }
+    fn search(
+        &self,
+        search: PersonSearch,
+    ) -> impl tx_rs::Tx<postgres::Client, Item = Vec<(PersonId, PersonDto)>, Err = DaoError> {
+        trace!("searching persons: {:?}", search);
+        tx_rs::with_tx(move |tx: &mut postgres::Client| {
+            let (sql, params) = build_search(&search);
+            let params = param_refs(&params);
+            tx.query(&sql, &params)
+                .map(|rows| rows.iter().map(to_person).collect())
+                .map_err(|e| DaoError::SelectError(e.to_string()))
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PgOutboxDao;
+impl OutboxDao<postgres::Client> for PgOutboxDao {
+    fn enqueue(
+        &self,
+        notification: Notification,
+    ) -> impl tx_rs::Tx<postgres::Client, Item = (), Err = DaoError> {
+        trace!("enqueueing notification: {:?}", notification);
+        tx_rs::with_tx(move |tx: &mut postgres::Client| {
+            let event = notification
+                .event
+                .as_ref()
+                .map(serde_json::to_value)
+                .transpose()
+                .map_err(|e| DaoError::InsertError(e.to_string()))?;
+
+            tx.execute(
+                r#"INSERT INTO outbox ( level
+                                      , queue
+                                      , message
+                                      , event
+                                      , file
+                                      , line
+                                      , column_no
+                                      )
+                   VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
                 &[
-                    &person.name,
-                    &person.birth_date,
-                    &person.death_date,
-                    &person.data.map(|d| d.as_str().as_bytes().to_vec()),
-                    &person.revision,
-                    &id,
-                    &revision,
+                    &notification.level.to_string(),
+                    &notification.to,
+                    &notification.message,
+                    &event,
+                    &notification.file,
+                    &(notification.line as i32),
+                    &(notification.column as i32),
                 ],
             )
             .map(|_| ())
+            .map_err(|e| DaoError::InsertError(e.to_string()))
+        })
+    }
+    fn fetch_pending(
+        &self,
+        limit: i64,
+    ) -> impl tx_rs::Tx<postgres::Client, Item = Vec<OutboxEntry>, Err = DaoError> {
+        trace!("fetching pending notifications: limit={}", limit);
+        tx_rs::with_tx(move |tx: &mut postgres::Client| {
+            // 他の relay が処理中のものは飛ばす
+            let rows = tx
+                .query(
+                    r#"SELECT id,
+                              level,
+                              queue,
+                              message,
+                              event,
+                              file,
+                              line,
+                              column_no,
+                              attempts
+                         FROM outbox
+                        WHERE next_attempt_at <= now()
+                     ORDER BY id
+                        LIMIT $1
+                          FOR UPDATE SKIP LOCKED"#,
+                    &[&limit],
+                )
+                .map_err(|e| DaoError::SelectError(e.to_string()))?;
+
+            rows.iter()
+                .map(to_outbox_entry)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(DaoError::SelectError)
+        })
+    }
+    fn mark_sent(
+        &self,
+        id: OutboxId,
+    ) -> impl tx_rs::Tx<postgres::Client, Item = (), Err = DaoError> {
+        trace!("notification sent: {}", id);
+        tx_rs::with_tx(move |tx: &mut postgres::Client| {
+            tx.execute("DELETE FROM outbox WHERE id = $1", &[&id])
+                .map(|_| ())
+                .map_err(|e| DaoError::DeleteError(e.to_string()))
+        })
+    }
+    fn mark_failed(
+        &self,
+        id: OutboxId,
+        error: &str,
+        retry_at: DateTime<Utc>,
+    ) -> impl tx_rs::Tx<postgres::Client, Item = (), Err = DaoError> {
+        trace!("notification failed: {} (retry at {})", id, retry_at);
+        let error = error.to_string();
+        tx_rs::with_tx(move |tx: &mut postgres::Client| {
+            tx.execute(
+                r#"UPDATE outbox
+                      SET attempts = attempts + 1,
+                          last_error = $2,
+                          next_attempt_at = $3
+                    WHERE id = $1"#,
+                &[&id, &error, &retry_at],
+            )
+            .map(|_| ())
             .map_err(|e| DaoError::UpdateError(e.to_string()))
         })
     }
-    fn delete(
+}
+
+// 複数の migrator が同時に動かないように, トランザクションの間だけ取る advisory lock の鍵
+const MIGRATION_LOCK_KEY: i64 = 0x7478_7273;
+
+#[derive(Debug, Clone)]
+pub struct PgMigrationDao;
+impl<'a> MigrationDao<postgres::Transaction<'a>> for PgMigrationDao {
+    fn prepare(&self) -> impl tx_rs::Tx<postgres::Transaction<'a>, Item = (), Err = DaoError> {
+        trace!("preparing schema_migrations");
+        tx_rs::with_tx(|tx: &mut postgres::Transaction<'_>| {
+            // 表を作る前に取るので, 初回に同時に起動しても作成がぶつからない
+            tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_KEY])
+                .map_err(|e| DaoError::SelectError(e.to_string()))?;
+            tx.batch_execute(
+                r#"CREATE TABLE IF NOT EXISTS schema_migrations (
+                     version    BIGINT PRIMARY KEY,
+                     name       TEXT NOT NULL,
+                     checksum   TEXT NOT NULL,
+                     applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+                   )"#,
+            )
+            .map_err(|e| DaoError::InsertError(e.to_string()))
+        })
+    }
+    fn applied(
         &self,
-        id: PersonId,
+    ) -> impl tx_rs::Tx<postgres::Transaction<'a>, Item = Vec<AppliedMigration>, Err = DaoError>
+    {
+        trace!("fetching applied migrations");
+        tx_rs::with_tx(|tx: &mut postgres::Transaction<'_>| {
+            let prepared = tx
+                .query_one("SELECT to_regclass('schema_migrations') IS NOT NULL", &[])
+                .map_err(|e| DaoError::SelectError(e.to_string()))?
+                .get::<usize, bool>(0);
+            if !prepared {
+                return Ok(vec![]);
+            }
+
+            let rows = tx
+                .query(
+                    r#"SELECT version, name, checksum, applied_at
+                         FROM schema_migrations
+                     ORDER BY version"#,
+                    &[],
+                )
+                .map_err(|e| DaoError::SelectError(e.to_string()))?;
+
+            Ok(rows
+                .iter()
+                .map(|row| AppliedMigration {
+                    version: row.get::<usize, MigrationVersion>(0),
+                    name: row.get::<usize, String>(1),
+                    checksum: row.get::<usize, String>(2),
+                    applied_at: row.get::<usize, DateTime<Utc>>(3),
+                })
+                .collect())
+        })
+    }
+    fn apply(
+        &self,
+        migration: Migration,
     ) -> impl tx_rs::Tx<postgres::Transaction<'a>, Item = (), Err = DaoError> {
-        trace!("deleting person: {:?}", id);
+        trace!(
+            "applying migration: {} {}",
+            migration.version,
+            migration.name
+        );
         tx_rs::with_tx(move |tx: &mut postgres::Transaction<'_>| {
-            tx.execute("DELETE FROM person WHERE id = $1", &[&id])
-                .map(|_| ())
-                .map_err(|e| DaoError::DeleteError(e.to_string()))
+            tx.batch_execute(migration.sql).map_err(|e| {
+                DaoError::UpdateError(format!("{} {}: {}", migration.version, migration.name, e))
+            })?;
+            tx.execute(
+                r#"INSERT INTO schema_migrations ( version
+                                                 , name
+                                                 , checksum
+                                                 )
+                   VALUES ($1, $2, $3)"#,
+                &[&migration.version, &migration.name, &migration.checksum()],
+            )
+            .map(|_| ())
+            .map_err(|e| DaoError::InsertError(e.to_string()))
         })
     }
 }
+
+// name, birth_date, death_date, data, revision の順に並んだ列を start から読む
+pub(crate) fn person_at(row: &postgres::Row, start: usize) -> PersonDto {
+    let name = row.get::<usize, &str>(start);
+    let birth_date = row.get::<usize, NaiveDate>(start + 1);
+    let death_date = row.get::<usize, Option<NaiveDate>>(start + 2);
+    // data is NULL when cleared by an update or imported without it
+    let data = row
+        .get::<usize, Option<&[u8]>>(start + 3)
+        .and_then(|d| str::from_utf8(d).ok());
+    let revision = row.get::<usize, Revision>(start + 4);
+
+    PersonDto::new(name, birth_date, death_date, data, revision)
+}
+
+pub(crate) fn to_person(row: &postgres::Row) -> (PersonId, PersonDto) {
+    (row.get::<usize, PersonId>(0), person_at(row, 1))
+}
+
+fn to_history(row: &postgres::Row) -> Result<PersonHistoryDto, String> {
+    let value = |i: usize| -> Result<Option<PersonDto>, String> {
+        row.get::<usize, Option<serde_json::Value>>(i)
+            .map(serde_json::from_value)
+            .transpose()
+            .map_err(|e| e.to_string())
+    };
+
+    Ok(PersonHistoryDto {
+        person_id: row.get::<usize, PersonId>(0),
+        revision: row.get::<usize, Revision>(1),
+        kind: row.get::<usize, &str>(2).parse()?,
+        old: value(3)?,
+        new: value(4)?,
+        changed_at: row.get::<usize, DateTime<Utc>>(5),
+        actor: row.get::<usize, String>(6),
+        reason: row.get::<usize, Option<String>>(7),
+    })
+}
+
+fn to_outbox_entry(row: &postgres::Row) -> Result<OutboxEntry, String> {
+    Ok(OutboxEntry {
+        id: row.get::<usize, OutboxId>(0),
+        notification: Notification {
+            level: row.get::<usize, &str>(1).parse()?,
+            to: row.get::<usize, String>(2),
+            message: row.get::<usize, String>(3),
+            event: row
+                .get::<usize, Option<serde_json::Value>>(4)
+                .map(serde_json::from_value)
+                .transpose()
+                .map_err(|e| e.to_string())?,
+            file: row.get::<usize, String>(5),
+            line: row.get::<usize, i32>(6) as u32,
+            column: row.get::<usize, i32>(7) as u32,
+        },
+        attempts: row.get::<usize, i32>(8),
+    })
+}
+
+// 履歴は追記のみ. 元の書き込みと同じトランザクションで書くので, どちらかだけが残ることはない
+fn record(
+    tx: &mut postgres::Client,
+    id: PersonId,
+    kind: ChangeKind,
+    old: Option<&PersonDto>,
+    new: Option<&PersonDto>,
+    actor: &str,
+    reason: Option<&str>,
+) -> Result<(), String> {
+    let revision = new.or(old).map(|p| p.revision).unwrap_or_default();
+    let to_value = |p: Option<&PersonDto>| p.map(serde_json::to_value).transpose();
+    let (old, new) = (
+        to_value(old).map_err(|e| e.to_string())?,
+        to_value(new).map_err(|e| e.to_string())?,
+    );
+
+    tx.execute(
+        r#"INSERT INTO person_history ( person_id
+                                      , revision
+                                      , kind
+                                      , old_value
+                                      , new_value
+                                      , actor
+                                      , reason
+                                      )
+           VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+        &[
+            &id,
+            &revision,
+            &kind.to_string(),
+            &old,
+            &new,
+            &actor,
+            &reason,
+        ],
+    )
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+// 削除と復元は deleted_at と版だけが変わるので, 変更前は版を戻したもの
+fn record_toggle(
+    tx: &mut postgres::Client,
+    id: PersonId,
+    kind: ChangeKind,
+    row: &postgres::Row,
+    actor: &str,
+) -> Result<(), String> {
+    let new = person_at(row, 0);
+    let old = PersonDto {
+        revision: new.revision - 1,
+        ..new.clone()
+    };
+
+    record(tx, id, kind, Some(&old), Some(&new), actor, None)
+}
+
+// プレースホルダに渡す値. async 版でも await をまたいで持てるように Send にしておく
+pub(crate) type SqlParams = Vec<Box<dyn ToSql + Sync + Send>>;
+
+pub(crate) fn param_refs(params: &SqlParams) -> Vec<&(dyn ToSql + Sync)> {
+    params
+        .iter()
+        .map(|p| p.as_ref() as &(dyn ToSql + Sync))
+        .collect()
+}
+
+// 値はすべてプレースホルダで渡し, SQL に埋め込むのは列名と演算子だけにする
+pub(crate) fn build_query(query: &PersonQuery) -> (String, SqlParams) {
+    let mut conds = vec!["deleted_at IS NULL".to_string()];
+    let mut params: SqlParams = vec![];
+
+    if let Some(prefix) = &query.name_prefix {
+        params.push(Box::new(prefix.clone()));
+        conds.push(format!("starts_with(name, ${})", params.len()));
+    }
+    match query.status {
+        Some(LifeStatus::Alive) => conds.push("death_date IS NULL".to_string()),
+        Some(LifeStatus::Dead) => conds.push("death_date IS NOT NULL".to_string()),
+        None => {}
+    }
+    if let Some(from) = query.born_from {
+        params.push(Box::new(from));
+        conds.push(format!("birth_date >= ${}", params.len()));
+    }
+    if let Some(until) = query.born_until {
+        params.push(Box::new(until));
+        conds.push(format!("birth_date <= ${}", params.len()));
+    }
+
+    let key = match query.sort {
+        SortKey::Id => "id",
+        SortKey::Name => "name, id",
+        SortKey::BirthDate => "birth_date, id",
+    };
+    let (op, dir) = match query.order {
+        SortOrder::Asc => (">", "ASC"),
+        SortOrder::Desc => ("<", "DESC"),
+    };
+    if let Some(after) = query.after {
+        // 削除された人はカーソルにならない. NULL と比べるので何も返らない
+        params.push(Box::new(after));
+        conds.push(format!(
+            "({key}) {op} (SELECT {key} FROM person WHERE id = ${} AND deleted_at IS NULL)",
+            params.len()
+        ));
+    }
+
+    let mut sql = format!(
+        "SELECT id, name, birth_date, death_date, data, revision FROM person WHERE {}",
+        conds.join(" AND ")
+    );
+    let order_by = key
+        .split(", ")
+        .map(|col| format!("{col} {dir}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    sql.push_str(&format!(" ORDER BY {order_by}"));
+    if let Some(limit) = query.limit {
+        params.push(Box::new(limit as i64));
+        sql.push_str(&format!(" LIMIT ${}", params.len()));
+    }
+
+    (sql, params)
+}
+
+// LIKE のワイルドカードをエスケープする (エスケープ文字は既定の \\)
+fn like_pattern(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
+// ランクは PersonSearch のドキュメントの順. `%` は pg_trgm の類似度演算子で
+// person_name_trgm_idx が効くように lower(name) に対して使う
+pub(crate) fn build_search(search: &PersonSearch) -> (String, SqlParams) {
+    let text = search.text.to_lowercase();
+    let mut params: SqlParams = vec![Box::new(like_pattern(&text)), Box::new(text)];
+
+    let data = "lower(convert_from(data, 'UTF8')) LIKE $1";
+    let (data_cond, data_rank) = if search.include_data {
+        (format!(" OR {data}"), format!(" WHEN {data} THEN 1"))
+    } else {
+        (String::new(), String::new())
+    };
+    let mut sql = format!(
+        "SELECT id, name, birth_date, death_date, data, revision FROM person \
+         WHERE deleted_at IS NULL AND (lower(name) LIKE $1 OR lower(name) % $2{data_cond}) \
+         ORDER BY CASE WHEN lower(name) = $2 THEN 4 \
+         WHEN starts_with(lower(name), $2) THEN 3 \
+         WHEN lower(name) LIKE $1 THEN 2{data_rank} \
+         ELSE 0 END DESC, similarity(lower(name), $2) DESC, id ASC"
+    );
+    if let Some(limit) = search.limit {
+        params.push(Box::new(limit as i64));
+        sql.push_str(&format!(" LIMIT ${}", params.len()));
+    }
+
+    (sql, params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::date;
+
+    #[test]
+    fn test_build_query_all() {
+        let (sql, params) = build_query(&PersonQuery::default());
+
+        assert_eq!(
+            sql,
+            "SELECT id, name, birth_date, death_date, data, revision FROM person \
+             WHERE deleted_at IS NULL ORDER BY id ASC"
+        );
+        assert!(params.is_empty());
+    }
+
+    #[test]
+    fn test_build_query() {
+        let (sql, params) = build_query(&PersonQuery {
+            name_prefix: Some("Ab".to_string()),
+            status: Some(LifeStatus::Dead),
+            born_from: Some(date(1800, 1, 1)),
+            born_until: Some(date(1899, 12, 31)),
+            sort: SortKey::Name,
+            order: SortOrder::Desc,
+            after: Some(13),
+            limit: Some(10),
+        });
+
+        assert_eq!(
+            sql,
+            "SELECT id, name, birth_date, death_date, data, revision FROM person \
+             WHERE deleted_at IS NULL \
+               AND starts_with(name, $1) \
+               AND death_date IS NOT NULL \
+               AND birth_date >= $2 \
+               AND birth_date <= $3 \
+               AND (name, id) < (SELECT name, id FROM person WHERE id = $4 AND deleted_at IS NULL) \
+             ORDER BY name DESC, id DESC \
+             LIMIT $5"
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+        assert_eq!(params.len(), 5);
+    }
+
+    #[test]
+    fn test_like_pattern() {
+        assert_eq!(like_pattern("abel"), "%abel%");
+        assert_eq!(like_pattern("100%_a\\b"), "%100\\%\\_a\\\\b%");
+    }
+
+    #[test]
+    fn test_build_search() {
+        let (sql, params) = build_search(&PersonSearch {
+            text: "Abel".to_string(),
+            include_data: true,
+            limit: Some(10),
+        });
+
+        assert!(sql.contains("WHERE deleted_at IS NULL AND (lower(name) LIKE $1"));
+        assert!(sql.contains("OR lower(convert_from(data, 'UTF8')) LIKE $1) ORDER BY"));
+        assert!(sql.ends_with("id ASC LIMIT $3"));
+        assert_eq!(params.len(), 3);
+
+        let (sql, params) = build_search(&PersonSearch {
+            text: "Abel".to_string(),
+            ..Default::default()
+        });
+        assert!(!sql.contains("convert_from"));
+        assert_eq!(params.len(), 2);
+    }
+}
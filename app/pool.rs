@@ -0,0 +1,367 @@
+//! A small blocking connection pool for postgres and redis.
+//!
+//! r2d2 would pool them as well, but its `get` retries a failed connect inside until the
+//! checkout timeout and then reports a timeout either way. The services tell the two apart:
+//! `PoolError::Connect` means the backend is down, so `ReconnectPolicy` backs off and tries
+//! again, while `PoolError::Timeout` means every connection is in use. Keeping the pool here
+//! also spares the background thread r2d2 runs to reap and refill connections.
+
+use log::{trace, warn};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// How to open and check the connections of a `Pool`.
+pub trait ManageConnection: Send + Sync + 'static {
+    type Connection: Send + 'static;
+    type Error: fmt::Display;
+
+    fn connect(&self) -> Result<Self::Connection, Self::Error>;
+    /// health check of an idle connection before it is checked out.
+    fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error>;
+    /// cheap check without I/O when a connection is returned. A broken one is closed.
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// max number of connections, both idle and checked out
+    pub max_size: usize,
+    /// how long `get` waits for a connection to be returned when all are checked out
+    pub checkout_timeout: Duration,
+    /// run `ManageConnection::is_valid` on every checkout
+    pub test_on_checkout: bool,
+}
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 10,
+            checkout_timeout: Duration::from_secs(5),
+            test_on_checkout: true,
+        }
+    }
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum PoolError {
+    #[error("cannot connect: {0}")]
+    Connect(String),
+    #[error("checkout timed out after {0:?}")]
+    Timeout(Duration),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolState {
+    pub connections: usize,
+    pub idle_connections: usize,
+}
+
+struct Shared<M: ManageConnection> {
+    manager: M,
+    config: PoolConfig,
+    state: Mutex<Inner<M::Connection>>,
+    returned: Condvar,
+}
+struct Inner<C> {
+    idle: Vec<C>,
+    // idle と貸し出し中の合計. 接続中のものも含む
+    open: usize,
+}
+
+/// A blocking connection pool shared across threads.
+///
+/// Connections are opened lazily up to `max_size` and are reused after they are returned,
+/// so a checkout costs no handshake unless the pool has to grow or replace a broken one.
+pub struct Pool<M: ManageConnection>(Arc<Shared<M>>);
+impl<M: ManageConnection> Clone for Pool<M> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+impl<M: ManageConnection> fmt::Debug for Pool<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pool")
+            .field("config", &self.0.config)
+            .field("state", &self.state())
+            .finish()
+    }
+}
+impl<M: ManageConnection> Pool<M> {
+    pub fn new(manager: M, config: PoolConfig) -> Self {
+        Self(Arc::new(Shared {
+            manager,
+            config,
+            state: Mutex::new(Inner {
+                idle: vec![],
+                open: 0,
+            }),
+            returned: Condvar::new(),
+        }))
+    }
+
+    pub fn manager(&self) -> &M {
+        &self.0.manager
+    }
+
+    pub fn config(&self) -> &PoolConfig {
+        &self.0.config
+    }
+
+    pub fn state(&self) -> PoolState {
+        let state = self.0.state.lock().unwrap();
+        PoolState {
+            connections: state.open,
+            idle_connections: state.idle.len(),
+        }
+    }
+
+    /// check out a connection, waiting up to `checkout_timeout` while all are in use.
+    pub fn get(&self) -> Result<PooledConnection<M>, PoolError> {
+        let config = &self.0.config;
+        let deadline = Instant::now() + config.checkout_timeout;
+
+        let mut state = self.0.state.lock().unwrap();
+        loop {
+            if let Some(mut conn) = state.idle.pop() {
+                drop(state);
+                if !config.test_on_checkout {
+                    return Ok(self.wrap(conn));
+                }
+                match self.0.manager.is_valid(&mut conn) {
+                    Ok(()) => return Ok(self.wrap(conn)),
+                    Err(e) => {
+                        // 壊れたものは捨てて作り直す
+                        warn!("discard broken connection: {}", e);
+                        state = self.0.state.lock().unwrap();
+                        state.open -= 1;
+                        continue;
+                    }
+                }
+            }
+            if state.open < config.max_size {
+                state.open += 1;
+                drop(state);
+                trace!("open new connection");
+                return match self.0.manager.connect() {
+                    Ok(conn) => Ok(self.wrap(conn)),
+                    Err(e) => {
+                        self.discard();
+                        Err(PoolError::Connect(e.to_string()))
+                    }
+                };
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                warn!("connection pool exhausted: {:?}", config);
+                return Err(PoolError::Timeout(config.checkout_timeout));
+            }
+            state = self
+                .0
+                .returned
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    fn wrap(&self, conn: M::Connection) -> PooledConnection<M> {
+        PooledConnection {
+            pool: self.clone(),
+            conn: Some(conn),
+        }
+    }
+
+    fn discard(&self) {
+        self.0.state.lock().unwrap().open -= 1;
+        self.0.returned.notify_one();
+    }
+
+    fn put_back(&self, mut conn: M::Connection) {
+        if self.0.manager.has_broken(&mut conn) {
+            warn!("discard broken connection on return");
+            drop(conn);
+            self.discard();
+            return;
+        }
+        self.0.state.lock().unwrap().idle.push(conn);
+        self.0.returned.notify_one();
+    }
+}
+
+/// A connection checked out of a `Pool`, returned to it when dropped.
+pub struct PooledConnection<M: ManageConnection> {
+    pool: Pool<M>,
+    conn: Option<M::Connection>,
+}
+impl<M: ManageConnection> Deref for PooledConnection<M> {
+    type Target = M::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn.as_ref().expect("connection")
+    }
+}
+impl<M: ManageConnection> DerefMut for PooledConnection<M> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.conn.as_mut().expect("connection")
+    }
+}
+impl<M: ManageConnection> Drop for PooledConnection<M> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool.put_back(conn);
+        }
+    }
+}
+
+// # フェイクテスト
+//
+// ## 目的
+//
+//   Pool が接続を使い回し, 上限を守り, 壊れた接続を捨てることを保障する
+//
+// ## 方針
+//
+//   接続を番号で表すフェイクの ManageConnection で置き換え, 何本作られたかと状態を確認する
+//
+#[cfg(test)]
+mod fake_tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+
+    use super::*;
+
+    struct FakeManager {
+        opened: AtomicUsize,
+        available: AtomicBool,
+        healthy: AtomicBool,
+    }
+    impl FakeManager {
+        fn new() -> Self {
+            Self {
+                opened: AtomicUsize::new(0),
+                available: AtomicBool::new(true),
+                healthy: AtomicBool::new(true),
+            }
+        }
+    }
+    // 接続は作られた順の番号と, 壊れているかどうか
+    impl ManageConnection for FakeManager {
+        type Connection = (usize, bool);
+        type Error = String;
+
+        fn connect(&self) -> Result<Self::Connection, Self::Error> {
+            if !self.available.load(Ordering::SeqCst) {
+                return Err("unavailable".to_string());
+            }
+            Ok((self.opened.fetch_add(1, Ordering::SeqCst), false))
+        }
+        fn is_valid(&self, _conn: &mut Self::Connection) -> Result<(), Self::Error> {
+            if self.healthy.load(Ordering::SeqCst) {
+                Ok(())
+            } else {
+                Err("unhealthy".to_string())
+            }
+        }
+        fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+            conn.1
+        }
+    }
+
+    fn pool(max_size: usize) -> Pool<FakeManager> {
+        Pool::new(
+            FakeManager::new(),
+            PoolConfig {
+                max_size,
+                checkout_timeout: Duration::from_millis(50),
+                test_on_checkout: true,
+            },
+        )
+    }
+
+    #[test]
+    fn test_reuse() {
+        let pool = pool(2);
+
+        let conn = pool.get().unwrap();
+        assert_eq!(conn.0, 0);
+        drop(conn);
+        assert_eq!(
+            pool.state(),
+            PoolState {
+                connections: 1,
+                idle_connections: 1
+            }
+        );
+
+        // 返したものを使い回す
+        let conn = pool.get().unwrap();
+        assert_eq!(conn.0, 0);
+        assert_eq!(pool.manager().opened.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_max_size() {
+        let pool = pool(2);
+
+        let conn1 = pool.get().unwrap();
+        let _conn2 = pool.get().unwrap();
+        assert_eq!(
+            pool.get().err(),
+            Some(PoolError::Timeout(Duration::from_millis(50)))
+        );
+
+        // 返されるのを待つ
+        let handle = {
+            let pool = pool.clone();
+            thread::spawn(move || pool.get().map(|conn| conn.0))
+        };
+        thread::sleep(Duration::from_millis(10));
+        drop(conn1);
+        assert_eq!(handle.join().unwrap(), Ok(0));
+        assert_eq!(pool.manager().opened.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_health_check() {
+        let pool = pool(1);
+        drop(pool.get().unwrap());
+
+        // 検査に落ちたものは捨てて作り直す
+        pool.manager().healthy.store(false, Ordering::SeqCst);
+        let conn = pool.get().unwrap();
+        assert_eq!(conn.0, 1);
+        assert_eq!(pool.state().connections, 1);
+    }
+
+    #[test]
+    fn test_broken_on_return() {
+        let pool = pool(1);
+
+        let mut conn = pool.get().unwrap();
+        conn.1 = true;
+        drop(conn);
+        assert_eq!(
+            pool.state(),
+            PoolState {
+                connections: 0,
+                idle_connections: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_connect_failed() {
+        let pool = pool(1);
+        pool.manager().available.store(false, Ordering::SeqCst);
+
+        assert!(matches!(pool.get(), Err(PoolError::Connect(_))));
+        // 失敗した分は数えない
+        assert_eq!(pool.state().connections, 0);
+
+        pool.manager().available.store(true, Ordering::SeqCst);
+        assert!(pool.get().is_ok());
+    }
+}
@@ -1,13 +1,21 @@
-pub use log::{error, trace};
-use serde::{Deserialize, Serialize};
-pub use std::rc::Rc;
+pub use log::{error, trace, warn};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
 
+use crate::backend::ReconnectPolicy;
+use crate::config::QueueSettings;
+use crate::event::{PersonEvent, EVENT_VERSION};
 use crate::reporter::{self, Level, Location, Observer};
 
+/// A publisher to RabbitMQ, which opens the connection again when it is lost.
+// Send + Sync なので SyncReporter に登録してスレッド間で共有できる
 #[derive(Debug, Clone)]
 pub struct Client {
-    async_runtime: Rc<tokio::runtime::Runtime>,
-    conn: Rc<lapin::Connection>,
+    async_runtime: Arc<tokio::runtime::Runtime>,
+    addr: String,
+    conn: Arc<Mutex<Arc<lapin::Connection>>>,
+    queues: QueueSettings,
+    reconnect_policy: ReconnectPolicy,
 }
 impl Client {
     pub fn open(addr: &str) -> Result<Self, reporter::ReporterError> {
@@ -15,31 +23,200 @@ impl Client {
             .enable_all()
             .build()
             .unwrap();
-
-        trace!("connecting to rabbitmq: {}", addr);
-        let conn = runtime.block_on(async {
-            lapin::Connection::connect(addr, lapin::ConnectionProperties::default())
-                .await
-                .map_err(|e| {
-                    error!("failed to connect to rabbitmq: {}", e);
-                    reporter::ReporterError::Unavailable(e.to_string())
-                })
-        })?;
-        trace!("connected to rabbitmq with {:?}", conn.configuration());
+        let conn = connect(&runtime, addr)?;
 
         Ok(Self {
-            async_runtime: Rc::new(runtime),
-            conn: Rc::new(conn),
+            async_runtime: Arc::new(runtime),
+            addr: addr.to_string(),
+            conn: Arc::new(Mutex::new(Arc::new(conn))),
+            queues: QueueSettings::default(),
+            reconnect_policy: ReconnectPolicy::default(),
         })
     }
+
+    /// publish to the queues of the settings instead of the default names
+    pub fn with_queues(mut self, queues: QueueSettings) -> Self {
+        self.queues = queues;
+        self
+    }
+
+    pub fn with_reconnect_policy(mut self, reconnect_policy: ReconnectPolicy) -> Self {
+        self.reconnect_policy = reconnect_policy;
+        self
+    }
+
+    // 切れていれば開き直す. 開き直す間は lock しないので, 他のスレッドは待たされずに失敗できる.
+    // 他のスレッドが先に開き直していれば, そちらを使って開いたものは閉じる
+    fn connection(&self) -> Result<Arc<lapin::Connection>, reporter::ReporterError> {
+        let conn = self.conn.lock().unwrap().clone();
+        if conn.status().connected() {
+            return Ok(conn);
+        }
+        warn!("rabbitmq connection is lost, reconnecting");
+        let reopened = Arc::new(self.reconnect_policy.run(
+            "rabbitmq",
+            |_| true,
+            || connect(&self.async_runtime, &self.addr),
+        )?);
+
+        let mut conn = self.conn.lock().unwrap();
+        if !conn.status().connected() {
+            *conn = reopened.clone();
+            return Ok(reopened);
+        }
+        let current = conn.clone();
+        drop(conn);
+        if let Err(e) = self.async_runtime.block_on(reopened.close(0, "superseded")) {
+            warn!("failed to close superseded rabbitmq connection: {}", e);
+        }
+        Ok(current)
+    }
+}
+
+fn connect(
+    runtime: &tokio::runtime::Runtime,
+    addr: &str,
+) -> Result<lapin::Connection, reporter::ReporterError> {
+    trace!("connecting to rabbitmq: {}", addr);
+    let conn = runtime.block_on(async {
+        lapin::Connection::connect(addr, lapin::ConnectionProperties::default())
+            .await
+            .map_err(|e| {
+                error!("failed to connect to rabbitmq: {}", e);
+                reporter::ReporterError::Unavailable(e.to_string())
+            })
+    })?;
+    trace!("connected to rabbitmq with {:?}", conn.configuration());
+
+    Ok(conn)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Payload<'a> {
+// version は event の JSON の版. 管理者向けの message だけのものにも付ける
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Payload<'a> {
+    version: u32,
     level: Level,
     message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: Option<&'a PersonEvent>,
     location: Location<'a>,
 }
+impl<'a> Payload<'a> {
+    pub(crate) fn new(level: Level, message: &'a str, loc: Location<'a>) -> Self {
+        Self {
+            version: EVENT_VERSION,
+            level,
+            message,
+            event: None,
+            location: loc,
+        }
+    }
+    // message には event の要約を入れる
+    pub(crate) fn event(event: &'a PersonEvent, message: &'a str, loc: Location<'a>) -> Self {
+        Self {
+            event: Some(event),
+            ..Self::new(Level::Info, message, loc)
+        }
+    }
+}
+
+impl Client {
+    // channel は送るたびに作って閉じるので, 接続さえ開き直せば channel も元に戻る
+    fn publish(&self, to: &str, payload: &Payload) -> Result<(), reporter::ReporterError> {
+        let conn = self.connection()?;
+        match self.publish_on(&conn, to, payload) {
+            // 送っている間に切れたなら, 開き直して一度だけ送り直す
+            Err(e) if !conn.status().connected() => {
+                warn!("rabbitmq connection is lost while publishing: {}", e);
+                let conn = self.connection()?;
+                self.publish_on(&conn, to, payload)
+            }
+            res => res,
+        }
+    }
+
+    fn publish_on(
+        &self,
+        conn: &lapin::Connection,
+        to: &str,
+        payload: &Payload,
+    ) -> Result<(), reporter::ReporterError> {
+        let to = self.queues.resolve(to);
+        self.async_runtime
+            .block_on(publish_confirmed(conn, to, payload))
+    }
+}
+
+/// publish `payload` to the queue `to` on a channel of its own, and wait until the broker
+/// confirms it. `Ok` means the broker has taken the message, so the outbox entry can be marked sent.
+/// The channel is closed afterwards, whether it is published or not.
+pub(crate) async fn publish_confirmed(
+    conn: &lapin::Connection,
+    to: &str,
+    payload: &Payload<'_>,
+) -> Result<(), reporter::ReporterError> {
+    let chan = conn.create_channel().await.map_err(|e| {
+        error!("failed to create channel: {}", e);
+        reporter::ReporterError::Unavailable(e.to_string())
+    })?;
+    trace!("channel created");
+    let result = publish_on_channel(&chan, to, payload).await;
+    // 切れていれば閉じられないが, 閉じる相手もいないので知らせるだけにする
+    if let Err(e) = chan
+        .close(lapin::protocol::constants::REPLY_SUCCESS, "published")
+        .await
+    {
+        warn!("failed to close channel: {}", e);
+    }
+    result
+}
+
+async fn publish_on_channel(
+    chan: &lapin::Channel,
+    to: &str,
+    payload: &Payload<'_>,
+) -> Result<(), reporter::ReporterError> {
+    let unavailable = |what: &str, e: lapin::Error| {
+        error!("failed to {}: {}", what, e);
+        reporter::ReporterError::Unavailable(e.to_string())
+    };
+
+    chan.confirm_select(lapin::options::ConfirmSelectOptions::default())
+        .await
+        .map_err(|e| unavailable("enable publisher confirms", e))?;
+    chan.queue_declare(
+        to,
+        lapin::options::QueueDeclareOptions::default(),
+        lapin::types::FieldTable::default(),
+    )
+    .await
+    .map_err(|e| unavailable("declare queue", e))?;
+    trace!("queue declared: {}", to);
+    let body = serde_json::to_string(payload).unwrap_or_default();
+    let confirmation = chan
+        .basic_publish(
+            "",
+            to,
+            lapin::options::BasicPublishOptions::default(),
+            body.as_bytes(),
+            lapin::BasicProperties::default(),
+        )
+        .await
+        .map_err(|e| unavailable("publish message", e))?
+        // 送っただけでは broker に届いたとは限らないので, 受け取ったと返ってくるまで待つ
+        .await
+        .map_err(|e| unavailable("confirm message", e))?;
+    if confirmation.is_nack() {
+        error!("message is not accepted by rabbitmq: {} to {}", body, to);
+        return Err(reporter::ReporterError::Unavailable(format!(
+            "message to {} is not accepted",
+            to
+        )));
+    }
+    trace!("published: {} to {}", payload.message, to);
+
+    Ok(())
+}
 
 impl Observer for Client {
     // to: queue name
@@ -51,44 +228,94 @@ impl Observer for Client {
         message: &str,
         loc: Location,
     ) -> Result<(), reporter::ReporterError> {
-        self.async_runtime.block_on(async {
-            let chan = self.conn.create_channel().await.map_err(|e| {
-                error!("failed to create channel: {}", e);
-                reporter::ReporterError::Unavailable(e.to_string())
-            })?;
-            trace!("channel created");
-            chan.queue_declare(
-                to,
-                lapin::options::QueueDeclareOptions::default(),
-                lapin::types::FieldTable::default(),
-            )
-            .await
-            .map_err(|e| {
-                error!("failed to declare queue: {}", e);
-                reporter::ReporterError::Unavailable(e.to_string())
-            })?;
-            trace!("queue declared: {}", to);
-            let payload = serde_json::to_string(&Payload {
-                level,
-                message,
-                location: loc,
+        self.publish(to, &Payload::new(level, message, loc))
+    }
+    // event は構造化したまま送り, message には要約を入れる
+    fn handle_event(
+        &self,
+        event: &PersonEvent,
+        loc: Location,
+    ) -> Result<(), reporter::ReporterError> {
+        let message = event.to_string();
+        self.publish(event.queue(), &Payload::event(event, &message, loc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_payload() {
+        let loc = Location {
+            file: "app/service.rs",
+            line: 1,
+            column: 2,
+        };
+        let payload = Payload::new(Level::Error, "cannot find person: id=42", loc.clone());
+        assert_eq!(
+            serde_json::to_value(&payload).unwrap(),
+            json!({
+                "version": EVENT_VERSION,
+                "level": "Error",
+                "message": "cannot find person: id=42",
+                "location": { "file": "app/service.rs", "line": 1, "column": 2 },
             })
-            .unwrap_or_default();
-            chan.basic_publish(
-                "",
-                to,
-                lapin::options::BasicPublishOptions::default(),
-                payload.as_bytes(),
-                lapin::BasicProperties::default(),
+        );
+
+        let event = PersonEvent::Unregistered {
+            id: 42,
+            revision: 2,
+        };
+        let payload = Payload::event(&event, "unregistered person_id: 42", loc);
+        assert_eq!(
+            serde_json::to_value(&payload).unwrap()["event"],
+            json!({ "type": "unregistered", "id": 42, "revision": 2 })
+        );
+    }
+
+    // 確認を待ってから返るので, 返った時点で queue に入っている
+    // rabbitmq が要るので, 既定では実行しない (TEST_MQ_URI を与えて cargo test -- --ignored で実行する)
+    #[test]
+    #[ignore = "needs rabbitmq at TEST_MQ_URI"]
+    fn test_publish_confirmed() {
+        let uri = std::env::var("TEST_MQ_URI").expect("TEST_MQ_URI is not set");
+        let queues = QueueSettings {
+            admin: "publish_confirmed_test".to_string(),
+            ..QueueSettings::default()
+        };
+        let client = Client::open(&uri).unwrap().with_queues(queues);
+        let loc = Location {
+            file: "app/rabbitmq.rs",
+            line: 1,
+            column: 2,
+        };
+
+        client
+            .handle_notification(Level::Error, "admin", "confirmed", loc)
+            .unwrap();
+
+        let conn = client.connection().unwrap();
+        let message = client.async_runtime.block_on(async {
+            let chan = conn.create_channel().await.unwrap();
+            let message = chan
+                .basic_get(
+                    "publish_confirmed_test",
+                    lapin::options::BasicGetOptions { no_ack: true },
+                )
+                .await
+                .unwrap();
+            chan.queue_delete(
+                "publish_confirmed_test",
+                lapin::options::QueueDeleteOptions::default(),
             )
             .await
-            .map_err(|e| {
-                error!("failed to publish message: {}", e);
-                reporter::ReporterError::Unavailable(e.to_string())
-            })?;
-            trace!("published: {} to {}", message, to);
-
-            Ok(())
-        })
+            .unwrap();
+            message
+        });
+        let body: serde_json::Value =
+            serde_json::from_slice(&message.expect("published message").data).unwrap();
+        assert_eq!(body["message"], "confirmed");
     }
 }
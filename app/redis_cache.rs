@@ -1,16 +1,18 @@
 use log::trace;
-use redis::{self, Commands, FromRedisValue, ToRedisArgs};
+use redis::{self, Commands, ConnectionLike, FromRedisValue, ToRedisArgs};
 use std::time::Duration;
 
+use crate::backend::Health;
 use crate::cache::{CaoError, PersonCao};
 use crate::domain::PersonId;
 use crate::dto::PersonDto;
+use crate::pool::{ManageConnection, Pool, PooledConnection};
 
 // this suppose PersonDto is serde-ized
 impl ToRedisArgs for PersonDto {
-    fn write_redis_args<W: ?Sized>(&self, out: &mut W)
+    fn write_redis_args<W>(&self, out: &mut W)
     where
-        W: redis::RedisWrite,
+        W: ?Sized + redis::RedisWrite,
     {
         let s = serde_json::to_string(self).expect("serialize");
         out.write_arg(s.as_bytes());
@@ -24,12 +26,14 @@ impl FromRedisValue for PersonDto {
         Ok(p)
     }
 }
+
+/// opens the connections of the pool to redis
 #[derive(Debug, Clone)]
-pub struct RedisPersonCao {
+pub struct RedisConnectionManager {
     client: redis::Client,
     connect_timeout: Duration,
 }
-impl RedisPersonCao {
+impl RedisConnectionManager {
     pub fn new(client: redis::Client, connect_timeout: Duration) -> Self {
         Self {
             client,
@@ -37,17 +41,82 @@ impl RedisPersonCao {
         }
     }
 }
+impl ManageConnection for RedisConnectionManager {
+    type Connection = redis::Connection;
+    type Error = redis::RedisError;
 
-impl PersonCao<redis::Connection> for RedisPersonCao {
-    fn get_conn(&self) -> Result<redis::Connection, CaoError> {
+    fn connect(&self) -> Result<redis::Connection, redis::RedisError> {
         self.client
             .get_connection_with_timeout(self.connect_timeout)
+    }
+    fn is_valid(&self, conn: &mut redis::Connection) -> Result<(), redis::RedisError> {
+        redis::cmd("PING").query::<String>(conn).map(|_| ())
+    }
+    fn has_broken(&self, conn: &mut redis::Connection) -> bool {
+        !conn.is_open()
+    }
+}
+
+pub type RedisConnection = PooledConnection<RedisConnectionManager>;
+
+#[derive(Debug, Clone)]
+pub struct RedisPersonCao {
+    pool: Pool<RedisConnectionManager>,
+    ttl: Option<Duration>,
+    health: Health,
+}
+impl RedisPersonCao {
+    pub fn new(pool: Pool<RedisConnectionManager>) -> Self {
+        Self {
+            pool,
+            ttl: None,
+            health: Health::up(),
+        }
+    }
+
+    /// expire cached persons after `ttl`, never by default
+    pub fn with_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// fail at once without trying redis while `health` is down
+    pub fn with_health(mut self, health: Health) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// remove every cached person, which may be stale after the cache has been skipped
+    pub fn clear(&self) -> Result<usize, CaoError> {
+        let mut conn = self
+            .pool
+            .get()
+            .map_err(|e| CaoError::Unavailable(e.to_string()))?;
+        let keys: Vec<String> = conn
+            .scan_match::<_, String>("person:*")
+            .map_err(|e| CaoError::Unavailable(e.to_string()))?
+            .collect();
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        conn.del::<_, usize>(&keys)
+            .map_err(|e| CaoError::Unavailable(e.to_string()))
+    }
+}
+
+impl PersonCao<RedisConnection> for RedisPersonCao {
+    fn get_conn(&self) -> Result<RedisConnection, CaoError> {
+        if !self.health.is_up() {
+            return Err(CaoError::Unavailable("cache is down".to_string()));
+        }
+        self.pool
+            .get()
             .map_err(|e| CaoError::Unavailable(e.to_string()))
     }
 
     fn run_tx<T, F>(&self, f: F) -> Result<T, CaoError>
     where
-        F: tx_rs::Tx<redis::Connection, Item = T, Err = CaoError>,
+        F: tx_rs::Tx<RedisConnection, Item = T, Err = CaoError>,
     {
         let mut conn = self.get_conn()?;
         trace!("redis connection obtained");
@@ -58,37 +127,41 @@ impl PersonCao<redis::Connection> for RedisPersonCao {
     fn find(
         &self,
         id: PersonId,
-    ) -> impl tx_rs::Tx<redis::Connection, Item = Option<PersonDto>, Err = CaoError> {
+    ) -> impl tx_rs::Tx<RedisConnection, Item = Option<PersonDto>, Err = CaoError> {
         trace!("find person: {}", id);
-        tx_rs::with_tx(move |conn: &mut redis::Connection| {
+        tx_rs::with_tx(move |conn: &mut RedisConnection| {
             let key = format!("person:{}", id);
             let p: Option<PersonDto> = conn
                 .get(&key)
                 .map_err(|e| CaoError::Unavailable(e.to_string()))?;
             trace!("found person in cache: {:?}", p);
-            Ok(p.into())
+            Ok(p)
         })
     }
     fn load(
         &self,
         id: PersonId,
         person: &PersonDto,
-    ) -> impl tx_rs::Tx<redis::Connection, Item = (), Err = CaoError> {
+    ) -> impl tx_rs::Tx<RedisConnection, Item = (), Err = CaoError> {
         trace!("load person: {}", id);
-        tx_rs::with_tx(move |conn: &mut redis::Connection| {
+        let ttl = self.ttl;
+        tx_rs::with_tx(move |conn: &mut RedisConnection| {
             let key = format!("person:{}", id);
             // NOTE: this is current workaround for: https://github.com/rust-lang/rust/issues/123748
             // reference: https://github.com/redis-rs/redis-rs/issues/1322
-            let _: () = conn
-                .set(&key, &person)
-                .map_err(|e| CaoError::Unavailable(e.to_string()))?;
+            let _: () = match ttl {
+                // SETEX は 0 秒を受け付けない
+                Some(ttl) => conn.set_ex(&key, person, ttl.as_secs().max(1)),
+                None => conn.set(&key, person),
+            }
+            .map_err(|e| CaoError::Unavailable(e.to_string()))?;
             trace!("person loaded into cache: {:?}", person);
             Ok(())
         })
     }
-    fn unload(&self, id: PersonId) -> impl tx_rs::Tx<redis::Connection, Item = (), Err = CaoError> {
+    fn unload(&self, id: PersonId) -> impl tx_rs::Tx<RedisConnection, Item = (), Err = CaoError> {
         trace!("unload person: {}", id);
-        tx_rs::with_tx(move |conn: &mut redis::Connection| {
+        tx_rs::with_tx(move |conn: &mut RedisConnection| {
             let key = format!("person:{}", id);
             // NOTE: this is current workaround for: https://github.com/rust-lang/rust/issues/123748
             // reference: https://github.com/redis-rs/redis-rs/issues/1322
@@ -1,8 +1,15 @@
+use log::warn;
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
 use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use thiserror::Error;
 
+use crate::event::PersonEvent;
 pub use crate::location::Location;
+use crate::outbox::Notification;
 
 #[derive(Debug, Error, Clone, PartialEq, Eq)]
 pub enum ReporterError {
@@ -17,6 +24,30 @@ pub enum Level {
     Warn,
     Error,
 }
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Level::Trace => "trace",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        };
+        f.pad(s)
+    }
+}
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "trace" => Ok(Level::Trace),
+            "info" => Ok(Level::Info),
+            "warn" => Ok(Level::Warn),
+            "error" => Ok(Level::Error),
+            _ => Err(format!("unknown level: {s}")),
+        }
+    }
+}
 
 pub trait Observer {
     fn handle_notification(
@@ -26,6 +57,26 @@ pub trait Observer {
         message: &str,
         loc: Location,
     ) -> Result<(), ReporterError>;
+    /// by default the event is sent as a message to its queue
+    fn handle_event(&self, event: &PersonEvent, loc: Location) -> Result<(), ReporterError> {
+        self.handle_notification(Level::Info, event.queue(), &event.to_string(), loc)
+    }
+}
+
+// Arc で共有している Observer もそのまま登録できるように
+impl<O: Observer + ?Sized> Observer for Arc<O> {
+    fn handle_notification(
+        &self,
+        level: Level,
+        to: &str,
+        message: &str,
+        loc: Location,
+    ) -> Result<(), ReporterError> {
+        self.as_ref().handle_notification(level, to, message, loc)
+    }
+    fn handle_event(&self, event: &PersonEvent, loc: Location) -> Result<(), ReporterError> {
+        self.as_ref().handle_event(event, loc)
+    }
 }
 
 pub trait Reporter<'a> {
@@ -48,6 +99,10 @@ pub trait Reporter<'a> {
         }
         Ok(())
     }
+    /// by default the event is sent as a report to its queue
+    fn send_event(&self, event: &PersonEvent, loc: Location) -> Result<(), ReporterError> {
+        self.send_report(Level::Info, event.queue(), &event.to_string(), loc)
+    }
 }
 
 #[derive(Clone)]
@@ -61,6 +116,11 @@ impl<'a> DefaultReporter<'a> {
         }
     }
 }
+impl Default for DefaultReporter<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl<'a> Reporter<'a> for DefaultReporter<'a> {
     fn register(&mut self, observer: impl Observer + 'a) -> Result<(), ReporterError> {
         self.observers.push(Rc::new(observer));
@@ -69,6 +129,154 @@ impl<'a> Reporter<'a> for DefaultReporter<'a> {
     fn get_observers(&self) -> Vec<&dyn Observer> {
         self.observers.iter().map(|o| o.as_ref()).collect()
     }
+    fn send_event(&self, event: &PersonEvent, loc: Location) -> Result<(), ReporterError> {
+        for observer in self.get_observers() {
+            if let Err(e) = observer.handle_event(event, loc.clone()) {
+                eprintln!("reporter error: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A set of observers shared across threads.
+///
+/// It is not a `Reporter` itself, since its observers must be `Send + Sync`.
+/// `local` gives a `DefaultReporter` for the current thread, which sends to the same observers.
+#[derive(Clone, Default)]
+pub struct SyncReporter {
+    observers: Vec<Arc<dyn Observer + Send + Sync>>,
+}
+impl SyncReporter {
+    pub fn new() -> Self {
+        Self {
+            observers: Vec::new(),
+        }
+    }
+    pub fn register(
+        &mut self,
+        observer: impl Observer + Send + Sync + 'static,
+    ) -> Result<(), ReporterError> {
+        self.observers.push(Arc::new(observer));
+        Ok(())
+    }
+    pub fn local(&self) -> DefaultReporter<'static> {
+        DefaultReporter {
+            observers: self
+                .observers
+                .iter()
+                .map(|o| Rc::new(o.clone()) as Rc<dyn Observer>)
+                .collect(),
+        }
+    }
+}
+
+/// An observer connected later, such as one whose server was down at startup.
+///
+/// Until `connect` gives it the observer, up to `capacity` notifications are kept and then
+/// sent to it in order. The ones beyond are refused with `ReporterError::Unavailable`,
+/// so with a capacity of 0 it fails like the observer itself would.
+pub struct BufferedObserver<O> {
+    state: Arc<Mutex<Buffered<O>>>,
+}
+struct Buffered<O> {
+    observer: Option<Arc<O>>,
+    pending: VecDeque<Notification>,
+    capacity: usize,
+}
+// derive では O: Clone が要るので
+impl<O> Clone for BufferedObserver<O> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+impl<O: Observer> BufferedObserver<O> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(Buffered {
+                observer: None,
+                pending: VecDeque::new(),
+                capacity,
+            })),
+        }
+    }
+    pub fn connected(observer: O) -> Self {
+        let buffered = Self::new(0);
+        buffered.connect(observer);
+        buffered
+    }
+
+    /// send the kept notifications to `observer`, and pass the later ones through to it
+    pub fn connect(&self, observer: O) {
+        // 送る間は lock を放しておく. その間に来たものは溜まるので, 空になるまで繰り返して順序を保つ
+        loop {
+            let drained = {
+                let mut state = self.state.lock().unwrap();
+                if state.pending.is_empty() {
+                    state.observer = Some(Arc::new(observer));
+                    return;
+                }
+                std::mem::take(&mut state.pending)
+            };
+            for notification in drained {
+                if let Err(e) = notification.send_to(&observer) {
+                    warn!(
+                        "dropped a buffered notification to {}: {}",
+                        notification.to, e
+                    );
+                }
+            }
+        }
+    }
+    pub fn is_connected(&self) -> bool {
+        self.state.lock().unwrap().observer.is_some()
+    }
+    /// how many notifications are kept
+    pub fn pending(&self) -> usize {
+        self.state.lock().unwrap().pending.len()
+    }
+
+    // 繋がっていなければ溜める. 繋がっていれば, 送る間は lock を放しておけるよう observer を返す
+    fn observer_or_keep(
+        &self,
+        notification: impl FnOnce() -> Notification,
+    ) -> Result<Option<Arc<O>>, ReporterError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(observer) = &state.observer {
+            return Ok(Some(observer.clone()));
+        }
+        if state.pending.len() >= state.capacity {
+            return Err(ReporterError::Unavailable(
+                "observer is not connected".to_string(),
+            ));
+        }
+        state.pending.push_back(notification());
+        Ok(None)
+    }
+}
+impl<O: Observer> Observer for BufferedObserver<O> {
+    fn handle_notification(
+        &self,
+        level: Level,
+        to: &str,
+        message: &str,
+        loc: Location,
+    ) -> Result<(), ReporterError> {
+        let notification = || Notification::new(level.clone(), to, message, loc.clone());
+        match self.observer_or_keep(notification)? {
+            Some(observer) => observer.handle_notification(level, to, message, loc),
+            None => Ok(()),
+        }
+    }
+    fn handle_event(&self, event: &PersonEvent, loc: Location) -> Result<(), ReporterError> {
+        let notification = || Notification::event(event.clone(), loc.clone());
+        match self.observer_or_keep(notification)? {
+            Some(observer) => observer.handle_event(event, loc),
+            None => Ok(()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -96,6 +304,14 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_level() {
+        for level in [Level::Trace, Level::Info, Level::Warn, Level::Error] {
+            assert_eq!(level.to_string().parse(), Ok(level));
+        }
+        assert!("fatal".parse::<Level>().is_err());
+    }
+
     #[test]
     fn test_reporter_for_single_observer() {
         let observer = MockObserver {
@@ -135,4 +351,198 @@ mod test {
             &[(Level::Info, "to".to_string(), "message".to_string())]
         );
     }
+
+    #[derive(Debug, Clone)]
+    struct MockEventObserver {
+        events: Rc<RefCell<Vec<PersonEvent>>>,
+    }
+    impl Observer for MockEventObserver {
+        fn handle_notification(
+            &self,
+            _level: Level,
+            _to: &str,
+            _message: &str,
+            _loc: Location,
+        ) -> Result<(), ReporterError> {
+            unreachable!("events are handled by handle_event")
+        }
+        fn handle_event(&self, event: &PersonEvent, _loc: Location) -> Result<(), ReporterError> {
+            self.events.borrow_mut().push(event.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_reporter_send_event() {
+        let observer1 = MockObserver {
+            messages: Rc::new(RefCell::new(Vec::new())),
+        };
+        let observer2 = MockEventObserver {
+            events: Rc::new(RefCell::new(Vec::new())),
+        };
+        let mut reporter = DefaultReporter::new();
+        reporter.register(observer1.clone()).unwrap();
+        reporter.register(observer2.clone()).unwrap();
+
+        let event = PersonEvent::Unregistered {
+            id: 42,
+            revision: 2,
+        };
+        reporter.send_event(&event, location!()).unwrap();
+
+        // handle_event を実装していなければメッセージとして届く
+        assert_eq!(
+            observer1.messages.borrow().as_slice(),
+            &[(
+                Level::Info,
+                "unregister_person".to_string(),
+                "unregistered person_id: 42".to_string()
+            )]
+        );
+        assert_eq!(observer2.events.borrow().as_slice(), &[event]);
+    }
+
+    #[test]
+    fn test_buffered_observer() {
+        let observer = BufferedObserver::new(2);
+        observer
+            .handle_notification(Level::Warn, "admin", "first", location!())
+            .unwrap();
+        let event = PersonEvent::Unregistered {
+            id: 42,
+            revision: 2,
+        };
+        observer.handle_event(&event, location!()).unwrap();
+        // 溢れた分は断る
+        assert!(observer
+            .handle_notification(Level::Warn, "admin", "third", location!())
+            .is_err());
+        assert!(!observer.is_connected());
+        assert_eq!(observer.pending(), 2);
+
+        // 繋がったら溜めた順に送り, その後は素通しする
+        let messages = MockObserver {
+            messages: Rc::new(RefCell::new(Vec::new())),
+        };
+        observer.connect(messages.clone());
+        observer
+            .handle_notification(Level::Info, "admin", "fourth", location!())
+            .unwrap();
+        assert!(observer.is_connected());
+        assert_eq!(observer.pending(), 0);
+        assert_eq!(
+            messages.messages.borrow().as_slice(),
+            &[
+                (Level::Warn, "admin".to_string(), "first".to_string()),
+                (
+                    Level::Info,
+                    "unregister_person".to_string(),
+                    "unregistered person_id: 42".to_string()
+                ),
+                (Level::Info, "admin".to_string(), "fourth".to_string()),
+            ]
+        );
+    }
+
+    // 受け取ったときに, 同じ BufferedObserver へさらに送る
+    struct ReentrantObserver {
+        messages: Rc<RefCell<Vec<String>>>,
+        buffered: BufferedObserver<ReentrantObserver>,
+    }
+    impl Observer for ReentrantObserver {
+        fn handle_notification(
+            &self,
+            _level: Level,
+            _to: &str,
+            message: &str,
+            _loc: Location,
+        ) -> Result<(), ReporterError> {
+            self.messages.borrow_mut().push(message.to_string());
+            if message == "first" {
+                self.buffered
+                    .handle_notification(Level::Info, "admin", "during", location!())?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_buffered_observer_connect_unlocked() {
+        let observer = BufferedObserver::new(2);
+        observer
+            .handle_notification(Level::Warn, "admin", "first", location!())
+            .unwrap();
+        observer
+            .handle_notification(Level::Warn, "admin", "second", location!())
+            .unwrap();
+
+        // 溜めたものを送る間は lock を持たないので, 送り先から送り返しても止まらない
+        let messages = Rc::new(RefCell::new(Vec::new()));
+        observer.connect(ReentrantObserver {
+            messages: messages.clone(),
+            buffered: observer.clone(),
+        });
+        assert!(observer.is_connected());
+        assert_eq!(observer.pending(), 0);
+        // 送る間に来たものは, 溜めたものの後に送る
+        assert_eq!(*messages.borrow(), vec!["first", "second", "during"]);
+    }
+
+    #[test]
+    fn test_buffered_observer_without_buffer() {
+        let observer = BufferedObserver::<MockObserver>::new(0);
+        assert_eq!(
+            observer.handle_notification(Level::Info, "to", "message", location!()),
+            Err(ReporterError::Unavailable(
+                "observer is not connected".to_string()
+            ))
+        );
+        assert_eq!(observer.pending(), 0);
+    }
+
+    #[derive(Debug, Clone)]
+    struct SyncMockObserver {
+        messages: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+    impl Observer for SyncMockObserver {
+        fn handle_notification(
+            &self,
+            _level: Level,
+            _to: &str,
+            message: &str,
+            _loc: Location,
+        ) -> Result<(), ReporterError> {
+            self.messages.lock().unwrap().push(message.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_sync_reporter() {
+        let observer = SyncMockObserver {
+            messages: Arc::new(std::sync::Mutex::new(Vec::new())),
+        };
+        let mut reporter = SyncReporter::new();
+        reporter.register(observer.clone()).unwrap();
+
+        // スレッドごとに local で取り出して同じ Observer に送る
+        let handles = (0..4)
+            .map(|i| {
+                let reporter = reporter.clone();
+                std::thread::spawn(move || {
+                    reporter
+                        .local()
+                        .send_report(Level::Info, "to", &i.to_string(), location!())
+                        .unwrap();
+                })
+            })
+            .collect::<Vec<_>>();
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        let mut messages = observer.messages.lock().unwrap().clone();
+        messages.sort();
+        assert_eq!(messages, vec!["0", "1", "2", "3"]);
+    }
 }
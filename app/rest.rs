@@ -0,0 +1,918 @@
+use chrono::NaiveDate;
+use log::{error, trace, warn};
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+use crate::cached_service::PersonCachedService;
+use crate::dao::{PersonQuery, PersonSearch};
+use crate::domain::{PersonDomainError, PersonId, Revision};
+use crate::dto::{PersonDto, PersonPatch};
+use crate::service::{PersonOutputBoundary, ServiceError};
+use crate::usecase::UsecaseError;
+
+/// HTTP response independent of any http server implementation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+impl Response {
+    pub fn json(status: u16, value: &impl Serialize) -> Self {
+        Self {
+            status,
+            body: serde_json::to_string(value).unwrap_or_default(),
+        }
+    }
+    pub fn no_content() -> Self {
+        Self {
+            status: 204,
+            body: String::new(),
+        }
+    }
+    pub fn error(status: u16, message: impl ToString) -> Self {
+        Self::json(
+            status,
+            &ErrorBody {
+                error: message.to_string(),
+            },
+        )
+    }
+}
+impl From<ServiceError> for Response {
+    fn from(e: ServiceError) -> Self {
+        Self::error(status_code(&e), e)
+    }
+}
+
+pub fn status_code(e: &ServiceError) -> u16 {
+    match e {
+        ServiceError::InvalidRequest(_) => 400,
+        ServiceError::RevisionConflict { .. } => 409,
+        ServiceError::ServiceUnavailable(_) => 503,
+        ServiceError::TransactionFailed(e) => match e {
+            UsecaseError::PersonNotFound(_) => 404,
+            UsecaseError::InvalidInput(_) => 400,
+            UsecaseError::RevisionConflict { .. } => 409,
+            UsecaseError::DomainObjectChangeFailed(PersonDomainError::AlreadyDead) => 409,
+            UsecaseError::DomainObjectChangeFailed(PersonDomainError::NotDead) => 409,
+            UsecaseError::DomainObjectChangeFailed(PersonDomainError::InvalidFieldValue(..)) => 400,
+            _ => 500,
+        },
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ErrorBody {
+    error: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PersonRequest {
+    pub name: String,
+    pub birth_date: NaiveDate,
+    pub death_date: Option<NaiveDate>,
+    pub data: Option<String>,
+}
+// register も batch import もこれを通し, data がなければ空文字列にそろえる
+impl From<PersonRequest> for PersonDto {
+    fn from(req: PersonRequest) -> Self {
+        PersonDto::new(
+            &req.name,
+            req.birth_date,
+            req.death_date,
+            Some(req.data.as_deref().unwrap_or_default()),
+            0,
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeathRequest {
+    pub death_date: NaiveDate,
+}
+
+/// correction of a recorded death. `death_date: null` clears it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CorrectDeathRequest {
+    pub death_date: Option<NaiveDate>,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PersonEntry {
+    pub id: PersonId,
+    #[serde(flatten)]
+    pub person: PersonDto,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportResponse {
+    pub ids: Vec<PersonId>,
+}
+
+// batch import の進捗はログに出すだけ
+struct ImportProgressLogger;
+impl PersonOutputBoundary<(u64, u64), ServiceError> for ImportProgressLogger {
+    fn started(&self) {
+        trace!("batch import started");
+    }
+    fn in_progress(&self, progress: (u64, u64)) {
+        trace!("batch import: {} of {} done", progress.1, progress.0);
+    }
+    fn completed(&self) {
+        trace!("batch import completed");
+    }
+    fn aborted(&self, err: ServiceError) {
+        error!("batch import aborted: {}", err);
+    }
+}
+
+/// dispatch one request to the service.
+///
+/// | method | path                             | service              |
+/// |--------|----------------------------------|----------------------|
+/// | POST   | /persons                         | cached_register      |
+/// | GET    | /persons                         | cached_list_all      |
+/// | GET    | /persons?{query}                 | list                 |
+/// | GET    | /persons/search?{q}              | search               |
+/// | POST   | /persons/import                  | cached_batch_import  |
+/// | GET    | /persons/{id}                    | cached_find          |
+/// | PATCH  | /persons/{id}                    | cached_update        |
+/// | DELETE | /persons/{id}                    | cached_unregister    |
+/// | POST   | /persons/{id}/restore            | cached_restore       |
+/// | POST   | /persons/{id}/death              | cached_death         |
+/// | PUT    | /persons/{id}/death              | cached_correct_death |
+/// | GET    | /persons/{id}/history            | history              |
+/// | GET    | /persons/{id}/history/{revision} | find_as_of           |
+pub fn handle<'a, Conn, Ctx, S>(service: &'a mut S, method: &str, url: &str, body: &str) -> Response
+where
+    S: PersonCachedService<'a, Conn, Ctx>,
+{
+    let (path, query) = match url.split_once('?') {
+        Some((path, query)) if !query.is_empty() => (path, Some(query)),
+        Some((path, _)) => (path, None),
+        None => (url, None),
+    };
+    let segments = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+    trace!("handle request: {} {}", method, path);
+
+    let result = match (method, segments.as_slice()) {
+        ("POST", ["persons"]) => register(service, body),
+        ("GET", ["persons"]) => match query {
+            Some(query) => list(service, query),
+            None => list_all(service),
+        },
+        ("POST", ["persons", "import"]) => batch_import(service, body),
+        ("GET", ["persons", "search"]) => search(service, query.unwrap_or_default()),
+        ("GET", ["persons", id]) => find(service, id),
+        ("PATCH", ["persons", id]) => update(service, id, body),
+        ("DELETE", ["persons", id]) => unregister(service, id),
+        ("POST", ["persons", id, "death"]) => death(service, id, body),
+        ("PUT", ["persons", id, "death"]) => correct_death(service, id, body),
+        ("POST", ["persons", id, "restore"]) => restore(service, id),
+        ("GET", ["persons", id, "history"]) => history(service, id),
+        ("GET", ["persons", id, "history", revision]) => find_as_of(service, id, revision),
+        (_, ["persons"])
+        | (_, ["persons", _])
+        | (_, ["persons", _, "death"])
+        | (_, ["persons", _, "restore"])
+        | (_, ["persons", _, "history"])
+        | (_, ["persons", _, "history", _]) => Err(Response::error(
+            405,
+            format!("method not allowed: {method}"),
+        )),
+        _ => Err(Response::error(404, format!("no such resource: {path}"))),
+    };
+
+    result.unwrap_or_else(|res| {
+        warn!("request failed: {} {} -> {}", method, path, res.status);
+        res
+    })
+}
+
+fn parse_id(id: &str) -> Result<PersonId, Response> {
+    id.parse()
+        .map_err(|_| Response::error(400, format!("invalid person id: {id}")))
+}
+
+fn parse_body<'de, T: Deserialize<'de>>(body: &'de str) -> Result<T, Response> {
+    serde_json::from_str(body).map_err(|e| Response::error(400, format!("invalid body: {e}")))
+}
+
+fn register<'a, Conn, Ctx, S>(service: &'a mut S, body: &str) -> Result<Response, Response>
+where
+    S: PersonCachedService<'a, Conn, Ctx>,
+{
+    let req: PersonDto = parse_body::<PersonRequest>(body)?.into();
+    let (id, person) = service.cached_register(
+        &req.name,
+        req.birth_date,
+        req.death_date,
+        req.data.as_deref().unwrap_or_default(),
+    )?;
+
+    Ok(Response::json(201, &PersonEntry { id, person }))
+}
+
+fn list_all<'a, Conn, Ctx, S>(service: &'a mut S) -> Result<Response, Response>
+where
+    S: PersonCachedService<'a, Conn, Ctx>,
+{
+    let persons = service
+        .cached_list_all()?
+        .into_iter()
+        .map(|(id, person)| PersonEntry { id, person })
+        .collect::<Vec<_>>();
+
+    Ok(Response::json(200, &persons))
+}
+
+/// parse `name_prefix`, `status` (alive|dead), `born_from`, `born_until`,
+/// `sort` (id|name|birth_date), `order` (asc|desc), `after` and `limit`
+pub fn parse_query(query: &str) -> Result<PersonQuery, String> {
+    let mut q = PersonQuery::default();
+    for pair in query.split('&').filter(|s| !s.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode(value).ok_or_else(|| format!("invalid {key}: {value}"))?;
+        match key {
+            "name_prefix" => q.name_prefix = Some(value),
+            "status" => q.status = Some(value.parse()?),
+            "born_from" => q.born_from = Some(parse(key, &value)?),
+            "born_until" => q.born_until = Some(parse(key, &value)?),
+            "sort" => q.sort = value.parse()?,
+            "order" => q.order = value.parse()?,
+            "after" => q.after = Some(parse(key, &value)?),
+            "limit" => q.limit = Some(parse(key, &value)?),
+            _ => return Err(format!("unknown query parameter: {key}")),
+        }
+    }
+
+    Ok(q)
+}
+
+fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid {key}: {value}"))
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = vec![];
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [iter.next()?, iter.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn list<'a, Conn, Ctx, S>(service: &'a mut S, query: &str) -> Result<Response, Response>
+where
+    S: PersonCachedService<'a, Conn, Ctx>,
+{
+    let query = parse_query(query).map_err(|e| Response::error(400, e))?;
+    let persons = service
+        .list(query)?
+        .into_iter()
+        .map(|(id, person)| PersonEntry { id, person })
+        .collect::<Vec<_>>();
+
+    Ok(Response::json(200, &persons))
+}
+
+/// parse `text`, `data` (true|false) and `limit`
+pub fn parse_search(query: &str) -> Result<PersonSearch, String> {
+    let mut search = PersonSearch::default();
+    for pair in query.split('&').filter(|s| !s.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode(value).ok_or_else(|| format!("invalid {key}: {value}"))?;
+        match key {
+            "text" => search.text = value,
+            "data" => search.include_data = parse(key, &value)?,
+            "limit" => search.limit = Some(parse(key, &value)?),
+            _ => return Err(format!("unknown query parameter: {key}")),
+        }
+    }
+
+    Ok(search)
+}
+
+fn search<'a, Conn, Ctx, S>(service: &'a mut S, query: &str) -> Result<Response, Response>
+where
+    S: PersonCachedService<'a, Conn, Ctx>,
+{
+    let search = parse_search(query).map_err(|e| Response::error(400, e))?;
+    let persons = service
+        .search(search)?
+        .into_iter()
+        .map(|(id, person)| PersonEntry { id, person })
+        .collect::<Vec<_>>();
+
+    Ok(Response::json(200, &persons))
+}
+
+fn batch_import<'a, Conn, Ctx, S>(service: &'a mut S, body: &str) -> Result<Response, Response>
+where
+    S: PersonCachedService<'a, Conn, Ctx>,
+{
+    let req: Vec<PersonRequest> = parse_body(body)?;
+    let persons = req.into_iter().map(PersonDto::from).collect();
+    let ids = service.cached_batch_import(persons, Rc::new(ImportProgressLogger))?;
+
+    Ok(Response::json(201, &ImportResponse { ids }))
+}
+
+fn find<'a, Conn, Ctx, S>(service: &'a mut S, id: &str) -> Result<Response, Response>
+where
+    S: PersonCachedService<'a, Conn, Ctx>,
+{
+    let id = parse_id(id)?;
+    match service.cached_find(id)? {
+        Some(person) => Ok(Response::json(200, &PersonEntry { id, person })),
+        None => Err(Response::error(404, format!("person not found: {id}"))),
+    }
+}
+
+fn update<'a, Conn, Ctx, S>(service: &'a mut S, id: &str, body: &str) -> Result<Response, Response>
+where
+    S: PersonCachedService<'a, Conn, Ctx>,
+{
+    let id = parse_id(id)?;
+    let patch: PersonPatch = parse_body(body)?;
+    let person = service.cached_update(id, patch)?;
+
+    Ok(Response::json(200, &PersonEntry { id, person }))
+}
+
+fn unregister<'a, Conn, Ctx, S>(service: &'a mut S, id: &str) -> Result<Response, Response>
+where
+    S: PersonCachedService<'a, Conn, Ctx>,
+{
+    let id = parse_id(id)?;
+    service.cached_unregister(id)?;
+
+    Ok(Response::no_content())
+}
+
+fn restore<'a, Conn, Ctx, S>(service: &'a mut S, id: &str) -> Result<Response, Response>
+where
+    S: PersonCachedService<'a, Conn, Ctx>,
+{
+    let id = parse_id(id)?;
+    let person = service.cached_restore(id)?;
+
+    Ok(Response::json(200, &PersonEntry { id, person }))
+}
+
+fn history<'a, Conn, Ctx, S>(service: &'a mut S, id: &str) -> Result<Response, Response>
+where
+    S: PersonCachedService<'a, Conn, Ctx>,
+{
+    let id = parse_id(id)?;
+    let history = service.history(id)?;
+    // 物理削除された人の履歴も残るので, 空なら一度も登録されていない
+    if history.is_empty() {
+        return Err(Response::error(404, format!("person not found: {id}")));
+    }
+
+    Ok(Response::json(200, &history))
+}
+
+fn find_as_of<'a, Conn, Ctx, S>(
+    service: &'a mut S,
+    id: &str,
+    revision: &str,
+) -> Result<Response, Response>
+where
+    S: PersonCachedService<'a, Conn, Ctx>,
+{
+    let id = parse_id(id)?;
+    let revision: Revision = revision
+        .parse()
+        .map_err(|_| Response::error(400, format!("invalid revision: {revision}")))?;
+    match service.find_as_of(id, revision)? {
+        Some(person) => Ok(Response::json(200, &PersonEntry { id, person })),
+        None => Err(Response::error(
+            404,
+            format!("person not found: {id} as of revision {revision}"),
+        )),
+    }
+}
+
+fn death<'a, Conn, Ctx, S>(service: &'a mut S, id: &str, body: &str) -> Result<Response, Response>
+where
+    S: PersonCachedService<'a, Conn, Ctx>,
+{
+    let id = parse_id(id)?;
+    let req: DeathRequest = parse_body(body)?;
+    service.cached_death(id, req.death_date)?;
+
+    Ok(Response::no_content())
+}
+
+fn correct_death<'a, Conn, Ctx, S>(
+    service: &'a mut S,
+    id: &str,
+    body: &str,
+) -> Result<Response, Response>
+where
+    S: PersonCachedService<'a, Conn, Ctx>,
+{
+    let id = parse_id(id)?;
+    let req: CorrectDeathRequest = parse_body(body)?;
+    let person = service.cached_correct_death(id, req.death_date, &req.reason)?;
+
+    Ok(Response::json(200, &PersonEntry { id, person }))
+}
+
+// # フェイクテスト
+//
+// ## 目的
+//
+//   REST の各エンドポイントが CachedService を適切に呼び出し、
+//   その結果を適切な HTTP ステータスと JSON に変換することを保障する
+//
+// ## 方針
+//
+//   メモリ上で完結する MemoryPersonServiceImpl に対してリクエストを処理させ、レスポンスとサービスの状態を確認する
+//   Postgres, Redis, RabbitMQ は一切使わない
+//
+// ## 注意
+//
+//   1. このテストは REST の実装を保障するものであって、CachedService の実装を保障するものではない
+//
+#[cfg(test)]
+mod fake_tests {
+    use super::*;
+    use crate::{
+        dao::DaoError, domain::date, dto::ChangeKind, dto::PersonHistoryDto,
+        service::PersonService, MemoryPersonServiceImpl,
+    };
+
+    // persons を登録したサービス. id は 1 から順に振られる
+    fn service_with(persons: Vec<PersonDto>) -> MemoryPersonServiceImpl {
+        let mut service = MemoryPersonServiceImpl::new();
+        service
+            .batch_import(persons.into_iter(), Rc::new(ImportProgressLogger))
+            .unwrap();
+        service
+    }
+
+    fn alice() -> PersonDto {
+        PersonDto::new("Alice", date(2012, 11, 2), None, Some("Alice is sender"), 0)
+    }
+
+    #[test]
+    fn test_register() {
+        let mut service = MemoryPersonServiceImpl::new();
+
+        let res = handle(
+            &mut service,
+            "POST",
+            "/persons",
+            r#"{"name":"Alice","birth_date":"2012-11-02","death_date":null,"data":"Alice is sender"}"#,
+        );
+
+        assert_eq!(
+            res,
+            Response::json(
+                201,
+                &PersonEntry {
+                    id: 1,
+                    person: alice()
+                }
+            )
+        );
+        assert_eq!(service.find(1), Ok(Some(alice())));
+    }
+
+    #[test]
+    fn test_register_invalid_body() {
+        let mut service = MemoryPersonServiceImpl::new();
+
+        let res = handle(&mut service, "POST", "/persons", r#"{"name":"Alice"}"#);
+
+        assert_eq!(res.status, 400);
+        assert_eq!(service.list_all(), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_find() {
+        let mut service = service_with(vec![alice()]);
+
+        let res = handle(&mut service, "GET", "/persons/1", "");
+        assert_eq!(
+            res,
+            Response::json(
+                200,
+                &PersonEntry {
+                    id: 1,
+                    person: alice()
+                }
+            )
+        );
+
+        let res = handle(&mut service, "GET", "/persons/2", "");
+        assert_eq!(res.status, 404);
+        let res = handle(&mut service, "GET", "/persons/alice", "");
+        assert_eq!(res.status, 400);
+    }
+
+    #[test]
+    fn test_list_all() {
+        let bob = PersonDto::new("Bob", date(1995, 11, 6), None, None, 0);
+        let mut service = service_with(vec![alice(), bob.clone()]);
+
+        let res = handle(&mut service, "GET", "/persons", "");
+
+        assert_eq!(
+            res,
+            Response::json(
+                200,
+                &vec![
+                    PersonEntry {
+                        id: 1,
+                        person: alice()
+                    },
+                    PersonEntry { id: 2, person: bob },
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_list() {
+        let bob = PersonDto::new("Bob", date(1995, 11, 6), None, None, 0);
+        let eve = PersonDto::new("Eve", date(1996, 12, 15), Some(date(2020, 1, 1)), None, 0);
+        let mut service = service_with(vec![alice(), bob.clone(), eve]);
+
+        let res = handle(
+            &mut service,
+            "GET",
+            "/persons?status=alive&sort=birth_date&order=desc",
+            "",
+        );
+        assert_eq!(
+            res,
+            Response::json(
+                200,
+                &vec![
+                    PersonEntry {
+                        id: 1,
+                        person: alice()
+                    },
+                    PersonEntry {
+                        id: 2,
+                        person: bob.clone()
+                    },
+                ]
+            )
+        );
+
+        let res = handle(&mut service, "GET", "/persons?after=1&limit=1", "");
+        assert_eq!(
+            res,
+            Response::json(200, &vec![PersonEntry { id: 2, person: bob }])
+        );
+
+        let res = handle(&mut service, "GET", "/persons?limit=ten", "");
+        assert_eq!(res.status, 400);
+        let res = handle(&mut service, "GET", "/persons?color=red", "");
+        assert_eq!(res.status, 400);
+    }
+
+    #[test]
+    fn test_search() {
+        let bob = PersonDto::new("Bob", date(1995, 11, 6), None, Some("Bob likes Alice"), 0);
+        let mut service = service_with(vec![alice(), bob.clone()]);
+
+        let res = handle(
+            &mut service,
+            "GET",
+            "/persons/search?text=alice&data=true",
+            "",
+        );
+        assert_eq!(
+            res,
+            Response::json(
+                200,
+                &vec![
+                    PersonEntry {
+                        id: 1,
+                        person: alice()
+                    },
+                    PersonEntry { id: 2, person: bob },
+                ]
+            )
+        );
+
+        let res = handle(&mut service, "GET", "/persons/search", "");
+        assert_eq!(res.status, 400);
+        let res = handle(
+            &mut service,
+            "GET",
+            "/persons/search?text=alice&data=yes",
+            "",
+        );
+        assert_eq!(res.status, 400);
+    }
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(
+            parse_query("name_prefix=Jean%20Baptiste+J&born_from=1700-01-01&sort=name&limit=5"),
+            Ok(PersonQuery {
+                name_prefix: Some("Jean Baptiste J".to_string()),
+                born_from: Some(date(1700, 1, 1)),
+                sort: crate::dao::SortKey::Name,
+                limit: Some(5),
+                ..Default::default()
+            })
+        );
+        assert!(parse_query("status=zombie").is_err());
+        assert!(parse_query("name_prefix=%zz").is_err());
+    }
+
+    #[test]
+    fn test_death() {
+        let mut service = service_with(vec![alice()]);
+
+        let res = handle(
+            &mut service,
+            "POST",
+            "/persons/1/death",
+            r#"{"death_date":"2100-04-07"}"#,
+        );
+        assert_eq!(res, Response::no_content());
+        let person = service.find(1).unwrap().unwrap();
+        assert_eq!(person.death_date, Some(date(2100, 4, 7)));
+
+        let res = handle(
+            &mut service,
+            "POST",
+            "/persons/1/death",
+            r#"{"death_date":"2100-04-08"}"#,
+        );
+        assert_eq!(res.status, 409, "already dead");
+
+        let res = handle(
+            &mut service,
+            "POST",
+            "/persons/99/death",
+            r#"{"death_date":"2100-04-08"}"#,
+        );
+        assert_eq!(res.status, 404, "not found");
+    }
+
+    #[test]
+    fn test_correct_death() {
+        let mut service = service_with(vec![alice()]);
+
+        let res = handle(
+            &mut service,
+            "PUT",
+            "/persons/1/death",
+            r#"{"death_date":null,"reason":"alive"}"#,
+        );
+        assert_eq!(res.status, 409, "not dead");
+
+        service.death(1, date(2100, 4, 7)).unwrap();
+        let res = handle(
+            &mut service,
+            "PUT",
+            "/persons/1/death",
+            r#"{"death_date":null,"reason":"wrong person"}"#,
+        );
+        let expected = PersonDto::new("Alice", date(2012, 11, 2), None, Some("Alice is sender"), 2);
+        assert_eq!(
+            res,
+            Response::json(
+                200,
+                &PersonEntry {
+                    id: 1,
+                    person: expected.clone()
+                }
+            )
+        );
+        assert_eq!(service.find(1), Ok(Some(expected)));
+
+        let res = handle(
+            &mut service,
+            "PUT",
+            "/persons/1/death",
+            r#"{"death_date":null}"#,
+        );
+        assert_eq!(res.status, 400, "reason is required");
+        let res = handle(
+            &mut service,
+            "PUT",
+            "/persons/99/death",
+            r#"{"death_date":null,"reason":"wrong person"}"#,
+        );
+        assert_eq!(res.status, 404, "not found");
+    }
+
+    #[test]
+    fn test_update() {
+        let mut service = service_with(vec![alice()]);
+
+        let res = handle(
+            &mut service,
+            "PATCH",
+            "/persons/1",
+            r#"{"name":"Alice Liddell","data":null}"#,
+        );
+        let expected = PersonDto::new("Alice Liddell", date(2012, 11, 2), None, None, 1);
+        assert_eq!(
+            res,
+            Response::json(
+                200,
+                &PersonEntry {
+                    id: 1,
+                    person: expected.clone()
+                }
+            )
+        );
+        assert_eq!(service.find(1), Ok(Some(expected)));
+
+        let res = handle(&mut service, "PATCH", "/persons/2", r#"{"name":"Bob"}"#);
+        assert_eq!(res.status, 404);
+        let res = handle(&mut service, "PATCH", "/persons/1", r#"{"name":1}"#);
+        assert_eq!(res.status, 400);
+    }
+
+    #[test]
+    fn test_unregister() {
+        let mut service = service_with(vec![alice()]);
+
+        let res = handle(&mut service, "DELETE", "/persons/1", "");
+
+        assert_eq!(res, Response::no_content());
+        assert_eq!(service.find(1), Ok(None));
+    }
+
+    #[test]
+    fn test_restore() {
+        let mut service = service_with(vec![alice()]);
+
+        let _ = handle(&mut service, "DELETE", "/persons/1", "");
+        let res = handle(&mut service, "POST", "/persons/1/restore", "");
+
+        // 削除と復元でそれぞれ版が上がる
+        let expected = PersonDto {
+            revision: 2,
+            ..alice()
+        };
+        assert_eq!(
+            res,
+            Response::json(
+                200,
+                &PersonEntry {
+                    id: 1,
+                    person: expected.clone()
+                }
+            )
+        );
+        assert_eq!(service.find(1), Ok(Some(expected)));
+
+        let res = handle(&mut service, "POST", "/persons/99/restore", "");
+        assert_eq!(res.status, 404);
+        let res = handle(&mut service, "GET", "/persons/1/restore", "");
+        assert_eq!(res.status, 405);
+    }
+
+    #[test]
+    fn test_history() {
+        let mut service = service_with(vec![alice()]);
+
+        let res = handle(&mut service, "GET", "/persons/1/history", "");
+        assert_eq!(res.status, 200);
+        let history: Vec<PersonHistoryDto> = serde_json::from_str(&res.body).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].kind, ChangeKind::Insert);
+        assert_eq!(history[0].new, Some(alice()));
+
+        // 削除されても履歴は残る
+        let _ = handle(&mut service, "DELETE", "/persons/1", "");
+        let res = handle(&mut service, "GET", "/persons/1/history", "");
+        let history: Vec<PersonHistoryDto> = serde_json::from_str(&res.body).unwrap();
+        assert_eq!(
+            history.iter().map(|h| h.kind).collect::<Vec<_>>(),
+            [ChangeKind::Insert, ChangeKind::Delete]
+        );
+
+        // 一度も登録されていない人
+        let res = handle(&mut service, "GET", "/persons/99/history", "");
+        assert_eq!(res.status, 404);
+
+        let res = handle(&mut service, "POST", "/persons/1/history", "");
+        assert_eq!(res.status, 405);
+    }
+
+    #[test]
+    fn test_find_as_of() {
+        let mut service = service_with(vec![alice()]);
+        service.death(1, date(2100, 4, 7)).unwrap();
+
+        let res = handle(&mut service, "GET", "/persons/1/history/0", "");
+        assert_eq!(
+            res,
+            Response::json(
+                200,
+                &PersonEntry {
+                    id: 1,
+                    person: alice()
+                }
+            )
+        );
+
+        let res = handle(&mut service, "GET", "/persons/1/history/-1", "");
+        assert_eq!(res.status, 404);
+        let res = handle(&mut service, "GET", "/persons/1/history/latest", "");
+        assert_eq!(res.status, 400);
+        let res = handle(&mut service, "DELETE", "/persons/1/history/0", "");
+        assert_eq!(res.status, 405);
+    }
+
+    #[test]
+    fn test_batch_import() {
+        let mut service = MemoryPersonServiceImpl::new();
+
+        let res = handle(
+            &mut service,
+            "POST",
+            "/persons/import",
+            r#"[{"name":"Alice","birth_date":"2012-11-02","death_date":null,"data":"Alice is sender"},
+                {"name":"Bob","birth_date":"1995-11-06","death_date":null,"data":null}]"#,
+        );
+        assert_eq!(
+            res,
+            Response::json(201, &ImportResponse { ids: vec![1, 2] })
+        );
+        assert_eq!(service.list_all().unwrap().len(), 2);
+
+        let res = handle(&mut service, "POST", "/persons/import", "[]");
+        assert_eq!(res.status, 400, "empty argument");
+    }
+
+    #[test]
+    fn test_register_and_batch_import_without_data() {
+        let mut service = MemoryPersonServiceImpl::new();
+        let body = r#"{"name":"Bob","birth_date":"1995-11-06","death_date":null,"data":null}"#;
+
+        let res = handle(&mut service, "POST", "/persons", body);
+        assert_eq!(res.status, 201);
+        let res = handle(
+            &mut service,
+            "POST",
+            "/persons/import",
+            &format!("[{body}]"),
+        );
+        assert_eq!(res.status, 201);
+
+        // どちらから登録しても同じ値が残る
+        let bob = PersonDto::new("Bob", date(1995, 11, 6), None, Some(""), 0);
+        assert_eq!(service.find(1), Ok(Some(bob.clone())));
+        assert_eq!(service.find(2), Ok(Some(bob)));
+    }
+
+    #[test]
+    fn test_routing() {
+        let mut service = MemoryPersonServiceImpl::new();
+
+        assert_eq!(handle(&mut service, "GET", "/people", "").status, 404);
+        assert_eq!(handle(&mut service, "PUT", "/persons/1", "").status, 405);
+        assert_eq!(
+            handle(&mut service, "GET", "/persons/1/death", "").status,
+            405
+        );
+    }
+
+    #[test]
+    fn test_status_code() {
+        assert_eq!(
+            status_code(&ServiceError::RevisionConflict {
+                expected: 1,
+                actual: 2
+            }),
+            409
+        );
+        assert_eq!(
+            status_code(&ServiceError::ServiceUnavailable("down".to_string())),
+            503
+        );
+        assert_eq!(
+            status_code(&ServiceError::TransactionFailed(
+                UsecaseError::SavePersonFailed(DaoError::UpdateError("broken".to_string()))
+            )),
+            500
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use clap::Parser;
+use log::{error, info};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use app::config::{Backend, ConfigArgs, Settings};
+use app::migration::Migrator;
+use app::outbox::OutboxRelay;
+use app::rest;
+use app::{MemoryPersonServiceImpl, MigratorImpl, OutboxRelayImpl, SharedPersonServiceImpl};
+
+#[derive(Debug, Parser)]
+#[command(about = "REST API of the persons")]
+struct ServerCli {
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// address to listen on, such as 127.0.0.1:8080
+    #[arg(long)]
+    listen_addr: Option<String>,
+    /// number of threads handling requests
+    #[arg(long)]
+    workers: Option<usize>,
+}
+impl ServerCli {
+    fn settings(&self) -> Result<Settings, app::config::ConfigError> {
+        // workers は pool の大きさと合わせて検証するので, 上書きしてから検証する
+        let mut settings = self.config.load_unvalidated()?;
+        if let Some(addr) = &self.listen_addr {
+            settings.server.listen_addr = addr.clone();
+        }
+        if let Some(workers) = self.workers {
+            settings.server.workers = workers;
+        }
+        settings.validate()?;
+        Ok(settings)
+    }
+}
+
+// drain the outbox in the background, polling every `interval` while it is empty
+fn spawn_relay(settings: Settings, interval: Duration) {
+    thread::spawn(move || {
+        let mut relay = match OutboxRelayImpl::from_settings(&settings) {
+            Ok(relay) => relay,
+            Err(e) => {
+                error!("cannot start outbox relay: {}", e);
+                return;
+            }
+        };
+        loop {
+            match relay.relay(100) {
+                Ok(0) => thread::sleep(interval),
+                Ok(sent) => info!("relayed {} notifications", sent),
+                Err(e) => {
+                    error!("outbox relay failed: {}", e);
+                    thread::sleep(interval);
+                }
+            }
+        }
+    });
+}
+
+fn main() {
+    // ログを始める前なので標準エラーに出して終わる
+    let settings = match ServerCli::parse().settings() {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("invalid settings: {}", e);
+            process::exit(2);
+        }
+    };
+    env_logger::Builder::new()
+        .parse_filters(settings.log_level.as_deref().unwrap_or("info"))
+        .init();
+
+    let addr = &settings.server.listen_addr;
+    let server = tiny_http::Server::http(addr).expect("start http server");
+    info!("listening on {}", addr);
+    let workers = settings.server.workers;
+
+    // memory では外部のサービスを使わず, 通知はログに出すだけ
+    info!("backend: {}", settings.backend);
+    match settings.backend {
+        Backend::Postgres => {
+            if settings.server.migrate_on_startup {
+                migrate(&settings);
+            }
+            let shared = match SharedPersonServiceImpl::from_settings(&settings) {
+                Ok(shared) => shared.with_actor("server"),
+                Err(e) => {
+                    error!("cannot start: {}", e);
+                    process::exit(1);
+                }
+            };
+            spawn_relay(settings.clone(), Duration::from_secs(1));
+            serve(&server, workers, || {
+                let mut service = shared.service();
+                move |method: &str, url: &str, body: &str| {
+                    rest::handle(&mut service, method, url, body)
+                }
+            });
+        }
+        Backend::Memory => {
+            let shared = MemoryPersonServiceImpl::new()
+                .with_actor("server")
+                .with_retry_policy(settings.retry.policy());
+            serve(&server, workers, || {
+                let mut service = shared.clone();
+                move |method: &str, url: &str, body: &str| {
+                    rest::handle(&mut service, method, url, body)
+                }
+            });
+        }
+    }
+}
+
+// bring the schema up to date before serving, or give up if it cannot be
+fn migrate(settings: &Settings) {
+    let applied = match MigratorImpl::from_settings(settings)
+        .and_then(|mut migrator| migrator.migrate(false))
+    {
+        Ok(applied) => applied,
+        Err(e) => {
+            error!("cannot migrate schema: {}", e);
+            process::exit(1);
+        }
+    };
+    for m in applied {
+        info!("applied migration {}: {}", m.version, m.name);
+    }
+}
+
+// each worker takes requests from the same server, with its own handler made by `handler`
+fn serve<H>(server: &tiny_http::Server, workers: usize, handler: impl Fn() -> H + Sync)
+where
+    H: FnMut(&str, &str, &str) -> rest::Response,
+{
+    thread::scope(|s| {
+        for _ in 0..workers {
+            s.spawn(|| {
+                let mut handle = handler();
+                for request in server.incoming_requests() {
+                    respond(&mut handle, request);
+                }
+            });
+        }
+    });
+}
+
+fn respond(
+    handle: &mut impl FnMut(&str, &str, &str) -> rest::Response,
+    mut request: tiny_http::Request,
+) {
+    let method = request.method().to_string();
+    let url = request.url().to_string();
+
+    let mut body = String::new();
+    let res = match request.as_reader().read_to_string(&mut body) {
+        Ok(_) => handle(&method, &url, &body),
+        Err(e) => rest::Response::error(400, e),
+    };
+    info!("{} {} {}", method, url, res.status);
+
+    let header = tiny_http::Header::from_bytes("Content-Type", "application/json")
+        .expect("content type header");
+    let response = tiny_http::Response::from_string(res.body)
+        .with_status_code(res.status)
+        .with_header(header);
+    if let Err(e) = request.respond(response) {
+        error!("failed to respond: {}", e);
+    }
+}
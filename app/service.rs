@@ -1,12 +1,18 @@
-use chrono::NaiveDate;
-use log::{error, trace};
+use chrono::{DateTime, NaiveDate, Utc};
+use log::{error, trace, warn};
 use std::fmt;
 use std::iter::Iterator;
 use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
 use thiserror::Error;
 
-use crate::domain::PersonId;
-use crate::dto::PersonDto;
+use crate::backend::Backoff;
+use crate::dao::{PersonQuery, PersonSearch};
+use crate::domain::{PersonId, Revision};
+use crate::dto::{PersonDto, PersonHistoryDto, PersonPatch};
+use crate::event::PersonEvent;
+use crate::outbox::Notification;
 use crate::reporter::{Level, Reporter};
 use crate::usecase::{PersonUsecase, UsecaseError};
 use tx_rs::Tx;
@@ -19,6 +25,22 @@ pub enum ServiceError {
     ServiceUnavailable(String),
     #[error("invalid request: {0}")]
     InvalidRequest(InvalidErrorKind),
+    #[error("revision conflict: expected={expected}, actual={actual}")]
+    RevisionConflict {
+        expected: Revision,
+        actual: Revision,
+    },
+}
+impl From<UsecaseError> for ServiceError {
+    fn from(e: UsecaseError) -> Self {
+        match e {
+            // 呼び出し側で再読み込みしてリトライできるように区別する
+            UsecaseError::RevisionConflict { expected, actual } => {
+                ServiceError::RevisionConflict { expected, actual }
+            }
+            e => ServiceError::TransactionFailed(e),
+        }
+    }
 }
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum InvalidErrorKind {
@@ -32,6 +54,125 @@ impl fmt::Display for InvalidErrorKind {
     }
 }
 
+/// Retry policy for read-modify-write flows such as `death`.
+///
+/// `PersonService` applies it to `death`, `correct_death` and `update`, and `AsyncPersonService`
+/// to `death`. Only a revision conflict is retried, up to `max_attempts` in total. Each attempt
+/// runs in a new transaction, after the previous one has been rolled back and `backoff` has passed,
+/// so that it re-reads the person at its latest revision and holds no lock while waiting.
+/// Domain errors and outages are returned as is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub backoff: Backoff,
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff: Backoff {
+                initial: Duration::from_millis(50),
+                max: Duration::from_secs(1),
+            },
+        }
+    }
+}
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, backoff: Backoff) -> Self {
+        Self {
+            max_attempts,
+            backoff,
+        }
+    }
+    pub fn no_retry() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// run `f` again while it fails with a revision conflict.
+    ///
+    /// `f` should start a transaction of its own, so that a retry sees the changes committed
+    /// in the meantime:
+    ///
+    /// ```ignore
+    /// let policy = RetryPolicy::default();
+    /// policy.run(|| service.run_tx(|usecase, ctx| usecase.death(id, date).run(ctx)))?;
+    /// ```
+    pub fn run<T, E, F>(&self, f: F) -> Result<T, E>
+    where
+        E: Conflict,
+        F: FnMut() -> Result<T, E>,
+    {
+        self.run_with_sleep(f, thread::sleep)
+    }
+
+    fn run_with_sleep<T, E, F>(&self, mut f: F, mut sleep: impl FnMut(Duration)) -> Result<T, E>
+    where
+        E: Conflict,
+        F: FnMut() -> Result<T, E>,
+    {
+        let mut attempt = 1;
+        loop {
+            let res = f();
+            match self.retry_after(attempt, &res) {
+                Some(delay) => {
+                    sleep(delay);
+                    attempt += 1;
+                }
+                None => {
+                    trace!("finished after {} attempt(s)", attempt);
+                    return res;
+                }
+            }
+        }
+    }
+
+    /// how long to wait before running again what ended with `res` at the `attempt`-th attempt.
+    /// None unless it failed with a revision conflict and attempts are left.
+    ///
+    /// For a loop that `run` cannot express, such as one awaiting each attempt.
+    pub fn retry_after<T, E: Conflict>(
+        &self,
+        attempt: u32,
+        res: &Result<T, E>,
+    ) -> Option<Duration> {
+        let (expected, actual) = res.as_ref().err()?.revision_conflict()?;
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let delay = self.backoff.delay(attempt);
+        warn!(
+            "revision conflict (expected={}, actual={}), retry {} of {} after {:?}",
+            expected, actual, attempt, self.max_attempts, delay
+        );
+        Some(delay)
+    }
+}
+
+/// An error which `RetryPolicy` runs the call again on.
+pub trait Conflict {
+    /// the expected and actual revisions if the person was changed since it was read
+    fn revision_conflict(&self) -> Option<(Revision, Revision)>;
+}
+impl Conflict for ServiceError {
+    fn revision_conflict(&self) -> Option<(Revision, Revision)> {
+        match self {
+            ServiceError::RevisionConflict { expected, actual } => Some((*expected, *actual)),
+            _ => None,
+        }
+    }
+}
+impl Conflict for UsecaseError {
+    fn revision_conflict(&self) -> Option<(Revision, Revision)> {
+        match self {
+            UsecaseError::RevisionConflict { expected, actual } => Some((*expected, *actual)),
+            _ => None,
+        }
+    }
+}
+
 pub trait PersonOutputBoundary<T, E> {
     fn started(&self);
     fn in_progress(&self, progress: T);
@@ -43,12 +184,42 @@ pub trait PersonService<'a, Ctx> {
     type U: PersonUsecase<Ctx>;
     type N: Reporter<'a>;
 
-    fn run_tx<T, F>(&'a mut self, f: F) -> Result<T, ServiceError>
+    /// run `f` in a new transaction, which is committed if it succeeds and rolled back otherwise.
+    fn run_tx<T, F>(&mut self, f: F) -> Result<T, ServiceError>
     where
         F: FnOnce(&mut Self::U, &mut Ctx) -> Result<T, UsecaseError>;
 
     fn get_reporter(&self) -> Self::N;
 
+    /// how `death`, `correct_death` and `update` are retried on a revision conflict
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    /// run `f` in a transaction and deliver the notifications it returns once committed.
+    ///
+    /// By default they are sent to the reporter after the commit, and lost if that fails.
+    /// An implementation with an outbox writes them in the same transaction instead.
+    fn run_tx_and_notify<T, F>(&mut self, f: F) -> Result<T, ServiceError>
+    where
+        F: FnOnce(&mut Self::U, &mut Ctx) -> Result<(T, Vec<Notification>), UsecaseError>,
+    {
+        let reporter = self.get_reporter();
+
+        self.run_tx(f).map(|(v, notifications)| {
+            for n in notifications {
+                let res = match &n.event {
+                    Some(event) => reporter.send_event(event, n.location()),
+                    None => reporter.send_report(n.level.clone(), &n.to, &n.message, n.location()),
+                };
+                if let Err(e) = res {
+                    error!("reporter service not available: {}", e);
+                }
+            }
+            v
+        })
+    }
+
     fn register(
         &'a mut self,
         name: &str,
@@ -65,19 +236,19 @@ pub trait PersonService<'a, Ctx> {
         );
         let reporter = self.get_reporter();
 
-        self.run_tx(move |usecase, ctx| {
-            usecase
+        self.run_tx_and_notify(move |usecase, ctx| {
+            let (id, p) = usecase
                 .entry_and_verify(PersonDto::new(name, birth_date, death_date, Some(data), 0))
-                .run(ctx)
-        })
-        .and_then(|(id, p)| {
-            let msg = format!("registered person_id: {}", id);
-            if let Err(e) = reporter.send_report(Level::Info, "entry_person", &msg, location!()) {
-                error!("reporter service not available: {}", e);
-            }
-            return Ok((id, p));
+                .run(ctx)?;
+            let event = PersonEvent::Registered {
+                id,
+                revision: p.revision,
+                birth_date: p.birth_date,
+                death_date: p.death_date,
+            };
+            Ok(((id, p), vec![Notification::event(event, location!())]))
         })
-        .map_err(|e| {
+        .inspect_err(|_| {
             let msg = format!(
                 "cannot register person: name={}, birth_date={}, death_date={:?}, data={}",
                 name, birth_date, death_date, data
@@ -85,7 +256,6 @@ pub trait PersonService<'a, Ctx> {
             if let Err(e) = reporter.send_report(Level::Error, "admin", &msg, location!()) {
                 error!("reporter service not available: {}", e);
             }
-            return e;
         })
     }
 
@@ -94,12 +264,11 @@ pub trait PersonService<'a, Ctx> {
         let reporter = self.get_reporter();
 
         self.run_tx(move |usecase, ctx| usecase.find(id).run(ctx))
-            .map_err(|e| {
+            .inspect_err(|_| {
                 let msg = format!("cannot find person: id={}", id);
                 if let Err(e) = reporter.send_report(Level::Error, "admin", &msg, location!()) {
                     error!("reporter service not available: {}", e);
                 }
-                return e;
             })
     }
 
@@ -107,6 +276,18 @@ pub trait PersonService<'a, Ctx> {
         &'a mut self,
         persons: impl Iterator<Item = PersonDto>,
         out_port: Rc<impl PersonOutputBoundary<(u64, u64), ServiceError>>,
+    ) -> Result<Vec<PersonId>, ServiceError> {
+        self.try_batch_import(persons.map(Ok), out_port)
+    }
+
+    /// `batch_import` persons read from an input which may fail on the way, such as a file.
+    ///
+    /// A person that cannot be read aborts the import just like one that cannot be entered,
+    /// so the persons entered before it are not imported either.
+    fn try_batch_import(
+        &'a mut self,
+        persons: impl Iterator<Item = Result<PersonDto, String>>,
+        out_port: Rc<impl PersonOutputBoundary<(u64, u64), ServiceError>>,
     ) -> Result<Vec<PersonId>, ServiceError> {
         trace!("batch import persons");
         out_port.started();
@@ -115,19 +296,14 @@ pub trait PersonService<'a, Ctx> {
         let mut ids = vec![];
         let (lower_bound, upper_bound) = persons.size_hint();
         let total = upper_bound.unwrap_or(lower_bound) as u64;
-        self.run_tx(move |usecase, ctx| {
+        self.run_tx_and_notify(move |usecase, ctx| {
             for person in persons {
-                let res = usecase.entry(person).run(ctx);
+                let res = person
+                    .map_err(UsecaseError::InvalidInput)
+                    .and_then(|person| usecase.entry(person).run(ctx));
                 match res {
                     Ok(id) => {
                         ids.push(id);
-
-                        let msg = format!("registered person_id: {}", id);
-                        if let Err(e) =
-                            reporter.send_report(Level::Info, "entry_person", &msg, location!())
-                        {
-                            error!("reporter service not available: {}", e);
-                        }
                     }
                     Err(e) => {
                         trace!("batch import aborted: {:?}", e);
@@ -147,7 +323,9 @@ pub trait PersonService<'a, Ctx> {
             }
             trace!("batch import completed: {:?}", ids.len());
             out_port.completed();
-            Ok(ids)
+            // 1 件ずつではなくまとめて通知する
+            let event = PersonEvent::Imported { ids: ids.clone() };
+            Ok((ids, vec![Notification::event(event, location!())]))
         })
     }
 
@@ -156,7 +334,7 @@ pub trait PersonService<'a, Ctx> {
         let reporter = self.get_reporter();
 
         self.run_tx(move |usecase, ctx| usecase.collect().run(ctx))
-            .map_err(|e| {
+            .inspect_err(|_| {
                 if let Err(e) = reporter.send_report(
                     Level::Error,
                     "admin",
@@ -165,29 +343,145 @@ pub trait PersonService<'a, Ctx> {
                 ) {
                     error!("reporter service not available: {}", e);
                 }
-                return e;
             })
     }
 
-    fn death(&'a mut self, id: PersonId, death_date: NaiveDate) -> Result<(), ServiceError> {
-        trace!("death person: id={}, death_date={}", id, death_date);
+    fn list(&'a mut self, query: PersonQuery) -> Result<Vec<(PersonId, PersonDto)>, ServiceError> {
+        trace!("list persons: {:?}", query);
         let reporter = self.get_reporter();
 
-        self.run_tx(move |usecase, ctx| usecase.death(id, death_date).run(ctx))
-            .and_then(|_| {
-                let msg = format!("death person_id: {}, death_date: {}", id, death_date);
-                if let Err(e) = reporter.send_report(Level::Info, "death_person", &msg, location!())
+        self.run_tx(move |usecase, ctx| usecase.query(query).run(ctx))
+            .inspect_err(|_| {
+                if let Err(e) =
+                    reporter.send_report(Level::Error, "admin", "cannot list persons", location!())
                 {
                     error!("reporter service not available: {}", e);
                 }
-                return Ok(());
             })
-            .map_err(|e| {
+    }
+
+    fn search(
+        &'a mut self,
+        search: PersonSearch,
+    ) -> Result<Vec<(PersonId, PersonDto)>, ServiceError> {
+        trace!("search persons: {:?}", search);
+        if search.text.trim().is_empty() {
+            return Err(ServiceError::InvalidRequest(
+                InvalidErrorKind::EmptyArgument,
+            ));
+        }
+        let reporter = self.get_reporter();
+
+        self.run_tx(move |usecase, ctx| usecase.search(search).run(ctx))
+            .inspect_err(|_| {
+                if let Err(e) = reporter.send_report(
+                    Level::Error,
+                    "admin",
+                    "cannot search persons",
+                    location!(),
+                ) {
+                    error!("reporter service not available: {}", e);
+                }
+            })
+    }
+
+    fn death(&'a mut self, id: PersonId, death_date: NaiveDate) -> Result<(), ServiceError> {
+        trace!("death person: id={}, death_date={}", id, death_date);
+        let reporter = self.get_reporter();
+
+        // 競合したら rollback し, 新しいトランザクションで読み直す
+        self.retry_policy()
+            .run(|| {
+                self.run_tx_and_notify(move |usecase, ctx| {
+                    let person = usecase.death(id, death_date).run(ctx)?;
+                    let event = PersonEvent::Died {
+                        id,
+                        revision: person.revision,
+                        death_date,
+                    };
+                    Ok(((), vec![Notification::event(event, location!())]))
+                })
+            })
+            .inspect_err(|_| {
                 let msg = format!("cannot death person: id={}, death_date={}", id, death_date);
                 if let Err(e) = reporter.send_report(Level::Error, "admin", &msg, location!()) {
                     error!("reporter service not available: {}", e);
                 }
-                return e;
+            })
+    }
+
+    fn correct_death(
+        &'a mut self,
+        id: PersonId,
+        death_date: Option<NaiveDate>,
+        reason: &str,
+    ) -> Result<PersonDto, ServiceError> {
+        trace!(
+            "correct death person: id={}, death_date={:?}, reason={}",
+            id,
+            death_date,
+            reason
+        );
+        if reason.trim().is_empty() {
+            return Err(ServiceError::InvalidRequest(
+                InvalidErrorKind::EmptyArgument,
+            ));
+        }
+        let reporter = self.get_reporter();
+
+        self.retry_policy()
+            .run(|| {
+                let reason = reason.to_string();
+                self.run_tx_and_notify(move |usecase, ctx| {
+                    let person = usecase
+                        .correct_death(id, death_date, reason.clone())
+                        .run(ctx)?;
+                    let event = PersonEvent::DeathCorrected {
+                        id,
+                        revision: person.revision,
+                        death_date,
+                        reason,
+                    };
+                    Ok((person, vec![Notification::event(event, location!())]))
+                })
+            })
+            .inspect_err(|_| {
+                let msg = format!(
+                    "cannot correct death person: id={}, death_date={:?}",
+                    id, death_date
+                );
+                if let Err(e) = reporter.send_report(Level::Error, "admin", &msg, location!()) {
+                    error!("reporter service not available: {}", e);
+                }
+            })
+    }
+
+    fn update(&'a mut self, id: PersonId, patch: PersonPatch) -> Result<PersonDto, ServiceError> {
+        trace!("update person: id={}, patch={:?}", id, patch);
+        if patch.is_empty() {
+            return Err(ServiceError::InvalidRequest(
+                InvalidErrorKind::EmptyArgument,
+            ));
+        }
+        let reporter = self.get_reporter();
+
+        self.retry_policy()
+            .run(|| {
+                let patch = patch.clone();
+                self.run_tx_and_notify(move |usecase, ctx| {
+                    let person = usecase.update(id, patch).run(ctx)?;
+                    let event = PersonEvent::Updated {
+                        id,
+                        revision: person.revision,
+                    };
+                    Ok((person, vec![Notification::event(event, location!())]))
+                })
+            })
+            .inspect_err(|_| {
+                let msg = format!("cannot update person: id={}", id);
+                if let Err(e) = reporter.send_report(Level::Error, "admin", &msg, location!()) {
+                    error!("reporter service not available: {}", e);
+                }
             })
     }
 
@@ -195,26 +489,357 @@ pub trait PersonService<'a, Ctx> {
         trace!("unregister person: id={}", id);
         let reporter = self.get_reporter();
 
-        self.run_tx(move |usecase, ctx| usecase.remove(id).run(ctx))
-            .and_then(|_| {
-                let msg = format!("unregistered person_id: {}", id);
-                if let Err(e) =
-                    reporter.send_report(Level::Info, "unregister_person", &msg, location!())
-                {
+        self.run_tx_and_notify(move |usecase, ctx| {
+            // 削除するものがなければ何も起きていないので知らせない
+            let notifications = usecase
+                .remove(id)
+                .run(ctx)?
+                .map(|revision| {
+                    let event = PersonEvent::Unregistered { id, revision };
+                    Notification::event(event, location!())
+                })
+                .into_iter()
+                .collect();
+            Ok(((), notifications))
+        })
+        .inspect_err(|_| {
+            let msg = format!("cannot remove person: id={}", id);
+            if let Err(e) = reporter.send_report(Level::Error, "admin", &msg, location!()) {
+                error!("reporter service not available: {}", e);
+            }
+        })
+    }
+
+    fn restore(&'a mut self, id: PersonId) -> Result<PersonDto, ServiceError> {
+        trace!("restore person: id={}", id);
+        let reporter = self.get_reporter();
+
+        self.run_tx_and_notify(move |usecase, ctx| {
+            let person = usecase.restore(id).run(ctx)?;
+            let event = PersonEvent::Restored {
+                id,
+                revision: person.revision,
+            };
+            Ok((person, vec![Notification::event(event, location!())]))
+        })
+        .inspect_err(|_| {
+            let msg = format!("cannot restore person: id={}", id);
+            if let Err(e) = reporter.send_report(Level::Error, "admin", &msg, location!()) {
+                error!("reporter service not available: {}", e);
+            }
+        })
+    }
+
+    fn purge(&'a mut self, before: DateTime<Utc>) -> Result<u64, ServiceError> {
+        trace!("purge persons deleted before: {}", before);
+        let reporter = self.get_reporter();
+
+        self.run_tx_and_notify(move |usecase, ctx| {
+            let count = usecase.purge(before).run(ctx)?;
+            let event = PersonEvent::Purged { count, before };
+            Ok((count, vec![Notification::event(event, location!())]))
+        })
+        .inspect_err(|_| {
+            let msg = format!("cannot purge persons deleted before: {}", before);
+            if let Err(e) = reporter.send_report(Level::Error, "admin", &msg, location!()) {
+                error!("reporter service not available: {}", e);
+            }
+        })
+    }
+
+    fn history(&'a mut self, id: PersonId) -> Result<Vec<PersonHistoryDto>, ServiceError> {
+        trace!("history of person: id={}", id);
+        let reporter = self.get_reporter();
+
+        self.run_tx(move |usecase, ctx| usecase.history(id).run(ctx))
+            .inspect_err(|_| {
+                let msg = format!("cannot find history of person: id={}", id);
+                if let Err(e) = reporter.send_report(Level::Error, "admin", &msg, location!()) {
                     error!("reporter service not available: {}", e);
                 }
-                return Ok(());
             })
-            .map_err(|e| {
-                let msg = format!("cannot remove person: id={}", id);
+    }
+
+    fn find_as_of(
+        &'a mut self,
+        id: PersonId,
+        revision: Revision,
+    ) -> Result<Option<PersonDto>, ServiceError> {
+        trace!("find person: id={} as of revision={}", id, revision);
+        let reporter = self.get_reporter();
+
+        self.run_tx(move |usecase, ctx| usecase.find_as_of(id, revision).run(ctx))
+            .inspect_err(|_| {
+                let msg = format!("cannot find person: id={} as of revision={}", id, revision);
                 if let Err(e) = reporter.send_report(Level::Error, "admin", &msg, location!()) {
                     error!("reporter service not available: {}", e);
                 }
-                return e;
             })
     }
 }
 
+#[cfg(test)]
+mod retry_tests {
+    use std::cell::{Cell, RefCell};
+
+    use super::*;
+    use crate::dao::{DaoError, HavePersonDao, PersonDao};
+    use crate::domain::{date, PersonDomainError};
+    use crate::memory::{MemoryDb, MemoryPersonDao};
+    use crate::reporter::DefaultReporter;
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy::new(
+            max_attempts,
+            Backoff {
+                initial: Duration::ZERO,
+                max: Duration::ZERO,
+            },
+        )
+    }
+
+    #[test]
+    fn test_retry_until_success() {
+        let attempts = RefCell::new(0);
+        let result = policy(3).run(|| {
+            *attempts.borrow_mut() += 1;
+            if *attempts.borrow() < 3 {
+                return Err(ServiceError::RevisionConflict {
+                    expected: 0,
+                    actual: 1,
+                });
+            }
+            Ok(42)
+        });
+
+        assert_eq!(result, Ok(42));
+        assert_eq!(*attempts.borrow(), 3);
+    }
+
+    #[test]
+    fn test_retry_gives_up() {
+        let attempts = RefCell::new(0);
+        let result: Result<(), ServiceError> = policy(2).run(|| {
+            *attempts.borrow_mut() += 1;
+            Err(ServiceError::RevisionConflict {
+                expected: 0,
+                actual: 1,
+            })
+        });
+
+        assert_eq!(
+            result,
+            Err(ServiceError::RevisionConflict {
+                expected: 0,
+                actual: 1
+            })
+        );
+        assert_eq!(*attempts.borrow(), 2);
+    }
+
+    #[test]
+    fn test_no_retry_for_domain_error() {
+        let attempts = RefCell::new(0);
+        let expected = ServiceError::TransactionFailed(UsecaseError::DomainObjectChangeFailed(
+            PersonDomainError::AlreadyDead,
+        ));
+        let result: Result<(), ServiceError> = policy(3).run(|| {
+            *attempts.borrow_mut() += 1;
+            Err(expected.clone())
+        });
+
+        assert_eq!(result, Err(expected));
+        assert_eq!(*attempts.borrow(), 1);
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy::new(
+            5,
+            Backoff {
+                initial: Duration::from_millis(100),
+                max: Duration::from_millis(300),
+            },
+        );
+        let mut slept = vec![];
+        let result: Result<(), ServiceError> = policy.run_with_sleep(
+            || {
+                Err(ServiceError::RevisionConflict {
+                    expected: 0,
+                    actual: 1,
+                })
+            },
+            |d| slept.push(d),
+        );
+
+        // 間隔は Backoff と同じく倍々に延び, 上限で止まる
+        assert!(result.is_err());
+        assert_eq!(slept, [100, 200, 300, 300].map(Duration::from_millis));
+    }
+
+    // 最初の conflicts 回の保存の直前に, 他の誰かが同じ人を更新したことにする DAO
+    struct RacingPersonDao {
+        dao: MemoryPersonDao,
+        conflicts: Cell<u32>,
+    }
+    impl PersonDao<MemoryDb> for RacingPersonDao {
+        fn insert(
+            &self,
+            person: PersonDto,
+        ) -> impl tx_rs::Tx<MemoryDb, Item = PersonId, Err = DaoError> {
+            self.dao.insert(person)
+        }
+        fn fetch(
+            &self,
+            id: PersonId,
+        ) -> impl tx_rs::Tx<MemoryDb, Item = Option<PersonDto>, Err = DaoError> {
+            self.dao.fetch(id)
+        }
+        fn select(
+            &self,
+        ) -> impl tx_rs::Tx<MemoryDb, Item = Vec<(PersonId, PersonDto)>, Err = DaoError> {
+            self.dao.select()
+        }
+        fn save(
+            &self,
+            id: PersonId,
+            revision: Revision,
+            person: PersonDto,
+        ) -> impl tx_rs::Tx<MemoryDb, Item = (), Err = DaoError> {
+            let race = self.conflicts.get() > 0;
+            if race {
+                self.conflicts.set(self.conflicts.get() - 1);
+            }
+            let dao = self.dao.clone();
+            tx_rs::with_tx(move |db: &mut MemoryDb| {
+                if race {
+                    let mut other = dao.fetch(id).run(db)?.expect("person to race");
+                    let revision = other.revision;
+                    other.revision += 1;
+                    dao.save(id, revision, other).run(db)?;
+                }
+                dao.save(id, revision, person).run(db)
+            })
+        }
+        fn delete(
+            &self,
+            id: PersonId,
+        ) -> impl tx_rs::Tx<MemoryDb, Item = Option<Revision>, Err = DaoError> {
+            self.dao.delete(id)
+        }
+        fn restore(
+            &self,
+            id: PersonId,
+        ) -> impl tx_rs::Tx<MemoryDb, Item = Option<Revision>, Err = DaoError> {
+            self.dao.restore(id)
+        }
+        fn purge(
+            &self,
+            before: DateTime<Utc>,
+        ) -> impl tx_rs::Tx<MemoryDb, Item = u64, Err = DaoError> {
+            self.dao.purge(before)
+        }
+        fn history(
+            &self,
+            id: PersonId,
+        ) -> impl tx_rs::Tx<MemoryDb, Item = Vec<PersonHistoryDto>, Err = DaoError> {
+            self.dao.history(id)
+        }
+    }
+
+    struct TargetPersonUsecase {
+        dao: RacingPersonDao,
+    }
+    impl HavePersonDao<MemoryDb> for TargetPersonUsecase {
+        fn get_dao(&self) -> &impl PersonDao<MemoryDb> {
+            &self.dao
+        }
+    }
+    impl PersonUsecase<MemoryDb> for TargetPersonUsecase {}
+
+    struct TargetPersonService {
+        db: MemoryDb,
+        usecase: TargetPersonUsecase,
+        retry_policy: RetryPolicy,
+        transactions: u32,
+    }
+    impl TargetPersonService {
+        fn new(conflicts: u32, retry_policy: RetryPolicy) -> Self {
+            let person = PersonDto::new("Abel", date(1802, 8, 5), None, Some("Abel's theorem"), 1);
+            Self {
+                db: MemoryDb::with_persons(2, vec![(1, person)]),
+                usecase: TargetPersonUsecase {
+                    dao: RacingPersonDao {
+                        dao: MemoryPersonDao::new("test"),
+                        conflicts: Cell::new(conflicts),
+                    },
+                },
+                retry_policy,
+                transactions: 0,
+            }
+        }
+    }
+    impl<'a> PersonService<'a, MemoryDb> for TargetPersonService {
+        type U = TargetPersonUsecase;
+        type N = DefaultReporter<'a>;
+
+        fn run_tx<T, F>(&mut self, f: F) -> Result<T, ServiceError>
+        where
+            F: FnOnce(&mut Self::U, &mut MemoryDb) -> Result<T, UsecaseError>,
+        {
+            self.transactions += 1;
+            f(&mut self.usecase, &mut self.db).map_err(ServiceError::from)
+        }
+
+        fn get_reporter(&self) -> Self::N {
+            DefaultReporter::new()
+        }
+
+        fn retry_policy(&self) -> RetryPolicy {
+            self.retry_policy.clone()
+        }
+    }
+
+    #[test]
+    fn test_death_retried() {
+        let mut service = TargetPersonService::new(2, policy(3));
+
+        assert_eq!(service.death(1, date(1829, 4, 6)), Ok(()));
+        // 他の 2 回の更新の後に保存されている
+        let (_, person) = service.db.persons().remove(0);
+        assert_eq!(person.death_date, Some(date(1829, 4, 6)));
+        assert_eq!(person.revision, 4);
+        // 試すたびに新しいトランザクションで読み直している
+        assert_eq!(service.transactions, 3);
+    }
+
+    #[test]
+    fn test_update_retried() {
+        let mut service = TargetPersonService::new(1, policy(3));
+        let patch = PersonPatch {
+            name: Some("Niels Henrik Abel".to_string()),
+            ..Default::default()
+        };
+
+        let result = service.update(1, patch).map(|p| (p.name, p.revision));
+        assert_eq!(result, Ok(("Niels Henrik Abel".to_string(), 3)));
+    }
+
+    #[test]
+    fn test_death_retry_gives_up() {
+        let mut service = TargetPersonService::new(3, policy(3));
+
+        let result = service.death(1, date(1829, 4, 6));
+        assert_eq!(
+            result,
+            Err(ServiceError::RevisionConflict {
+                expected: 3,
+                actual: 4
+            })
+        );
+    }
+}
+
 // # フェイクテスト
 //
 // ## 目的
@@ -309,8 +934,26 @@ mod fake_tests {
         ) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
             tx_rs::with_tx(move |&mut ()| Ok(()))
         }
-        fn delete(&self, _id: PersonId) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
-            tx_rs::with_tx(move |&mut ()| Ok(()))
+        fn delete(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(None))
+        }
+        fn restore(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(None))
+        }
+        fn purge(&self, _before: DateTime<Utc>) -> impl tx_rs::Tx<(), Item = u64, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(0))
+        }
+        fn history(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Vec<PersonHistoryDto>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(vec![]))
         }
     }
 
@@ -378,28 +1021,38 @@ mod fake_tests {
             &'a mut self,
             id: PersonId,
             date: NaiveDate,
-        ) -> impl tx_rs::Tx<(), Item = (), Err = UsecaseError>
+        ) -> impl tx_rs::Tx<(), Item = PersonDto, Err = UsecaseError>
         where
             (): 'a,
         {
             let person = self.db.iter_mut().find(|(i, _)| *i == id);
 
-            if let Some((_, p)) = person {
-                p.death_date = Some(date);
-            }
+            let result = match person {
+                Some((_, p)) => {
+                    p.death_date = Some(date);
+                    p.revision += 1;
+                    Ok(p.clone())
+                }
+                None => Err(UsecaseError::PersonNotFound(id)),
+            };
 
-            tx_rs::with_tx(move |&mut ()| Ok(()))
+            tx_rs::with_tx(move |&mut ()| result)
         }
         fn remove<'a>(
             &'a mut self,
             id: PersonId,
-        ) -> impl tx_rs::Tx<(), Item = (), Err = UsecaseError>
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = UsecaseError>
         where
             (): 'a,
         {
+            let revision = self
+                .db
+                .iter()
+                .find(|(i, _)| *i == id)
+                .map(|(_, p)| p.revision + 1);
             self.db.retain(|(i, _)| *i != id);
 
-            tx_rs::with_tx(move |&mut ()| Ok(()))
+            tx_rs::with_tx(move |&mut ()| Ok(revision))
         }
     }
 
@@ -540,7 +1193,7 @@ mod fake_tests {
             .borrow()
             .db
             .iter()
-            .map(|(id, p)| (id.clone(), p.clone()))
+            .map(|(id, p)| (*id, p.clone()))
             .collect::<Vec<_>>();
 
         assert_eq!(result, Ok(expected))
@@ -573,7 +1226,7 @@ mod fake_tests {
                 date(2020, 5, 7),
                 Some(date(2100, 4, 7)),
                 Some("poor man will be dead"),
-                0,
+                1,
             ),
         )];
 
@@ -733,8 +1386,26 @@ mod spy_tests {
         ) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
             tx_rs::with_tx(move |&mut ()| Ok(()))
         }
-        fn delete(&self, _id: PersonId) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
-            tx_rs::with_tx(move |&mut ()| Ok(()))
+        fn delete(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(None))
+        }
+        fn restore(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(None))
+        }
+        fn purge(&self, _before: DateTime<Utc>) -> impl tx_rs::Tx<(), Item = u64, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(0))
+        }
+        fn history(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Vec<PersonHistoryDto>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(vec![]))
         }
     }
 
@@ -744,8 +1415,16 @@ mod spy_tests {
         find: RefCell<Vec<PersonId>>,
         entry_and_verify: RefCell<Vec<PersonDto>>,
         collect: RefCell<i32>,
+        query: RefCell<Vec<PersonQuery>>,
+        search: RefCell<Vec<PersonSearch>>,
         death: RefCell<Vec<(PersonId, NaiveDate)>>,
+        correct_death: RefCell<Vec<(PersonId, Option<NaiveDate>, String)>>,
+        update: RefCell<Vec<(PersonId, PersonPatch)>>,
         remove: RefCell<Vec<PersonId>>,
+        restore: RefCell<Vec<PersonId>>,
+        purge: RefCell<Vec<DateTime<Utc>>>,
+        history: RefCell<Vec<PersonId>>,
+        find_as_of: RefCell<Vec<(PersonId, Revision)>>,
     }
     impl HavePersonDao<()> for SpyPersonUsecase {
         fn get_dao(&self) -> &impl PersonDao<()> {
@@ -800,30 +1479,130 @@ mod spy_tests {
             // 返り値に意味はない
             tx_rs::with_tx(|&mut ()| Ok(vec![]))
         }
+        fn query<'a>(
+            &'a mut self,
+            query: PersonQuery,
+        ) -> impl tx_rs::Tx<(), Item = Vec<(PersonId, PersonDto)>, Err = UsecaseError>
+        where
+            (): 'a,
+        {
+            self.query.borrow_mut().push(query);
+
+            // 返り値に意味はない
+            tx_rs::with_tx(|&mut ()| Ok(vec![]))
+        }
+        fn search<'a>(
+            &'a mut self,
+            search: PersonSearch,
+        ) -> impl tx_rs::Tx<(), Item = Vec<(PersonId, PersonDto)>, Err = UsecaseError>
+        where
+            (): 'a,
+        {
+            self.search.borrow_mut().push(search);
+
+            // 返り値に意味はない
+            tx_rs::with_tx(|&mut ()| Ok(vec![]))
+        }
         fn death<'a>(
             &'a mut self,
             id: PersonId,
             date: NaiveDate,
-        ) -> impl tx_rs::Tx<(), Item = (), Err = UsecaseError>
+        ) -> impl tx_rs::Tx<(), Item = PersonDto, Err = UsecaseError>
         where
             (): 'a,
         {
             self.death.borrow_mut().push((id, date));
 
             // 返り値に意味はない
-            tx_rs::with_tx(move |&mut ()| Ok(()))
+            tx_rs::with_tx(move |&mut ()| Ok(PersonDto::default()))
+        }
+        fn correct_death<'a>(
+            &'a mut self,
+            id: PersonId,
+            date: Option<NaiveDate>,
+            reason: String,
+        ) -> impl tx_rs::Tx<(), Item = PersonDto, Err = UsecaseError>
+        where
+            (): 'a,
+        {
+            self.correct_death.borrow_mut().push((id, date, reason));
+
+            // 返り値に意味はない
+            tx_rs::with_tx(move |&mut ()| Ok(PersonDto::default()))
+        }
+        fn update<'a>(
+            &'a mut self,
+            id: PersonId,
+            patch: PersonPatch,
+        ) -> impl tx_rs::Tx<(), Item = PersonDto, Err = UsecaseError>
+        where
+            (): 'a,
+        {
+            self.update.borrow_mut().push((id, patch));
+
+            // 返り値に意味はない
+            tx_rs::with_tx(move |&mut ()| Ok(PersonDto::default()))
         }
         fn remove<'a>(
             &'a mut self,
             id: PersonId,
-        ) -> impl tx_rs::Tx<(), Item = (), Err = UsecaseError>
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = UsecaseError>
         where
             (): 'a,
         {
             self.remove.borrow_mut().push(id);
 
+            // 削除できたことにして通知させる
+            tx_rs::with_tx(move |&mut ()| Ok(Some(1)))
+        }
+        fn restore<'a>(
+            &'a mut self,
+            id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = PersonDto, Err = UsecaseError>
+        where
+            (): 'a,
+        {
+            self.restore.borrow_mut().push(id);
+
             // 返り値に意味はない
-            tx_rs::with_tx(move |&mut ()| Ok(()))
+            tx_rs::with_tx(move |&mut ()| Ok(PersonDto::default()))
+        }
+        fn purge<'a>(
+            &'a mut self,
+            before: DateTime<Utc>,
+        ) -> impl tx_rs::Tx<(), Item = u64, Err = UsecaseError>
+        where
+            (): 'a,
+        {
+            self.purge.borrow_mut().push(before);
+
+            // 返り値に意味はない
+            tx_rs::with_tx(move |&mut ()| Ok(0))
+        }
+        fn history<'a>(
+            &'a mut self,
+            id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Vec<PersonHistoryDto>, Err = UsecaseError>
+        where
+            (): 'a,
+        {
+            self.history.borrow_mut().push(id);
+
+            // 返り値に意味はない
+            tx_rs::with_tx(move |&mut ()| Ok(vec![]))
+        }
+        fn find_as_of<'a>(
+            &'a mut self,
+            id: PersonId,
+            revision: Revision,
+        ) -> impl tx_rs::Tx<(), Item = Option<PersonDto>, Err = UsecaseError>
+        where
+            (): 'a,
+        {
+            self.find_as_of.borrow_mut().push((id, revision));
+
+            // 返り値に意味はない
+            tx_rs::with_tx(move |&mut ()| Ok(None))
         }
     }
 
@@ -901,15 +1680,242 @@ mod spy_tests {
     }
 
     #[test]
-    fn test_register() {
+    fn test_register() {
+        let usecase = Rc::new(RefCell::new(SpyPersonUsecase {
+            dao: DummyPersonDao,
+            entry: RefCell::new(vec![]),
+            find: RefCell::new(vec![]),
+            entry_and_verify: RefCell::new(vec![]),
+            collect: RefCell::new(0),
+            query: RefCell::new(vec![]),
+            search: RefCell::new(vec![]),
+            death: RefCell::new(vec![]),
+            correct_death: RefCell::new(vec![]),
+            update: RefCell::new(vec![]),
+            remove: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
+            find_as_of: RefCell::new(vec![]),
+        }));
+        let reporter = SpyReporter {
+            report: RefCell::new(vec![]).into(),
+        };
+        let mut service = TargetPersonService {
+            usecase: usecase.clone(),
+            reporter,
+        };
+
+        let expected = PersonDto::new("Alice", date(2012, 11, 2), None, Some("Alice is sender"), 0);
+
+        let _ = service.register("Alice", date(2012, 11, 2), None, "Alice is sender");
+
+        // Usecase のメソッドの呼び出し記録の検証
+        assert_eq!(usecase.borrow().entry.borrow().len(), 0);
+        assert_eq!(usecase.borrow().find.borrow().len(), 0);
+        assert_eq!(usecase.borrow().entry_and_verify.borrow().len(), 1);
+        assert_eq!(*usecase.borrow().collect.borrow(), 0);
+        assert_eq!(usecase.borrow().death.borrow().len(), 0);
+        assert_eq!(usecase.borrow().remove.borrow().len(), 0);
+
+        // Service の引数が Usecase にそのまま渡されていることを検証
+        assert_eq!(usecase.borrow().entry_and_verify.borrow()[0], expected);
+
+        // Reporter のメソッド呼び出しの記録の検証
+        assert_eq!(service.get_reporter().report.borrow().len(), 1);
+
+        // Service の引数が Reporter にそのまま渡されていることを検証
+        assert_eq!(
+            service.get_reporter().report.borrow()[0],
+            (
+                "entry_person".to_string(),
+                "registered person_id: 42".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_batch_import() {
+        let usecase = Rc::new(RefCell::new(SpyPersonUsecase {
+            dao: DummyPersonDao,
+            entry: RefCell::new(vec![]),
+            find: RefCell::new(vec![]),
+            entry_and_verify: RefCell::new(vec![]),
+            collect: RefCell::new(0),
+            query: RefCell::new(vec![]),
+            search: RefCell::new(vec![]),
+            death: RefCell::new(vec![]),
+            correct_death: RefCell::new(vec![]),
+            update: RefCell::new(vec![]),
+            remove: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
+            find_as_of: RefCell::new(vec![]),
+        }));
+        let reporter = SpyReporter {
+            report: RefCell::new(vec![]).into(),
+        };
+        let mut service = TargetPersonService {
+            usecase: usecase.clone(),
+            reporter,
+        };
+
+        let persons = vec![
+            PersonDto::new("Alice", date(2012, 11, 2), None, Some("Alice is sender"), 3),
+            PersonDto::new("Bob", date(1995, 11, 6), None, Some("Bob is receiver"), 1),
+            PersonDto::new(
+                "Eve",
+                date(1996, 12, 15),
+                None,
+                Some("Eve is interseptor"),
+                7,
+            ),
+        ];
+        let expected = persons.clone();
+        let out_port = Rc::new(SpyPersonOutputBoundary::default());
+
+        let _ = service.batch_import(persons.into_iter(), out_port.clone());
+
+        // Usecase のメソッドの呼び出し記録の検証
+        assert_eq!(usecase.borrow().entry.borrow().len(), 3);
+        assert_eq!(usecase.borrow().find.borrow().len(), 0);
+        assert_eq!(usecase.borrow().entry_and_verify.borrow().len(), 0);
+        assert_eq!(*usecase.borrow().collect.borrow(), 0);
+        assert_eq!(usecase.borrow().death.borrow().len(), 0);
+        assert_eq!(usecase.borrow().remove.borrow().len(), 0);
+
+        // Service の引数が Usecase にそのまま渡されていることを検証
+        assert_eq!(usecase.borrow().entry.borrow().clone(), expected);
+
+        // Reporter のメソッド呼び出しの記録の検証
+        // 登録した全件をまとめて 1 回だけ通知する
+        assert_eq!(service.get_reporter().report.borrow().len(), 1);
+
+        // Service の引数が Reporter にそのまま渡されていることを検証
+        assert_eq!(
+            *service.get_reporter().report.borrow(),
+            vec![(
+                "entry_person".to_string(),
+                "imported person_ids: [42, 42, 42]".to_string()
+            )]
+        );
+
+        // PersonOutputBoundary のメソッド呼び出しの記録の検証
+        assert_eq!(*out_port.started.borrow(), 1);
+        assert_eq!(out_port.in_progress.borrow().len(), 3);
+        assert_eq!(*out_port.completed.borrow(), 1);
+        assert_eq!(out_port.aborted.borrow().len(), 0);
+
+        // PersonOutputboundary の引数がそのまま渡されていることを検証
+        assert_eq!(*out_port.in_progress.borrow(), vec![(3, 1), (3, 2), (3, 3)]);
+    }
+
+    #[test]
+    fn list_all() {
+        let usecase = Rc::new(RefCell::new(SpyPersonUsecase {
+            dao: DummyPersonDao,
+            entry: RefCell::new(vec![]),
+            find: RefCell::new(vec![]),
+            entry_and_verify: RefCell::new(vec![]),
+            collect: RefCell::new(0),
+            query: RefCell::new(vec![]),
+            search: RefCell::new(vec![]),
+            death: RefCell::new(vec![]),
+            correct_death: RefCell::new(vec![]),
+            update: RefCell::new(vec![]),
+            remove: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
+            find_as_of: RefCell::new(vec![]),
+        }));
+        let reporter = SpyReporter {
+            report: RefCell::new(vec![]).into(),
+        };
+        let mut service = TargetPersonService {
+            usecase: usecase.clone(),
+            reporter,
+        };
+
+        let _ = service.list_all();
+
+        // Usecase のメソッドの呼び出し記録の検証
+        assert_eq!(usecase.borrow().entry.borrow().len(), 0);
+        assert_eq!(usecase.borrow().find.borrow().len(), 0);
+        assert_eq!(usecase.borrow().entry_and_verify.borrow().len(), 0);
+        assert_eq!(*usecase.borrow().collect.borrow(), 1);
+        assert_eq!(usecase.borrow().death.borrow().len(), 0);
+        assert_eq!(usecase.borrow().remove.borrow().len(), 0);
+
+        // Reporter のメソッド呼び出しの記録の検証
+        assert_eq!(service.get_reporter().report.borrow().len(), 0);
+    }
+    #[test]
+    fn test_list() {
+        let usecase = Rc::new(RefCell::new(SpyPersonUsecase {
+            dao: DummyPersonDao,
+            entry: RefCell::new(vec![]),
+            find: RefCell::new(vec![]),
+            entry_and_verify: RefCell::new(vec![]),
+            collect: RefCell::new(0),
+            query: RefCell::new(vec![]),
+            search: RefCell::new(vec![]),
+            death: RefCell::new(vec![]),
+            correct_death: RefCell::new(vec![]),
+            update: RefCell::new(vec![]),
+            remove: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
+            find_as_of: RefCell::new(vec![]),
+        }));
+        let reporter = SpyReporter {
+            report: RefCell::new(vec![]).into(),
+        };
+        let mut service = TargetPersonService {
+            usecase: usecase.clone(),
+            reporter,
+        };
+        let query = PersonQuery {
+            name_prefix: Some("Ab".to_string()),
+            after: Some(13),
+            limit: Some(10),
+            ..Default::default()
+        };
+
+        let _ = service.list(query.clone());
+
+        // Usecase のメソッドの呼び出し記録の検証
+        assert_eq!(usecase.borrow().entry.borrow().len(), 0);
+        assert_eq!(usecase.borrow().find.borrow().len(), 0);
+        assert_eq!(usecase.borrow().entry_and_verify.borrow().len(), 0);
+        assert_eq!(*usecase.borrow().collect.borrow(), 0);
+        assert_eq!(*usecase.borrow().query.borrow(), vec![query]);
+        assert_eq!(usecase.borrow().death.borrow().len(), 0);
+        assert_eq!(usecase.borrow().remove.borrow().len(), 0);
+
+        // Reporter のメソッド呼び出しの記録の検証
+        assert_eq!(service.get_reporter().report.borrow().len(), 0);
+    }
+    #[test]
+    fn test_update() {
         let usecase = Rc::new(RefCell::new(SpyPersonUsecase {
             dao: DummyPersonDao,
             entry: RefCell::new(vec![]),
             find: RefCell::new(vec![]),
             entry_and_verify: RefCell::new(vec![]),
             collect: RefCell::new(0),
+            query: RefCell::new(vec![]),
+            search: RefCell::new(vec![]),
             death: RefCell::new(vec![]),
+            correct_death: RefCell::new(vec![]),
+            update: RefCell::new(vec![]),
             remove: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
+            find_as_of: RefCell::new(vec![]),
         }));
         let reporter = SpyReporter {
             report: RefCell::new(vec![]).into(),
@@ -918,45 +1924,55 @@ mod spy_tests {
             usecase: usecase.clone(),
             reporter,
         };
+        let patch = PersonPatch {
+            name: Some("Gauss".to_string()),
+            birth_date: Some(date(1777, 4, 30)),
+            data: Some(None),
+        };
 
-        let expected = PersonDto::new("Alice", date(2012, 11, 2), None, Some("Alice is sender"), 0);
-
-        let _ = service.register("Alice", date(2012, 11, 2), None, "Alice is sender");
+        let _ = service.update(13, patch.clone());
 
         // Usecase のメソッドの呼び出し記録の検証
-        assert_eq!(usecase.borrow().entry.borrow().len(), 0);
         assert_eq!(usecase.borrow().find.borrow().len(), 0);
-        assert_eq!(usecase.borrow().entry_and_verify.borrow().len(), 1);
-        assert_eq!(*usecase.borrow().collect.borrow(), 0);
         assert_eq!(usecase.borrow().death.borrow().len(), 0);
+        assert_eq!(*usecase.borrow().update.borrow(), vec![(13, patch)]);
         assert_eq!(usecase.borrow().remove.borrow().len(), 0);
 
-        // Service の引数が Usecase にそのまま渡されていることを検証
-        assert_eq!(usecase.borrow().entry_and_verify.borrow()[0], expected);
-
         // Reporter のメソッド呼び出しの記録の検証
         assert_eq!(service.get_reporter().report.borrow().len(), 1);
+        assert_eq!(
+            service.get_reporter().report.borrow()[0].0,
+            "update_person".to_string()
+        );
 
-        // Service の引数が Reporter にそのまま渡されていることを検証
+        // 何も変えない更新は Usecase まで届かない
+        let result = service.update(13, PersonPatch::default());
         assert_eq!(
-            service.get_reporter().report.borrow()[0],
-            (
-                "entry_person".to_string(),
-                "registered person_id: 42".to_string()
-            )
+            result,
+            Err(ServiceError::InvalidRequest(
+                InvalidErrorKind::EmptyArgument
+            ))
         );
+        assert_eq!(usecase.borrow().update.borrow().len(), 1);
     }
-
     #[test]
-    fn test_batch_import() {
+    fn test_correct_death() {
         let usecase = Rc::new(RefCell::new(SpyPersonUsecase {
             dao: DummyPersonDao,
             entry: RefCell::new(vec![]),
             find: RefCell::new(vec![]),
             entry_and_verify: RefCell::new(vec![]),
             collect: RefCell::new(0),
+            query: RefCell::new(vec![]),
+            search: RefCell::new(vec![]),
             death: RefCell::new(vec![]),
+            correct_death: RefCell::new(vec![]),
+            update: RefCell::new(vec![]),
             remove: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
+            find_as_of: RefCell::new(vec![]),
         }));
         let reporter = SpyReporter {
             report: RefCell::new(vec![]).into(),
@@ -966,75 +1982,55 @@ mod spy_tests {
             reporter,
         };
 
-        let persons = vec![
-            PersonDto::new("Alice", date(2012, 11, 2), None, Some("Alice is sender"), 3),
-            PersonDto::new("Bob", date(1995, 11, 6), None, Some("Bob is receiver"), 1),
-            PersonDto::new(
-                "Eve",
-                date(1996, 12, 15),
-                None,
-                Some("Eve is interseptor"),
-                7,
-            ),
-        ];
-        let expected = persons.clone();
-        let out_port = Rc::new(SpyPersonOutputBoundary::default());
-
-        let _ = service.batch_import(persons.into_iter(), out_port.clone());
+        let _ = service.correct_death(13, None, "wrong person");
 
         // Usecase のメソッドの呼び出し記録の検証
-        assert_eq!(usecase.borrow().entry.borrow().len(), 3);
         assert_eq!(usecase.borrow().find.borrow().len(), 0);
-        assert_eq!(usecase.borrow().entry_and_verify.borrow().len(), 0);
-        assert_eq!(*usecase.borrow().collect.borrow(), 0);
         assert_eq!(usecase.borrow().death.borrow().len(), 0);
-        assert_eq!(usecase.borrow().remove.borrow().len(), 0);
-
-        // Service の引数が Usecase にそのまま渡されていることを検証
-        assert_eq!(usecase.borrow().entry.borrow().clone(), expected);
+        assert_eq!(
+            *usecase.borrow().correct_death.borrow(),
+            vec![(13, None, "wrong person".to_string())]
+        );
+        assert_eq!(usecase.borrow().update.borrow().len(), 0);
 
         // Reporter のメソッド呼び出しの記録の検証
-        assert_eq!(service.get_reporter().report.borrow().len(), 3);
-
-        // Service の引数が Reporter にそのまま渡されていることを検証
+        assert_eq!(service.get_reporter().report.borrow().len(), 1);
         assert_eq!(
-            *service.get_reporter().report.borrow(),
-            vec![
-                (
-                    "entry_person".to_string(),
-                    "registered person_id: 42".to_string()
-                ),
-                (
-                    "entry_person".to_string(),
-                    "registered person_id: 42".to_string()
-                ),
-                (
-                    "entry_person".to_string(),
-                    "registered person_id: 42".to_string()
-                )
-            ]
+            service.get_reporter().report.borrow()[0].0,
+            "correct_death_person".to_string()
         );
+        assert!(service.get_reporter().report.borrow()[0]
+            .1
+            .contains("wrong person"));
 
-        // PersonOutputBoundary のメソッド呼び出しの記録の検証
-        assert_eq!(*out_port.started.borrow(), 1);
-        assert_eq!(out_port.in_progress.borrow().len(), 3);
-        assert_eq!(*out_port.completed.borrow(), 1);
-        assert_eq!(out_port.aborted.borrow().len(), 0);
-
-        // PersonOutputboundary の引数がそのまま渡されていることを検証
-        assert_eq!(*out_port.in_progress.borrow(), vec![(3, 1), (3, 2), (3, 3)]);
+        // 理由のない訂正は Usecase まで届かない
+        let result = service.correct_death(13, Some(date(2020, 7, 19)), " ");
+        assert_eq!(
+            result,
+            Err(ServiceError::InvalidRequest(
+                InvalidErrorKind::EmptyArgument
+            ))
+        );
+        assert_eq!(usecase.borrow().correct_death.borrow().len(), 1);
     }
-
     #[test]
-    fn list_all() {
+    fn test_search() {
         let usecase = Rc::new(RefCell::new(SpyPersonUsecase {
             dao: DummyPersonDao,
             entry: RefCell::new(vec![]),
             find: RefCell::new(vec![]),
             entry_and_verify: RefCell::new(vec![]),
             collect: RefCell::new(0),
+            query: RefCell::new(vec![]),
+            search: RefCell::new(vec![]),
             death: RefCell::new(vec![]),
+            correct_death: RefCell::new(vec![]),
+            update: RefCell::new(vec![]),
             remove: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
+            find_as_of: RefCell::new(vec![]),
         }));
         let reporter = SpyReporter {
             report: RefCell::new(vec![]).into(),
@@ -1043,16 +2039,31 @@ mod spy_tests {
             usecase: usecase.clone(),
             reporter,
         };
+        let search = PersonSearch {
+            text: "abel".to_string(),
+            include_data: true,
+            limit: Some(10),
+        };
 
-        let _ = service.list_all();
+        let _ = service.search(search.clone());
 
         // Usecase のメソッドの呼び出し記録の検証
-        assert_eq!(usecase.borrow().entry.borrow().len(), 0);
-        assert_eq!(usecase.borrow().find.borrow().len(), 0);
-        assert_eq!(usecase.borrow().entry_and_verify.borrow().len(), 0);
-        assert_eq!(*usecase.borrow().collect.borrow(), 1);
-        assert_eq!(usecase.borrow().death.borrow().len(), 0);
-        assert_eq!(usecase.borrow().remove.borrow().len(), 0);
+        assert_eq!(*usecase.borrow().collect.borrow(), 0);
+        assert_eq!(usecase.borrow().query.borrow().len(), 0);
+        assert_eq!(*usecase.borrow().search.borrow(), vec![search]);
+
+        // 空の検索語は Usecase まで届かない
+        let result = service.search(PersonSearch {
+            text: " ".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(
+            result,
+            Err(ServiceError::InvalidRequest(
+                InvalidErrorKind::EmptyArgument
+            ))
+        );
+        assert_eq!(usecase.borrow().search.borrow().len(), 1);
 
         // Reporter のメソッド呼び出しの記録の検証
         assert_eq!(service.get_reporter().report.borrow().len(), 0);
@@ -1065,8 +2076,16 @@ mod spy_tests {
             find: RefCell::new(vec![]),
             entry_and_verify: RefCell::new(vec![]),
             collect: RefCell::new(0),
+            query: RefCell::new(vec![]),
+            search: RefCell::new(vec![]),
             death: RefCell::new(vec![]),
+            correct_death: RefCell::new(vec![]),
+            update: RefCell::new(vec![]),
             remove: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
+            find_as_of: RefCell::new(vec![]),
         }));
         let reporter = SpyReporter {
             report: RefCell::new(vec![]).into(),
@@ -1108,8 +2127,16 @@ mod spy_tests {
             find: RefCell::new(vec![]),
             entry_and_verify: RefCell::new(vec![]),
             collect: RefCell::new(0),
+            query: RefCell::new(vec![]),
+            search: RefCell::new(vec![]),
             death: RefCell::new(vec![]),
+            correct_death: RefCell::new(vec![]),
+            update: RefCell::new(vec![]),
             remove: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
+            find_as_of: RefCell::new(vec![]),
         }));
         let reporter = SpyReporter {
             report: RefCell::new(vec![]).into(),
@@ -1144,6 +2171,166 @@ mod spy_tests {
             )]
         );
     }
+    #[test]
+    fn test_restore() {
+        let usecase = Rc::new(RefCell::new(SpyPersonUsecase {
+            dao: DummyPersonDao,
+            entry: RefCell::new(vec![]),
+            find: RefCell::new(vec![]),
+            entry_and_verify: RefCell::new(vec![]),
+            collect: RefCell::new(0),
+            query: RefCell::new(vec![]),
+            search: RefCell::new(vec![]),
+            death: RefCell::new(vec![]),
+            correct_death: RefCell::new(vec![]),
+            update: RefCell::new(vec![]),
+            remove: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
+            find_as_of: RefCell::new(vec![]),
+        }));
+        let reporter = SpyReporter {
+            report: RefCell::new(vec![]).into(),
+        };
+        let mut service = TargetPersonService {
+            usecase: usecase.clone(),
+            reporter,
+        };
+
+        let _ = service.restore(42);
+
+        // Usecase のメソッドの呼び出し記録の検証
+        assert_eq!(usecase.borrow().find.borrow().len(), 0);
+        assert_eq!(usecase.borrow().remove.borrow().len(), 0);
+        assert_eq!(*usecase.borrow().restore.borrow(), vec![42]);
+        assert_eq!(usecase.borrow().purge.borrow().len(), 0);
+
+        // Reporter のメソッド呼び出しの記録の検証
+        assert_eq!(
+            *service.get_reporter().report.borrow(),
+            vec![(
+                "restore_person".to_string(),
+                "restored person_id: 42, revision: 0".to_string()
+            )]
+        );
+    }
+    #[test]
+    fn test_purge() {
+        let usecase = Rc::new(RefCell::new(SpyPersonUsecase {
+            dao: DummyPersonDao,
+            entry: RefCell::new(vec![]),
+            find: RefCell::new(vec![]),
+            entry_and_verify: RefCell::new(vec![]),
+            collect: RefCell::new(0),
+            query: RefCell::new(vec![]),
+            search: RefCell::new(vec![]),
+            death: RefCell::new(vec![]),
+            correct_death: RefCell::new(vec![]),
+            update: RefCell::new(vec![]),
+            remove: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
+            find_as_of: RefCell::new(vec![]),
+        }));
+        let reporter = SpyReporter {
+            report: RefCell::new(vec![]).into(),
+        };
+        let mut service = TargetPersonService {
+            usecase: usecase.clone(),
+            reporter,
+        };
+
+        let before = Utc::now();
+        let _ = service.purge(before);
+
+        // Usecase のメソッドの呼び出し記録の検証
+        assert_eq!(usecase.borrow().remove.borrow().len(), 0);
+        assert_eq!(usecase.borrow().restore.borrow().len(), 0);
+        assert_eq!(*usecase.borrow().purge.borrow(), vec![before]);
+
+        // Reporter のメソッド呼び出しの記録の検証
+        assert_eq!(service.get_reporter().report.borrow().len(), 1);
+        assert_eq!(
+            service.get_reporter().report.borrow()[0].0,
+            "purge_person".to_string()
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let usecase = Rc::new(RefCell::new(SpyPersonUsecase {
+            dao: DummyPersonDao,
+            entry: RefCell::new(vec![]),
+            find: RefCell::new(vec![]),
+            entry_and_verify: RefCell::new(vec![]),
+            collect: RefCell::new(0),
+            query: RefCell::new(vec![]),
+            search: RefCell::new(vec![]),
+            death: RefCell::new(vec![]),
+            correct_death: RefCell::new(vec![]),
+            update: RefCell::new(vec![]),
+            remove: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
+            find_as_of: RefCell::new(vec![]),
+        }));
+        let reporter = SpyReporter {
+            report: RefCell::new(vec![]).into(),
+        };
+        let mut service = TargetPersonService {
+            usecase: usecase.clone(),
+            reporter,
+        };
+
+        let _ = service.history(1);
+
+        // Usecase のメソッドの呼び出し記録の検証
+        assert_eq!(*usecase.borrow().history.borrow(), vec![1]);
+        assert_eq!(usecase.borrow().find_as_of.borrow().len(), 0);
+
+        // 参照系なので成功時は Reporter を呼ばない
+        assert_eq!(service.get_reporter().report.borrow().len(), 0);
+    }
+
+    #[test]
+    fn test_find_as_of() {
+        let usecase = Rc::new(RefCell::new(SpyPersonUsecase {
+            dao: DummyPersonDao,
+            entry: RefCell::new(vec![]),
+            find: RefCell::new(vec![]),
+            entry_and_verify: RefCell::new(vec![]),
+            collect: RefCell::new(0),
+            query: RefCell::new(vec![]),
+            search: RefCell::new(vec![]),
+            death: RefCell::new(vec![]),
+            correct_death: RefCell::new(vec![]),
+            update: RefCell::new(vec![]),
+            remove: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
+            find_as_of: RefCell::new(vec![]),
+        }));
+        let reporter = SpyReporter {
+            report: RefCell::new(vec![]).into(),
+        };
+        let mut service = TargetPersonService {
+            usecase: usecase.clone(),
+            reporter,
+        };
+
+        let _ = service.find_as_of(1, 3);
+
+        // Usecase のメソッドの呼び出し記録の検証
+        assert_eq!(usecase.borrow().history.borrow().len(), 0);
+        assert_eq!(*usecase.borrow().find_as_of.borrow(), vec![(1, 3)]);
+
+        // 参照系なので成功時は Reporter を呼ばない
+        assert_eq!(service.get_reporter().report.borrow().len(), 0);
+    }
 }
 
 // # エラー系スタブテスト
@@ -1233,8 +2420,26 @@ mod error_stub_tests {
         ) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
             tx_rs::with_tx(move |&mut ()| Ok(()))
         }
-        fn delete(&self, _id: PersonId) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
-            tx_rs::with_tx(move |&mut ()| Ok(()))
+        fn delete(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(None))
+        }
+        fn restore(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(None))
+        }
+        fn purge(&self, _before: DateTime<Utc>) -> impl tx_rs::Tx<(), Item = u64, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(0))
+        }
+        fn history(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Vec<PersonHistoryDto>, Err = DaoError> {
+            tx_rs::with_tx(move |&mut ()| Ok(vec![]))
         }
     }
 
@@ -1244,8 +2449,8 @@ mod error_stub_tests {
         find_result: Result<Option<PersonDto>, UsecaseError>,
         entry_and_verify_result: Result<(PersonId, PersonDto), UsecaseError>,
         collect_result: Result<Vec<(PersonId, PersonDto)>, UsecaseError>,
-        death_result: Result<(), UsecaseError>,
-        remove_result: Result<(), UsecaseError>,
+        death_result: Result<PersonDto, UsecaseError>,
+        remove_result: Result<Option<Revision>, UsecaseError>,
     }
     impl HavePersonDao<()> for StubPersonUsecase {
         fn get_dao(&self) -> &impl PersonDao<()> {
@@ -1292,7 +2497,7 @@ mod error_stub_tests {
             &'a mut self,
             _id: PersonId,
             _date: NaiveDate,
-        ) -> impl tx_rs::Tx<(), Item = (), Err = UsecaseError>
+        ) -> impl tx_rs::Tx<(), Item = PersonDto, Err = UsecaseError>
         where
             (): 'a,
         {
@@ -1301,7 +2506,7 @@ mod error_stub_tests {
         fn remove<'a>(
             &'a mut self,
             _id: PersonId,
-        ) -> impl tx_rs::Tx<(), Item = (), Err = UsecaseError>
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = UsecaseError>
         where
             (): 'a,
         {
@@ -1357,7 +2562,7 @@ mod error_stub_tests {
             F: FnOnce(&mut Self::U, &mut ()) -> Result<T, UsecaseError>,
         {
             let mut usecase = self.usecase.borrow_mut();
-            f(&mut usecase, &mut ()).map_err(ServiceError::TransactionFailed)
+            f(&mut usecase, &mut ()).map_err(ServiceError::from)
         }
 
         fn get_reporter(&self) -> Self::N {
@@ -1382,9 +2587,9 @@ mod error_stub_tests {
             entry_and_verify_result: Err(UsecaseError::EntryAndVerifyPersonFailed(
                 DaoError::InsertError("valid dao".to_string()),
             )),
-            collect_result: Ok(vec![]), // 使わない
-            death_result: Ok(()),       // 使わない
-            remove_result: Ok(()),      // 使わない
+            collect_result: Ok(vec![]),             // 使わない
+            death_result: Ok(PersonDto::default()), // 使わない
+            remove_result: Ok(None),                // 使わない
         }));
         let reporter = StubReporter {
             admin_result: Ok(()),
@@ -1418,9 +2623,9 @@ mod error_stub_tests {
                 1,
                 PersonDto::new("Alice", date(2012, 11, 2), None, Some("Alice is sender"), 0),
             )),
-            collect_result: Ok(vec![]), // 使わない
-            death_result: Ok(()),       // 使わない
-            remove_result: Ok(()),      // 使わない
+            collect_result: Ok(vec![]),             // 使わない
+            death_result: Ok(PersonDto::default()), // 使わない
+            remove_result: Ok(None),                // 使わない
         }));
         let reporter = StubReporter {
             admin_result: Ok(()),
@@ -1448,9 +2653,9 @@ mod error_stub_tests {
             entry_and_verify_result: Err(UsecaseError::EntryAndVerifyPersonFailed(
                 DaoError::InsertError("valid dao".to_string()),
             )),
-            collect_result: Ok(vec![]), // 使わない
-            death_result: Ok(()),       // 使わない
-            remove_result: Ok(()),      // 使わない
+            collect_result: Ok(vec![]),             // 使わない
+            death_result: Ok(PersonDto::default()), // 使わない
+            remove_result: Ok(None),                // 使わない
         }));
         let reporter = StubReporter {
             admin_result: Err(ReporterError::Unavailable("valid req".to_string())),
@@ -1487,8 +2692,8 @@ mod error_stub_tests {
                 PersonDto::new("Alice", date(2012, 11, 2), None, None, 0),
             )), // 使わない
             collect_result: Ok(vec![]), // 使わない
-            death_result: Ok(()),  // 使わない
-            remove_result: Ok(()), // 使わない
+            death_result: Ok(PersonDto::default()), // 使わない
+            remove_result: Ok(None), // 使わない
         }));
         let reporter = StubReporter {
             admin_result: Ok(()),
@@ -1526,8 +2731,8 @@ mod error_stub_tests {
                 PersonDto::new("Alice", date(2012, 11, 2), None, None, 0),
             )), // 使わない
             collect_result: Ok(vec![]), // 使わない
-            death_result: Ok(()),  // 使わない
-            remove_result: Ok(()), // 使わない
+            death_result: Ok(PersonDto::default()), // 使わない
+            remove_result: Ok(None), // 使わない
         }));
         let reporter = StubReporter {
             admin_result: Ok(()),
@@ -1566,8 +2771,8 @@ mod error_stub_tests {
                 PersonDto::new("Alice", date(2012, 11, 2), None, None, 0),
             )), // 使わない
             collect_result: Ok(vec![]), // 使わない
-            death_result: Ok(()),  // 使わない
-            remove_result: Ok(()), // 使わない
+            death_result: Ok(PersonDto::default()), // 使わない
+            remove_result: Ok(None), // 使わない
         }));
         let reporter = StubReporter {
             admin_result: Err(ReporterError::Unavailable("valid req".to_string())),
@@ -1609,8 +2814,8 @@ mod error_stub_tests {
             collect_result: Err(UsecaseError::CollectPersonFailed(DaoError::SelectError(
                 "valid dao".to_string(),
             ))),
-            death_result: Ok(()),  // 使わない
-            remove_result: Ok(()), // 使わない
+            death_result: Ok(PersonDto::default()), // 使わない
+            remove_result: Ok(None),                // 使わない
         }));
         let reporter = StubReporter {
             admin_result: Ok(()),
@@ -1643,8 +2848,8 @@ mod error_stub_tests {
             collect_result: Err(UsecaseError::CollectPersonFailed(DaoError::SelectError(
                 "valid dao".to_string(),
             ))),
-            death_result: Ok(()),  // 使わない
-            remove_result: Ok(()), // 使わない
+            death_result: Ok(PersonDto::default()), // 使わない
+            remove_result: Ok(None),                // 使わない
         }));
         let reporter = StubReporter {
             admin_result: Err(ReporterError::Unavailable("valid req".to_string())),
@@ -1675,7 +2880,7 @@ mod error_stub_tests {
                 PersonDto::new("Alice", date(2012, 11, 2), None, None, 0),
             )), // 使わない
             collect_result: Ok(vec![]), // 使わない
-            death_result: Ok(()),  // 使わない
+            death_result: Ok(PersonDto::default()), // 使わない
             remove_result: Err(UsecaseError::RemovePersonFailed(DaoError::DeleteError(
                 "valid dao".to_string(),
             ))),
@@ -1709,8 +2914,14 @@ mod error_stub_tests {
                 PersonDto::new("Alice", date(2012, 11, 2), None, None, 0),
             )), // 使わない
             collect_result: Ok(vec![]), // 使わない
-            death_result: Ok(()),
-            remove_result: Ok(()), // 使わない
+            death_result: Ok(PersonDto::new(
+                "Alice",
+                date(2012, 11, 2),
+                Some(date(2020, 8, 30)),
+                None,
+                1,
+            )),
+            remove_result: Ok(None), // 使わない
         }));
         let reporter = StubReporter {
             admin_result: Ok(()),
@@ -1743,7 +2954,7 @@ mod error_stub_tests {
             death_result: Err(UsecaseError::SavePersonFailed(DaoError::UpdateError(
                 "valid dao".to_string(),
             ))),
-            remove_result: Ok(()), // 使わない
+            remove_result: Ok(None), // 使わない
         }));
         let reporter = StubReporter {
             admin_result: Err(ReporterError::Unavailable("valid req".to_string())),
@@ -1763,6 +2974,46 @@ mod error_stub_tests {
         assert_eq!(result, Err(ServiceError::TransactionFailed(expected)));
     }
 
+    #[test]
+    fn test_death_revision_conflict() {
+        let usecase = Rc::new(RefCell::new(StubPersonUsecase {
+            dao: DummyPersonDao,
+            entry_result: Ok(1),   // 使わない
+            find_result: Ok(None), // 使わない
+            entry_and_verify_result: Ok((
+                42,
+                PersonDto::new("Alice", date(2012, 11, 2), None, None, 0),
+            )), // 使わない
+            collect_result: Ok(vec![]), // 使わない
+            death_result: Err(UsecaseError::RevisionConflict {
+                expected: 3,
+                actual: 4,
+            }),
+            remove_result: Ok(None), // 使わない
+        }));
+        let reporter = StubReporter {
+            admin_result: Ok(()),
+            entry_person_result: Ok(()),
+            death_person_result: Ok(()),
+            unregister_person_result: Ok(()),
+            otherwise_result: Ok(()),
+        };
+        let mut service = TargetPersonService {
+            usecase: usecase.clone(),
+            reporter,
+        };
+
+        let result = service.death(42, date(2020, 8, 30));
+
+        assert_eq!(
+            result,
+            Err(ServiceError::RevisionConflict {
+                expected: 3,
+                actual: 4
+            })
+        );
+    }
+
     #[test]
     fn test_unregister_reporter_for_unregister_person() {
         let usecase = Rc::new(RefCell::new(StubPersonUsecase {
@@ -1774,8 +3025,8 @@ mod error_stub_tests {
                 PersonDto::new("Alice", date(2012, 11, 2), None, None, 0),
             )), // 使わない
             collect_result: Ok(vec![]), // 使わない
-            death_result: Ok(()),  // 使わない
-            remove_result: Ok(()),
+            death_result: Ok(PersonDto::default()), // 使わない
+            remove_result: Ok(Some(1)),
         }));
         let reporter = StubReporter {
             admin_result: Ok(()),
@@ -1805,7 +3056,7 @@ mod error_stub_tests {
                 PersonDto::new("Alice", date(2012, 11, 2), None, None, 0),
             )), // 使わない
             collect_result: Ok(vec![]), // 使わない
-            death_result: Ok(()),  // 使わない
+            death_result: Ok(PersonDto::default()), // 使わない
             remove_result: Err(UsecaseError::RemovePersonFailed(DaoError::DeleteError(
                 "valid dao".to_string(),
             ))),
@@ -0,0 +1,694 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use log::{trace, warn};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::str;
+
+use crate::dao::{DaoError, PersonDao};
+use crate::domain::{PersonId, Revision};
+use crate::dto::{ChangeKind, PersonDto, PersonHistoryDto};
+
+/// the tables of `SqlitePersonDao`, the same as those of postgres in `migrations`
+pub const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS person (
+  id           INTEGER PRIMARY KEY AUTOINCREMENT,
+  name         TEXT NOT NULL,
+  birth_date   TEXT NOT NULL,
+  death_date   TEXT,
+  data         BLOB,
+  deleted_at   TEXT,
+
+  revision     INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS person_history (
+  id           INTEGER PRIMARY KEY AUTOINCREMENT,
+  person_id    INTEGER NOT NULL,
+  revision     INTEGER NOT NULL,
+  kind         TEXT NOT NULL,
+  old_value    TEXT,
+  new_value    TEXT,
+  changed_at   TEXT NOT NULL,
+  actor        TEXT NOT NULL,
+  reason       TEXT
+);
+CREATE INDEX IF NOT EXISTS person_history_person_id_idx ON person_history (person_id, id);
+CREATE TRIGGER IF NOT EXISTS person_history_no_update BEFORE UPDATE ON person_history
+BEGIN SELECT RAISE(IGNORE); END;
+CREATE TRIGGER IF NOT EXISTS person_history_no_delete BEFORE DELETE ON person_history
+BEGIN SELECT RAISE(IGNORE); END;
+"#;
+
+/// create the tables unless they exist, and add the columns added after they were created
+pub fn create_tables(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(SCHEMA)?;
+    // SQLite には ADD COLUMN IF NOT EXISTS がないので, 列があるか確かめてから足す
+    let has_reason = conn
+        .prepare("SELECT 1 FROM pragma_table_info('person_history') WHERE name = 'reason'")?
+        .exists([])?;
+    if !has_reason {
+        conn.execute_batch("ALTER TABLE person_history ADD COLUMN reason TEXT")?;
+    }
+    Ok(())
+}
+
+/// `PersonDao` on an embedded SQLite database, a file or in memory.
+///
+/// The semantics, the revision check and the history are the same as `PgPersonDao`,
+/// so the services can be run end to end without any external service.
+#[derive(Debug, Clone)]
+pub struct SqlitePersonDao {
+    actor: String,
+}
+impl SqlitePersonDao {
+    /// `actor` is recorded in the history as who made the changes.
+    pub fn new(actor: &str) -> Self {
+        Self {
+            actor: actor.to_string(),
+        }
+    }
+
+    // save と correct の共通部分. reason は履歴に残す
+    fn save_with(
+        &self,
+        id: PersonId,
+        revision: Revision,
+        person: PersonDto,
+        reason: Option<String>,
+    ) -> impl tx_rs::Tx<Connection, Item = (), Err = DaoError> {
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |tx: &mut Connection| {
+            // SQLite は書き込みでデータベース全体をロックするので FOR UPDATE は要らない
+            let old = tx
+                .query_row(
+                    r#"SELECT name,
+                              birth_date,
+                              death_date,
+                              data,
+                              revision
+                         FROM person
+                        WHERE id = ?1
+                          AND deleted_at IS NULL"#,
+                    [id],
+                    |row| person_at(row, 0),
+                )
+                .optional()
+                .map_err(|e| DaoError::UpdateError(e.to_string()))?;
+            let Some(old) = old else {
+                return Err(DaoError::UpdateError(format!("person not found: {id}")));
+            };
+            if old.revision != revision {
+                warn!(
+                    "revision conflict on person {}: expected={}, actual={}",
+                    id, revision, old.revision
+                );
+                return Err(DaoError::RevisionConflict {
+                    expected: revision,
+                    actual: old.revision,
+                });
+            }
+
+            tx.execute(
+                r#"UPDATE person
+                      SET name = ?1,
+                          birth_date = ?2,
+                          death_date = ?3,
+                          data = ?4,
+                          revision = ?5
+                    WHERE id = ?6"#,
+                params![
+                    person.name,
+                    person.birth_date,
+                    person.death_date,
+                    person.data.as_ref().map(|d| d.as_bytes()),
+                    person.revision,
+                    id,
+                ],
+            )
+            .map_err(|e| DaoError::UpdateError(e.to_string()))?;
+
+            record(
+                tx,
+                id,
+                ChangeKind::Update,
+                Some(&old),
+                Some(&person),
+                &actor,
+                reason.as_deref(),
+            )
+            .map_err(DaoError::UpdateError)
+        })
+    }
+}
+// query と search は既定の実装 (select して絞り込む) のまま
+impl PersonDao<Connection> for SqlitePersonDao {
+    fn insert(
+        &self,
+        person: PersonDto,
+    ) -> impl tx_rs::Tx<Connection, Item = PersonId, Err = DaoError> {
+        trace!("inserting person: {:?}", person);
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |tx: &mut Connection| {
+            let id = tx
+                .query_row(
+                    r#"INSERT INTO person ( name
+                                          , birth_date
+                                          , death_date
+                                          , data
+                                          , revision
+                                          )
+                       VALUES (?1, ?2, ?3, ?4, ?5)
+                    RETURNING id"#,
+                    params![
+                        person.name,
+                        person.birth_date,
+                        person.death_date,
+                        person.data.as_ref().map(|d| d.as_bytes()),
+                        person.revision,
+                    ],
+                    |row| row.get::<usize, PersonId>(0),
+                )
+                .map_err(|e| DaoError::InsertError(e.to_string()))?;
+
+            record(
+                tx,
+                id,
+                ChangeKind::Insert,
+                None,
+                Some(&person),
+                &actor,
+                None,
+            )
+            .map_err(DaoError::InsertError)?;
+
+            Ok(id)
+        })
+    }
+    fn fetch(
+        &self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<Connection, Item = Option<PersonDto>, Err = DaoError> {
+        trace!("fetching person: {:?}", id);
+        tx_rs::with_tx(move |tx: &mut Connection| {
+            tx.query_row(
+                r#"SELECT name,
+                          birth_date,
+                          death_date,
+                          data,
+                          revision
+                     FROM person
+                    WHERE id = ?1
+                      AND deleted_at IS NULL"#,
+                [id],
+                |row| person_at(row, 0),
+            )
+            .optional()
+            .map_err(|e| DaoError::SelectError(e.to_string()))
+        })
+    }
+    fn select(
+        &self,
+    ) -> impl tx_rs::Tx<Connection, Item = Vec<(PersonId, PersonDto)>, Err = DaoError> {
+        trace!("selecting all persons");
+        tx_rs::with_tx(|tx: &mut Connection| {
+            let mut stmt = tx
+                .prepare(
+                    r#"SELECT id,
+                              name,
+                              birth_date,
+                              death_date,
+                              data,
+                              revision
+                         FROM person
+                        WHERE deleted_at IS NULL
+                     ORDER BY id"#,
+                )
+                .map_err(|e| DaoError::SelectError(e.to_string()))?;
+            let rows = stmt
+                .query_map([], to_person)
+                .map_err(|e| DaoError::SelectError(e.to_string()))?;
+
+            rows.collect::<Result<Vec<_>, _>>()
+                .map_err(|e| DaoError::SelectError(e.to_string()))
+        })
+    }
+    fn save(
+        &self,
+        id: PersonId,
+        revision: Revision,
+        person: PersonDto,
+    ) -> impl tx_rs::Tx<Connection, Item = (), Err = DaoError> {
+        trace!("saving person: {:?}", id);
+        self.save_with(id, revision, person, None)
+    }
+    fn correct(
+        &self,
+        id: PersonId,
+        revision: Revision,
+        person: PersonDto,
+        reason: String,
+    ) -> impl tx_rs::Tx<Connection, Item = (), Err = DaoError> {
+        trace!("correcting person: {:?}", id);
+        self.save_with(id, revision, person, Some(reason))
+    }
+    fn delete(
+        &self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<Connection, Item = Option<Revision>, Err = DaoError> {
+        trace!("deleting person: {:?}", id);
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |tx: &mut Connection| {
+            let deleted = tx
+                .query_row(
+                    r#"UPDATE person
+                          SET deleted_at = ?2,
+                              revision = revision + 1
+                        WHERE id = ?1
+                          AND deleted_at IS NULL
+                    RETURNING name, birth_date, death_date, data, revision"#,
+                    params![id, Utc::now()],
+                    |row| person_at(row, 0),
+                )
+                .optional()
+                .map_err(|e| DaoError::DeleteError(e.to_string()))?;
+
+            match deleted {
+                Some(new) => {
+                    let revision = new.revision;
+                    record_toggle(tx, id, ChangeKind::Delete, new, &actor)
+                        .map(|_| Some(revision))
+                        .map_err(DaoError::DeleteError)
+                }
+                None => Ok(None),
+            }
+        })
+    }
+    fn restore(
+        &self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<Connection, Item = Option<Revision>, Err = DaoError> {
+        trace!("restoring person: {:?}", id);
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |tx: &mut Connection| {
+            let restored = tx
+                .query_row(
+                    r#"UPDATE person
+                          SET deleted_at = NULL,
+                              revision = revision + 1
+                        WHERE id = ?1
+                          AND deleted_at IS NOT NULL
+                    RETURNING name, birth_date, death_date, data, revision"#,
+                    [id],
+                    |row| person_at(row, 0),
+                )
+                .optional()
+                .map_err(|e| DaoError::RestoreError(e.to_string()))?;
+
+            match restored {
+                Some(new) => {
+                    let revision = new.revision;
+                    record_toggle(tx, id, ChangeKind::Restore, new, &actor)
+                        .map(|_| Some(revision))
+                        .map_err(DaoError::RestoreError)
+                }
+                None => Ok(None),
+            }
+        })
+    }
+    fn purge(
+        &self,
+        before: DateTime<Utc>,
+    ) -> impl tx_rs::Tx<Connection, Item = u64, Err = DaoError> {
+        trace!("purging persons deleted before: {:?}", before);
+        let actor = self.actor.clone();
+        tx_rs::with_tx(move |tx: &mut Connection| {
+            // deleted_at は同じ書式の UTC の文字列なので, 文字列の比較で前後が決まる
+            let purged = {
+                let mut stmt = tx
+                    .prepare(
+                        r#"DELETE FROM person
+                            WHERE deleted_at < ?1
+                        RETURNING id, name, birth_date, death_date, data, revision"#,
+                    )
+                    .map_err(|e| DaoError::PurgeError(e.to_string()))?;
+                let rows = stmt
+                    .query_map([before], to_person)
+                    .map_err(|e| DaoError::PurgeError(e.to_string()))?;
+                rows.collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| DaoError::PurgeError(e.to_string()))?
+            };
+
+            for (id, person) in &purged {
+                record(tx, *id, ChangeKind::Purge, Some(person), None, &actor, None)
+                    .map_err(DaoError::PurgeError)?;
+            }
+
+            Ok(purged.len() as u64)
+        })
+    }
+    fn history(
+        &self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<Connection, Item = Vec<PersonHistoryDto>, Err = DaoError> {
+        trace!("fetching history of person: {:?}", id);
+        tx_rs::with_tx(move |tx: &mut Connection| {
+            let mut stmt = tx
+                .prepare(
+                    r#"SELECT person_id,
+                              revision,
+                              kind,
+                              old_value,
+                              new_value,
+                              changed_at,
+                              actor,
+                              reason
+                         FROM person_history
+                        WHERE person_id = ?1
+                     ORDER BY id"#,
+                )
+                .map_err(|e| DaoError::SelectError(e.to_string()))?;
+            let rows = stmt
+                .query_map([id], |row| Ok(to_history(row)))
+                .map_err(|e| DaoError::SelectError(e.to_string()))?;
+
+            rows.map(|row| {
+                row.map_err(|e| e.to_string())
+                    .and_then(|h| h)
+                    .map_err(DaoError::SelectError)
+            })
+            .collect()
+        })
+    }
+}
+
+// name, birth_date, death_date, data, revision の順に並んだ列を start から読む
+fn person_at(row: &Row, start: usize) -> rusqlite::Result<PersonDto> {
+    let name = row.get::<usize, String>(start)?;
+    let birth_date = row.get::<usize, NaiveDate>(start + 1)?;
+    let death_date = row.get::<usize, Option<NaiveDate>>(start + 2)?;
+    let data = row.get::<usize, Option<Vec<u8>>>(start + 3)?;
+    let revision = row.get::<usize, Revision>(start + 4)?;
+
+    Ok(PersonDto::new(
+        &name,
+        birth_date,
+        death_date,
+        data.as_deref().and_then(|d| str::from_utf8(d).ok()),
+        revision,
+    ))
+}
+
+fn to_person(row: &Row) -> rusqlite::Result<(PersonId, PersonDto)> {
+    Ok((row.get::<usize, PersonId>(0)?, person_at(row, 1)?))
+}
+
+fn to_history(row: &Row) -> Result<PersonHistoryDto, String> {
+    let value = |i: usize| -> Result<Option<PersonDto>, String> {
+        row.get::<usize, Option<String>>(i)
+            .map_err(|e| e.to_string())?
+            .map(|v| serde_json::from_str(&v))
+            .transpose()
+            .map_err(|e| e.to_string())
+    };
+    let get_err = |e: rusqlite::Error| e.to_string();
+
+    Ok(PersonHistoryDto {
+        person_id: row.get::<usize, PersonId>(0).map_err(get_err)?,
+        revision: row.get::<usize, Revision>(1).map_err(get_err)?,
+        kind: row.get::<usize, String>(2).map_err(get_err)?.parse()?,
+        old: value(3)?,
+        new: value(4)?,
+        changed_at: row.get::<usize, DateTime<Utc>>(5).map_err(get_err)?,
+        actor: row.get::<usize, String>(6).map_err(get_err)?,
+        reason: row.get::<usize, Option<String>>(7).map_err(get_err)?,
+    })
+}
+
+// 履歴は追記のみ. 元の書き込みと同じトランザクションで書くので, どちらかだけが残ることはない
+fn record(
+    tx: &Connection,
+    id: PersonId,
+    kind: ChangeKind,
+    old: Option<&PersonDto>,
+    new: Option<&PersonDto>,
+    actor: &str,
+    reason: Option<&str>,
+) -> Result<(), String> {
+    let revision = new.or(old).map(|p| p.revision).unwrap_or_default();
+    let to_json = |p: Option<&PersonDto>| p.map(serde_json::to_string).transpose();
+    let (old, new) = (
+        to_json(old).map_err(|e| e.to_string())?,
+        to_json(new).map_err(|e| e.to_string())?,
+    );
+
+    tx.execute(
+        r#"INSERT INTO person_history ( person_id
+                                      , revision
+                                      , kind
+                                      , old_value
+                                      , new_value
+                                      , changed_at
+                                      , actor
+                                      , reason
+                                      )
+           VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"#,
+        params![
+            id,
+            revision,
+            kind.to_string(),
+            old,
+            new,
+            Utc::now(),
+            actor,
+            reason
+        ],
+    )
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+// 削除と復元は deleted_at と版だけが変わるので, 変更前は版を戻したもの
+fn record_toggle(
+    tx: &Connection,
+    id: PersonId,
+    kind: ChangeKind,
+    new: PersonDto,
+    actor: &str,
+) -> Result<(), String> {
+    let old = PersonDto {
+        revision: new.revision - 1,
+        ..new.clone()
+    };
+
+    record(tx, id, kind, Some(&old), Some(&new), actor, None)
+}
+
+// # 結合テスト
+//
+// ## 目的
+//
+//   SqlitePersonDao が PgPersonDao と同じ振る舞いをすることを保障する
+//
+// ## 方針
+//
+//   メモリ上の SQLite に対して実際に SQL を実行し, 結果と履歴を確認する
+//
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::domain::date;
+    use tx_rs::Tx;
+
+    fn db() -> rusqlite::Connection {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        create_tables(&conn).unwrap();
+        conn
+    }
+
+    fn run<T>(
+        conn: &mut rusqlite::Connection,
+        tx: impl FnOnce(&mut Connection) -> Result<T, DaoError>,
+    ) -> Result<T, DaoError> {
+        conn.execute_batch("BEGIN").unwrap();
+        let res = tx(conn);
+        conn.execute_batch("COMMIT").unwrap();
+        res
+    }
+
+    #[test]
+    fn test_insert_and_fetch() {
+        let mut conn = db();
+        let dao = SqlitePersonDao::new("test");
+        let person = PersonDto::new(
+            "Abel",
+            date(1802, 8, 5),
+            Some(date(1829, 4, 6)),
+            Some("Abel's theorem"),
+            0,
+        );
+
+        let id = run(&mut conn, |tx| dao.insert(person.clone()).run(tx)).unwrap();
+        assert_eq!(id, 1);
+        let id2 = run(&mut conn, |tx| {
+            dao.insert(PersonDto::new("Galois", date(1811, 10, 25), None, None, 0))
+                .run(tx)
+        })
+        .unwrap();
+        assert_eq!(id2, 2);
+
+        assert_eq!(
+            run(&mut conn, |tx| dao.fetch(id).run(tx)),
+            Ok(Some(person.clone()))
+        );
+        assert_eq!(run(&mut conn, |tx| dao.fetch(99).run(tx)), Ok(None));
+        let all = run(&mut conn, |tx| dao.select().run(tx)).unwrap();
+        assert_eq!(all.iter().map(|(id, _)| *id).collect::<Vec<_>>(), [1, 2]);
+    }
+
+    #[test]
+    fn test_save() {
+        let mut conn = db();
+        let dao = SqlitePersonDao::new("test");
+        let person = PersonDto::new("Abel", date(1802, 8, 5), None, None, 0);
+        let id = run(&mut conn, |tx| dao.insert(person.clone()).run(tx)).unwrap();
+
+        let dead = PersonDto {
+            death_date: Some(date(1829, 4, 6)),
+            revision: 1,
+            ..person
+        };
+        assert_eq!(
+            run(&mut conn, |tx| dao.save(id, 0, dead.clone()).run(tx)),
+            Ok(())
+        );
+        assert_eq!(
+            run(&mut conn, |tx| dao.fetch(id).run(tx)),
+            Ok(Some(dead.clone()))
+        );
+
+        // 古い版に基づく保存は衝突する
+        assert_eq!(
+            run(&mut conn, |tx| dao.save(id, 0, dead.clone()).run(tx)),
+            Err(DaoError::RevisionConflict {
+                expected: 0,
+                actual: 1
+            })
+        );
+        assert!(matches!(
+            run(&mut conn, |tx| dao.save(99, 0, dead).run(tx)),
+            Err(DaoError::UpdateError(_))
+        ));
+    }
+
+    #[test]
+    fn test_correct() {
+        let mut conn = db();
+        let dao = SqlitePersonDao::new("test");
+        let person = PersonDto::new("Abel", date(1802, 8, 5), None, None, 0);
+        let id = run(&mut conn, |tx| dao.insert(person.clone()).run(tx)).unwrap();
+
+        let dead = PersonDto {
+            death_date: Some(date(1829, 4, 6)),
+            revision: 1,
+            ..person
+        };
+        let reason = "found the death certificate".to_string();
+        assert_eq!(
+            run(&mut conn, |tx| dao
+                .correct(id, 0, dead.clone(), reason)
+                .run(tx)),
+            Ok(())
+        );
+
+        let history = run(&mut conn, |tx| dao.history(id).run(tx)).unwrap();
+        let reasons = history
+            .iter()
+            .map(|h| h.reason.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(reasons, [None, Some("found the death certificate")]);
+        assert_eq!(history[1].new, Some(dead));
+    }
+
+    #[test]
+    fn test_create_tables_adds_reason() {
+        // reason を足す前に作られた履歴の表
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch(&SCHEMA.replace(",\n  reason       TEXT\n", "\n"))
+            .unwrap();
+        let has_reason = |conn: &rusqlite::Connection| {
+            conn.prepare("SELECT 1 FROM pragma_table_info('person_history') WHERE name = 'reason'")
+                .unwrap()
+                .exists([])
+                .unwrap()
+        };
+        assert!(!has_reason(&conn));
+
+        create_tables(&conn).unwrap();
+        assert!(has_reason(&conn));
+        // 何度呼んでもよい
+        create_tables(&conn).unwrap();
+    }
+
+    #[test]
+    fn test_delete_restore_and_purge() {
+        let mut conn = db();
+        let dao = SqlitePersonDao::new("test");
+        let person = PersonDto::new("Abel", date(1802, 8, 5), None, None, 0);
+        let id = run(&mut conn, |tx| dao.insert(person.clone()).run(tx)).unwrap();
+
+        run(&mut conn, |tx| dao.delete(id).run(tx)).unwrap();
+        assert_eq!(run(&mut conn, |tx| dao.fetch(id).run(tx)), Ok(None));
+        // 削除済みなら何もしない
+        run(&mut conn, |tx| dao.delete(id).run(tx)).unwrap();
+
+        assert_eq!(run(&mut conn, |tx| dao.restore(id).run(tx)), Ok(Some(2)));
+        // 削除されていなければ何もしない
+        assert_eq!(run(&mut conn, |tx| dao.restore(id).run(tx)), Ok(None));
+        assert_eq!(
+            run(&mut conn, |tx| dao.fetch(id).run(tx)),
+            Ok(Some(PersonDto {
+                revision: 2,
+                ..person.clone()
+            }))
+        );
+
+        run(&mut conn, |tx| dao.delete(id).run(tx)).unwrap();
+        let before = Utc::now() + Duration::seconds(1);
+        assert_eq!(run(&mut conn, |tx| dao.purge(before).run(tx)), Ok(1));
+        assert_eq!(run(&mut conn, |tx| dao.purge(before).run(tx)), Ok(0));
+
+        let kinds = run(&mut conn, |tx| dao.history(id).run(tx))
+            .unwrap()
+            .into_iter()
+            .map(|h| (h.kind, h.revision))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                (ChangeKind::Insert, 0),
+                (ChangeKind::Delete, 1),
+                (ChangeKind::Restore, 2),
+                (ChangeKind::Delete, 3),
+                (ChangeKind::Purge, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_history_is_append_only() {
+        let mut conn = db();
+        let dao = SqlitePersonDao::new("test");
+        let person = PersonDto::new("Abel", date(1802, 8, 5), None, None, 0);
+        let id = run(&mut conn, |tx| dao.insert(person.clone()).run(tx)).unwrap();
+
+        conn.execute("UPDATE person_history SET actor = 'someone'", [])
+            .unwrap();
+        conn.execute("DELETE FROM person_history", []).unwrap();
+
+        let history = run(&mut conn, |tx| dao.history(id).run(tx)).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].actor, "test");
+        assert_eq!(history[0].new, Some(person));
+    }
+}
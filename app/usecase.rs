@@ -1,11 +1,11 @@
-use chrono::NaiveDate;
+use chrono::{DateTime, NaiveDate, Utc};
 use log::{trace, warn};
 use thiserror::Error;
 use tx_rs::Tx;
 
-use crate::dao::{DaoError, HavePersonDao, PersonDao};
-use crate::domain::{Person, PersonDomainError, PersonId};
-use crate::dto::PersonDto;
+use crate::dao::{DaoError, HavePersonDao, PersonDao, PersonQuery, PersonSearch};
+use crate::domain::{Person, PersonDomainError, PersonId, Revision};
+use crate::dto::{self, PersonDto, PersonHistoryDto, PersonPatch};
 
 #[derive(Error, Debug, Clone, PartialEq, Eq)]
 pub enum UsecaseError {
@@ -21,9 +21,44 @@ pub enum UsecaseError {
     SavePersonFailed(DaoError),
     #[error("remove person failed: {0}")]
     RemovePersonFailed(DaoError),
+    #[error("restore person failed: {0}")]
+    RestorePersonFailed(DaoError),
+    #[error("purge persons failed: {0}")]
+    PurgePersonFailed(DaoError),
+    #[error("enqueue notification failed: {0}")]
+    EnqueueNotificationFailed(DaoError),
     #[error("remove person failed: {0}")]
     DomainObjectChangeFailed(PersonDomainError),
+    #[error("revision conflict: expected={expected}, actual={actual}")]
+    RevisionConflict {
+        expected: Revision,
+        actual: Revision,
+    },
+    #[error("person not found: {0}")]
+    PersonNotFound(PersonId),
+    #[error("invalid input: {0}")]
+    InvalidInput(String),
 }
+impl UsecaseError {
+    /// save の失敗のうち楽観ロックの競合だけは区別して返す
+    pub(crate) fn save_failed(e: DaoError) -> Self {
+        match e {
+            DaoError::RevisionConflict { expected, actual } => {
+                UsecaseError::RevisionConflict { expected, actual }
+            }
+            e => UsecaseError::SavePersonFailed(e),
+        }
+    }
+}
+// ドメインの不変条件を満たさないものは DB に入れない
+fn validate<Ctx>(person: PersonDto) -> impl tx_rs::Tx<Ctx, Item = PersonDto, Err = UsecaseError> {
+    let result = Person::try_from(person)
+        .map(PersonDto::from)
+        .map_err(UsecaseError::DomainObjectChangeFailed);
+
+    tx_rs::with_tx(move |_: &mut Ctx| result)
+}
+
 pub trait PersonUsecase<Ctx>: HavePersonDao<Ctx> {
     fn entry<'a>(
         &'a mut self,
@@ -34,7 +69,7 @@ pub trait PersonUsecase<Ctx>: HavePersonDao<Ctx> {
     {
         let dao = self.get_dao();
         trace!("entry person: {:?}", person);
-        dao.insert(person).map_err(UsecaseError::EntryPersonFailed)
+        validate(person).and_then(move |p| dao.insert(p).map_err(UsecaseError::EntryPersonFailed))
     }
     fn find<'a>(
         &'a mut self,
@@ -56,18 +91,20 @@ pub trait PersonUsecase<Ctx>: HavePersonDao<Ctx> {
     {
         let dao = self.get_dao();
         trace!("entry and verify person: {:?}", person);
-        dao.insert(person)
-            .and_then(move |id| {
-                dao.fetch(id).try_map(move |person| {
-                    if let Some(p) = person {
-                        return Ok((id, p));
-                    }
-
-                    warn!("can't find the person just entried: {}", id);
-                    Err(DaoError::SelectError(format!("not found: {id}")))
+        validate(person).and_then(move |p| {
+            dao.insert(p)
+                .and_then(move |id| {
+                    dao.fetch(id).try_map(move |person| {
+                        if let Some(p) = person {
+                            return Ok((id, p));
+                        }
+
+                        warn!("can't find the person just entried: {}", id);
+                        Err(DaoError::SelectError(format!("not found: {id}")))
+                    })
                 })
-            })
-            .map_err(UsecaseError::EntryAndVerifyPersonFailed)
+                .map_err(UsecaseError::EntryAndVerifyPersonFailed)
+        })
     }
     fn collect<'a>(
         &'a mut self,
@@ -79,11 +116,34 @@ pub trait PersonUsecase<Ctx>: HavePersonDao<Ctx> {
         trace!("collect all persons");
         dao.select().map_err(UsecaseError::CollectPersonFailed)
     }
+    fn query<'a>(
+        &'a mut self,
+        query: PersonQuery,
+    ) -> impl tx_rs::Tx<Ctx, Item = Vec<(PersonId, PersonDto)>, Err = UsecaseError>
+    where
+        Ctx: 'a,
+    {
+        let dao = self.get_dao();
+        trace!("query persons: {:?}", query);
+        dao.query(query).map_err(UsecaseError::CollectPersonFailed)
+    }
+    fn search<'a>(
+        &'a mut self,
+        search: PersonSearch,
+    ) -> impl tx_rs::Tx<Ctx, Item = Vec<(PersonId, PersonDto)>, Err = UsecaseError>
+    where
+        Ctx: 'a,
+    {
+        let dao = self.get_dao();
+        trace!("search persons: {:?}", search);
+        dao.search(search)
+            .map_err(UsecaseError::CollectPersonFailed)
+    }
     fn death<'a>(
         &'a mut self,
         id: PersonId,
         date: NaiveDate,
-    ) -> impl tx_rs::Tx<Ctx, Item = (), Err = UsecaseError>
+    ) -> impl tx_rs::Tx<Ctx, Item = PersonDto, Err = UsecaseError>
     where
         Ctx: 'a,
     {
@@ -94,7 +154,8 @@ pub trait PersonUsecase<Ctx>: HavePersonDao<Ctx> {
             .try_map(move |p| {
                 if let Some(person) = p {
                     trace!("found person (id={}): {:?}", id, person);
-                    let mut p: Person = person.into();
+                    let mut p =
+                        Person::try_from(person).map_err(UsecaseError::DomainObjectChangeFailed)?;
                     return p
                         .dead_at(date)
                         .map(|_| p.into())
@@ -102,20 +163,109 @@ pub trait PersonUsecase<Ctx>: HavePersonDao<Ctx> {
                 }
 
                 warn!("can't find the person to dead: {}", id);
-                Err(UsecaseError::FindPersonFailed(DaoError::SelectError(
-                    format!("person not found: {id}"),
-                )))
+                Err(UsecaseError::PersonNotFound(id))
             })
             .and_then(move |mut p: PersonDto| {
                 trace!("save dead person (id={}): {:?}", id, p);
                 // 最新版の管理はユースケースの責務
                 let orig_revision = p.revision;
                 p.revision += 1;
-                dao.save(id, orig_revision, p)
-                    .map_err(UsecaseError::SavePersonFailed)
+                dao.save(id, orig_revision, p.clone())
+                    .map(move |_| p)
+                    .map_err(UsecaseError::save_failed)
+            })
+    }
+    fn correct_death<'a>(
+        &'a mut self,
+        id: PersonId,
+        date: Option<NaiveDate>,
+        reason: String,
+    ) -> impl tx_rs::Tx<Ctx, Item = PersonDto, Err = UsecaseError>
+    where
+        Ctx: 'a,
+    {
+        let dao = self.get_dao();
+        trace!(
+            "correct death person: id={} date={:?} reason={}",
+            id,
+            date,
+            reason
+        );
+        let recorded = reason.clone();
+        dao.fetch(id)
+            .map_err(UsecaseError::FindPersonFailed)
+            .try_map(move |p| {
+                let Some(person) = p else {
+                    warn!("can't find the person to correct death: {}", id);
+                    return Err(UsecaseError::PersonNotFound(id));
+                };
+                trace!("found person (id={}): {:?}", id, person);
+                let mut p =
+                    Person::try_from(person).map_err(UsecaseError::DomainObjectChangeFailed)?;
+                p.correct_death(date, &reason)
+                    .map_err(UsecaseError::DomainObjectChangeFailed)?;
+
+                Ok(p.into())
+            })
+            .and_then(move |mut p: PersonDto| {
+                trace!("save corrected person (id={}): {:?}", id, p);
+                // 最新版の管理はユースケースの責務
+                let orig_revision = p.revision;
+                p.revision += 1;
+                dao.correct(id, orig_revision, p.clone(), recorded)
+                    .map(move |_| p)
+                    .map_err(UsecaseError::save_failed)
+            })
+    }
+    fn update<'a>(
+        &'a mut self,
+        id: PersonId,
+        patch: PersonPatch,
+    ) -> impl tx_rs::Tx<Ctx, Item = PersonDto, Err = UsecaseError>
+    where
+        Ctx: 'a,
+    {
+        let dao = self.get_dao();
+        trace!("update person: id={} patch={:?}", id, patch);
+        dao.fetch(id)
+            .map_err(UsecaseError::FindPersonFailed)
+            .try_map(move |p| {
+                let Some(person) = p else {
+                    warn!("can't find the person to update: {}", id);
+                    return Err(UsecaseError::PersonNotFound(id));
+                };
+                trace!("found person (id={}): {:?}", id, person);
+                let mut p =
+                    Person::try_from(person).map_err(UsecaseError::DomainObjectChangeFailed)?;
+                if let Some(name) = &patch.name {
+                    p.rename(name)
+                        .map_err(UsecaseError::DomainObjectChangeFailed)?;
+                }
+                if let Some(birth_date) = patch.birth_date {
+                    p.change_birth_date(birth_date)
+                        .map_err(UsecaseError::DomainObjectChangeFailed)?;
+                }
+                if let Some(data) = &patch.data {
+                    p.change_data(data.as_deref());
+                }
+
+                Ok(p.into())
+            })
+            .and_then(move |mut p: PersonDto| {
+                trace!("save updated person (id={}): {:?}", id, p);
+                // 最新版の管理はユースケースの責務
+                let orig_revision = p.revision;
+                p.revision += 1;
+                dao.save(id, orig_revision, p.clone())
+                    .map(move |_| p)
+                    .map_err(UsecaseError::save_failed)
             })
     }
-    fn remove<'a>(&'a mut self, id: PersonId) -> impl tx_rs::Tx<Ctx, Item = (), Err = UsecaseError>
+    /// soft delete the person, and return its new revision. None if it is not alive.
+    fn remove<'a>(
+        &'a mut self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<Ctx, Item = Option<Revision>, Err = UsecaseError>
     where
         Ctx: 'a,
     {
@@ -123,6 +273,68 @@ pub trait PersonUsecase<Ctx>: HavePersonDao<Ctx> {
         trace!("remove person_id: {:?}", id);
         dao.delete(id).map_err(UsecaseError::RemovePersonFailed)
     }
+    fn restore<'a>(
+        &'a mut self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<Ctx, Item = PersonDto, Err = UsecaseError>
+    where
+        Ctx: 'a,
+    {
+        let dao = self.get_dao();
+        trace!("restore person_id: {:?}", id);
+        dao.restore(id)
+            .map_err(UsecaseError::RestorePersonFailed)
+            .try_map(move |revision| {
+                // 削除されていなければ戻すものがない
+                revision.ok_or_else(|| {
+                    warn!("no deleted person to restore: {}", id);
+                    UsecaseError::PersonNotFound(id)
+                })
+            })
+            .and_then(move |_| dao.fetch(id).map_err(UsecaseError::FindPersonFailed))
+            .try_map(move |p| {
+                p.ok_or_else(|| {
+                    warn!("can't find the person to restore: {}", id);
+                    UsecaseError::PersonNotFound(id)
+                })
+            })
+    }
+    fn purge<'a>(
+        &'a mut self,
+        before: DateTime<Utc>,
+    ) -> impl tx_rs::Tx<Ctx, Item = u64, Err = UsecaseError>
+    where
+        Ctx: 'a,
+    {
+        let dao = self.get_dao();
+        trace!("purge persons deleted before: {:?}", before);
+        dao.purge(before).map_err(UsecaseError::PurgePersonFailed)
+    }
+    fn history<'a>(
+        &'a mut self,
+        id: PersonId,
+    ) -> impl tx_rs::Tx<Ctx, Item = Vec<PersonHistoryDto>, Err = UsecaseError>
+    where
+        Ctx: 'a,
+    {
+        let dao = self.get_dao();
+        trace!("history of person_id: {:?}", id);
+        dao.history(id).map_err(UsecaseError::FindPersonFailed)
+    }
+    fn find_as_of<'a>(
+        &'a mut self,
+        id: PersonId,
+        revision: Revision,
+    ) -> impl tx_rs::Tx<Ctx, Item = Option<PersonDto>, Err = UsecaseError>
+    where
+        Ctx: 'a,
+    {
+        let dao = self.get_dao();
+        trace!("find person_id: {:?} as of revision: {}", id, revision);
+        dao.history(id)
+            .map(move |history| dto::as_of(&history, revision))
+            .map_err(UsecaseError::FindPersonFailed)
+    }
 }
 
 // # フェイクテスト
@@ -135,8 +347,8 @@ pub trait PersonUsecase<Ctx>: HavePersonDao<Ctx> {
 // ## 方針
 //
 //   DAO のフェイクに対して Usecase を実行し、その結果を確認する
-//   フェイクはテスト時の比較チェックのしやすさを考慮して HashMap ではなく Vec で登録データを保持する
-//   データ数は多くないので、Vec でリニアサーチしても十分な速度が出ると考える
+//   フェイクにはインメモリのバックエンド (crate::memory) を使う
+//   比較しやすいように MemoryDb からは id 順で取り出す
 //
 // ## 実装
 //
@@ -155,7 +367,7 @@ pub trait PersonUsecase<Ctx>: HavePersonDao<Ctx> {
 //     +-- ここを確認する
 //
 //   1. DAO のメソッド呼び出しに対して、凡そ正常系で期待される結果を返す DAO 構造体を用意する
-//      この DAO 構造体はフェイクなので、間接的な入力と間接的な出力が整合するようにする
+//      MemoryPersonDao は MemoryDb 上で実際に動くので、間接的な入力と間接的な出力が整合する
 //   2. Usecase にそのフェイクをプラグインする
 //   3. Usecase のメソッドを呼び出す
 //   4. Usecase からの戻り値を検証する
@@ -166,78 +378,35 @@ pub trait PersonUsecase<Ctx>: HavePersonDao<Ctx> {
 //
 #[cfg(test)]
 mod fake_tests {
-    use std::cell::RefCell;
+    use chrono::Local;
 
     use super::*;
-    use crate::domain::{date, Revision};
-    use crate::dto::PersonDto;
+    use crate::dao::{LifeStatus, SortKey, SortOrder};
+    use crate::domain::date;
+    use crate::dto::{ChangeKind, PersonDto};
+    use crate::memory::{MemoryDb, MemoryPersonDao};
 
-    struct FakePersonDao {
-        next_id: RefCell<PersonId>,
-        data: RefCell<Vec<(PersonId, PersonDto)>>,
+    struct TargetPersonUsecase {
+        dao: MemoryPersonDao,
     }
-    // Ctx 不要なので () にしている
-    impl PersonDao<()> for FakePersonDao {
-        fn insert(&self, person: PersonDto) -> impl tx_rs::Tx<(), Item = PersonId, Err = DaoError> {
-            let id = self.next_id.replace_with(|&mut id| id + 1);
-            self.data.borrow_mut().push((id, person));
-
-            tx_rs::with_tx(move |()| Ok(id))
-        }
-        fn fetch(
-            &self,
-            id: PersonId,
-        ) -> impl tx_rs::Tx<(), Item = Option<PersonDto>, Err = DaoError> {
-            let data = self.data.borrow();
-            let result = data.iter().find(|(i, _)| *i == id).map(|(_, p)| p.clone());
-
-            tx_rs::with_tx(move |()| Ok(result))
-        }
-        fn select(&self) -> impl tx_rs::Tx<(), Item = Vec<(PersonId, PersonDto)>, Err = DaoError> {
-            let result = self.data.borrow().clone();
-
-            tx_rs::with_tx(move |()| Ok(result))
-        }
-        fn save(
-            &self,
-            id: PersonId,
-            revision: Revision,
-            person: PersonDto,
-        ) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
-            let result = self
-                .data
-                .borrow_mut()
-                .iter_mut()
-                .find(|(i, p)| *i == id && p.revision == revision)
-                .map(|(_, p)| *p = person)
-                .ok_or(DaoError::UpdateError(format!("person not found: {id}")));
-
-            tx_rs::with_tx(move |()| result)
-        }
-        fn delete(&self, id: PersonId) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
-            let _ = self.data.borrow_mut().retain(|(i, _)| *i != id);
-
-            tx_rs::with_tx(move |()| Ok(()))
+    impl TargetPersonUsecase {
+        fn new() -> Self {
+            Self {
+                dao: MemoryPersonDao::new("fake"),
+            }
         }
     }
-
-    struct TargetPersonUsecase {
-        dao: FakePersonDao,
-    }
-    impl HavePersonDao<()> for TargetPersonUsecase {
-        fn get_dao(&self) -> &impl PersonDao<()> {
+    impl HavePersonDao<MemoryDb> for TargetPersonUsecase {
+        fn get_dao(&self) -> &impl PersonDao<MemoryDb> {
             &self.dao
         }
     }
-    impl PersonUsecase<()> for TargetPersonUsecase {}
+    impl PersonUsecase<MemoryDb> for TargetPersonUsecase {}
 
     #[test]
     fn test_entry() {
-        let dao = FakePersonDao {
-            next_id: RefCell::new(42),
-            data: RefCell::new(vec![]),
-        };
-        let mut usecase = TargetPersonUsecase { dao };
+        let mut db = MemoryDb::with_persons(42, vec![]);
+        let mut usecase = TargetPersonUsecase::new();
 
         let person = PersonDto::new(
             "Alice",
@@ -246,19 +415,46 @@ mod fake_tests {
             Some("Alice wonderland"),
             0,
         );
-        let expected = person.clone().into();
+        let expected = person.clone();
         let expected_id = 42;
 
-        let result = usecase.entry(person).run(&mut ());
+        let result = usecase.entry(person).run(&mut db);
         assert_eq!(result, Ok(expected_id));
-        assert_eq!(usecase.dao.data.borrow().len(), 1);
-        assert_eq!(*usecase.dao.data.borrow(), vec![(expected_id, expected)]);
+        assert_eq!(db.persons().len(), 1);
+        assert_eq!(db.persons(), vec![(expected_id, expected)]);
+    }
+    #[test]
+    fn test_entry_invalid() {
+        let mut db = MemoryDb::with_persons(42, vec![]);
+        let mut usecase = TargetPersonUsecase::new();
+
+        // 死亡日が誕生日より前の person は登録されない
+        let person = PersonDto::new("Alice", date(2012, 11, 2), Some(date(2012, 11, 1)), None, 0);
+        let result = usecase.entry(person).run(&mut db);
+        assert!(matches!(
+            result,
+            Err(UsecaseError::DomainObjectChangeFailed(
+                PersonDomainError::InvalidFieldValue(..)
+            ))
+        ));
+
+        let person = PersonDto::new("", date(2012, 11, 2), None, None, 0);
+        let result = usecase.entry_and_verify(person).run(&mut db);
+        assert!(matches!(
+            result,
+            Err(UsecaseError::DomainObjectChangeFailed(
+                PersonDomainError::InvalidFieldValue(..)
+            ))
+        ));
+
+        assert!(db.persons().is_empty());
+        assert_eq!(db.next_id(), 42);
     }
     #[test]
     fn test_find() {
-        let dao = FakePersonDao {
-            next_id: RefCell::new(0), // 使わない
-            data: RefCell::new(vec![
+        let mut db = MemoryDb::with_persons(
+            0,
+            vec![
                 (
                     13,
                     PersonDto::new("Alice", date(2012, 11, 2), None, Some("Alice is sender"), 3),
@@ -277,11 +473,11 @@ mod fake_tests {
                         8,
                     ),
                 ),
-            ]),
-        };
-        let mut usecase = TargetPersonUsecase { dao };
+            ],
+        ); // 使わない
+        let mut usecase = TargetPersonUsecase::new();
 
-        let result = usecase.find(13).run(&mut ());
+        let result = usecase.find(13).run(&mut db);
         let expected = Some(PersonDto::new(
             "Alice",
             date(2012, 11, 2),
@@ -293,11 +489,8 @@ mod fake_tests {
     }
     #[test]
     fn test_entry_and_verify() {
-        let dao = FakePersonDao {
-            next_id: RefCell::new(13),
-            data: RefCell::new(vec![]),
-        };
-        let mut usecase = TargetPersonUsecase { dao };
+        let mut db = MemoryDb::with_persons(13, vec![]);
+        let mut usecase = TargetPersonUsecase::new();
 
         let person = PersonDto::new(
             "Alice",
@@ -308,7 +501,7 @@ mod fake_tests {
         );
         let expected = (13, person.clone());
 
-        let result = usecase.entry_and_verify(person).run(&mut ());
+        let result = usecase.entry_and_verify(person).run(&mut db);
         assert_eq!(result, Ok(expected));
     }
     #[test]
@@ -333,19 +526,12 @@ mod fake_tests {
                 ),
             ),
         ];
-        let expected = data
-            .clone()
-            .into_iter()
-            .map(|(id, p)| (id, p.into()))
-            .collect::<Vec<_>>();
+        let expected = data.clone();
 
-        let dao = FakePersonDao {
-            next_id: RefCell::new(0), // 使わない
-            data: RefCell::new(data),
-        };
-        let mut usecase = TargetPersonUsecase { dao };
+        let mut db = MemoryDb::with_persons(0, data); // 使わない
+        let mut usecase = TargetPersonUsecase::new();
 
-        let result = usecase.collect().run(&mut ());
+        let result = usecase.collect().run(&mut db);
         assert_eq!(
             result.map(|mut v: Vec<(PersonId, PersonDto)>| {
                 v.sort_by_key(|(id, _)| *id);
@@ -355,17 +541,168 @@ mod fake_tests {
         );
     }
     #[test]
+    fn test_query() {
+        let data = vec![
+            (
+                1,
+                PersonDto::new("Gauss", date(1777, 4, 30), Some(date(1855, 2, 23)), None, 0),
+            ),
+            (
+                2,
+                PersonDto::new("Galois", date(1811, 10, 25), None, None, 0),
+            ),
+            (3, PersonDto::new("Abel", date(1802, 8, 5), None, None, 0)),
+            (
+                4,
+                PersonDto::new(
+                    "Galileo",
+                    date(1564, 2, 15),
+                    Some(date(1642, 1, 8)),
+                    None,
+                    0,
+                ),
+            ),
+        ];
+        let mut db = MemoryDb::with_persons(0, data.clone()); // 使わない
+        let mut usecase = TargetPersonUsecase::new();
+
+        let query = PersonQuery {
+            name_prefix: Some("Ga".to_string()),
+            sort: SortKey::Name,
+            ..Default::default()
+        };
+        let result = usecase.query(query).run(&mut db);
+        assert_eq!(
+            result,
+            Ok(vec![data[3].clone(), data[1].clone(), data[0].clone()])
+        );
+
+        let query = PersonQuery {
+            status: Some(LifeStatus::Alive),
+            born_from: Some(date(1800, 1, 1)),
+            born_until: Some(date(1811, 10, 25)),
+            order: SortOrder::Desc,
+            ..Default::default()
+        };
+        let result = usecase.query(query).run(&mut db);
+        assert_eq!(result, Ok(vec![data[2].clone(), data[1].clone()]));
+    }
+    #[test]
+    fn test_query_pagination() {
+        let data = (1..=7)
+            .map(|id| {
+                // 名前が重複するものは id 順になる
+                let name = if id % 2 == 0 { "Euler" } else { "Bernoulli" };
+                (
+                    id,
+                    PersonDto::new(name, date(1700, 1, id as u32), None, None, 0),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut db = MemoryDb::with_persons(0, data); // 使わない
+        let mut usecase = TargetPersonUsecase::new();
+
+        let mut pages = vec![];
+        let mut after = None;
+        loop {
+            let query = PersonQuery {
+                sort: SortKey::Name,
+                after,
+                limit: Some(3),
+                ..Default::default()
+            };
+            let page = usecase
+                .query(query)
+                .run(&mut db)
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+            if page.is_empty() {
+                break;
+            }
+            after = page.last().copied();
+            pages.push(page);
+        }
+
+        assert_eq!(pages, vec![vec![1, 3, 5], vec![7, 2, 4], vec![6]]);
+    }
+    #[test]
+    fn test_search() {
+        let data = vec![
+            (
+                1,
+                PersonDto::new("Bernoulli", date(1700, 2, 8), None, None, 0),
+            ),
+            (
+                2,
+                PersonDto::new(
+                    "Euler",
+                    date(1707, 4, 15),
+                    None,
+                    Some("Euler's identity"),
+                    0,
+                ),
+            ),
+            (
+                3,
+                PersonDto::new("Leonhard Euler", date(1707, 4, 15), None, None, 0),
+            ),
+            (
+                4,
+                PersonDto::new("Eulerian", date(1800, 1, 1), None, None, 0),
+            ),
+            (
+                5,
+                PersonDto::new("Gauss", date(1777, 4, 30), None, Some("not euler"), 0),
+            ),
+            (6, PersonDto::new("Eular", date(1800, 1, 1), None, None, 0)),
+        ];
+        let mut db = MemoryDb::with_persons(0, data); // 使わない
+        let mut usecase = TargetPersonUsecase::new();
+
+        let ids = |result: Result<Vec<(PersonId, PersonDto)>, UsecaseError>| {
+            result.map(|v| v.into_iter().map(|(id, _)| id).collect::<Vec<_>>())
+        };
+
+        // 完全一致, 前方一致, 部分一致, 類似の順
+        let search = PersonSearch {
+            text: "EULER".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(usecase.search(search).run(&mut db)),
+            Ok(vec![2, 4, 3, 6])
+        );
+
+        let search = PersonSearch {
+            text: "euler".to_string(),
+            include_data: true,
+            limit: Some(5),
+        };
+        assert_eq!(
+            ids(usecase.search(search).run(&mut db)),
+            Ok(vec![2, 4, 3, 5, 6])
+        );
+
+        let search = PersonSearch {
+            text: "riemann".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(ids(usecase.search(search).run(&mut db)), Ok(vec![]));
+    }
+    #[test]
     fn test_death() {
-        let dao = FakePersonDao {
-            next_id: RefCell::new(0), // 使わない
-            data: RefCell::new(vec![(
+        let mut db = MemoryDb::with_persons(
+            0,
+            vec![(
                 13,
                 PersonDto::new("Alice", date(2012, 11, 2), None, Some("Alice is sender"), 0),
-            )]),
-        };
-        let mut usecase = TargetPersonUsecase { dao };
+            )],
+        ); // 使わない
+        let mut usecase = TargetPersonUsecase::new();
 
-        let result = usecase.death(13, date(2020, 12, 30)).run(&mut ());
+        let result = usecase.death(13, date(2020, 12, 30)).run(&mut db);
         let expected = vec![(
             13,
             PersonDto::new(
@@ -376,8 +713,144 @@ mod fake_tests {
                 1,
             ),
         )];
-        assert_eq!(result, Ok(()));
-        assert_eq!(*usecase.dao.data.borrow(), expected);
+        assert_eq!(result, Ok(expected[0].1.clone()));
+        assert_eq!(db.persons(), expected);
+    }
+    #[test]
+    fn test_update() {
+        let mut db = MemoryDb::with_persons(
+            0,
+            vec![(
+                3,
+                PersonDto::new(
+                    "Gauss",
+                    date(1777, 4, 30),
+                    Some(date(1855, 2, 23)),
+                    Some("prince of mathematicians"),
+                    1,
+                ),
+            )],
+        ); // 使わない
+        let mut usecase = TargetPersonUsecase::new();
+
+        let patch = PersonPatch {
+            name: Some(" Carl Friedrich Gauss ".to_string()),
+            birth_date: Some(date(1777, 4, 30)),
+            data: Some(None),
+        };
+        let expected = PersonDto::new(
+            "Carl Friedrich Gauss",
+            date(1777, 4, 30),
+            Some(date(1855, 2, 23)),
+            None,
+            2,
+        );
+        let result = usecase.update(3, patch).run(&mut db);
+        assert_eq!(result, Ok(expected.clone()));
+        assert_eq!(db.persons(), vec![(3, expected)]);
+
+        // 死亡日より後の誕生日には変更できない
+        let patch = PersonPatch {
+            birth_date: Some(date(1856, 1, 1)),
+            ..Default::default()
+        };
+        let result = usecase.update(3, patch).run(&mut db);
+        assert!(matches!(
+            result,
+            Err(UsecaseError::DomainObjectChangeFailed(
+                PersonDomainError::InvalidFieldValue(..)
+            ))
+        ));
+
+        let patch = PersonPatch {
+            name: Some("".to_string()),
+            ..Default::default()
+        };
+        let result = usecase.update(3, patch).run(&mut db);
+        assert!(matches!(
+            result,
+            Err(UsecaseError::DomainObjectChangeFailed(
+                PersonDomainError::InvalidFieldValue(..)
+            ))
+        ));
+        assert_eq!(db.persons()[0].1.revision, 2);
+
+        let result = usecase.update(4, PersonPatch::default()).run(&mut db);
+        assert_eq!(result, Err(UsecaseError::PersonNotFound(4)));
+
+        // 生きている人でも未来の誕生日には変更できない
+        let mut db = MemoryDb::with_persons(
+            0,
+            vec![(5, PersonDto::new("Abel", date(1802, 8, 5), None, None, 1))],
+        );
+        let patch = PersonPatch {
+            birth_date: Some(Local::now().date_naive().succ_opt().unwrap()),
+            ..Default::default()
+        };
+        let result = usecase.update(5, patch).run(&mut db);
+        assert_eq!(
+            result,
+            Err(UsecaseError::DomainObjectChangeFailed(
+                PersonDomainError::InvalidFieldValue(
+                    "birth_date".into(),
+                    "must not be in the future".into()
+                )
+            ))
+        );
+        assert_eq!(db.persons()[0].1.revision, 1);
+    }
+    #[test]
+    fn test_correct_death() {
+        let mut db = MemoryDb::with_persons(
+            0,
+            vec![(
+                3,
+                PersonDto::new("Gauss", date(1777, 4, 30), Some(date(1855, 2, 22)), None, 1),
+            )],
+        ); // 使わない
+        let mut usecase = TargetPersonUsecase::new();
+
+        // 死亡日の訂正
+        let expected = PersonDto::new("Gauss", date(1777, 4, 30), Some(date(1855, 2, 23)), None, 2);
+        let result = usecase
+            .correct_death(3, Some(date(1855, 2, 23)), "typo".to_string())
+            .run(&mut db);
+        assert_eq!(result, Ok(expected.clone()));
+        assert_eq!(db.persons(), vec![(3, expected)]);
+
+        // 理由なしでは訂正できない
+        let result = usecase.correct_death(3, None, " ".to_string()).run(&mut db);
+        assert!(matches!(
+            result,
+            Err(UsecaseError::DomainObjectChangeFailed(
+                PersonDomainError::InvalidFieldValue(..)
+            ))
+        ));
+
+        // 死亡日の取り消し
+        let expected = PersonDto::new("Gauss", date(1777, 4, 30), None, None, 3);
+        let result = usecase
+            .correct_death(3, None, "wrong person".to_string())
+            .run(&mut db);
+        assert_eq!(result, Ok(expected.clone()));
+        assert_eq!(db.persons(), vec![(3, expected)]);
+
+        // 生きている人は訂正できない
+        let result = usecase
+            .correct_death(3, None, "wrong person".to_string())
+            .run(&mut db);
+        assert_eq!(
+            result,
+            Err(UsecaseError::DomainObjectChangeFailed(
+                PersonDomainError::NotDead
+            ))
+        );
+        assert_eq!(db.persons()[0].1.revision, 3);
+
+        let result = usecase
+            .correct_death(4, None, "wrong person".to_string())
+            .run(&mut db);
+        assert_eq!(result, Err(UsecaseError::PersonNotFound(4)));
     }
     #[test]
     fn test_remove() {
@@ -402,13 +875,10 @@ mod fake_tests {
             ),
         ];
 
-        let dao = FakePersonDao {
-            next_id: RefCell::new(0), // 使わない
-            data: RefCell::new(data),
-        };
-        let mut usecase = TargetPersonUsecase { dao };
+        let mut db = MemoryDb::with_persons(0, data); // 使わない
+        let mut usecase = TargetPersonUsecase::new();
 
-        let result = usecase.remove(24).run(&mut ());
+        let result = usecase.remove(24).run(&mut db);
         let expected = vec![
             (
                 13,
@@ -425,8 +895,119 @@ mod fake_tests {
                 ),
             ),
         ];
-        assert_eq!(result, Ok(()));
-        assert_eq!(*usecase.dao.data.borrow(), expected);
+        assert_eq!(result, Ok(Some(2)));
+        assert_eq!(db.persons(), expected);
+        // 論理削除なので版を上げて残っている
+        assert_eq!(db.deleted().len(), 1);
+        assert_eq!(db.deleted()[0].1.revision, 2);
+        assert_eq!(usecase.find(24).run(&mut db), Ok(None));
+        // 削除済みなら何も起きない
+        assert_eq!(usecase.remove(24).run(&mut db), Ok(None));
+    }
+    #[test]
+    fn test_restore() {
+        let mut db = MemoryDb::with_persons(
+            0,
+            vec![(
+                24,
+                PersonDto::new("Bob", date(1995, 11, 6), None, Some("Bob is receiver"), 1),
+            )],
+        ); // 使わない
+        let mut usecase = TargetPersonUsecase::new();
+
+        let _ = usecase.remove(24).run(&mut db);
+        let result = usecase.restore(24).run(&mut db);
+
+        let expected = PersonDto::new("Bob", date(1995, 11, 6), None, Some("Bob is receiver"), 3);
+        assert_eq!(result, Ok(expected.clone()));
+        assert_eq!(db.persons(), vec![(24, expected.clone())]);
+        assert!(db.deleted().is_empty());
+
+        // 削除されていなければ戻すものがない
+        let result = usecase.restore(24).run(&mut db);
+        assert_eq!(result, Err(UsecaseError::PersonNotFound(24)));
+        assert_eq!(db.persons(), vec![(24, expected)]);
+
+        let result = usecase.restore(99).run(&mut db);
+        assert_eq!(result, Err(UsecaseError::PersonNotFound(99)));
+    }
+    #[test]
+    fn test_purge() {
+        let bob = PersonDto::new("Bob", date(1995, 11, 6), None, None, 2);
+        let eve = PersonDto::new("Eve", date(1996, 12, 15), None, None, 8);
+        let now = Utc::now();
+        let mut db = MemoryDb::new().with_deleted(vec![
+            (24, bob, now - chrono::Duration::days(40)),
+            (99, eve.clone(), now - chrono::Duration::days(10)),
+        ]);
+        let mut usecase = TargetPersonUsecase::new();
+
+        let result = usecase.purge(now - chrono::Duration::days(30)).run(&mut db);
+
+        assert_eq!(result, Ok(1));
+        assert_eq!(db.deleted().len(), 1);
+        assert_eq!(db.deleted()[0].1, eve);
+        // 物理削除したものは戻せない
+        assert_eq!(
+            usecase.restore(24).run(&mut db),
+            Err(UsecaseError::PersonNotFound(24))
+        );
+    }
+    #[test]
+    fn test_history() {
+        let mut db = MemoryDb::new();
+        let mut usecase = TargetPersonUsecase::new();
+
+        let alice = PersonDto::new("Alice", date(2012, 11, 2), None, None, 0);
+        let id = usecase.entry(alice.clone()).run(&mut db).unwrap();
+        let _ = usecase.death(id, date(2100, 1, 1)).run(&mut db);
+        let _ = usecase.remove(id).run(&mut db);
+        let _ = usecase.restore(id).run(&mut db);
+
+        let result = usecase.history(id).run(&mut db).unwrap();
+        let kinds = result.iter().map(|h| h.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                ChangeKind::Insert,
+                ChangeKind::Update,
+                ChangeKind::Delete,
+                ChangeKind::Restore
+            ]
+        );
+        let revisions = result.iter().map(|h| h.revision).collect::<Vec<_>>();
+        assert_eq!(revisions, vec![0, 1, 2, 3]);
+        assert_eq!(result[0].old, None);
+        assert_eq!(result[0].new, Some(alice));
+
+        // 他の人の履歴は含まない
+        assert_eq!(usecase.history(99).run(&mut db), Ok(vec![]));
+    }
+    #[test]
+    fn test_find_as_of() {
+        let mut db = MemoryDb::new();
+        let mut usecase = TargetPersonUsecase::new();
+
+        let alice = PersonDto::new("Alice", date(2012, 11, 2), None, None, 0);
+        let id = usecase.entry(alice.clone()).run(&mut db).unwrap();
+        let _ = usecase.death(id, date(2100, 1, 1)).run(&mut db);
+        let _ = usecase.remove(id).run(&mut db);
+
+        assert_eq!(usecase.find_as_of(id, 0).run(&mut db), Ok(Some(alice)));
+        assert_eq!(
+            usecase.find_as_of(id, 1).run(&mut db),
+            Ok(Some(PersonDto::new(
+                "Alice",
+                date(2012, 11, 2),
+                Some(date(2100, 1, 1)),
+                None,
+                1
+            )))
+        );
+        // 削除された版
+        assert_eq!(usecase.find_as_of(id, 2).run(&mut db), Ok(None));
+        // 存在しない人
+        assert_eq!(usecase.find_as_of(99, 0).run(&mut db), Ok(None));
     }
 }
 
@@ -484,7 +1065,7 @@ mod spy_tests {
 
     use super::*;
     use crate::domain::{date, Revision};
-    use crate::dto::PersonDto;
+    use crate::dto::{PersonDto, PersonHistoryDto};
 
     struct SpyPersonDao {
         insert: RefCell<Vec<PersonDto>>,
@@ -494,6 +1075,9 @@ mod spy_tests {
         select: RefCell<i32>,
         save: RefCell<Vec<(PersonId, Revision, PersonDto)>>,
         delete: RefCell<Vec<PersonId>>,
+        restore: RefCell<Vec<PersonId>>,
+        purge: RefCell<Vec<DateTime<Utc>>>,
+        history: RefCell<Vec<PersonId>>,
     }
     // Ctx 不要なので () にしている
     impl PersonDao<()> for SpyPersonDao {
@@ -528,11 +1112,38 @@ mod spy_tests {
             // 返り値には意味なし
             tx_rs::with_tx(|()| Ok(()))
         }
-        fn delete(&self, id: PersonId) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
+        fn delete(
+            &self,
+            id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
             self.delete.borrow_mut().push(id);
 
             // 返り値には意味なし
-            tx_rs::with_tx(|()| Ok(()))
+            tx_rs::with_tx(|()| Ok(None))
+        }
+        fn restore(
+            &self,
+            id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
+            self.restore.borrow_mut().push(id);
+
+            // 戻したことにする. 返り値には意味なし
+            tx_rs::with_tx(|()| Ok(Some(1)))
+        }
+        fn purge(&self, before: DateTime<Utc>) -> impl tx_rs::Tx<(), Item = u64, Err = DaoError> {
+            self.purge.borrow_mut().push(before);
+
+            // 返り値には意味なし
+            tx_rs::with_tx(|()| Ok(0))
+        }
+        fn history(
+            &self,
+            id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Vec<PersonHistoryDto>, Err = DaoError> {
+            self.history.borrow_mut().push(id);
+
+            // 返り値には意味なし
+            tx_rs::with_tx(|()| Ok(vec![]))
         }
     }
 
@@ -556,11 +1167,14 @@ mod spy_tests {
             select: RefCell::new(0),
             save: RefCell::new(vec![]),
             delete: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
         };
         let mut usecase = TargetPersonUsecase { dao };
 
         let person = PersonDto::new("Alice", date(2012, 11, 2), None, None, 0);
-        let expected = person.clone().into();
+        let expected = person.clone();
 
         let _ = usecase.entry(person).run(&mut ()).unwrap();
 
@@ -585,6 +1199,9 @@ mod spy_tests {
             select: RefCell::new(0),
             save: RefCell::new(vec![]),
             delete: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
         };
         let mut usecase = TargetPersonUsecase { dao };
 
@@ -613,11 +1230,14 @@ mod spy_tests {
             select: RefCell::new(0),
             save: RefCell::new(vec![]),
             delete: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
         };
         let mut usecase = TargetPersonUsecase { dao };
 
         let person = PersonDto::new("Alice", date(2012, 11, 2), None, None, 0);
-        let expected = person.clone().into();
+        let expected = person.clone();
 
         let _ = usecase.entry_and_verify(person).run(&mut ());
 
@@ -644,6 +1264,9 @@ mod spy_tests {
             select: RefCell::new(0),
             save: RefCell::new(vec![]),
             delete: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
         };
         let mut usecase = TargetPersonUsecase { dao };
 
@@ -672,6 +1295,9 @@ mod spy_tests {
             select: RefCell::new(0),
             save: RefCell::new(vec![]),
             delete: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
         };
         let mut usecase = TargetPersonUsecase { dao };
 
@@ -686,8 +1312,7 @@ mod spy_tests {
                 Some(date(2100, 9, 8)),
                 None,
                 rev + 1,
-            )
-            .into(),
+            ),
         );
         let _ = usecase.death(id, date(2100, 9, 8)).run(&mut ());
 
@@ -710,6 +1335,9 @@ mod spy_tests {
             select: RefCell::new(0),
             save: RefCell::new(vec![]),
             delete: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
         };
         let mut usecase = TargetPersonUsecase { dao };
 
@@ -727,6 +1355,104 @@ mod spy_tests {
         // Usecase の引数が DAO にそのまま渡されていることを確認
         assert_eq!(usecase.dao.delete.borrow()[0], expected);
     }
+    #[test]
+    fn test_restore() {
+        let dao = SpyPersonDao {
+            insert: RefCell::new(vec![]),
+            inserted_id: 0, // 使わない
+            fetch: RefCell::new(vec![]),
+            fetch_result: Ok(None),
+            select: RefCell::new(0),
+            save: RefCell::new(vec![]),
+            delete: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
+        };
+        let mut usecase = TargetPersonUsecase { dao };
+
+        let _ = usecase.restore(42).run(&mut ());
+
+        // DAO のメソッドの呼び出し記録の検証
+        assert_eq!(usecase.dao.insert.borrow().len(), 0);
+        assert_eq!(*usecase.dao.fetch.borrow(), vec![42]);
+        assert_eq!(*usecase.dao.select.borrow(), 0);
+        assert_eq!(usecase.dao.save.borrow().len(), 0);
+        assert_eq!(usecase.dao.delete.borrow().len(), 0);
+        assert_eq!(*usecase.dao.restore.borrow(), vec![42]);
+        assert_eq!(usecase.dao.purge.borrow().len(), 0);
+    }
+    #[test]
+    fn test_purge() {
+        let dao = SpyPersonDao {
+            insert: RefCell::new(vec![]),
+            inserted_id: 0, // 使わない
+            fetch: RefCell::new(vec![]),
+            fetch_result: Ok(None),
+            select: RefCell::new(0),
+            save: RefCell::new(vec![]),
+            delete: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
+        };
+        let mut usecase = TargetPersonUsecase { dao };
+
+        let before = Utc::now();
+        let _ = usecase.purge(before).run(&mut ());
+
+        // DAO のメソッドの呼び出し記録の検証
+        assert_eq!(usecase.dao.fetch.borrow().len(), 0);
+        assert_eq!(usecase.dao.delete.borrow().len(), 0);
+        assert_eq!(usecase.dao.restore.borrow().len(), 0);
+        assert_eq!(*usecase.dao.purge.borrow(), vec![before]);
+    }
+    #[test]
+    fn test_history() {
+        let dao = SpyPersonDao {
+            insert: RefCell::new(vec![]),
+            inserted_id: 0, // 使わない
+            fetch: RefCell::new(vec![]),
+            fetch_result: Ok(None),
+            select: RefCell::new(0),
+            save: RefCell::new(vec![]),
+            delete: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
+        };
+        let mut usecase = TargetPersonUsecase { dao };
+
+        let _ = usecase.history(42).run(&mut ());
+
+        // DAO のメソッドの呼び出し記録の検証
+        assert_eq!(usecase.dao.fetch.borrow().len(), 0);
+        assert_eq!(*usecase.dao.select.borrow(), 0);
+        assert_eq!(*usecase.dao.history.borrow(), vec![42]);
+    }
+    #[test]
+    fn test_find_as_of() {
+        let dao = SpyPersonDao {
+            insert: RefCell::new(vec![]),
+            inserted_id: 0, // 使わない
+            fetch: RefCell::new(vec![]),
+            fetch_result: Ok(None),
+            select: RefCell::new(0),
+            save: RefCell::new(vec![]),
+            delete: RefCell::new(vec![]),
+            restore: RefCell::new(vec![]),
+            purge: RefCell::new(vec![]),
+            history: RefCell::new(vec![]),
+        };
+        let mut usecase = TargetPersonUsecase { dao };
+
+        let _ = usecase.find_as_of(42, 3).run(&mut ());
+
+        // DAO のメソッドの呼び出し記録の検証
+        // 現在の値ではなく履歴から組み立てる
+        assert_eq!(usecase.dao.fetch.borrow().len(), 0);
+        assert_eq!(*usecase.dao.history.borrow(), vec![42]);
+    }
 }
 
 // # エラー系スタブテスト
@@ -767,14 +1493,17 @@ mod spy_tests {
 mod error_stub_tests {
     use super::*;
     use crate::domain::{date, Revision};
-    use crate::dto::PersonDto;
+    use crate::dto::{PersonDto, PersonHistoryDto};
 
     struct StubPersonDao {
         insert_result: Result<PersonId, DaoError>,
         fetch_result: Result<Option<PersonDto>, DaoError>,
         select_result: Result<Vec<(PersonId, PersonDto)>, DaoError>,
         save_result: Result<(), DaoError>,
-        delete_result: Result<(), DaoError>,
+        delete_result: Result<Option<Revision>, DaoError>,
+        restore_result: Result<Option<Revision>, DaoError>,
+        purge_result: Result<u64, DaoError>,
+        history_result: Result<Vec<PersonHistoryDto>, DaoError>,
     }
     // Ctx 不要なので () にしている
     impl PersonDao<()> for StubPersonDao {
@@ -801,9 +1530,27 @@ mod error_stub_tests {
         ) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
             tx_rs::with_tx(move |()| self.save_result.clone())
         }
-        fn delete(&self, _id: PersonId) -> impl tx_rs::Tx<(), Item = (), Err = DaoError> {
+        fn delete(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
             tx_rs::with_tx(move |()| self.delete_result.clone())
         }
+        fn restore(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Option<Revision>, Err = DaoError> {
+            tx_rs::with_tx(move |()| self.restore_result.clone())
+        }
+        fn purge(&self, _before: DateTime<Utc>) -> impl tx_rs::Tx<(), Item = u64, Err = DaoError> {
+            tx_rs::with_tx(move |()| self.purge_result.clone())
+        }
+        fn history(
+            &self,
+            _id: PersonId,
+        ) -> impl tx_rs::Tx<(), Item = Vec<PersonHistoryDto>, Err = DaoError> {
+            tx_rs::with_tx(move |()| self.history_result.clone())
+        }
     }
 
     struct TargetPersonUsecase {
@@ -820,10 +1567,13 @@ mod error_stub_tests {
     fn test_entry() {
         let dao = StubPersonDao {
             insert_result: Err(DaoError::InsertError("valid dao".to_string())),
-            fetch_result: Ok(None),    // 使わない
-            select_result: Ok(vec![]), // 使わない
-            save_result: Ok(()),       // 使わない
-            delete_result: Ok(()),     // 使わない
+            fetch_result: Ok(None),     // 使わない
+            select_result: Ok(vec![]),  // 使わない
+            save_result: Ok(()),        // 使わない
+            delete_result: Ok(None),    // 使わない
+            restore_result: Ok(None),   // 使わない
+            purge_result: Ok(0),        // 使わない
+            history_result: Ok(vec![]), // 使わない
         };
         let expected = UsecaseError::EntryPersonFailed(dao.insert_result.clone().unwrap_err());
 
@@ -841,9 +1591,12 @@ mod error_stub_tests {
         let dao = StubPersonDao {
             insert_result: Ok(42), // 使わない
             fetch_result: Err(DaoError::SelectError("valid dao".to_string())),
-            select_result: Ok(vec![]), // 使わない
-            save_result: Ok(()),       // 使わない
-            delete_result: Ok(()),     // 使わない
+            select_result: Ok(vec![]),  // 使わない
+            save_result: Ok(()),        // 使わない
+            delete_result: Ok(None),    // 使わない
+            restore_result: Ok(None),   // 使わない
+            purge_result: Ok(0),        // 使わない
+            history_result: Ok(vec![]), // 使わない
         };
         let expected = UsecaseError::FindPersonFailed(dao.fetch_result.clone().unwrap_err());
 
@@ -860,10 +1613,13 @@ mod error_stub_tests {
     fn test_entry_and_verify_insert_error() {
         let dao = StubPersonDao {
             insert_result: Err(DaoError::InsertError("valid dao".to_string())),
-            fetch_result: Ok(None),    // 使わない
-            select_result: Ok(vec![]), // 使わない
-            save_result: Ok(()),       // 使わない
-            delete_result: Ok(()),     // 使わない
+            fetch_result: Ok(None),     // 使わない
+            select_result: Ok(vec![]),  // 使わない
+            save_result: Ok(()),        // 使わない
+            delete_result: Ok(None),    // 使わない
+            restore_result: Ok(None),   // 使わない
+            purge_result: Ok(0),        // 使わない
+            history_result: Ok(vec![]), // 使わない
         };
         let expected =
             UsecaseError::EntryAndVerifyPersonFailed(dao.insert_result.clone().unwrap_err());
@@ -882,9 +1638,12 @@ mod error_stub_tests {
         let dao = StubPersonDao {
             insert_result: Ok(42),
             fetch_result: Err(DaoError::SelectError("valid dao".to_string())),
-            select_result: Ok(vec![]), // 使わない
-            save_result: Ok(()),       // 使わない
-            delete_result: Ok(()),     // 使わない
+            select_result: Ok(vec![]),  // 使わない
+            save_result: Ok(()),        // 使わない
+            delete_result: Ok(None),    // 使わない
+            restore_result: Ok(None),   // 使わない
+            purge_result: Ok(0),        // 使わない
+            history_result: Ok(vec![]), // 使わない
         };
         let expected =
             UsecaseError::EntryAndVerifyPersonFailed(dao.fetch_result.clone().unwrap_err());
@@ -904,8 +1663,11 @@ mod error_stub_tests {
             insert_result: Ok(42),  // 使わない
             fetch_result: Ok(None), // 使わない
             select_result: Err(DaoError::SelectError("valid dao".to_string())),
-            save_result: Ok(()),   // 使わない
-            delete_result: Ok(()), // 使わない
+            save_result: Ok(()),        // 使わない
+            delete_result: Ok(None),    // 使わない
+            restore_result: Ok(None),   // 使わない
+            purge_result: Ok(0),        // 使わない
+            history_result: Ok(vec![]), // 使わない
         };
         let expected = UsecaseError::CollectPersonFailed(dao.select_result.clone().unwrap_err());
 
@@ -929,7 +1691,10 @@ mod error_stub_tests {
             ))),
             select_result: Ok(vec![]), // 使わない
             save_result: Err(DaoError::UpdateError("valid dao".to_string())),
-            delete_result: Ok(()), // 使わない
+            delete_result: Ok(None),    // 使わない
+            restore_result: Ok(None),   // 使わない
+            purge_result: Ok(0),        // 使わない
+            history_result: Ok(vec![]), // 使わない
         };
         let expected =
             UsecaseError::SavePersonFailed(DaoError::UpdateError("valid dao".to_string()));
@@ -943,13 +1708,72 @@ mod error_stub_tests {
         assert_eq!(result.err().unwrap(), expected);
     }
     #[test]
+    fn test_death_revision_conflict() {
+        let dao = StubPersonDao {
+            insert_result: Ok(42), // 使わない
+            fetch_result: Ok(Some(PersonDto::new(
+                "Alice",
+                date(2020, 5, 5),
+                None,
+                None,
+                3,
+            ))),
+            select_result: Ok(vec![]), // 使わない
+            save_result: Err(DaoError::RevisionConflict {
+                expected: 3,
+                actual: 4,
+            }),
+            delete_result: Ok(None),    // 使わない
+            restore_result: Ok(None),   // 使わない
+            purge_result: Ok(0),        // 使わない
+            history_result: Ok(vec![]), // 使わない
+        };
+        let expected = UsecaseError::RevisionConflict {
+            expected: 3,
+            actual: 4,
+        };
+
+        let mut usecase = TargetPersonUsecase { dao };
+
+        let id: PersonId = 42;
+        let result = usecase.death(id, date(2100, 10, 15)).run(&mut ());
+
+        assert!(result.is_err());
+        assert_eq!(result.err().unwrap(), expected);
+    }
+    #[test]
+    fn test_death_not_found() {
+        let dao = StubPersonDao {
+            insert_result: Ok(42), // 使わない
+            fetch_result: Ok(None),
+            select_result: Ok(vec![]),  // 使わない
+            save_result: Ok(()),        // 使わない
+            delete_result: Ok(None),    // 使わない
+            restore_result: Ok(None),   // 使わない
+            purge_result: Ok(0),        // 使わない
+            history_result: Ok(vec![]), // 使わない
+        };
+        let expected = UsecaseError::PersonNotFound(42);
+
+        let mut usecase = TargetPersonUsecase { dao };
+
+        let id: PersonId = 42;
+        let result = usecase.death(id, date(2100, 10, 15)).run(&mut ());
+
+        assert!(result.is_err());
+        assert_eq!(result.err().unwrap(), expected);
+    }
+    #[test]
     fn test_death_fetch_error() {
         let dao = StubPersonDao {
             insert_result: Ok(42), // 使わない
             fetch_result: Err(DaoError::SelectError("valid dao".to_string())),
-            select_result: Ok(vec![]), // 使わない
-            save_result: Ok(()),       // 使わない
-            delete_result: Ok(()),     // 使わない
+            select_result: Ok(vec![]),  // 使わない
+            save_result: Ok(()),        // 使わない
+            delete_result: Ok(None),    // 使わない
+            restore_result: Ok(None),   // 使わない
+            purge_result: Ok(0),        // 使わない
+            history_result: Ok(vec![]), // 使わない
         };
         let expected =
             UsecaseError::FindPersonFailed(DaoError::SelectError("valid dao".to_string()));
@@ -970,6 +1794,9 @@ mod error_stub_tests {
             select_result: Ok(vec![]), // 使わない
             save_result: Ok(()),       // 使わない
             delete_result: Err(DaoError::DeleteError("valid dao".to_string())),
+            restore_result: Ok(None),   // 使わない
+            purge_result: Ok(0),        // 使わない
+            history_result: Ok(vec![]), // 使わない
         };
         let expected = UsecaseError::RemovePersonFailed(dao.delete_result.clone().unwrap_err());
 
@@ -981,4 +1808,92 @@ mod error_stub_tests {
         assert!(result.is_err());
         assert_eq!(result.err().unwrap(), expected);
     }
+
+    #[test]
+    fn test_restore() {
+        let dao = StubPersonDao {
+            insert_result: Ok(42),     // 使わない
+            fetch_result: Ok(None),    // 使わない
+            select_result: Ok(vec![]), // 使わない
+            save_result: Ok(()),       // 使わない
+            delete_result: Ok(None),   // 使わない
+            restore_result: Err(DaoError::RestoreError("valid dao".to_string())),
+            purge_result: Ok(0),        // 使わない
+            history_result: Ok(vec![]), // 使わない
+        };
+        let expected = UsecaseError::RestorePersonFailed(dao.restore_result.clone().unwrap_err());
+
+        let mut usecase = TargetPersonUsecase { dao };
+
+        let result = usecase.restore(42).run(&mut ());
+
+        assert!(result.is_err());
+        assert_eq!(result.err().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_purge() {
+        let dao = StubPersonDao {
+            insert_result: Ok(42),     // 使わない
+            fetch_result: Ok(None),    // 使わない
+            select_result: Ok(vec![]), // 使わない
+            save_result: Ok(()),       // 使わない
+            delete_result: Ok(None),   // 使わない
+            restore_result: Ok(None),  // 使わない
+            purge_result: Err(DaoError::PurgeError("valid dao".to_string())),
+            history_result: Ok(vec![]), // 使わない
+        };
+        let expected = UsecaseError::PurgePersonFailed(dao.purge_result.clone().unwrap_err());
+
+        let mut usecase = TargetPersonUsecase { dao };
+
+        let result = usecase.purge(Utc::now()).run(&mut ());
+
+        assert!(result.is_err());
+        assert_eq!(result.err().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_history() {
+        let dao = StubPersonDao {
+            insert_result: Ok(42),     // 使わない
+            fetch_result: Ok(None),    // 使わない
+            select_result: Ok(vec![]), // 使わない
+            save_result: Ok(()),       // 使わない
+            delete_result: Ok(None),   // 使わない
+            restore_result: Ok(None),  // 使わない
+            purge_result: Ok(0),       // 使わない
+            history_result: Err(DaoError::SelectError("valid dao".to_string())),
+        };
+        let expected = UsecaseError::FindPersonFailed(dao.history_result.clone().unwrap_err());
+
+        let mut usecase = TargetPersonUsecase { dao };
+
+        let result = usecase.history(42).run(&mut ());
+
+        assert!(result.is_err());
+        assert_eq!(result.err().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_find_as_of() {
+        let dao = StubPersonDao {
+            insert_result: Ok(42),     // 使わない
+            fetch_result: Ok(None),    // 使わない
+            select_result: Ok(vec![]), // 使わない
+            save_result: Ok(()),       // 使わない
+            delete_result: Ok(None),   // 使わない
+            restore_result: Ok(None),  // 使わない
+            purge_result: Ok(0),       // 使わない
+            history_result: Err(DaoError::SelectError("valid dao".to_string())),
+        };
+        let expected = UsecaseError::FindPersonFailed(dao.history_result.clone().unwrap_err());
+
+        let mut usecase = TargetPersonUsecase { dao };
+
+        let result = usecase.find_as_of(42, 0).run(&mut ());
+
+        assert!(result.is_err());
+        assert_eq!(result.err().unwrap(), expected);
+    }
 }
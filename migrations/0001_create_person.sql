@@ -0,0 +1,23 @@
+-- the tables created by docker-entrypoint-initdb.d/initdb.sh before migrations were introduced,
+-- so every statement is idempotent to adopt such a database as it is
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE TABLE IF NOT EXISTS person (
+  id           SERIAL PRIMARY KEY,
+  name         TEXT NOT NULL,
+  birth_date   DATE NOT NULL,
+  death_date   DATE,
+  data         BYTEA,
+  deleted_at   TIMESTAMPTZ,
+
+  revision     INT NOT NULL
+);
+-- the columns added after the first initdb.sh, which a database created by it has not
+ALTER TABLE person ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+-- keyset pagination sorted by name or birth_date (ties broken by id)
+CREATE INDEX IF NOT EXISTS person_name_id_idx ON person (name, id);
+CREATE INDEX IF NOT EXISTS person_birth_date_id_idx ON person (birth_date, id);
+-- case-insensitive substring and fuzzy search on name
+CREATE INDEX IF NOT EXISTS person_name_trgm_idx ON person USING gin (lower(name) gin_trgm_ops);
+-- purge of soft deleted persons
+CREATE INDEX IF NOT EXISTS person_deleted_at_idx ON person (deleted_at) WHERE deleted_at IS NOT NULL;
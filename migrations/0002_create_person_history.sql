@@ -0,0 +1,17 @@
+-- append-only history of every change of person, written in the same transaction
+CREATE TABLE IF NOT EXISTS person_history (
+  id           BIGSERIAL PRIMARY KEY,
+  person_id    INT NOT NULL,
+  revision     INT NOT NULL,
+  kind         TEXT NOT NULL,
+  old_value    JSONB,
+  new_value    JSONB,
+  changed_at   TIMESTAMPTZ NOT NULL DEFAULT now(),
+  actor        TEXT NOT NULL,
+  reason       TEXT
+);
+-- the reason of a correction was added after the table had been created without it
+ALTER TABLE person_history ADD COLUMN IF NOT EXISTS reason TEXT;
+CREATE INDEX IF NOT EXISTS person_history_person_id_idx ON person_history (person_id, id);
+CREATE OR REPLACE RULE person_history_no_update AS ON UPDATE TO person_history DO INSTEAD NOTHING;
+CREATE OR REPLACE RULE person_history_no_delete AS ON DELETE TO person_history DO INSTEAD NOTHING;
@@ -0,0 +1,18 @@
+-- notifications written in the same transaction as the change, and drained by the relay
+CREATE TABLE IF NOT EXISTS outbox (
+  id              BIGSERIAL PRIMARY KEY,
+  level           TEXT NOT NULL,
+  queue           TEXT NOT NULL,
+  message         TEXT NOT NULL,
+  event           JSONB,
+  file            TEXT NOT NULL,
+  line            INT NOT NULL,
+  column_no       INT NOT NULL,
+  attempts        INT NOT NULL DEFAULT 0,
+  last_error      TEXT,
+  next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  created_at      TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+-- initdb.sh created outbox without event before the events were typed
+ALTER TABLE outbox ADD COLUMN IF NOT EXISTS event JSONB;
+CREATE INDEX IF NOT EXISTS outbox_next_attempt_at_idx ON outbox (next_attempt_at, id);